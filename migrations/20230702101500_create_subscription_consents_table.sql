@@ -0,0 +1,17 @@
+-- Create Subscription Consents Table
+--
+-- Evidence of how and when each subscriber opted in, captured when the
+-- sign-up form is submitted and completed when the confirmation link is
+-- clicked.
+CREATE TABLE subscription_consents(
+   subscriber_id uuid NOT NULL REFERENCES subscriptions (id),
+   PRIMARY KEY (subscriber_id),
+   consented_at timestamptz NOT NULL,
+   ip_address TEXT NULL,
+   user_agent TEXT NULL,
+   form_id TEXT NOT NULL,
+   consent_text_version TEXT NOT NULL,
+   confirmed_at timestamptz NULL,
+   confirmation_ip_address TEXT NULL,
+   confirmation_user_agent TEXT NULL
+);
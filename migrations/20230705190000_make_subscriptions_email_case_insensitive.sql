@@ -0,0 +1,34 @@
+-- Make email uniqueness case-insensitive.
+--
+-- `email_canonical` holds the form of the address used to detect duplicates
+-- (see `SubscriberEmail::dedup_key`): lowercased and, optionally, with
+-- provider aliases folded.
+--
+-- The old constraint was case-sensitive, so `Foo@x.com` and `foo@x.com` may
+-- both be subscribed already. The confirmed one, or else the oldest, keeps
+-- the address; the others get their id appended, which no address can end
+-- with, so that they stay around for an admin to review.
+BEGIN;
+    ALTER TABLE subscriptions ADD COLUMN email_canonical TEXT NULL;
+    UPDATE subscriptions s
+    SET email_canonical = CASE
+        WHEN d.rank = 1 THEN d.email_canonical
+        ELSE d.email_canonical || '#' || s.id
+    END
+    FROM (
+        SELECT
+            id,
+            lower(trim(email)) AS email_canonical,
+            row_number() OVER (
+                PARTITION BY lower(trim(email))
+                ORDER BY status = 'confirmed' DESC, subscribed_at, id
+            ) AS rank
+        FROM subscriptions
+    ) d
+    WHERE s.id = d.id;
+    ALTER TABLE subscriptions ALTER COLUMN email_canonical SET NOT NULL;
+    ALTER TABLE subscriptions
+        ADD CONSTRAINT subscriptions_email_canonical_key
+        UNIQUE (email_canonical);
+    ALTER TABLE subscriptions DROP CONSTRAINT subscriptions_email_key;
+COMMIT;
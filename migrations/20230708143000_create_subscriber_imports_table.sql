@@ -0,0 +1,19 @@
+-- Create Subscriber Imports Table
+--
+-- One row per CSV import run from the admin UI. The consent note documents
+-- how people imported as confirmed opted in on the platform we migrated
+-- from, `error_report` is a CSV of the rows that were rejected.
+CREATE TABLE subscriber_imports(
+   id uuid NOT NULL,
+   PRIMARY KEY (id),
+   imported_by uuid NOT NULL REFERENCES users (user_id),
+   imported_at timestamptz NOT NULL,
+   mode TEXT NOT NULL,
+   consent_note TEXT NULL,
+   imported_count INTEGER NOT NULL,
+   rejected_count INTEGER NOT NULL,
+   error_report TEXT NOT NULL
+);
+
+ALTER TABLE subscription_consents
+    ADD COLUMN import_id uuid NULL REFERENCES subscriber_imports (id);
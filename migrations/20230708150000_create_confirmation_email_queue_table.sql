@@ -0,0 +1,11 @@
+-- Create Confirmation Email Queue Table
+--
+-- Confirmation emails waiting to be sent by the background worker, e.g. for
+-- the subscribers of a CSV import. Tasks go away with their token.
+CREATE TABLE confirmation_email_queue(
+   subscription_token TEXT NOT NULL
+      REFERENCES subscription_tokens (subscription_token) ON DELETE CASCADE,
+   n_retries SMALLINT NOT NULL,
+   execute_after timestamptz NOT NULL,
+   PRIMARY KEY (subscription_token)
+);
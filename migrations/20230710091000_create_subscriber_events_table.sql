@@ -0,0 +1,22 @@
+-- Create Subscriber Events Table
+--
+-- The history of each subscriber: sign-up, confirmation and the actions
+-- admins take from the subscriber management pages. `performed_by` is set
+-- when an admin triggered the event.
+CREATE TABLE subscriber_events(
+   id BIGSERIAL PRIMARY KEY,
+   subscriber_id uuid NOT NULL REFERENCES subscriptions (id) ON DELETE CASCADE,
+   occurred_at timestamptz NOT NULL,
+   event TEXT NOT NULL,
+   performed_by uuid NULL REFERENCES users (user_id)
+);
+CREATE INDEX subscriber_events_subscriber_id_idx
+    ON subscriber_events (subscriber_id, occurred_at);
+
+-- Backfill the history we can reconstruct for existing subscribers.
+INSERT INTO subscriber_events (subscriber_id, occurred_at, event)
+SELECT id, subscribed_at, 'signed_up' FROM subscriptions;
+INSERT INTO subscriber_events (subscriber_id, occurred_at, event)
+SELECT subscriber_id, confirmed_at, 'confirmed'
+FROM subscription_consents
+WHERE confirmed_at IS NOT NULL;
@@ -0,0 +1,75 @@
+-- Make Subscription Status An Enum
+--
+-- `subscriptions.status` becomes a Postgres enum, mirrored by the
+-- `SubscriptionStatus` domain type, and every status change is recorded in
+-- `subscription_status_changes` along with its cause.
+BEGIN;
+    CREATE TYPE subscription_status AS ENUM (
+        'pending_confirmation',
+        'confirmed',
+        'unsubscribed',
+        'bounced',
+        'complained',
+        'erased'
+    );
+    ALTER TABLE subscriptions
+        ALTER COLUMN status TYPE subscription_status
+        USING status::subscription_status;
+
+    -- `from_status` is NULL for the status a subscriber was created with.
+    CREATE TABLE subscription_status_changes(
+       id BIGSERIAL PRIMARY KEY,
+       subscriber_id uuid NOT NULL
+           REFERENCES subscriptions (id) ON DELETE CASCADE,
+       occurred_at timestamptz NOT NULL,
+       from_status subscription_status NULL,
+       to_status subscription_status NOT NULL,
+       cause TEXT NOT NULL,
+       performed_by uuid NULL REFERENCES users (user_id)
+    );
+    CREATE INDEX subscription_status_changes_subscriber_id_idx
+        ON subscription_status_changes (subscriber_id, occurred_at);
+
+    -- Status changes used to be recorded as subscriber events: move them
+    -- over, working out the status each one started from.
+    INSERT INTO subscription_status_changes (
+        subscriber_id, occurred_at, from_status, to_status, cause, performed_by
+    )
+    SELECT
+        subscriber_id,
+        occurred_at,
+        LAG(to_status) OVER (
+            PARTITION BY subscriber_id ORDER BY occurred_at, id
+        ),
+        to_status,
+        cause,
+        performed_by
+    FROM (
+        SELECT
+            e.id,
+            e.subscriber_id,
+            e.occurred_at,
+            e.performed_by,
+            CASE e.event
+                WHEN 'signed_up' THEN 'pending_confirmation'
+                WHEN 'imported' THEN
+                    CASE i.mode
+                        WHEN 'confirmed' THEN 'confirmed'
+                        ELSE 'pending_confirmation'
+                    END
+                ELSE e.event
+            END::subscription_status AS to_status,
+            CASE
+                WHEN e.event = 'signed_up' THEN 'sign_up'
+                WHEN e.event = 'imported' THEN 'import'
+                WHEN e.performed_by IS NULL THEN 'confirmation_link'
+                ELSE 'admin'
+            END AS cause
+        FROM subscriber_events e
+        LEFT JOIN subscription_consents c ON c.subscriber_id = e.subscriber_id
+        LEFT JOIN subscriber_imports i ON i.id = c.import_id
+        WHERE e.event IN ('signed_up', 'imported', 'confirmed', 'unsubscribed')
+    ) AS events;
+    DELETE FROM subscriber_events
+        WHERE event IN ('signed_up', 'imported', 'confirmed', 'unsubscribed');
+COMMIT;
@@ -0,0 +1,3 @@
+-- Custom attributes collected at sign-up, validated against the configured
+-- schema before being stored.
+ALTER TABLE subscriptions ADD COLUMN attributes JSONB NOT NULL DEFAULT '{}';
@@ -0,0 +1,9 @@
+-- Where each sign-up came from: our own source identifier and the UTM
+-- parameters of the campaign link, when there was one.
+ALTER TABLE subscriptions
+    ADD COLUMN source TEXT NULL,
+    ADD COLUMN utm_source TEXT NULL,
+    ADD COLUMN utm_medium TEXT NULL,
+    ADD COLUMN utm_campaign TEXT NULL,
+    ADD COLUMN utm_term TEXT NULL,
+    ADD COLUMN utm_content TEXT NULL;
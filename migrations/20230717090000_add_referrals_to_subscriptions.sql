@@ -0,0 +1,8 @@
+-- Every subscriber gets a referral code, used in the link they can share
+-- once confirmed, and sign-ups through such a link point to the referrer.
+ALTER TABLE subscriptions
+    ADD COLUMN referral_code TEXT NOT NULL UNIQUE
+        DEFAULT substr(md5(gen_random_uuid()::text), 1, 16),
+    ADD COLUMN referred_by uuid NULL
+        REFERENCES subscriptions (id) ON DELETE SET NULL;
+CREATE INDEX subscriptions_referred_by_idx ON subscriptions (referred_by);
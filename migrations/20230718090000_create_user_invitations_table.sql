@@ -0,0 +1,21 @@
+-- Admins invite colleagues by email. Invited users pick their own username
+-- and password; admins can deactivate users instead of deleting them, so
+-- that what they did stays attributed.
+BEGIN;
+    ALTER TABLE users
+        ADD COLUMN email TEXT NULL UNIQUE,
+        ADD COLUMN is_active BOOLEAN NOT NULL DEFAULT TRUE,
+        ADD COLUMN created_at timestamptz NOT NULL DEFAULT now();
+
+    -- Only a hash of the token is stored, the link itself is in the email.
+    CREATE TABLE user_invitations(
+       id uuid PRIMARY KEY,
+       email TEXT NOT NULL,
+       token_hash TEXT NOT NULL UNIQUE,
+       invited_by uuid NOT NULL REFERENCES users (user_id),
+       created_at timestamptz NOT NULL,
+       expires_at timestamptz NOT NULL,
+       accepted_at timestamptz NULL,
+       user_id uuid NULL REFERENCES users (user_id)
+    );
+COMMIT;
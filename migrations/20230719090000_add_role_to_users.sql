@@ -0,0 +1,9 @@
+-- What each user may do in the admin area. Existing users keep full access,
+-- new ones get the least privileged role unless told otherwise.
+BEGIN;
+    CREATE TYPE user_role AS ENUM ('admin', 'editor', 'viewer');
+    ALTER TABLE users ADD COLUMN role user_role NOT NULL DEFAULT 'admin';
+    ALTER TABLE users ALTER COLUMN role SET DEFAULT 'viewer';
+    ALTER TABLE user_invitations
+        ADD COLUMN role user_role NOT NULL DEFAULT 'viewer';
+COMMIT;
@@ -0,0 +1,21 @@
+-- Optional TOTP two-factor authentication. `totp_last_used_step` keeps a
+-- code from being used twice, and recovery codes are only stored hashed.
+BEGIN;
+    ALTER TABLE users
+        ADD COLUMN totp_secret TEXT NULL,
+        ADD COLUMN totp_last_used_step BIGINT NULL;
+
+    CREATE TABLE user_recovery_codes(
+       user_id uuid NOT NULL REFERENCES users (user_id) ON DELETE CASCADE,
+       code_hash TEXT NOT NULL,
+       used_at timestamptz NULL,
+       PRIMARY KEY (user_id, code_hash)
+    );
+
+    -- Settings of the admin area, changed by admins at runtime.
+    CREATE TABLE admin_settings(
+       id BOOLEAN PRIMARY KEY DEFAULT TRUE CHECK (id),
+       require_two_factor BOOLEAN NOT NULL DEFAULT FALSE
+    );
+    INSERT INTO admin_settings DEFAULT VALUES;
+COMMIT;
@@ -0,0 +1,16 @@
+-- Password reset links. Only a hash of their token is stored, like for
+-- invitations.
+BEGIN;
+    CREATE TABLE password_reset_tokens(
+       token_hash TEXT PRIMARY KEY,
+       user_id uuid NOT NULL REFERENCES users (user_id) ON DELETE CASCADE,
+       created_at timestamptz NOT NULL,
+       expires_at timestamptz NOT NULL,
+       used_at timestamptz NULL
+    );
+    CREATE INDEX password_reset_tokens_user_id_idx
+        ON password_reset_tokens (user_id);
+
+    -- Sessions that were authenticated before this are no longer accepted.
+    ALTER TABLE users ADD COLUMN sessions_invalidated_at timestamptz NULL;
+COMMIT;
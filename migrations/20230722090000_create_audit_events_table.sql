@@ -0,0 +1,12 @@
+-- Who did what in the admin area. Events are only ever appended.
+CREATE TABLE audit_events(
+   id BIGSERIAL PRIMARY KEY,
+   user_id uuid NOT NULL REFERENCES users (user_id),
+   occurred_at timestamptz NOT NULL,
+   ip_address TEXT NULL,
+   action TEXT NOT NULL,
+   payload JSONB NOT NULL DEFAULT '{}'
+);
+CREATE INDEX audit_events_occurred_at_idx ON audit_events (occurred_at);
+CREATE INDEX audit_events_user_id_idx ON audit_events (user_id);
+CREATE INDEX audit_events_action_idx ON audit_events (action);
@@ -0,0 +1,157 @@
+use actix_web::http::header::HeaderMap;
+use anyhow::Context;
+use rand::distributions::Alphanumeric;
+use rand::{thread_rng, Rng};
+use secrecy::{ExposeSecret, Secret};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use super::{hash_token, AuthError, Permission};
+
+/// Marks our tokens, so that they are easy to spot when leaked, e.g. by
+/// secret scanners.
+const API_TOKEN_PREFIX: &str = "z2p_";
+const API_TOKEN_LENGTH: usize = 40;
+
+/// What an API token may be used for. A token never grants more than the
+/// role of its owner allows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ApiScope {
+    PublishNewsletters,
+}
+
+impl ApiScope {
+    pub const ALL: [ApiScope; 1] = [Self::PublishNewsletters];
+
+    pub fn parse(s: &str) -> Result<Self, String> {
+        Self::ALL
+            .into_iter()
+            .find(|scope| scope.as_str() == s)
+            .ok_or_else(|| format!("{} is not a valid scope.", s))
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::PublishNewsletters => "publish_newsletters",
+        }
+    }
+
+    /// The permission the owner of the token needs for it to be used with
+    /// this scope.
+    pub fn permission(&self) -> Permission {
+        match self {
+            Self::PublishNewsletters => Permission::PublishNewsletters,
+        }
+    }
+}
+
+impl std::fmt::Display for ApiScope {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// A token that can currently be used.
+pub struct ApiToken {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub scopes: Vec<ApiScope>,
+}
+
+pub fn generate_api_token() -> Secret<String> {
+    let mut rng = thread_rng();
+    let random: String = std::iter::repeat_with(|| rng.sample(Alphanumeric))
+        .map(char::from)
+        .take(API_TOKEN_LENGTH)
+        .collect();
+    Secret::new(format!("{}{}", API_TOKEN_PREFIX, random))
+}
+
+/// The token of an `Authorization: Bearer` header, if the request has one.
+pub fn bearer_token(headers: &HeaderMap) -> Option<Secret<String>> {
+    headers
+        .get("Authorization")?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")
+        .map(|token| Secret::new(token.trim().to_string()))
+}
+
+/// Look up an unexpired, unrevoked token of an active user, and record that
+/// it was used.
+#[tracing::instrument(name = "Validate API token", skip(token, pool))]
+pub async fn validate_api_token(
+    token: &Secret<String>,
+    pool: &PgPool,
+) -> Result<ApiToken, AuthError> {
+    let row = sqlx::query!(
+        r#"
+        UPDATE api_tokens t
+        SET last_used_at = now()
+        FROM users u
+        WHERE u.user_id = t.user_id
+            AND t.token_hash = $1
+            AND t.revoked_at IS NULL
+            AND (t.expires_at IS NULL OR t.expires_at > now())
+            AND u.is_active
+        RETURNING t.id, t.user_id, t.scopes
+        "#,
+        hash_token(token.expose_secret())
+    )
+    .fetch_optional(pool)
+    .await
+    .context("failed to validate API token")
+    .map_err(AuthError::UnexpectedError)?
+    .ok_or_else(|| {
+        AuthError::InvalidCredentials(anyhow::anyhow!("unknown API token."))
+    })?;
+
+    Ok(ApiToken {
+        id: row.id,
+        user_id: row.user_id,
+        // Scopes that no longer exist grant nothing.
+        scopes: row
+            .scopes
+            .iter()
+            .filter_map(|s| ApiScope::parse(s).ok())
+            .collect(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::http::header::{HeaderMap, HeaderValue, AUTHORIZATION};
+    use secrecy::ExposeSecret;
+
+    use super::{bearer_token, generate_api_token, ApiScope};
+
+    #[test]
+    fn tokens_are_prefixed_and_random() {
+        let a = generate_api_token();
+        let b = generate_api_token();
+        assert!(a.expose_secret().starts_with("z2p_"));
+        assert_eq!(a.expose_secret().len(), 44);
+        assert_ne!(a.expose_secret(), b.expose_secret());
+    }
+
+    #[test]
+    fn bearer_tokens_are_read_from_the_authorization_header() {
+        let mut headers = HeaderMap::new();
+        assert!(bearer_token(&headers).is_none());
+
+        headers.insert(AUTHORIZATION, HeaderValue::from_static("Basic abc"));
+        assert!(bearer_token(&headers).is_none());
+
+        headers
+            .insert(AUTHORIZATION, HeaderValue::from_static("Bearer z2p_abc"));
+        assert_eq!(bearer_token(&headers).unwrap().expose_secret(), "z2p_abc");
+    }
+
+    #[test]
+    fn scopes_round_trip_through_their_name() {
+        for scope in ApiScope::ALL {
+            assert_eq!(ApiScope::parse(scope.as_str()), Ok(scope));
+        }
+        assert!(ApiScope::parse("everything").is_err());
+    }
+}
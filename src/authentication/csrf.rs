@@ -0,0 +1,239 @@
+use std::future::{ready, Ready};
+
+use actix_multipart::Multipart;
+use actix_web::body::{EitherBody, MessageBody};
+use actix_web::dev::{Payload, ServiceRequest, ServiceResponse};
+use actix_web::error::PayloadError;
+use actix_web::http::header::{self, ContentType};
+use actix_web::http::Method;
+use actix_web::web::{Bytes, BytesMut};
+use actix_web::{FromRequest, HttpMessage, HttpRequest, HttpResponse};
+use actix_web_lab::middleware::Next;
+use futures_util::{stream, StreamExt, TryStreamExt};
+use rand::distributions::Alphanumeric;
+use rand::{thread_rng, Rng};
+
+use crate::routes::e500;
+use crate::session::TypedSession;
+
+/// Name of the hidden form field carrying the token.
+const CSRF_TOKEN_FIELD: &str = "csrf_token";
+/// Header carrying the token for requests that do not come from our forms.
+const CSRF_TOKEN_HEADER: &str = "X-CSRF-Token";
+const CSRF_TOKEN_LENGTH: usize = 32;
+/// Bodies are buffered to look for the token: larger ones are rejected.
+/// Enough for the largest form, the CSV import.
+const MAX_BODY_SIZE: usize = 32 * 1024 * 1024;
+
+/// The synchronizer token of the session, which every form of the admin
+/// area embeds so that `reject_invalid_csrf_tokens` lets it through. Other
+/// sites can make a browser post to us, but cannot read the token.
+pub struct CsrfToken(String);
+
+impl CsrfToken {
+    /// The hidden field to add to a form.
+    pub fn form_field(&self) -> String {
+        format!(
+            r#"<input type="hidden" name="{}" value="{}">"#,
+            CSRF_TOKEN_FIELD, self.0
+        )
+    }
+}
+
+impl FromRequest for CsrfToken {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<CsrfToken, Self::Error>>;
+
+    /// The token of the session, created the first time a form is shown.
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let session =
+            match TypedSession::from_request(req, payload).into_inner() {
+                Ok(session) => session,
+                Err(e) => return ready(Err(e)),
+            };
+        let token =
+            session.get_csrf_token().map_err(e500).and_then(
+                |token| match token {
+                    Some(token) => Ok(token),
+                    None => {
+                        let token = generate_csrf_token();
+                        session.insert_csrf_token(&token).map_err(e500)?;
+                        Ok(token)
+                    }
+                },
+            );
+        ready(token.map(CsrfToken))
+    }
+}
+
+fn generate_csrf_token() -> String {
+    let mut rng = thread_rng();
+    std::iter::repeat_with(|| rng.sample(Alphanumeric))
+        .map(char::from)
+        .take(CSRF_TOKEN_LENGTH)
+        .collect()
+}
+
+/// Reject requests changing anything without the token of the session, in
+/// the `csrf_token` field of the form or the `X-CSRF-Token` header. Must
+/// run after `reject_anonymous_users`.
+pub async fn reject_invalid_csrf_tokens(
+    mut req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, actix_web::Error> {
+    if matches!(*req.method(), Method::GET | Method::HEAD) {
+        return next
+            .call(req)
+            .await
+            .map(ServiceResponse::map_into_left_body);
+    }
+    let expected = {
+        let (http_request, payload) = req.parts_mut();
+        TypedSession::from_request(http_request, payload).await
+    }?
+    .get_csrf_token()
+    .map_err(e500)?;
+
+    let mut token = req
+        .headers()
+        .get(CSRF_TOKEN_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string);
+    if token.is_none() {
+        let Some(body) = read_body(&mut req).await? else {
+            return Ok(req
+                .into_response(HttpResponse::PayloadTooLarge().finish())
+                .map_into_right_body());
+        };
+        token = token_from_body(&req, body.clone()).await;
+        // Put the body back for the handler.
+        req.set_payload(Payload::Stream {
+            payload: Box::pin(stream::once(async { Ok(body) })),
+        });
+    }
+
+    match (expected, token) {
+        (Some(expected), Some(token)) if tokens_match(&expected, &token) => {
+            next.call(req)
+                .await
+                .map(ServiceResponse::map_into_left_body)
+        }
+        _ => {
+            tracing::warn!("Rejected a request without a valid CSRF token.");
+            Ok(req.into_response(csrf_rejected()).map_into_right_body())
+        }
+    }
+}
+
+/// The whole body of the request, unless it is larger than `MAX_BODY_SIZE`.
+async fn read_body(
+    req: &mut ServiceRequest,
+) -> Result<Option<Bytes>, PayloadError> {
+    let mut payload = req.take_payload();
+    let mut body = BytesMut::new();
+    while let Some(chunk) = payload.next().await {
+        let chunk = chunk?;
+        if body.len() + chunk.len() > MAX_BODY_SIZE {
+            return Ok(None);
+        }
+        body.extend_from_slice(&chunk);
+    }
+    Ok(Some(body.freeze()))
+}
+
+/// The `csrf_token` field of an urlencoded or multipart form.
+async fn token_from_body(req: &ServiceRequest, body: Bytes) -> Option<String> {
+    let content_type =
+        req.headers().get(header::CONTENT_TYPE)?.to_str().ok()?;
+    if content_type.starts_with("application/x-www-form-urlencoded") {
+        return token_from_urlencoded(&body);
+    }
+    if !content_type.starts_with("multipart/form-data") {
+        return None;
+    }
+    let mut multipart = Multipart::new(
+        req.headers(),
+        stream::once(async { Ok::<_, PayloadError>(body) }),
+    );
+    while let Ok(Some(mut field)) = multipart.try_next().await {
+        let is_token = field.name() == Some(CSRF_TOKEN_FIELD);
+        let mut value = Vec::new();
+        while let Ok(Some(chunk)) = field.try_next().await {
+            if is_token {
+                value.extend_from_slice(&chunk);
+            }
+        }
+        if is_token {
+            return String::from_utf8(value).ok();
+        }
+    }
+    None
+}
+
+fn token_from_urlencoded(body: &[u8]) -> Option<String> {
+    std::str::from_utf8(body)
+        .ok()?
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(name, _)| *name == CSRF_TOKEN_FIELD)
+        .and_then(|(_, value)| {
+            urlencoding::decode(&value.replace('+', " "))
+                .ok()
+                .map(|value| value.into_owned())
+        })
+}
+
+/// Compare in constant time, so that the token cannot be guessed one
+/// character at a time.
+fn tokens_match(expected: &str, token: &str) -> bool {
+    expected.len() == token.len()
+        && expected
+            .bytes()
+            .zip(token.bytes())
+            .fold(0, |acc, (a, b)| acc | (a ^ b))
+            == 0
+}
+
+/// The page shown when a form is posted without a valid token.
+fn csrf_rejected() -> HttpResponse {
+    HttpResponse::Forbidden()
+        .content_type(ContentType::html())
+        .body(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Forbidden</title>
+</head>
+<body>
+    <p>This form has expired or was not sent from this site.</p>
+    <p>Go back, reload the page and try again.</p>
+    <p><a href="/admin/dashboard">&lt;- Back</a></p>
+</body>
+</html>
+"#,
+        )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{token_from_urlencoded, tokens_match};
+
+    #[test]
+    fn the_token_is_found_among_other_fields() {
+        assert_eq!(
+            token_from_urlencoded(b"title=Hi+there&csrf_token=abc123&x=1"),
+            Some("abc123".to_string())
+        );
+        assert_eq!(token_from_urlencoded(b"title=Hi&csrf=abc123"), None);
+        assert_eq!(token_from_urlencoded(b""), None);
+    }
+
+    #[test]
+    fn tokens_must_match_exactly() {
+        assert!(tokens_match("abc123", "abc123"));
+        assert!(!tokens_match("abc123", "abc124"));
+        assert!(!tokens_match("abc123", "abc12"));
+        assert!(!tokens_match("abc123", ""));
+    }
+}
@@ -1,14 +1,23 @@
 use std::ops::Deref;
 
-use actix_web::body::MessageBody;
+use actix_web::body::{BoxBody, EitherBody, MessageBody};
 use actix_web::dev::{ServiceRequest, ServiceResponse};
 use actix_web::error::InternalError;
-use actix_web::{FromRequest, HttpMessage};
-use actix_web_lab::middleware::Next;
+use actix_web::http::header::ContentType;
+use actix_web::{web, FromRequest, HttpMessage, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use actix_web_lab::middleware::{from_fn, MiddlewareFn, Next};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use futures_util::future::LocalBoxFuture;
+use sqlx::PgPool;
 use uuid::Uuid;
 
+use super::{Permission, Role};
+use crate::client_info::ClientInfo;
 use crate::routes::{e500, seeother};
-use crate::session::TypedSession;
+use crate::session::{SessionTimeouts, TypedSession};
+use crate::session_index::SessionIndex;
 
 #[derive(Clone, Copy, Debug)]
 pub struct UserId(Uuid);
@@ -29,21 +38,177 @@ impl Deref for UserId {
 pub async fn reject_anonymous_users(
     mut req: ServiceRequest,
     next: Next<impl MessageBody>,
-) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, actix_web::Error> {
     let session = {
         let (http_request, payload) = req.parts_mut();
         TypedSession::from_request(http_request, payload).await
     }?;
 
-    match session.get_user_id().map_err(e500)? {
-        Some(user_id) => {
-            req.extensions_mut().insert(UserId(user_id));
-            next.call(req).await
-        }
+    let user_id = match session.get_user_id().map_err(e500)? {
+        Some(user_id) => user_id,
         None => {
             let response = seeother("/login");
             let e = anyhow::anyhow!("user is not logged in");
-            Err(InternalError::from_response(e, response).into())
+            return Err(InternalError::from_response(e, response).into());
+        }
+    };
+    // Sessions left unattended, or open for too long, have to log in again.
+    // The response goes through, rather than an error, so that the flash
+    // message explaining why is delivered.
+    let timeouts = req
+        .app_data::<web::Data<SessionTimeouts>>()
+        .context("the session timeouts are missing from the application data")
+        .map_err(e500)?;
+    if let Some(expiry) = session.check_timeouts(timeouts).map_err(e500)? {
+        tracing::info!(?expiry, "Session expired.");
+        session.logout();
+        FlashMessage::info(expiry.message()).send();
+        return Ok(req.into_response(seeother("/login")).map_into_right_body());
+    }
+    // Deactivating a user or resetting their password logs them out of the
+    // sessions they already have, and role changes apply right away.
+    let pool = req
+        .app_data::<web::Data<PgPool>>()
+        .context("the database pool is missing from the application data")
+        .map_err(e500)?;
+    let authenticated_at = session.get_authenticated_at().map_err(e500)?;
+    let Some(role) = get_session_role(pool, user_id, authenticated_at)
+        .await
+        .map_err(e500)?
+    else {
+        session.logout();
+        let response = seeother("/login");
+        let e = anyhow::anyhow!(
+            "user has been deactivated or their sessions were invalidated"
+        );
+        return Err(InternalError::from_response(e, response).into());
+    };
+    // Sessions revoked from another one are logged out.
+    let session_index = req
+        .app_data::<web::Data<SessionIndex>>()
+        .context("the session index is missing from the application data")
+        .map_err(e500)?;
+    let client_info = ClientInfo::from_http_request(req.request());
+    let is_active = match session.get_session_id().map_err(e500)? {
+        Some(session_id) => session_index
+            .touch(session_id, &client_info)
+            .await
+            .map_err(e500)?,
+        None => {
+            // Logged in before sessions were indexed.
+            let session_id = session.insert_session_id().map_err(e500)?;
+            session_index
+                .add(user_id, session_id, &client_info)
+                .await
+                .map_err(e500)?;
+            true
         }
+    };
+    if !is_active {
+        session.logout();
+        let response = seeother("/login");
+        let e = anyhow::anyhow!("the session has been revoked");
+        return Err(InternalError::from_response(e, response).into());
     }
+    req.extensions_mut().insert(UserId(user_id));
+    req.extensions_mut().insert(role);
+    next.call(req)
+        .await
+        .map(ServiceResponse::map_into_left_body)
+}
+
+/// The role of the user, unless they have been deactivated.
+#[tracing::instrument(name = "Get role of active user", skip(pool))]
+pub async fn get_active_user_role(
+    pool: &PgPool,
+    user_id: Uuid,
+) -> Result<Option<Role>, anyhow::Error> {
+    let user = sqlx::query!(
+        r#"
+        SELECT role AS "role: Role"
+        FROM users
+        WHERE user_id = $1 AND is_active
+        "#,
+        user_id
+    )
+    .fetch_optional(pool)
+    .await
+    .context("failed to retrieve the role of the user")?;
+    Ok(user.map(|u| u.role))
+}
+
+/// The role of the user, unless they have been deactivated or their sessions
+/// were invalidated after `authenticated_at`.
+#[tracing::instrument(name = "Get role of session user", skip(pool))]
+async fn get_session_role(
+    pool: &PgPool,
+    user_id: Uuid,
+    authenticated_at: Option<DateTime<Utc>>,
+) -> Result<Option<Role>, anyhow::Error> {
+    let user = sqlx::query!(
+        r#"
+        SELECT role AS "role: Role"
+        FROM users
+        WHERE user_id = $1
+            AND is_active
+            AND (sessions_invalidated_at IS NULL OR sessions_invalidated_at < $2)
+        "#,
+        user_id,
+        authenticated_at
+    )
+    .fetch_optional(pool)
+    .await
+    .context("failed to retrieve the role of the user")?;
+    Ok(user.map(|u| u.role))
+}
+
+type PermissionCheck = MiddlewareFn<
+    Box<
+        dyn Fn(
+            ServiceRequest,
+            Next<BoxBody>,
+        ) -> LocalBoxFuture<
+            'static,
+            Result<ServiceResponse, actix_web::Error>,
+        >,
+    >,
+    (),
+>;
+
+/// Route middleware letting through users whose role grants `permission`,
+/// e.g. `web::get().to(handler).wrap(require(Permission::ManageUsers))`.
+/// Must run after `reject_anonymous_users`, which looks up the role.
+pub fn require(permission: Permission) -> PermissionCheck {
+    from_fn(Box::new(move |req: ServiceRequest, next: Next<BoxBody>| {
+        Box::pin(async move {
+            let role = req.extensions().get::<Role>().copied();
+            if role.is_some_and(|role| role.can(permission)) {
+                next.call(req).await
+            } else {
+                tracing::warn!(?role, ?permission, "Forbidden request.");
+                Ok(req.into_response(forbidden()))
+            }
+        })
+    }))
+}
+
+/// The page shown to users who lack the permission a page needs.
+pub fn forbidden() -> HttpResponse {
+    HttpResponse::Forbidden()
+        .content_type(ContentType::html())
+        .body(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Forbidden</title>
+</head>
+<body>
+    <p>You do not have permission to do this.</p>
+    <p>Ask an admin to change your role if you need to.</p>
+    <p><a href="/admin/dashboard">&lt;- Back</a></p>
+</body>
+</html>
+"#,
+        )
 }
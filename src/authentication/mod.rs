@@ -1,5 +1,28 @@
+mod api_token;
+mod csrf;
 mod middleware;
 mod password;
+mod password_policy;
+mod password_strength;
+mod role;
+mod token;
+mod totp;
+mod two_factor;
 
-pub use middleware::{reject_anonymous_users, UserId};
+pub use api_token::{
+    bearer_token, generate_api_token, validate_api_token, ApiScope, ApiToken,
+};
+pub use csrf::{reject_invalid_csrf_tokens, CsrfToken};
+pub use middleware::{
+    forbidden, get_active_user_role, reject_anonymous_users, require, UserId,
+};
 pub use password::*;
+pub use password_policy::{
+    BreachedPasswords, PasswordPolicy, PasswordPolicyError,
+    MAX_PASSWORD_LENGTH, MIN_PASSWORD_LENGTH,
+};
+pub use password_strength::{estimate_strength, PasswordStrength};
+pub use role::{Permission, Role};
+pub use token::hash_token;
+pub use totp::{generate_recovery_codes, Totp, RECOVERY_CODE_COUNT};
+pub use two_factor::*;
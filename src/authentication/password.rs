@@ -1,11 +1,16 @@
 use actix_web::http::header::HeaderMap;
+use actix_web::web;
 use anyhow::{anyhow, Context};
 use argon2::password_hash::SaltString;
-use argon2::{Argon2, Params, PasswordHash, PasswordHasher, PasswordVerifier};
+use argon2::{
+    Algorithm, Argon2, Params, PasswordHash, PasswordHasher, PasswordVerifier,
+    Version,
+};
 use base64::Engine;
 use secrecy::{ExposeSecret, Secret};
 use sqlx::PgPool;
 
+use crate::configuration::PasswordHashingSettings;
 use crate::telemetry::spawn_blocking_with_tracing;
 
 pub struct Credentials {
@@ -22,20 +27,94 @@ pub enum AuthError {
     UnexpectedError(#[from] anyhow::Error),
 }
 
-#[tracing::instrument(name = "validate credentials", skip(credentials, pool))]
+/// Hashes new passwords with the configured Argon2id parameters.
+pub struct PasswordHashing {
+    params: Params,
+    /// Checked when the username is unknown, so that the response takes as
+    /// long as for a wrong password.
+    dummy_hash: Secret<String>,
+}
+
+impl PasswordHashing {
+    pub fn new(
+        settings: &PasswordHashingSettings,
+    ) -> Result<Self, anyhow::Error> {
+        let params = settings
+            .params()
+            .map_err(|e| anyhow!(e))
+            .context("invalid password hashing parameters")?;
+        let mut hashing = Self {
+            params,
+            dummy_hash: Secret::new(String::new()),
+        };
+        hashing.dummy_hash =
+            hashing.hash(Secret::new(uuid::Uuid::new_v4().to_string()))?;
+        Ok(hashing)
+    }
+
+    fn argon2(&self) -> Argon2<'static> {
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, self.params.clone())
+    }
+
+    pub fn hash(
+        &self,
+        password: Secret<String>,
+    ) -> Result<Secret<String>, anyhow::Error> {
+        let salt = SaltString::generate(&mut rand::thread_rng());
+        let password_hash = self
+            .argon2()
+            .hash_password(password.expose_secret().as_bytes(), &salt)?
+            .to_string();
+
+        Ok(Secret::new(password_hash))
+    }
+
+    /// A new hash of a verified password, if its stored hash is outdated.
+    fn upgrade(
+        &self,
+        stored_hash: &Secret<String>,
+        password: Secret<String>,
+    ) -> Result<Option<Secret<String>>, anyhow::Error> {
+        let stored_hash = PasswordHash::new(stored_hash.expose_secret())
+            .context("failed to parse hash to PHC string format.")?;
+        if !self.is_outdated(&stored_hash) {
+            return Ok(None);
+        }
+        Ok(Some(self.hash(password)?))
+    }
+
+    /// Whether a stored hash is weaker than one computed now would be, on any
+    /// of the parameters.
+    fn is_outdated(&self, hash: &PasswordHash) -> bool {
+        if hash.algorithm != Algorithm::Argon2id.ident()
+            || hash.version != Some(Version::V0x13.into())
+        {
+            return true;
+        }
+        match Params::try_from(hash) {
+            Ok(params) => {
+                params.m_cost() < self.params.m_cost()
+                    || params.t_cost() < self.params.t_cost()
+                    || params.p_cost() < self.params.p_cost()
+            }
+            Err(_) => true,
+        }
+    }
+}
+
+#[tracing::instrument(
+    name = "validate credentials",
+    skip(credentials, hashing, pool)
+)]
 pub async fn validate_credentials(
     credentials: Credentials,
+    hashing: &web::Data<PasswordHashing>,
     pool: &PgPool,
 ) -> Result<uuid::Uuid, AuthError> {
     let mut user_id = None;
 
     // prevent timing attack with a default hash to be calculated.
-    let mut expected_password_hash = Secret::new(
-        "$argon2id$v=19$m=15000,t=2,p=1$\
-        gZiV/M1gPc22ElAH/Jh1Hw$\
-        CWOrkoo7oJBQ/iyh7uJ0LO2aLEfrHwTwllSAxT0zRno"
-            .to_string(),
-    );
+    let mut expected_password_hash = hashing.dummy_hash.clone();
 
     if let Some((stored_user_id, stored_pwd_hash)) =
         get_stored_credentials(&credentials.username, pool)
@@ -48,15 +127,69 @@ pub async fn validate_credentials(
 
     // calculating hashes is CPU intensive (order of 10ms to hash), therefore
     // launch in a separate thread pool.
-    spawn_blocking_with_tracing(move || {
-        verify_password_hash(expected_password_hash, credentials.password)
+    let current_hash = expected_password_hash.clone();
+    let hashing = hashing.clone();
+    let upgraded_hash = spawn_blocking_with_tracing(move || {
+        verify_password_hash(
+            expected_password_hash.clone(),
+            credentials.password.clone(),
+        )?;
+        Ok::<_, AuthError>(
+            hashing.upgrade(&expected_password_hash, credentials.password),
+        )
     })
     .await
     .context("failed to spawn blocking task.")??;
 
-    user_id
+    let user_id = user_id
         .ok_or_else(|| anyhow!("unknown username."))
-        .map_err(AuthError::InvalidCredentials)
+        .map_err(AuthError::InvalidCredentials)?;
+
+    let upgrade = match upgraded_hash {
+        Ok(Some(upgraded_hash)) => {
+            store_upgraded_hash(user_id, &current_hash, &upgraded_hash, pool)
+                .await
+        }
+        Ok(None) => Ok(()),
+        Err(e) => Err(e),
+    };
+    if let Err(e) = upgrade {
+        // The password was right: logging in matters more than the upgrade,
+        // which is tried again next time.
+        tracing::warn!(
+            error.cause_chain = ?e,
+            "Failed to upgrade a password hash."
+        );
+    }
+    Ok(user_id)
+}
+
+/// Replace a password hash computed with weaker parameters, unless the
+/// password was changed in the meantime.
+#[tracing::instrument(
+    name = "upgrade password hash",
+    skip(current_hash, upgraded_hash, pool)
+)]
+async fn store_upgraded_hash(
+    user_id: uuid::Uuid,
+    current_hash: &Secret<String>,
+    upgraded_hash: &Secret<String>,
+    pool: &PgPool,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE users
+        SET password_hash = $1
+        WHERE user_id = $2 AND password_hash = $3
+        "#,
+        upgraded_hash.expose_secret(),
+        user_id,
+        current_hash.expose_secret(),
+    )
+    .execute(pool)
+    .await
+    .context("failed to store the upgraded password hash.")?;
+    Ok(())
 }
 
 #[tracing::instrument(
@@ -80,6 +213,7 @@ pub fn verify_password_hash(
         .map_err(AuthError::InvalidCredentials)
 }
 
+/// Deactivated users have no credentials: they cannot log in anymore.
 #[tracing::instrument(name = "get stored credentials", skip(username, pool))]
 pub async fn get_stored_credentials(
     username: &str,
@@ -89,7 +223,7 @@ pub async fn get_stored_credentials(
         r#"
         SELECT user_id, password_hash
         FROM users
-        WHERE username = $1 
+        WHERE username = $1 AND is_active
         "#,
         username
     )
@@ -141,14 +275,16 @@ pub fn basic_authentication(
     })
 }
 
-#[tracing::instrument(name = "change password", skip(password, pool))]
+#[tracing::instrument(name = "change password", skip(password, hashing, pool))]
 pub async fn change_password(
     user_id: uuid::Uuid,
     password: Secret<String>,
+    hashing: &web::Data<PasswordHashing>,
     pool: &PgPool,
 ) -> Result<(), anyhow::Error> {
+    let hashing = hashing.clone();
     let password_hash =
-        spawn_blocking_with_tracing(move || compute_password_hash(password))
+        spawn_blocking_with_tracing(move || hashing.hash(password))
             .await?
             .context("failed to hash password")?;
 
@@ -168,17 +304,77 @@ pub async fn change_password(
     Ok(())
 }
 
-fn compute_password_hash(
-    password: Secret<String>,
-) -> Result<Secret<String>, anyhow::Error> {
-    let salt = SaltString::generate(&mut rand::thread_rng());
-    let password_hash = Argon2::new(
-        argon2::Algorithm::Argon2id,
-        argon2::Version::V0x13,
-        Params::new(15000, 2, 1, None).unwrap(),
-    )
-    .hash_password(password.expose_secret().as_bytes(), &salt)?
-    .to_string();
+#[cfg(test)]
+mod tests {
+    use argon2::PasswordHash;
+    use secrecy::{ExposeSecret, Secret};
+
+    use super::{verify_password_hash, PasswordHashing};
+    use crate::configuration::PasswordHashingSettings;
+
+    fn hashing(memory_kib: u32, iterations: u32) -> PasswordHashing {
+        PasswordHashing::new(&PasswordHashingSettings {
+            memory_kib,
+            iterations,
+            parallelism: 1,
+        })
+        .unwrap()
+    }
+
+    fn hash(hashing: &PasswordHashing, password: &str) -> Secret<String> {
+        hashing.hash(Secret::new(password.to_string())).unwrap()
+    }
 
-    Ok(Secret::new(password_hash))
+    #[test]
+    fn hashes_use_the_configured_parameters() {
+        let hashing = hashing(8192, 3);
+        let password_hash = hash(&hashing, "password");
+
+        assert!(password_hash
+            .expose_secret()
+            .starts_with("$argon2id$v=19$m=8192,t=3,p=1$"));
+        assert!(hashing
+            .dummy_hash
+            .expose_secret()
+            .starts_with("$argon2id$v=19$m=8192,t=3,p=1$"));
+        assert!(verify_password_hash(
+            password_hash,
+            Secret::new("password".to_string())
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn hashes_with_any_weaker_parameter_are_outdated() {
+        let current = hashing(8192, 2);
+        for (memory_kib, iterations, outdated) in [
+            (8192, 2, false),
+            (16384, 3, false),
+            (4096, 2, true),
+            (8192, 1, true),
+            (16384, 1, true),
+        ] {
+            let stored = hash(&hashing(memory_kib, iterations), "password");
+            let stored = PasswordHash::new(stored.expose_secret()).unwrap();
+            assert_eq!(
+                current.is_outdated(&stored),
+                outdated,
+                "m={}, t={}",
+                memory_kib,
+                iterations
+            );
+        }
+    }
+
+    #[test]
+    fn hashes_of_other_algorithms_are_outdated() {
+        let current = hashing(8192, 2);
+        let stored = PasswordHash::new(
+            "$argon2i$v=19$m=16384,t=3,p=1$\
+            c2FsdHNhbHRzYWx0$\
+            K+oZ6lyLxuAnxIpcvYuNGEyS66N8OzXuHK7xMPYFsn0",
+        )
+        .unwrap();
+        assert!(current.is_outdated(&stored));
+    }
 }
@@ -0,0 +1,174 @@
+use std::path::PathBuf;
+
+use anyhow::Context;
+use secrecy::{ExposeSecret, Secret};
+use sha1::{Digest, Sha1};
+
+use super::password_strength::estimate_strength;
+use crate::configuration::PasswordPolicySettings;
+use crate::telemetry::spawn_blocking_with_tracing;
+
+/// SHA-1 hashes of passwords known from data breaches, in the layout of the
+/// Pwned Passwords k-anonymity range API: a directory holding one
+/// `PREFIX.txt` file per first five hex digits of the hash, with one
+/// `SUFFIX:COUNT` line per password.
+///
+/// The full list is tens of gigabytes: only the range of the password being
+/// checked is ever read.
+pub struct BreachedPasswords {
+    directory: PathBuf,
+}
+
+impl BreachedPasswords {
+    pub fn open(directory: impl Into<PathBuf>) -> Result<Self, anyhow::Error> {
+        let directory = directory.into();
+        if !directory.is_dir() {
+            anyhow::bail!("{} is not a directory", directory.display());
+        }
+        Ok(Self { directory })
+    }
+
+    pub async fn contains(
+        &self,
+        password: &str,
+    ) -> Result<bool, anyhow::Error> {
+        let hash = hex::encode_upper(Sha1::digest(password.as_bytes()));
+        let (prefix, suffix) = hash.split_at(5);
+        let path = self.directory.join(format!("{}.txt", prefix));
+        let suffix = suffix.to_string();
+        spawn_blocking_with_tracing(move || {
+            let range = match std::fs::read_to_string(&path) {
+                Ok(range) => range,
+                // No breached password starts with this prefix.
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                    return Ok(false)
+                }
+                Err(e) => {
+                    return Err(e).with_context(|| {
+                        format!("failed to read {}", path.display())
+                    })
+                }
+            };
+            Ok(range.lines().any(|line| {
+                line.split(':')
+                    .next()
+                    .unwrap_or_default()
+                    .trim()
+                    .eq_ignore_ascii_case(&suffix)
+            }))
+        })
+        .await
+        .context("failed to spawn blocking task.")?
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum PasswordPolicyError {
+    /// Why the password may not be used, to show to the user.
+    #[error("{0}")]
+    Rejected(String),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+pub const MIN_PASSWORD_LENGTH: usize = 12;
+pub const MAX_PASSWORD_LENGTH: usize = 128;
+
+/// What new passwords must pass.
+pub struct PasswordPolicy {
+    breached_passwords: BreachedPasswords,
+}
+
+impl PasswordPolicy {
+    pub fn new(
+        settings: &PasswordPolicySettings,
+    ) -> Result<Self, anyhow::Error> {
+        Ok(Self {
+            breached_passwords: settings.breached_passwords()?,
+        })
+    }
+
+    /// Why `password` may not be used, if it may not. `confirmation` is the
+    /// password typed a second time, and `user_inputs`, e.g. the username,
+    /// are treated as easy to guess.
+    pub async fn check(
+        &self,
+        password: &Secret<String>,
+        confirmation: &Secret<String>,
+        user_inputs: &[&str],
+    ) -> Result<(), PasswordPolicyError> {
+        let password = password.expose_secret();
+        if password != confirmation.expose_secret() {
+            return Err(PasswordPolicyError::Rejected(
+                "Password fields must match.".into(),
+            ));
+        }
+        if !(MIN_PASSWORD_LENGTH..=MAX_PASSWORD_LENGTH)
+            .contains(&password.len())
+        {
+            return Err(PasswordPolicyError::Rejected(format!(
+                "New password must be between {} and {} characters.",
+                MIN_PASSWORD_LENGTH, MAX_PASSWORD_LENGTH,
+            )));
+        }
+        let strength = estimate_strength(password, user_inputs);
+        if !strength.is_strong_enough() {
+            return Err(PasswordPolicyError::Rejected(strength.feedback()));
+        }
+        if self
+            .breached_passwords
+            .contains(password)
+            .await
+            .context("failed to check the breached passwords")?
+        {
+            return Err(PasswordPolicyError::Rejected(
+                "This password has appeared in a data breach, please \
+                choose another one."
+                    .into(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::BreachedPasswords;
+
+    /// A range directory where `contents` is the range of "password", whose
+    /// SHA-1 is 5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD8. It is removed when
+    /// dropped.
+    fn ranges(contents: &str) -> TempDir {
+        let directory = TempDir::new().unwrap();
+        std::fs::write(directory.path().join("5BAA6.txt"), contents).unwrap();
+        directory
+    }
+
+    #[tokio::test]
+    async fn listed_passwords_are_found_in_their_range() {
+        let directory = ranges(
+            "1E4C9B93F3F0682250B6CF8331B7EE68FD7:3\n\
+            1E4C9B93F3F0682250B6CF8331B7EE68FD8:9659365\n",
+        );
+        let breached = BreachedPasswords::open(directory.path()).unwrap();
+
+        assert!(breached.contains("password").await.unwrap());
+        assert!(!breached.contains("Password").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn suffixes_are_case_insensitive_and_counts_optional() {
+        let directory = ranges("1e4c9b93f3f0682250b6cf8331b7ee68fd8\r\n");
+        let breached = BreachedPasswords::open(directory.path()).unwrap();
+        assert!(breached.contains("password").await.unwrap());
+    }
+
+    #[test]
+    fn the_range_directory_must_exist() {
+        let directory = TempDir::new().unwrap();
+        let missing = directory.path().join("missing");
+        assert!(BreachedPasswords::open(missing).is_err());
+    }
+}
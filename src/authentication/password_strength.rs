@@ -0,0 +1,415 @@
+//! A rough estimate of how hard a password is to guess, in the spirit of
+//! zxcvbn: well-known patterns are worth a few bits, however long they are,
+//! and only the remaining characters count for their alphabet.
+
+/// Passwords estimated to take fewer than 2^MIN_STRENGTH_BITS guesses are
+/// rejected.
+pub const MIN_STRENGTH_BITS: f64 = 50.0;
+
+/// A short sample of the words and passwords attackers try first.
+const COMMON_WORDS: &[&str] = &[
+    "password",
+    "passwort",
+    "motdepasse",
+    "contrasena",
+    "qwerty",
+    "azerty",
+    "letmein",
+    "welcome",
+    "admin",
+    "administrator",
+    "root",
+    "login",
+    "user",
+    "guest",
+    "master",
+    "secret",
+    "changeme",
+    "default",
+    "test",
+    "iloveyou",
+    "love",
+    "dragon",
+    "monkey",
+    "football",
+    "baseball",
+    "soccer",
+    "hockey",
+    "sunshine",
+    "princess",
+    "shadow",
+    "superman",
+    "batman",
+    "trustno1",
+    "starwars",
+    "pokemon",
+    "freedom",
+    "whatever",
+    "hello",
+    "flower",
+    "summer",
+    "winter",
+    "spring",
+    "autumn",
+    "january",
+    "february",
+    "march",
+    "april",
+    "june",
+    "july",
+    "august",
+    "september",
+    "october",
+    "november",
+    "december",
+    "monday",
+    "friday",
+    "sunday",
+    "cheese",
+    "chocolate",
+    "computer",
+    "internet",
+    "michael",
+    "jennifer",
+    "jordan",
+    "charlie",
+    "thomas",
+    "daniel",
+    "ashley",
+    "jessica",
+    "matthew",
+    "andrew",
+    "killer",
+    "mustang",
+    "ferrari",
+    "banana",
+    "orange",
+    "purple",
+    "silver",
+    "golden",
+    "newsletter",
+    "zero2prod",
+];
+
+/// Rows of common keyboard layouts.
+const KEYBOARD_ROWS: &[&str] = &[
+    "`1234567890-=",
+    "qwertyuiop[]\\",
+    "asdfghjkl;'",
+    "zxcvbnm,./",
+    "azertyuiop",
+    "qsdfghjklm",
+    "wxcvbn",
+    "qwertzuiop",
+    "yxcvbnm",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Pattern {
+    UserInput,
+    CommonWord,
+    KeyboardRow,
+    Sequence,
+    Repeat,
+}
+
+impl Pattern {
+    /// What a match is worth, whatever its length.
+    fn bits(&self) -> f64 {
+        match self {
+            Self::UserInput => 2.0,
+            Self::CommonWord => 10.0,
+            Self::KeyboardRow => 6.0,
+            Self::Sequence => 5.0,
+            Self::Repeat => 5.0,
+        }
+    }
+
+    fn feedback(&self) -> &'static str {
+        match self {
+            Self::UserInput => "Do not include your username or email address.",
+            Self::CommonWord => {
+                r#"Avoid common words and passwords such as "password" or "welcome"."#
+            }
+            Self::KeyboardRow => r#"Avoid keyboard patterns such as "qwerty"."#,
+            Self::Sequence => r#"Avoid sequences such as "abc" or "123"."#,
+            Self::Repeat => r#"Avoid repeated characters such as "aaa"."#,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct PasswordStrength {
+    /// Base-2 logarithm of the estimated number of guesses.
+    pub bits: f64,
+    patterns: Vec<Pattern>,
+}
+
+impl PasswordStrength {
+    pub fn is_strong_enough(&self) -> bool {
+        self.bits >= MIN_STRENGTH_BITS
+    }
+
+    /// What to change to make the password stronger.
+    pub fn feedback(&self) -> String {
+        let mut feedback = vec!["This password is too easy to guess."];
+        for pattern in [
+            Pattern::UserInput,
+            Pattern::CommonWord,
+            Pattern::KeyboardRow,
+            Pattern::Sequence,
+            Pattern::Repeat,
+        ] {
+            if self.patterns.contains(&pattern) {
+                feedback.push(pattern.feedback());
+            }
+        }
+        feedback.push("Add a few more uncommon words or characters.");
+        feedback.join(" ")
+    }
+}
+
+/// Estimate the strength of `password`. `user_inputs`, e.g. the username,
+/// are as easy to guess as common words.
+pub fn estimate_strength(
+    password: &str,
+    user_inputs: &[&str],
+) -> PasswordStrength {
+    let chars: Vec<char> = password.chars().collect();
+    let lowercase: Vec<char> = chars.iter().map(|c| to_lowercase(*c)).collect();
+    let unleeted: Vec<char> = lowercase.iter().map(|c| unleet(*c)).collect();
+    let mut covered = vec![false; chars.len()];
+    let mut patterns = Vec::new();
+
+    // The local part of an email address is as guessable as the address.
+    let mut user_inputs: Vec<Vec<char>> = user_inputs
+        .iter()
+        .flat_map(|input| [*input, input.split('@').next().unwrap_or(input)])
+        .map(|input| input.chars().map(to_lowercase).collect())
+        .collect();
+    // Longer matches first, e.g. the whole email address before the username.
+    user_inputs.sort_by_key(|input| std::cmp::Reverse(input.len()));
+    for input in &user_inputs {
+        if input.len() >= 3 {
+            find_word(
+                &lowercase,
+                input,
+                Pattern::UserInput,
+                &mut covered,
+                &mut patterns,
+            );
+        }
+    }
+    let mut words: Vec<Vec<char>> =
+        COMMON_WORDS.iter().map(|w| w.chars().collect()).collect();
+    words.sort_by_key(|w| std::cmp::Reverse(w.len()));
+    for word in &words {
+        find_word(
+            &unleeted,
+            word,
+            Pattern::CommonWord,
+            &mut covered,
+            &mut patterns,
+        );
+    }
+    // Digits are sequences before they are a keyboard row.
+    let runs: [(usize, Step, Pattern); 3] = [
+        (3, sequence_step, Pattern::Sequence),
+        (4, keyboard_step, Pattern::KeyboardRow),
+        (3, repeat_step, Pattern::Repeat),
+    ];
+    for (min_len, step, pattern) in runs {
+        find_runs(
+            &lowercase,
+            min_len,
+            step,
+            pattern,
+            &mut covered,
+            &mut patterns,
+        );
+    }
+
+    let per_char = alphabet_size(&chars).log2();
+    let bits = patterns.iter().map(Pattern::bits).sum::<f64>()
+        + covered.iter().filter(|c| !**c).count() as f64 * per_char;
+    PasswordStrength { bits, patterns }
+}
+
+fn to_lowercase(c: char) -> char {
+    c.to_lowercase().next().unwrap_or(c)
+}
+
+/// Undo the substitutions commonly used to disguise words.
+fn unleet(c: char) -> char {
+    match c {
+        '0' => 'o',
+        '1' | '!' => 'i',
+        '3' => 'e',
+        '4' | '@' => 'a',
+        '5' | '$' => 's',
+        '7' => 't',
+        other => other,
+    }
+}
+
+/// Mark every occurrence of `word` not overlapping an earlier match.
+fn find_word(
+    chars: &[char],
+    word: &[char],
+    pattern: Pattern,
+    covered: &mut [bool],
+    patterns: &mut Vec<Pattern>,
+) {
+    if word.len() > chars.len() {
+        return;
+    }
+    let mut i = 0;
+    while i + word.len() <= chars.len() {
+        let end = i + word.len();
+        if &chars[i..end] == word && !covered[i..end].iter().any(|c| *c) {
+            covered[i..end].iter_mut().for_each(|c| *c = true);
+            patterns.push(pattern);
+            i = end;
+        } else {
+            i += 1;
+        }
+    }
+}
+
+/// The direction in which `b` follows `a`, if it does.
+type Step = fn(char, char) -> Option<i32>;
+
+/// Mark runs of at least `min_len` characters where each one follows the
+/// previous one in the same direction, as told by `step`.
+fn find_runs(
+    chars: &[char],
+    min_len: usize,
+    step: Step,
+    pattern: Pattern,
+    covered: &mut [bool],
+    patterns: &mut Vec<Pattern>,
+) {
+    let mut start = 0;
+    while start < chars.len() {
+        let mut end = start + 1;
+        let mut direction = None;
+        while end < chars.len() && !covered[end] && !covered[start] {
+            match step(chars[end - 1], chars[end]) {
+                Some(d) if direction.is_none_or(|direction| direction == d) => {
+                    direction = Some(d);
+                    end += 1;
+                }
+                _ => break,
+            }
+        }
+        if end - start >= min_len {
+            covered[start..end].iter_mut().for_each(|c| *c = true);
+            patterns.push(pattern);
+            start = end;
+        } else {
+            start += 1;
+        }
+    }
+}
+
+fn keyboard_step(a: char, b: char) -> Option<i32> {
+    KEYBOARD_ROWS.iter().find_map(|row| {
+        let a = row.chars().position(|c| c == a)? as i32;
+        let b = row.chars().position(|c| c == b)? as i32;
+        let d = b - a;
+        (d == 1 || d == -1).then_some(d)
+    })
+}
+
+fn sequence_step(a: char, b: char) -> Option<i32> {
+    let same_kind = (a.is_ascii_lowercase() && b.is_ascii_lowercase())
+        || (a.is_ascii_digit() && b.is_ascii_digit());
+    let d = b as i32 - a as i32;
+    (same_kind && (d == 1 || d == -1)).then_some(d)
+}
+
+fn repeat_step(a: char, b: char) -> Option<i32> {
+    (a == b).then_some(0)
+}
+
+/// How many characters an attacker has to try for each position, going by
+/// the kinds of characters the password uses.
+fn alphabet_size(chars: &[char]) -> f64 {
+    let mut size = 0.0;
+    if chars.iter().any(char::is_ascii_lowercase) {
+        size += 26.0;
+    }
+    if chars.iter().any(char::is_ascii_uppercase) {
+        size += 26.0;
+    }
+    if chars.iter().any(char::is_ascii_digit) {
+        size += 10.0;
+    }
+    if chars
+        .iter()
+        .any(|c| c.is_ascii() && !c.is_ascii_alphanumeric())
+    {
+        size += 33.0;
+    }
+    if chars.iter().any(|c| !c.is_ascii()) {
+        size += 100.0;
+    }
+    f64::max(size, 1.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::estimate_strength;
+
+    fn is_strong(password: &str) -> bool {
+        estimate_strength(password, &["alice", "alice@example.com"])
+            .is_strong_enough()
+    }
+
+    #[test]
+    fn random_and_passphrase_passwords_are_strong_enough() {
+        for password in [
+            "c0a4e4b5-6d1f-4ac2-9a3e-2f4d8b1c7e90",
+            "correct horse battery staple",
+            "vQ7#pLm2$xR9",
+            "kwjdhrnvzlqp",
+        ] {
+            assert!(is_strong(password), "{}", password);
+        }
+    }
+
+    #[test]
+    fn common_patterns_are_too_weak() {
+        for password in [
+            "Password1234",
+            "P@ssw0rd2023!",
+            "qwertyuiop12",
+            "aaaaaaaaaaaaaaaa",
+            "abcdefghijklmnop",
+            "123456789012",
+            "alice1234567",
+            "ilovesummer!!",
+        ] {
+            assert!(!is_strong(password), "{}", password);
+        }
+    }
+
+    #[test]
+    fn the_feedback_names_the_patterns_found() {
+        let feedback = estimate_strength("Password1234", &[]).feedback();
+        assert!(feedback.contains("Avoid common words"));
+        assert!(feedback.contains("Avoid sequences"));
+        assert!(!feedback.contains("Avoid repeated characters"));
+
+        let feedback =
+            estimate_strength("alice1234567", &["alice@example.com"])
+                .feedback();
+        assert!(feedback.contains("Do not include your username"));
+    }
+
+    #[test]
+    fn the_feedback_never_includes_the_password() {
+        let feedback = estimate_strength("xyzzyzzzzzzz", &[]).feedback();
+        assert!(!feedback.contains("xyzzy"));
+    }
+}
@@ -0,0 +1,106 @@
+/// What a user may do in the admin area, stored as the `user_role` Postgres
+/// enum.
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    serde::Serialize,
+    serde::Deserialize,
+    sqlx::Type,
+)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "user_role", rename_all = "snake_case")]
+pub enum Role {
+    /// Can do everything, including managing other users.
+    Admin,
+    /// Writes and publishes newsletters.
+    Editor,
+    /// Can look but not touch.
+    Viewer,
+}
+
+/// Something a route needs the logged-in user to be allowed to do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Permission {
+    ViewSubscribers,
+    ManageSubscribers,
+    PublishNewsletters,
+    ManageUsers,
+    ViewAuditLog,
+}
+
+impl Role {
+    pub const ALL: [Role; 3] = [Self::Admin, Self::Editor, Self::Viewer];
+
+    pub fn parse(s: &str) -> Result<Self, String> {
+        Self::ALL
+            .into_iter()
+            .find(|role| role.as_str() == s)
+            .ok_or_else(|| format!("{} is not a valid role.", s))
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Admin => "admin",
+            Self::Editor => "editor",
+            Self::Viewer => "viewer",
+        }
+    }
+
+    pub fn can(&self, permission: Permission) -> bool {
+        use Permission::*;
+
+        match self {
+            Self::Admin => true,
+            Self::Editor => {
+                matches!(permission, ViewSubscribers | PublishNewsletters)
+            }
+            Self::Viewer => matches!(permission, ViewSubscribers),
+        }
+    }
+}
+
+impl std::fmt::Display for Role {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Permission, Role};
+
+    #[test]
+    fn permission_matrix() {
+        use Permission::*;
+
+        let matrix = [
+            (ViewSubscribers, [true, true, true]),
+            (ManageSubscribers, [true, false, false]),
+            (PublishNewsletters, [true, true, false]),
+            (ManageUsers, [true, false, false]),
+            (ViewAuditLog, [true, false, false]),
+        ];
+        for (permission, allowed) in matrix {
+            for (role, allowed) in Role::ALL.into_iter().zip(allowed) {
+                assert_eq!(
+                    role.can(permission),
+                    allowed,
+                    "{} / {:?}",
+                    role,
+                    permission
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn roles_round_trip_through_their_name() {
+        for role in Role::ALL {
+            assert_eq!(Role::parse(role.as_str()), Ok(role));
+        }
+        assert!(Role::parse("root").is_err());
+    }
+}
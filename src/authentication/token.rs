@@ -0,0 +1,8 @@
+use sha2::{Digest, Sha256};
+
+/// Hash of a single-use token sent by email, e.g. in an invitation link.
+/// Only the hash is stored, so that reading the database is not enough to
+/// use the links.
+pub fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
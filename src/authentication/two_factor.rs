@@ -0,0 +1,203 @@
+use anyhow::Context;
+use chrono::Utc;
+use secrecy::{ExposeSecret, Secret};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use super::hash_token;
+use super::totp::{normalize_recovery_code, Totp};
+
+/// Name authenticator apps list our codes under.
+pub const TOTP_ISSUER: &str = "zero2prod";
+
+/// Whether every user must set up two-factor authentication.
+#[tracing::instrument(
+    name = "Check whether two-factor is required",
+    skip(pool)
+)]
+pub async fn is_two_factor_required(
+    pool: &PgPool,
+) -> Result<bool, anyhow::Error> {
+    let settings =
+        sqlx::query!("SELECT require_two_factor FROM admin_settings")
+            .fetch_one(pool)
+            .await
+            .context("failed to retrieve admin settings")?;
+    Ok(settings.require_two_factor)
+}
+
+#[tracing::instrument(name = "Require two-factor", skip(pool))]
+pub async fn set_two_factor_required(
+    pool: &PgPool,
+    required: bool,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        "UPDATE admin_settings SET require_two_factor = $1",
+        required
+    )
+    .execute(pool)
+    .await
+    .context("failed to update admin settings")?;
+    Ok(())
+}
+
+/// Users who set up two-factor authentication, or who have to because an
+/// admin requires it, cannot authenticate with their password alone.
+pub async fn needs_second_factor(
+    pool: &PgPool,
+    user_id: Uuid,
+) -> Result<bool, anyhow::Error> {
+    Ok(get_totp(pool, user_id).await?.is_some()
+        || is_two_factor_required(pool).await?)
+}
+
+/// The TOTP generator of the user, if they set up two-factor authentication.
+#[tracing::instrument(name = "Get TOTP secret", skip(pool))]
+pub async fn get_totp(
+    pool: &PgPool,
+    user_id: Uuid,
+) -> Result<Option<Totp>, anyhow::Error> {
+    let user = sqlx::query!(
+        "SELECT totp_secret FROM users WHERE user_id = $1",
+        user_id
+    )
+    .fetch_one(pool)
+    .await
+    .context("failed to retrieve TOTP secret")?;
+    user.totp_secret
+        .map(|secret| Totp::from_base32(&secret).map_err(anyhow::Error::msg))
+        .transpose()
+        .context("the stored TOTP secret is invalid")
+}
+
+/// Check a code from the user's authenticator app, or one of their unused
+/// recovery codes. Either can only be used once.
+#[tracing::instrument(name = "Verify second factor", skip(pool, totp, code))]
+pub async fn verify_second_factor(
+    pool: &PgPool,
+    user_id: Uuid,
+    totp: &Totp,
+    code: &Secret<String>,
+) -> Result<bool, anyhow::Error> {
+    if let Some(step) =
+        totp.verify(code.expose_secret(), Utc::now().timestamp())
+    {
+        // Only accept steps after the last one used, atomically.
+        let updated = sqlx::query!(
+            r#"
+            UPDATE users
+            SET totp_last_used_step = $1
+            WHERE user_id = $2
+                AND (totp_last_used_step IS NULL OR totp_last_used_step < $1)
+            "#,
+            step,
+            user_id
+        )
+        .execute(pool)
+        .await
+        .context("failed to record the use of a TOTP code")?;
+        return Ok(updated.rows_affected() == 1);
+    }
+
+    let used = sqlx::query!(
+        r#"
+        UPDATE user_recovery_codes
+        SET used_at = now()
+        WHERE user_id = $1 AND code_hash = $2 AND used_at IS NULL
+        "#,
+        user_id,
+        hash_token(&normalize_recovery_code(code.expose_secret()))
+    )
+    .execute(pool)
+    .await
+    .context("failed to record the use of a recovery code")?;
+    Ok(used.rows_affected() == 1)
+}
+
+/// Turn two-factor authentication on, replacing any previous recovery codes.
+#[tracing::instrument(
+    name = "Enable two-factor",
+    skip(pool, totp, recovery_codes)
+)]
+pub async fn enable_two_factor(
+    pool: &PgPool,
+    user_id: Uuid,
+    totp: &Totp,
+    step: i64,
+    recovery_codes: &[String],
+) -> Result<(), anyhow::Error> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("failed to acquire postgres connection from pool")?;
+    let secret = totp.to_base32();
+    sqlx::query!(
+        r#"
+        UPDATE users
+        SET totp_secret = $1, totp_last_used_step = $2
+        WHERE user_id = $3
+        "#,
+        secret.expose_secret(),
+        step,
+        user_id
+    )
+    .execute(&mut transaction)
+    .await
+    .context("failed to store TOTP secret")?;
+    sqlx::query!(
+        "DELETE FROM user_recovery_codes WHERE user_id = $1",
+        user_id
+    )
+    .execute(&mut transaction)
+    .await
+    .context("failed to delete previous recovery codes")?;
+    for code in recovery_codes {
+        sqlx::query!(
+            "INSERT INTO user_recovery_codes (user_id, code_hash) VALUES ($1, $2)",
+            user_id,
+            hash_token(&normalize_recovery_code(code))
+        )
+        .execute(&mut transaction)
+        .await
+        .context("failed to store recovery code")?;
+    }
+    transaction
+        .commit()
+        .await
+        .context("failed to commit sql transaction to enable two-factor")?;
+    Ok(())
+}
+
+#[tracing::instrument(name = "Disable two-factor", skip(pool))]
+pub async fn disable_two_factor(
+    pool: &PgPool,
+    user_id: Uuid,
+) -> Result<(), anyhow::Error> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("failed to acquire postgres connection from pool")?;
+    sqlx::query!(
+        r#"
+        UPDATE users
+        SET totp_secret = NULL, totp_last_used_step = NULL
+        WHERE user_id = $1
+        "#,
+        user_id
+    )
+    .execute(&mut transaction)
+    .await
+    .context("failed to remove TOTP secret")?;
+    sqlx::query!(
+        "DELETE FROM user_recovery_codes WHERE user_id = $1",
+        user_id
+    )
+    .execute(&mut transaction)
+    .await
+    .context("failed to delete recovery codes")?;
+    transaction
+        .commit()
+        .await
+        .context("failed to commit sql transaction to disable two-factor")?;
+    Ok(())
+}
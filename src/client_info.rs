@@ -0,0 +1,158 @@
+use std::future::{ready, Ready};
+use std::net::IpAddr;
+
+use actix_web::http::header::USER_AGENT;
+use actix_web::web::Data;
+use actix_web::{FromRequest, HttpRequest};
+use ipnet::IpNet;
+
+const X_FORWARDED_FOR: &str = "X-Forwarded-For";
+
+/// Details about the client that issued a request, as seen by the server.
+///
+/// The IP address is the peer address of the socket. The `X-Forwarded-For`
+/// header is only looked at when the peer is one of our `TrustedProxies`:
+/// anybody else can put whatever they like in it.
+#[derive(Clone, Debug, Default)]
+pub struct ClientInfo {
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+/// The load balancers and reverse proxies in front of the application,
+/// whose `X-Forwarded-For` header is trusted.
+#[derive(Clone, Debug, Default)]
+pub struct TrustedProxies(pub Vec<IpNet>);
+
+impl TrustedProxies {
+    fn contains(&self, ip: &IpAddr) -> bool {
+        self.0.iter().any(|range| range.contains(ip))
+    }
+
+    /// The address of the client, given the peer of the socket and the
+    /// entries of `X-Forwarded-For` in the order they were appended.
+    ///
+    /// Each trusted proxy appends the address it received the request from,
+    /// so the chain is walked back from the peer until the first hop that is
+    /// not one of ours. Whatever comes before it was written by the client.
+    fn client_ip<'a>(
+        &self,
+        peer: IpAddr,
+        forwarded_for: impl DoubleEndedIterator<Item = &'a str>,
+    ) -> IpAddr {
+        let mut client = peer;
+        for hop in forwarded_for.rev() {
+            if !self.contains(&client) {
+                break;
+            }
+            match hop.trim().parse() {
+                Ok(ip) => client = ip,
+                Err(_) => break,
+            }
+        }
+        client
+    }
+}
+
+impl ClientInfo {
+    pub fn from_http_request(req: &HttpRequest) -> Self {
+        let no_proxies = TrustedProxies::default();
+        let trusted_proxies = req
+            .app_data::<Data<TrustedProxies>>()
+            .map_or(&no_proxies, |proxies| proxies.get_ref());
+        let ip_address = req.peer_addr().map(|peer| {
+            let forwarded_for = req
+                .headers()
+                .get_all(X_FORWARDED_FOR)
+                .filter_map(|v| v.to_str().ok())
+                .flat_map(|v| v.split(','))
+                .collect::<Vec<_>>();
+            trusted_proxies
+                .client_ip(peer.ip(), forwarded_for.into_iter())
+                .to_string()
+        });
+        let user_agent = req
+            .headers()
+            .get(USER_AGENT)
+            .and_then(|v| v.to_str().ok())
+            .map(ToOwned::to_owned);
+
+        Self {
+            ip_address,
+            user_agent,
+        }
+    }
+}
+
+impl FromRequest for ClientInfo {
+    type Error = actix_web::Error;
+
+    type Future = Ready<Result<ClientInfo, Self::Error>>;
+
+    fn from_request(
+        req: &HttpRequest,
+        _payload: &mut actix_web::dev::Payload,
+    ) -> Self::Future {
+        ready(Ok(ClientInfo::from_http_request(req)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::IpAddr;
+
+    use super::TrustedProxies;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    fn proxies() -> TrustedProxies {
+        TrustedProxies(vec![
+            "10.0.0.1/32".parse().unwrap(),
+            "10.0.0.2/32".parse().unwrap(),
+        ])
+    }
+
+    #[test]
+    fn forwarded_headers_from_untrusted_peers_are_ignored() {
+        let client = proxies()
+            .client_ip(ip("203.0.113.7"), ["198.51.100.1"].into_iter());
+        assert_eq!(client, ip("203.0.113.7"));
+    }
+
+    #[test]
+    fn the_chain_is_walked_back_through_trusted_proxies() {
+        let client = proxies()
+            .client_ip(ip("10.0.0.1"), ["203.0.113.7", "10.0.0.2"].into_iter());
+        assert_eq!(client, ip("203.0.113.7"));
+    }
+
+    #[test]
+    fn hops_written_by_the_client_are_ignored() {
+        // The client sent `X-Forwarded-For: 198.51.100.1` itself, and our
+        // proxy appended the address it connected from.
+        let client = proxies().client_ip(
+            ip("10.0.0.1"),
+            ["198.51.100.1", " 203.0.113.7"].into_iter(),
+        );
+        assert_eq!(client, ip("203.0.113.7"));
+    }
+
+    #[test]
+    fn invalid_hops_stop_the_walk() {
+        let client = proxies()
+            .client_ip(ip("10.0.0.1"), ["203.0.113.7", "junk"].into_iter());
+        assert_eq!(client, ip("10.0.0.1"));
+    }
+
+    #[test]
+    fn every_address_of_a_trusted_range_is_trusted() {
+        let proxies = TrustedProxies(vec!["10.0.0.0/8".parse().unwrap()]);
+        let client = proxies.client_ip(
+            ip("10.1.2.3"),
+            ["203.0.113.7", "10.244.0.9"].into_iter(),
+        );
+        assert_eq!(client, ip("203.0.113.7"));
+    }
+}
@@ -1,18 +1,35 @@
 use std::convert::{TryFrom, TryInto};
+use std::net::IpAddr;
 
+use anyhow::Context;
+use ipnet::IpNet;
 use secrecy::{ExposeSecret, Secret};
 use serde_aux::field_attributes::deserialize_number_from_string;
 use sqlx::postgres::{PgConnectOptions, PgSslMode};
 use sqlx::ConnectOptions;
 
-use crate::domain::SubscriberEmail;
+use crate::authentication::BreachedPasswords;
+use crate::client_info::TrustedProxies;
+use crate::domain::{
+    AttributeDefinition, AttributeSchema, EmailDomainBlocklist, SubscriberEmail,
+};
+use crate::email_client::EmailClient;
+use crate::session::SessionTimeouts;
 
 #[derive(serde::Deserialize, Clone)]
 pub struct Settings {
     pub database: DatabaseSettings,
     pub application: ApplicationSettings,
     pub email_client: EmailClientSettings,
+    pub subscriptions: SubscriptionSettings,
+    pub password_reset: PasswordResetSettings,
+    pub login_throttling: LoginThrottlingSettings,
+    pub password_hashing: PasswordHashingSettings,
+    pub password_policy: PasswordPolicySettings,
     pub redis_uri: Secret<String>, // marked as secret as make contain an embed password.
+    /// Namespace for the keys we manage in Redis ourselves (i.e. everything
+    /// but sessions).
+    pub redis_key_prefix: String,
 }
 
 #[derive(serde::Deserialize, Clone)]
@@ -22,6 +39,59 @@ pub struct ApplicationSettings {
     pub host: String,
     pub base_url: String,
     pub hmac_secret: Secret<String>,
+    /// Admin sessions unused for this long are logged out.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub session_idle_timeout_seconds: i64,
+    /// Admin sessions are logged out this long after logging in, however
+    /// active they are.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub session_absolute_timeout_seconds: i64,
+    /// Load balancers allowed to tell us the client address with
+    /// `X-Forwarded-For`, as addresses or CIDR ranges.
+    #[serde(default, deserialize_with = "deserialize_ip_ranges")]
+    pub trusted_proxies: Vec<IpNet>,
+}
+
+impl ApplicationSettings {
+    /// Zero timeouts would log admins out on every request. Behind a load
+    /// balancer, an empty list of trusted proxies makes every client share
+    /// the address of the balancer, and with it the per-IP rate limits.
+    pub fn validate(&self, environment: &Environment) -> Result<(), String> {
+        if self.session_idle_timeout_seconds <= 0 {
+            return Err(
+                "application.session_idle_timeout_seconds must be positive."
+                    .into(),
+            );
+        }
+        if self.session_absolute_timeout_seconds
+            < self.session_idle_timeout_seconds
+        {
+            return Err("application.session_absolute_timeout_seconds must \
+                not be shorter than session_idle_timeout_seconds."
+                .into());
+        }
+        if let Environment::Production = environment {
+            if self.trusted_proxies.is_empty() {
+                return Err("application.trusted_proxies must list the load \
+                    balancers in front of the application in production."
+                    .into());
+            }
+        }
+        Ok(())
+    }
+
+    pub fn trusted_proxies(&self) -> TrustedProxies {
+        TrustedProxies(self.trusted_proxies.clone())
+    }
+
+    pub fn session_timeouts(&self) -> SessionTimeouts {
+        SessionTimeouts {
+            idle: chrono::Duration::seconds(self.session_idle_timeout_seconds),
+            absolute: chrono::Duration::seconds(
+                self.session_absolute_timeout_seconds,
+            ),
+        }
+    }
 }
 
 #[derive(serde::Deserialize, Clone)]
@@ -73,6 +143,185 @@ impl EmailClientSettings {
     pub fn timeout(&self) -> std::time::Duration {
         std::time::Duration::from_millis(self.timeout_milliseconds)
     }
+
+    pub fn client(self) -> EmailClient {
+        let sender_email =
+            self.sender().expect("Invalid sender email address.");
+        let timeout = self.timeout();
+        EmailClient::new(
+            self.base_url,
+            sender_email,
+            self.authorization_token,
+            timeout,
+        )
+    }
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct SubscriptionSettings {
+    /// Version identifier of the consent wording shown next to the sign-up
+    /// form. Bump it whenever the wording changes so consent records stay
+    /// provable.
+    pub consent_text_version: String,
+    /// Sign-ups submitted faster than this after the form was rendered are
+    /// assumed to come from bots.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub min_seconds_to_submit: i64,
+    /// Forms rendered longer ago than this must be reloaded.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_form_age_seconds: i64,
+    pub rate_limit: SubscriptionRateLimitSettings,
+    /// Path to a list of disposable email domains, one per line.
+    pub email_domain_blocklist_path: String,
+    /// Treat provider aliases (Gmail dots, `+tags`) of an address as the same
+    /// subscriber when detecting duplicates.
+    ///
+    /// Stored subscribers keep the duplicate key computed when they signed
+    /// up, so this cannot be changed once there are subscribers: duplicates
+    /// of the existing ones would go unnoticed.
+    pub fold_email_aliases: bool,
+    pub pending_cleanup: PendingCleanupSettings,
+    /// Extra fields collected at sign-up, e.g. the subscriber's company.
+    #[serde(default)]
+    pub custom_attributes: Vec<AttributeDefinition>,
+    /// Origins of the sites embedding the sign-up widget, e.g.
+    /// `https://www.example.com`, allowed to call the API from a browser.
+    #[serde(default)]
+    pub cors_allowed_origins: Vec<String>,
+}
+
+impl SubscriptionSettings {
+    pub fn email_domain_blocklist(
+        &self,
+    ) -> Result<EmailDomainBlocklist, std::io::Error> {
+        let contents =
+            std::fs::read_to_string(&self.email_domain_blocklist_path)?;
+        Ok(EmailDomainBlocklist::parse(&contents))
+    }
+
+    pub fn attribute_schema(&self) -> Result<AttributeSchema, String> {
+        AttributeSchema::new(self.custom_attributes.clone())
+    }
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct SubscriptionRateLimitSettings {
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub window_seconds: u64,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_attempts_per_ip: u64,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_attempts_per_email: u64,
+}
+
+/// Limits on failed password checks, on the login form and the API alike.
+#[derive(serde::Deserialize, Clone)]
+pub struct LoginThrottlingSettings {
+    /// How long failed attempts are remembered.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub window_seconds: u64,
+    /// Failures for a username after which each further attempt waits
+    /// `base_delay_milliseconds`, doubling every time.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub delay_after_failures: u64,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub base_delay_milliseconds: u64,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_delay_milliseconds: u64,
+    /// Failures for a username after which it cannot log in for
+    /// `lockout_seconds`.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub lockout_after_failures: u64,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub lockout_seconds: u64,
+    /// Failures from an IP address after which it cannot log in until the
+    /// window ends, whatever the username.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_failures_per_ip: u64,
+}
+
+/// Argon2id parameters of new password hashes. Stored hashes computed with
+/// weaker ones are upgraded the next time their user logs in.
+#[derive(serde::Deserialize, Clone)]
+pub struct PasswordHashingSettings {
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub memory_kib: u32,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub iterations: u32,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub parallelism: u32,
+}
+
+impl PasswordHashingSettings {
+    pub fn params(&self) -> Result<argon2::Params, argon2::Error> {
+        argon2::Params::new(
+            self.memory_kib,
+            self.iterations,
+            self.parallelism,
+            None,
+        )
+    }
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct PasswordPolicySettings {
+    /// Directory of SHA-1 hashes of breached passwords, which new passwords
+    /// may not match, split by prefix as by the Pwned Passwords downloader.
+    pub breached_password_ranges_path: String,
+}
+
+impl PasswordPolicySettings {
+    pub fn breached_passwords(
+        &self,
+    ) -> Result<BreachedPasswords, anyhow::Error> {
+        BreachedPasswords::open(&self.breached_password_ranges_path)
+            .context("invalid breached password ranges")
+    }
+}
+
+/// Settings of the background job deleting subscribers who never confirmed.
+#[derive(serde::Deserialize, Clone)]
+pub struct PendingCleanupSettings {
+    /// Pending subscriptions older than this are deleted.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_age_hours: i64,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub interval_seconds: u64,
+    /// Subscribers deleted per transaction.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub batch_size: i64,
+    /// Only log how many subscribers would be deleted.
+    pub dry_run: bool,
+}
+
+impl PendingCleanupSettings {
+    /// The worker would spin without pause on a zero interval, and never get
+    /// past an empty batch.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.interval_seconds == 0 {
+            return Err(
+                "pending_cleanup.interval_seconds must be positive.".into()
+            );
+        }
+        if self.batch_size <= 0 {
+            return Err("pending_cleanup.batch_size must be positive.".into());
+        }
+        Ok(())
+    }
+}
+
+/// Limits on password reset requests, so that the form cannot be used to
+/// flood a mailbox.
+#[derive(serde::Deserialize, Clone)]
+pub struct PasswordResetSettings {
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub window_seconds: u64,
+    /// Reset emails sent to an address per window.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_emails_per_address: u64,
+    /// Resets requested from an IP address per window, whatever the address.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_requests_per_ip: u64,
 }
 
 pub fn get_configuration() -> Result<Settings, config::ConfigError> {
@@ -100,7 +349,57 @@ pub fn get_configuration() -> Result<Settings, config::ConfigError> {
         )
         .build()?;
 
-    settings.try_deserialize::<Settings>()
+    let settings = settings.try_deserialize::<Settings>()?;
+    settings
+        .application
+        .validate(&environment)
+        .map_err(config::ConfigError::Message)?;
+    settings
+        .subscriptions
+        .pending_cleanup
+        .validate()
+        .map_err(config::ConfigError::Message)?;
+    Ok(settings)
+}
+
+/// A list of IP ranges, or a comma-separated string of them when set from
+/// the environment. Plain addresses are ranges of their own.
+fn deserialize_ip_ranges<'de, D>(
+    deserializer: D,
+) -> Result<Vec<IpNet>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    #[derive(serde::Deserialize)]
+    #[serde(untagged)]
+    enum IpRanges {
+        List(Vec<String>),
+        Joined(String),
+    }
+
+    let ranges = match serde::Deserialize::deserialize(deserializer)? {
+        IpRanges::List(ranges) => ranges,
+        IpRanges::Joined(ranges) => ranges
+            .split(',')
+            .filter(|r| !r.trim().is_empty())
+            .map(ToOwned::to_owned)
+            .collect(),
+    };
+    ranges
+        .iter()
+        .map(|range| {
+            let range = range.trim();
+            range
+                .parse::<IpNet>()
+                .or_else(|_| range.parse::<IpAddr>().map(IpNet::from))
+                .map_err(|_| {
+                    serde::de::Error::custom(format!(
+                        "{} is not an IP address or range.",
+                        range
+                    ))
+                })
+        })
+        .collect()
 }
 
 /// The possible runtime environment for our application.
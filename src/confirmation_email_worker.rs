@@ -0,0 +1,228 @@
+use std::time::Duration;
+
+use anyhow::Context;
+use sqlx::{PgPool, Postgres, Transaction};
+use tracing::field::display;
+use tracing::Span;
+
+use crate::configuration::Settings;
+use crate::domain::{
+    NewSubscriber, SubscriberEmail, SubscriberName, SubscriptionStatus,
+};
+use crate::email_client::EmailClient;
+use crate::routes::send_confirmation_email;
+use crate::startup::get_connection_pool;
+
+/// Failed deliveries are retried this many times, waiting a minute longer
+/// each time.
+const MAX_RETRIES: i16 = 3;
+
+/// Send the confirmation emails queued with `enqueue_confirmation_emails`,
+/// so that the request queueing them does not wait on the email API.
+pub async fn run_worker_until_stopped(
+    configuration: Settings,
+) -> Result<(), anyhow::Error> {
+    let pool = get_connection_pool(&configuration.database);
+    let email_client = configuration.email_client.client();
+    let base_url = configuration.application.base_url;
+    loop {
+        match try_execute_task(&pool, &email_client, &base_url).await {
+            Ok(ExecutionOutcome::TaskCompleted) => {}
+            Ok(ExecutionOutcome::EmptyQueue) => {
+                tokio::time::sleep(Duration::from_secs(10)).await;
+            }
+            Err(e) => {
+                tracing::error!(
+                    error.cause_chain = ?e,
+                    error.message = %e,
+                    "Failed to process the confirmation email queue."
+                );
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+        }
+    }
+}
+
+pub enum ExecutionOutcome {
+    TaskCompleted,
+    EmptyQueue,
+}
+
+/// Queue a confirmation email for each token, in the transaction storing
+/// the tokens.
+#[tracing::instrument(
+    name = "Queue confirmation emails",
+    skip_all,
+    fields(emails = subscription_tokens.len())
+)]
+pub async fn enqueue_confirmation_emails(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscription_tokens: &[String],
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+    INSERT INTO confirmation_email_queue (
+        subscription_token, n_retries, execute_after
+    )
+    SELECT subscription_token, 0, now()
+    FROM UNNEST($1::text[]) AS t(subscription_token)
+        "#,
+        subscription_tokens,
+    )
+    .execute(transaction)
+    .await?;
+    Ok(())
+}
+
+struct ConfirmationTask {
+    subscription_token: String,
+    n_retries: i16,
+    email: String,
+    name: String,
+    status: SubscriptionStatus,
+}
+
+/// Send the oldest email due, if any.
+#[tracing::instrument(
+    skip_all,
+    fields(subscriber_email = tracing::field::Empty),
+    err
+)]
+pub async fn try_execute_task(
+    pool: &PgPool,
+    email_client: &EmailClient,
+    base_url: &str,
+) -> Result<ExecutionOutcome, anyhow::Error> {
+    let Some((mut transaction, task)) = dequeue_task(pool).await? else {
+        return Ok(ExecutionOutcome::EmptyQueue);
+    };
+    Span::current().record("subscriber_email", display(&task.email));
+
+    if task.status != SubscriptionStatus::PendingConfirmation {
+        // Confirmed or unsubscribed in the meantime.
+        delete_task(&mut transaction, &task.subscription_token).await?;
+    } else {
+        match deliver(&task, email_client, base_url).await {
+            Ok(()) => {
+                delete_task(&mut transaction, &task.subscription_token).await?
+            }
+            Err(e) if task.n_retries < MAX_RETRIES => {
+                tracing::warn!(
+                    error.cause_chain = ?e,
+                    error.message = %e,
+                    n_retries = task.n_retries,
+                    "Failed to send a confirmation email, retrying later."
+                );
+                retry_later(&mut transaction, &task).await?;
+            }
+            Err(e) => {
+                tracing::error!(
+                    error.cause_chain = ?e,
+                    error.message = %e,
+                    "Failed to send a confirmation email, giving up."
+                );
+                delete_task(&mut transaction, &task.subscription_token).await?;
+            }
+        }
+    }
+    transaction
+        .commit()
+        .await
+        .context("failed to commit sql transaction to complete a task")?;
+    Ok(ExecutionOutcome::TaskCompleted)
+}
+
+async fn deliver(
+    task: &ConfirmationTask,
+    email_client: &EmailClient,
+    base_url: &str,
+) -> Result<(), anyhow::Error> {
+    // Stored subscribers were valid when they were added, but the rules may
+    // have been tightened since.
+    let new_subscriber = SubscriberEmail::parse(task.email.clone())
+        .and_then(|email| {
+            Ok(NewSubscriber {
+                email,
+                name: SubscriberName::parse(task.name.clone())?,
+            })
+        })
+        .map_err(anyhow::Error::msg)?;
+    send_confirmation_email(
+        email_client,
+        new_subscriber,
+        base_url,
+        &task.subscription_token,
+    )
+    .await
+    .context("failed to send confirmation email")
+}
+
+/// The oldest task due, locked until the returned transaction ends so that
+/// concurrent workers skip it.
+async fn dequeue_task(
+    pool: &PgPool,
+) -> Result<
+    Option<(Transaction<'static, Postgres>, ConfirmationTask)>,
+    anyhow::Error,
+> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("failed to acquire postgres connection from pool")?;
+    let task = sqlx::query_as!(
+        ConfirmationTask,
+        r#"
+        SELECT
+            q.subscription_token,
+            q.n_retries,
+            s.email,
+            s.name,
+            s.status AS "status: SubscriptionStatus"
+        FROM confirmation_email_queue q
+        JOIN subscription_tokens t USING (subscription_token)
+        JOIN subscriptions s ON s.id = t.subscriber_id
+        WHERE q.execute_after <= now()
+        ORDER BY q.execute_after
+        LIMIT 1
+        FOR UPDATE OF q SKIP LOCKED
+        "#,
+    )
+    .fetch_optional(&mut transaction)
+    .await
+    .context("failed to dequeue a confirmation email")?;
+    Ok(task.map(|task| (transaction, task)))
+}
+
+async fn delete_task(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscription_token: &str,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        "DELETE FROM confirmation_email_queue WHERE subscription_token = $1",
+        subscription_token
+    )
+    .execute(transaction)
+    .await
+    .context("failed to delete a confirmation email from the queue")?;
+    Ok(())
+}
+
+async fn retry_later(
+    transaction: &mut Transaction<'_, Postgres>,
+    task: &ConfirmationTask,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE confirmation_email_queue
+        SET
+            n_retries = n_retries + 1,
+            execute_after = now() + make_interval(mins => n_retries + 1)
+        WHERE subscription_token = $1
+        "#,
+        task.subscription_token
+    )
+    .execute(transaction)
+    .await
+    .context("failed to reschedule a confirmation email")?;
+    Ok(())
+}
@@ -0,0 +1,86 @@
+use std::collections::HashSet;
+
+use crate::domain::SubscriberEmail;
+
+/// Email domains we refuse to send confirmation emails to, e.g. disposable
+/// mail providers.
+#[derive(Debug, Default)]
+pub struct EmailDomainBlocklist(HashSet<String>);
+
+impl EmailDomainBlocklist {
+    /// Parse a blocklist with one domain per line. Blank lines and lines
+    /// starting with `#` are ignored.
+    pub fn parse(contents: &str) -> Self {
+        let domains = contents
+            .lines()
+            .map(str::trim)
+            .filter(|l| !l.is_empty() && !l.starts_with('#'))
+            .map(str::to_lowercase)
+            .collect();
+        Self(domains)
+    }
+
+    /// A domain is blocked if it, or any of its parent domains, is listed.
+    pub fn is_blocked(&self, email: &SubscriberEmail) -> bool {
+        let mut domain = email.domain();
+        loop {
+            if self.0.contains(domain) {
+                return true;
+            }
+            match domain.split_once('.') {
+                Some((_, parent)) => domain = parent,
+                None => return false,
+            }
+        }
+    }
+
+    pub fn check(&self, email: &SubscriberEmail) -> Result<(), String> {
+        if self.is_blocked(email) {
+            Err(format!(
+                "{} is a disposable email address, please use another one.",
+                email
+            ))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use claims::{assert_err, assert_ok};
+
+    use crate::domain::{EmailDomainBlocklist, SubscriberEmail};
+
+    fn blocklist() -> EmailDomainBlocklist {
+        EmailDomainBlocklist::parse(
+            "# disposable providers\n\nMailinator.com\n  yopmail.com  \n",
+        )
+    }
+
+    fn email(s: &str) -> SubscriberEmail {
+        SubscriberEmail::parse(s.to_string()).unwrap()
+    }
+
+    #[test]
+    fn listed_domains_are_rejected_regardless_of_case() {
+        assert_err!(blocklist().check(&email("ursula@mailinator.com")));
+        assert_err!(blocklist().check(&email("ursula@YOPMAIL.com")));
+    }
+
+    #[test]
+    fn subdomains_of_listed_domains_are_rejected() {
+        assert_err!(blocklist().check(&email("ursula@eu.mailinator.com")));
+    }
+
+    #[test]
+    fn other_domains_are_accepted() {
+        assert_ok!(blocklist().check(&email("ursula@gmail.com")));
+        assert_ok!(blocklist().check(&email("ursula@notmailinator.com")));
+    }
+
+    #[test]
+    fn comments_are_not_domains() {
+        assert_eq!(blocklist().0.len(), 2);
+    }
+}
@@ -0,0 +1,261 @@
+use crate::domain::SubscriberEmail;
+
+/// Domains of the mail providers most of our subscribers use.
+const POPULAR_DOMAINS: [&str; 16] = [
+    "aol.com",
+    "gmail.com",
+    "gmx.com",
+    "googlemail.com",
+    "hotmail.co.uk",
+    "hotmail.com",
+    "icloud.com",
+    "live.com",
+    "mail.com",
+    "me.com",
+    "msn.com",
+    "outlook.com",
+    "proton.me",
+    "protonmail.com",
+    "yahoo.co.uk",
+    "yahoo.com",
+];
+
+/// Real providers' domains that happen to be a typo away from a popular one,
+/// e.g. `ymail.com` and `gmail.com`, or that are the same provider in another
+/// country, e.g. `yahoo.co.jp`.
+const KNOWN_VALID_DOMAINS: [&str; 10] = [
+    "email.com",
+    "hotmail.ca",
+    "hotmail.co.jp",
+    "live.ca",
+    "yahoo.ca",
+    "yahoo.co.id",
+    "yahoo.co.in",
+    "yahoo.co.jp",
+    "yahoo.co.nz",
+    "ymail.com",
+];
+
+/// Second levels under which country-code registries sell domains, e.g.
+/// `co.uk` or `com.au`.
+const COUNTRY_SECOND_LEVELS: [&str; 9] =
+    ["ac", "co", "com", "edu", "gov", "ne", "net", "or", "org"];
+
+/// Generic top-level domains a misspelled `.com` could be mistaken for.
+const GENERIC_TLDS: [&str; 9] = [
+    "app", "biz", "cam", "com", "edu", "gov", "info", "net", "org",
+];
+
+/// Returns the address the subscriber most likely meant if the domain of
+/// `email` looks like a misspelling of a popular provider's, e.g.
+/// `name@gmial.com` -> `name@gmail.com`.
+///
+/// The provider's name and the public suffix are compared separately: a
+/// popular name under a real suffix, e.g. `gmail.de`, is not taken for a
+/// typo, unless the suffix is itself a likely misspelling of `.com`.
+pub fn suggest_email_correction(email: &SubscriberEmail) -> Option<String> {
+    let domain = email.domain();
+    if KNOWN_VALID_DOMAINS.contains(&domain) {
+        return None;
+    }
+    let (name, suffix) = split_domain(domain)?;
+
+    let (distance, candidate) = POPULAR_DOMAINS
+        .iter()
+        .filter_map(|candidate| {
+            let (candidate_name, candidate_suffix) =
+                split_domain(candidate).unwrap();
+            let suffix_distance = if suffix == candidate_suffix {
+                0
+            } else if is_real_suffix(suffix) {
+                return None;
+            } else {
+                edit_distance(suffix, candidate_suffix)
+            };
+            let name_distance = edit_distance(name, candidate_name);
+            (suffix_distance <= 1
+                && name_distance <= max_name_distance(candidate_name))
+            .then_some((suffix_distance + name_distance, candidate))
+        })
+        .min_by_key(|(distance, _)| *distance)?;
+    (distance > 0).then(|| format!("{}@{}", email.local_part(), candidate))
+}
+
+/// Short names are only a couple of edits away from other real providers,
+/// e.g. `msn` and `msa`, so we tolerate fewer typos there.
+fn max_name_distance(name: &str) -> usize {
+    match name.len() {
+        0..=4 => 0,
+        5..=6 => 1,
+        _ => 2,
+    }
+}
+
+/// Split `domain` into the name registered by the provider and the public
+/// suffix it was registered under, e.g. `mail.yahoo.co.jp` -> `("yahoo",
+/// "co.jp")`.
+fn split_domain(domain: &str) -> Option<(&str, &str)> {
+    let labels: Vec<&str> = domain.split('.').collect();
+    let suffix_labels = match labels.as_slice() {
+        [.., _, second_level, tld]
+            if is_country_code(tld)
+                && COUNTRY_SECOND_LEVELS.contains(second_level) =>
+        {
+            2
+        }
+        [.., _, _] => 1,
+        _ => return None,
+    };
+    let (rest, suffix) = labels.split_at(labels.len() - suffix_labels);
+    let suffix_len = suffix.iter().map(|label| label.len() + 1).sum::<usize>();
+    Some((
+        rest[rest.len() - 1],
+        &domain[domain.len() + 1 - suffix_len..],
+    ))
+}
+
+fn is_country_code(tld: &str) -> bool {
+    tld.len() == 2 && tld.bytes().all(|b| b.is_ascii_alphabetic())
+}
+
+/// Whether `suffix` is a real public suffix rather than a misspelled one.
+/// Country codes a single edit away from `com`, i.e. `co`, `cm` and `om`,
+/// are far more often typos: the providers really registered there are in
+/// `KNOWN_VALID_DOMAINS`.
+fn is_real_suffix(suffix: &str) -> bool {
+    let tld = suffix.rsplit('.').next().unwrap_or(suffix);
+    (is_country_code(tld) && edit_distance(tld, "com") > 1)
+        || GENERIC_TLDS.contains(&tld)
+}
+
+/// Optimal string alignment distance: the number of insertions, deletions,
+/// substitutions and transpositions of adjacent characters needed to turn
+/// `a` into `b`.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let mut d = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for (i, row) in d.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    for (j, cell) in d[0].iter_mut().enumerate() {
+        *cell = j;
+    }
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            d[i][j] = (d[i - 1][j] + 1)
+                .min(d[i][j - 1] + 1)
+                .min(d[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                d[i][j] = d[i][j].min(d[i - 2][j - 2] + 1);
+            }
+        }
+    }
+    d[a.len()][b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use claims::{assert_none, assert_some_eq};
+
+    use super::{edit_distance, split_domain, suggest_email_correction};
+    use crate::domain::SubscriberEmail;
+
+    fn email(s: &str) -> SubscriberEmail {
+        SubscriberEmail::parse(s.to_string()).unwrap()
+    }
+
+    #[test]
+    fn edit_distance_counts_transpositions_as_a_single_edit() {
+        assert_eq!(edit_distance("gmial.com", "gmail.com"), 1);
+        assert_eq!(edit_distance("gmail.com", "gmail.com"), 0);
+        assert_eq!(edit_distance("gamil.con", "gmail.com"), 2);
+        assert_eq!(edit_distance("", "abc"), 3);
+    }
+
+    #[test]
+    fn common_misspellings_get_a_suggestion() {
+        let test_cases = [
+            ("name@gmial.com", "name@gmail.com"),
+            ("name@gmail.cmo", "name@gmail.com"),
+            ("name@gmail.co", "name@gmail.com"),
+            ("name@gmail.cm", "name@gmail.com"),
+            ("name@hotmail.co", "name@hotmail.com"),
+            ("name@me.co", "name@me.com"),
+            ("name@hotmial.co.uk", "name@hotmail.co.uk"),
+            ("name@hotmial.com", "name@hotmail.com"),
+            ("name@yaho.com", "name@yahoo.com"),
+            ("name@outlok.com", "name@outlook.com"),
+            ("Name@GAMIL.CON", "Name@gmail.com"),
+        ];
+        for (typo, suggestion) in test_cases {
+            assert_some_eq!(
+                suggest_email_correction(&email(typo)),
+                suggestion.to_string(),
+            );
+        }
+    }
+
+    #[test]
+    fn popular_domains_get_no_suggestion() {
+        assert_none!(suggest_email_correction(&email("name@gmail.com")));
+        assert_none!(suggest_email_correction(&email("name@me.com")));
+    }
+
+    #[test]
+    fn real_providers_close_to_a_popular_domain_get_no_suggestion() {
+        for domain in [
+            "ymail.com",
+            "aim.com",
+            "email.com",
+            "mac.com",
+            "msa.com",
+            "gmx.net",
+        ] {
+            assert_none!(
+                suggest_email_correction(&email(&format!("name@{}", domain))),
+                "{}",
+                domain
+            );
+        }
+    }
+
+    #[test]
+    fn country_code_suffixes_are_not_taken_for_typos() {
+        for domain in [
+            "yahoo.co.jp",
+            "yahoo.co.in",
+            "yahoo.co.nz",
+            "yahoo.co.id",
+            "hotmail.co.jp",
+            "yahoo.ca",
+            "hotmail.ca",
+            "live.ca",
+            "mail.ru",
+            "hotmail.fr",
+        ] {
+            assert_none!(
+                suggest_email_correction(&email(&format!("name@{}", domain))),
+                "{}",
+                domain
+            );
+        }
+    }
+
+    #[test]
+    fn domains_are_split_into_name_and_public_suffix() {
+        assert_eq!(split_domain("gmail.com"), Some(("gmail", "com")));
+        assert_eq!(split_domain("yahoo.co.jp"), Some(("yahoo", "co.jp")));
+        assert_eq!(split_domain("mail.yahoo.co.jp"), Some(("yahoo", "co.jp")));
+        assert_eq!(split_domain("co.uk"), Some(("co", "uk")));
+        assert_eq!(split_domain("localhost"), None);
+    }
+
+    #[test]
+    fn unrelated_domains_get_no_suggestion() {
+        assert_none!(suggest_email_correction(&email("name@example.com")));
+        assert_none!(suggest_email_correction(&email("name@gmx.de")));
+        assert_none!(suggest_email_correction(&email("name@ucl.ac.uk")));
+    }
+}
@@ -1,7 +1,24 @@
+mod email_domain_blocklist;
+mod email_domain_typo;
 mod new_subscriber;
+mod newsletter_template;
+mod sign_up_source;
+mod subscriber_attributes;
+mod subscriber_csv;
 mod subscriber_email;
 mod subscriber_name;
+mod subscription_status;
 
+pub use email_domain_blocklist::EmailDomainBlocklist;
+pub use email_domain_typo::suggest_email_correction;
 pub use new_subscriber::NewSubscriber;
+pub use newsletter_template::NewsletterTemplate;
+pub use sign_up_source::SignUpSource;
+pub use subscriber_attributes::{
+    AttributeDefinition, AttributeError, AttributeSchema, AttributeType,
+    SubscriberAttributes,
+};
+pub use subscriber_csv::{CsvLayout, CsvRow};
 pub use subscriber_email::SubscriberEmail;
 pub use subscriber_name::SubscriberName;
+pub use subscription_status::SubscriptionStatus;
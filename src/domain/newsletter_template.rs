@@ -0,0 +1,86 @@
+/// A newsletter title or body with `{{ variable }}` placeholders, filled in
+/// for each subscriber.
+#[derive(Debug, PartialEq)]
+pub struct NewsletterTemplate(Vec<Segment>);
+
+#[derive(Debug, PartialEq)]
+enum Segment {
+    Text(String),
+    Variable(String),
+}
+
+impl NewsletterTemplate {
+    /// Placeholders must name one of `variables`, so that typos are caught
+    /// before anything is sent.
+    pub fn parse(s: &str, variables: &[&str]) -> Result<Self, String> {
+        let mut segments = Vec::new();
+        let mut rest = s;
+        while let Some(start) = rest.find("{{") {
+            let (text, after) = rest.split_at(start);
+            let end = after.find("}}").ok_or_else(|| {
+                "A template variable is not closed.".to_string()
+            })?;
+            let name = after[2..end].trim();
+            if !variables.contains(&name) {
+                return Err(format!(
+                    "{{{{{}}}}} is not a known variable.",
+                    name
+                ));
+            }
+            if !text.is_empty() {
+                segments.push(Segment::Text(text.to_string()));
+            }
+            segments.push(Segment::Variable(name.to_string()));
+            rest = &after[end + 2..];
+        }
+        if !rest.is_empty() {
+            segments.push(Segment::Text(rest.to_string()));
+        }
+        Ok(Self(segments))
+    }
+
+    /// Replace each placeholder with `value_of` its variable, or nothing if
+    /// the subscriber has no value for it.
+    pub fn render(&self, value_of: impl Fn(&str) -> Option<String>) -> String {
+        self.0
+            .iter()
+            .map(|segment| match segment {
+                Segment::Text(text) => text.clone(),
+                Segment::Variable(name) => value_of(name).unwrap_or_default(),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use claims::assert_err;
+
+    use super::NewsletterTemplate;
+
+    const VARIABLES: [&str; 2] = ["name", "company"];
+
+    fn render(template: &str) -> String {
+        NewsletterTemplate::parse(template, &VARIABLES)
+            .unwrap()
+            .render(|name| (name == "name").then(|| "Ursula".to_string()))
+    }
+
+    #[test]
+    fn placeholders_are_replaced_with_the_subscriber_values() {
+        assert_eq!(render("Hi {{name}}!"), "Hi Ursula!");
+        assert_eq!(render("{{ name }}, {{name}}"), "Ursula, Ursula");
+        assert_eq!(render("No variables"), "No variables");
+    }
+
+    #[test]
+    fn missing_values_are_rendered_as_nothing() {
+        assert_eq!(render("At {{company}}."), "At .");
+    }
+
+    #[test]
+    fn unknown_or_unclosed_placeholders_are_rejected() {
+        assert_err!(NewsletterTemplate::parse("Hi {{nmae}}", &VARIABLES));
+        assert_err!(NewsletterTemplate::parse("Hi {{name", &VARIABLES));
+    }
+}
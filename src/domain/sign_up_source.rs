@@ -0,0 +1,166 @@
+const MAX_SOURCE_LENGTH: usize = 64;
+const MAX_UTM_LENGTH: usize = 128;
+
+/// Where a sign-up came from: our own identifier for the landing page or
+/// placement (`source`), and the `utm_*` parameters of the campaign that
+/// brought the visitor in. Every field is optional.
+#[derive(serde::Deserialize, Debug, Default, Clone, PartialEq, Eq)]
+pub struct SignUpSource {
+    #[serde(default)]
+    pub source: Option<String>,
+    #[serde(default)]
+    pub utm_source: Option<String>,
+    #[serde(default)]
+    pub utm_medium: Option<String>,
+    #[serde(default)]
+    pub utm_campaign: Option<String>,
+    #[serde(default)]
+    pub utm_term: Option<String>,
+    #[serde(default)]
+    pub utm_content: Option<String>,
+}
+
+impl SignUpSource {
+    /// The fields that are set, by name.
+    pub fn fields(&self) -> impl Iterator<Item = (&'static str, &str)> {
+        [
+            ("source", &self.source),
+            ("utm_source", &self.utm_source),
+            ("utm_medium", &self.utm_medium),
+            ("utm_campaign", &self.utm_campaign),
+            ("utm_term", &self.utm_term),
+            ("utm_content", &self.utm_content),
+        ]
+        .into_iter()
+        .filter_map(|(name, value)| Some((name, value.as_deref()?)))
+    }
+
+    /// Validate every field, dropping blank ones. Errors come with the name
+    /// of the field they are about.
+    pub fn parse(self) -> Result<Self, Vec<(&'static str, String)>> {
+        let mut errors = Vec::new();
+        let mut check = |field: &'static str, value: Option<String>| {
+            let result = if field == "source" {
+                parse_source(value)
+            } else {
+                parse_utm_value(field, value)
+            };
+            result.map_err(|e| errors.push((field, e))).ok().flatten()
+        };
+        let parsed = Self {
+            source: check("source", self.source),
+            utm_source: check("utm_source", self.utm_source),
+            utm_medium: check("utm_medium", self.utm_medium),
+            utm_campaign: check("utm_campaign", self.utm_campaign),
+            utm_term: check("utm_term", self.utm_term),
+            utm_content: check("utm_content", self.utm_content),
+        };
+        if errors.is_empty() {
+            Ok(parsed)
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+fn non_blank(value: Option<String>) -> Option<String> {
+    value
+        .map(|v| v.trim().to_string())
+        .filter(|v| !v.is_empty())
+}
+
+/// Sources are identifiers we choose, e.g. `pricing-page` or `blog/rust`.
+fn parse_source(value: Option<String>) -> Result<Option<String>, String> {
+    let Some(source) = non_blank(value) else {
+        return Ok(None);
+    };
+    let is_valid = source.len() <= MAX_SOURCE_LENGTH
+        && source.chars().all(|c| {
+            c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | '/')
+        });
+    if is_valid {
+        Ok(Some(source))
+    } else {
+        Err(format!("{} is not a valid source.", source))
+    }
+}
+
+/// UTM parameters are free text set by whoever built the campaign link.
+fn parse_utm_value(
+    field: &str,
+    value: Option<String>,
+) -> Result<Option<String>, String> {
+    let Some(value) = non_blank(value) else {
+        return Ok(None);
+    };
+    if value.chars().count() > MAX_UTM_LENGTH {
+        return Err(format!(
+            "{} must be at most {} characters long.",
+            field, MAX_UTM_LENGTH
+        ));
+    }
+    if value.chars().any(char::is_control) {
+        return Err(format!("{} contains invalid characters.", field));
+    }
+    Ok(Some(value))
+}
+
+#[cfg(test)]
+mod tests {
+    use claims::assert_err;
+
+    use super::SignUpSource;
+
+    #[test]
+    fn blank_values_are_dropped_and_others_trimmed() {
+        let source = SignUpSource {
+            source: Some(" pricing-page ".into()),
+            utm_source: Some("newsletter".into()),
+            utm_medium: Some("  ".into()),
+            ..Default::default()
+        }
+        .parse()
+        .unwrap();
+
+        assert_eq!(
+            source,
+            SignUpSource {
+                source: Some("pricing-page".into()),
+                utm_source: Some("newsletter".into()),
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn sources_must_be_short_identifiers() {
+        for invalid in ["<script>", "spring sale", &"a".repeat(65)] {
+            let source = SignUpSource {
+                source: Some(invalid.to_string()),
+                ..Default::default()
+            };
+            assert_err!(source.parse());
+        }
+        let source = SignUpSource {
+            source: Some("blog/rust-2023.html".into()),
+            ..Default::default()
+        };
+        assert_eq!(source.clone().parse(), Ok(source));
+    }
+
+    #[test]
+    fn utm_values_are_length_limited_and_reported_by_field() {
+        let errors = SignUpSource {
+            utm_campaign: Some("a".repeat(129)),
+            utm_term: Some("new\nline".into()),
+            utm_content: Some("Spring sale — 20% off".into()),
+            ..Default::default()
+        }
+        .parse()
+        .unwrap_err();
+
+        let fields: Vec<&str> =
+            errors.iter().map(|(field, _)| *field).collect();
+        assert_eq!(fields, vec!["utm_campaign", "utm_term"]);
+    }
+}
@@ -0,0 +1,347 @@
+use std::collections::HashMap;
+
+use serde_json::{Map, Number, Value};
+
+/// Names that cannot be used for custom attributes, as they are taken by
+/// the sign-up form, the CSRF token of admin forms or by newsletter template
+/// variables.
+const RESERVED_NAMES: [&str; 14] = [
+    "name",
+    "email",
+    "form_id",
+    "source",
+    "ref",
+    "referral_link",
+    "referral_count",
+    "website",
+    "form_issued_at",
+    "form_signature",
+    "confirm_email",
+    "subscription_token",
+    "attributes",
+    "csrf_token",
+];
+const MAX_NAME_LENGTH: usize = 64;
+const MAX_TEXT_LENGTH: usize = 256;
+
+#[derive(serde::Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum AttributeType {
+    Text,
+    Number,
+    Boolean,
+}
+
+impl AttributeType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Text => "text",
+            Self::Number => "number",
+            Self::Boolean => "boolean",
+        }
+    }
+}
+
+/// A custom attribute collected at sign-up, e.g. the subscriber's company.
+#[derive(serde::Deserialize, Clone, Debug)]
+pub struct AttributeDefinition {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub kind: AttributeType,
+    #[serde(default)]
+    pub required: bool,
+}
+
+/// Why the value of `attribute` was rejected.
+#[derive(Debug, PartialEq, Eq)]
+pub struct AttributeError {
+    pub attribute: String,
+    pub message: String,
+}
+
+impl AttributeError {
+    fn new(attribute: &str, message: String) -> Self {
+        Self {
+            attribute: attribute.to_string(),
+            message,
+        }
+    }
+}
+
+impl std::fmt::Display for AttributeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.message.fmt(f)
+    }
+}
+
+/// The custom attributes subscribers may have, as configured.
+#[derive(Debug, Default)]
+pub struct AttributeSchema(Vec<AttributeDefinition>);
+
+/// Validated custom attributes of a subscriber, stored as a JSON object.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SubscriberAttributes(Map<String, Value>);
+
+impl AttributeSchema {
+    /// Attribute names must be unique snake_case identifiers, usable as
+    /// form field names and template variables.
+    pub fn new(definitions: Vec<AttributeDefinition>) -> Result<Self, String> {
+        for (i, definition) in definitions.iter().enumerate() {
+            let name = &definition.name;
+            let is_identifier = !name.is_empty()
+                && name.len() <= MAX_NAME_LENGTH
+                && name.chars().all(|c| {
+                    c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_'
+                });
+            if !is_identifier {
+                return Err(format!("{} is not a valid attribute name.", name));
+            }
+            if RESERVED_NAMES.contains(&name.as_str())
+                || name.starts_with("utm_")
+            {
+                return Err(format!("{} is a reserved attribute name.", name));
+            }
+            if definitions[..i].iter().any(|d| &d.name == name) {
+                return Err(format!("{} is defined more than once.", name));
+            }
+        }
+        Ok(Self(definitions))
+    }
+
+    pub fn definitions(&self) -> &[AttributeDefinition] {
+        &self.0
+    }
+
+    pub fn get(&self, name: &str) -> Option<&AttributeDefinition> {
+        self.0.iter().find(|d| d.name == name)
+    }
+
+    /// Validate attributes submitted through an HTML form, where every value
+    /// is a string and empty inputs count as missing. Fields that are not in
+    /// the schema are ignored.
+    pub fn parse_form(
+        &self,
+        values: &HashMap<String, String>,
+    ) -> Result<SubscriberAttributes, AttributeError> {
+        let mut attributes = Map::new();
+        for definition in &self.0 {
+            let raw = values
+                .get(&definition.name)
+                .map(|v| v.trim())
+                .filter(|v| !v.is_empty());
+            let value = match raw {
+                Some(raw) => parse_value(definition, raw)?,
+                None if definition.required => {
+                    return Err(AttributeError::new(
+                        &definition.name,
+                        format!("{} is required.", definition.name),
+                    ))
+                }
+                None => continue,
+            };
+            attributes.insert(definition.name.clone(), value);
+        }
+        Ok(SubscriberAttributes(attributes))
+    }
+
+    /// Attributes a subscriber must have to match a filter, from
+    /// `(name, value)` conditions. Values are parsed as in forms; an empty
+    /// filter matches everyone.
+    pub fn parse_filter<'a>(
+        &self,
+        conditions: impl IntoIterator<Item = (&'a str, &'a str)>,
+    ) -> Result<SubscriberAttributes, AttributeError> {
+        let mut attributes = Map::new();
+        for (name, raw) in conditions {
+            let definition = self.get(name).ok_or_else(|| {
+                AttributeError::new(
+                    name,
+                    format!("{} is not a known attribute.", name),
+                )
+            })?;
+            let value = parse_value(definition, raw.trim())?;
+            attributes.insert(definition.name.clone(), value);
+        }
+        Ok(SubscriberAttributes(attributes))
+    }
+}
+
+fn parse_value(
+    definition: &AttributeDefinition,
+    raw: &str,
+) -> Result<Value, AttributeError> {
+    let invalid = || {
+        AttributeError::new(
+            &definition.name,
+            format!(
+                "{} is not a valid {} for {}.",
+                raw,
+                definition.kind.as_str(),
+                definition.name
+            ),
+        )
+    };
+    match definition.kind {
+        AttributeType::Text => {
+            if raw.chars().count() > MAX_TEXT_LENGTH {
+                return Err(AttributeError::new(
+                    &definition.name,
+                    format!(
+                        "{} must be at most {} characters long.",
+                        definition.name, MAX_TEXT_LENGTH
+                    ),
+                ));
+            }
+            Ok(Value::String(raw.to_string()))
+        }
+        AttributeType::Number => raw
+            .parse::<f64>()
+            .ok()
+            .and_then(Number::from_f64)
+            .map(|n| match raw.parse::<i64>() {
+                Ok(i) => Value::from(i),
+                Err(_) => Value::Number(n),
+            })
+            .ok_or_else(invalid),
+        AttributeType::Boolean => match raw.to_lowercase().as_str() {
+            "true" | "on" | "yes" | "1" => Ok(Value::Bool(true)),
+            "false" | "off" | "no" | "0" => Ok(Value::Bool(false)),
+            _ => Err(invalid()),
+        },
+    }
+}
+
+impl SubscriberAttributes {
+    /// Wrap attributes read back from the database, which were validated
+    /// when stored.
+    pub fn from_json(value: Value) -> Self {
+        match value {
+            Value::Object(map) => Self(map),
+            _ => Self::default(),
+        }
+    }
+
+    pub fn to_json(&self) -> Value {
+        Value::Object(self.0.clone())
+    }
+
+    /// The value of an attribute as displayed to people, e.g. in emails.
+    pub fn display(&self, name: &str) -> Option<String> {
+        match self.0.get(name)? {
+            Value::String(s) => Some(s.clone()),
+            Value::Null => None,
+            other => Some(other.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use claims::{assert_err, assert_ok};
+    use serde_json::json;
+
+    use super::{AttributeDefinition, AttributeSchema, AttributeType};
+
+    fn definition(
+        name: &str,
+        kind: AttributeType,
+        required: bool,
+    ) -> AttributeDefinition {
+        AttributeDefinition {
+            name: name.into(),
+            kind,
+            required,
+        }
+    }
+
+    fn schema() -> AttributeSchema {
+        AttributeSchema::new(vec![
+            definition("company", AttributeType::Text, true),
+            definition("team_size", AttributeType::Number, false),
+            definition("developer", AttributeType::Boolean, false),
+        ])
+        .unwrap()
+    }
+
+    fn form(values: &[(&str, &str)]) -> HashMap<String, String> {
+        values
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn reserved_duplicate_and_malformed_names_are_rejected() {
+        for name in ["email", "utm_source", "Company", "", "company name"] {
+            assert_err!(AttributeSchema::new(vec![definition(
+                name,
+                AttributeType::Text,
+                false
+            )]));
+        }
+        assert_err!(AttributeSchema::new(vec![
+            definition("company", AttributeType::Text, false),
+            definition("company", AttributeType::Number, false),
+        ]));
+    }
+
+    #[test]
+    fn the_csrf_token_field_is_reserved() {
+        assert_err!(AttributeSchema::new(vec![definition(
+            "csrf_token",
+            AttributeType::Text,
+            false
+        )]));
+    }
+
+    #[test]
+    fn form_values_are_converted_to_their_type() {
+        let attributes = schema()
+            .parse_form(&form(&[
+                ("company", " Acme "),
+                ("team_size", "12"),
+                ("developer", "on"),
+                ("unrelated", "ignored"),
+            ]))
+            .unwrap();
+        assert_eq!(
+            attributes.to_json(),
+            json!({"company": "Acme", "team_size": 12, "developer": true})
+        );
+        assert_eq!(attributes.display("team_size"), Some("12".into()));
+    }
+
+    #[test]
+    fn required_attributes_must_be_present_and_optional_ones_may_be_empty() {
+        assert_err!(schema().parse_form(&form(&[("team_size", "3")])));
+        assert_err!(schema().parse_form(&form(&[("company", "  ")])));
+        assert_ok!(schema()
+            .parse_form(&form(&[("company", "Acme"), ("team_size", "")])));
+    }
+
+    #[test]
+    fn filters_only_accept_known_attributes() {
+        let filter = schema()
+            .parse_filter([("team_size", "3"), ("developer", "no")])
+            .unwrap();
+        assert_eq!(
+            filter.to_json(),
+            json!({"team_size": 3, "developer": false})
+        );
+        assert_err!(schema().parse_filter([("country", "FR")]));
+        assert_err!(schema().parse_filter([("team_size", "")]));
+    }
+
+    #[test]
+    fn values_of_the_wrong_type_are_rejected() {
+        assert_err!(schema()
+            .parse_form(&form(&[("company", "Acme"), ("team_size", "a few")])));
+        assert_err!(schema()
+            .parse_form(&form(&[("company", "Acme"), ("developer", "maybe")])));
+        assert_err!(
+            schema().parse_form(&form(&[("company", &"a".repeat(257))]))
+        );
+    }
+}
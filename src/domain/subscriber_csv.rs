@@ -0,0 +1,174 @@
+use crate::domain::{NewSubscriber, SubscriberEmail, SubscriberName};
+
+/// Header names used for each column by the export formats of the
+/// newsletter platforms we know about (Mailchimp, ConvertKit, Substack,
+/// Buttondown, MailerLite, ...), compared case-insensitively.
+const EMAIL_HEADERS: [&str; 6] = [
+    "email",
+    "email address",
+    "email_address",
+    "e-mail",
+    "subscriber email",
+    "subscriber",
+];
+const NAME_HEADERS: [&str; 4] =
+    ["name", "full name", "full_name", "subscriber name"];
+const FIRST_NAME_HEADERS: [&str; 5] = [
+    "first name",
+    "first_name",
+    "firstname",
+    "fname",
+    "given name",
+];
+const LAST_NAME_HEADERS: [&str; 5] =
+    ["last name", "last_name", "lastname", "lname", "surname"];
+
+/// Where the subscriber fields live in a given CSV file.
+#[derive(Debug, PartialEq, Eq)]
+pub struct CsvLayout {
+    email: usize,
+    name: Option<usize>,
+    first_name: Option<usize>,
+    last_name: Option<usize>,
+}
+
+/// A data row of an imported CSV file, validated into a `NewSubscriber`.
+pub struct CsvRow {
+    /// Line number in the file, header included, for error reporting.
+    pub line: u64,
+    pub email: String,
+    pub name: String,
+    pub subscriber: Result<NewSubscriber, String>,
+}
+
+impl CsvLayout {
+    /// Work out the layout from the header row, if we can find an email
+    /// column in it.
+    pub fn detect(headers: &csv::StringRecord) -> Result<Self, String> {
+        let find = |candidates: &[&str]| {
+            headers.iter().position(|h| {
+                let h = h.trim().trim_start_matches('\u{feff}').to_lowercase();
+                candidates.contains(&h.as_str())
+            })
+        };
+        let email = find(&EMAIL_HEADERS).ok_or_else(|| {
+            "The CSV file must have a header row with an email column."
+                .to_string()
+        })?;
+        Ok(Self {
+            email,
+            name: find(&NAME_HEADERS),
+            first_name: find(&FIRST_NAME_HEADERS),
+            last_name: find(&LAST_NAME_HEADERS),
+        })
+    }
+
+    /// Validate a record through `SubscriberEmail::parse` and
+    /// `SubscriberName::parse`.
+    ///
+    /// Platforms that do not export names (e.g. Substack) would otherwise
+    /// have every row rejected, so the local part of the email address is
+    /// used as name when none is available.
+    pub fn row(&self, record: &csv::StringRecord) -> CsvRow {
+        let get = |i: Option<usize>| {
+            i.and_then(|i| record.get(i)).unwrap_or_default().trim()
+        };
+        let email = get(Some(self.email)).to_string();
+        let name = match get(self.name) {
+            "" => [get(self.first_name), get(self.last_name)]
+                .into_iter()
+                .filter(|s| !s.is_empty())
+                .collect::<Vec<_>>()
+                .join(" "),
+            name => name.to_string(),
+        };
+
+        let subscriber = SubscriberEmail::parse(email.clone()).and_then(|e| {
+            let name = if name.is_empty() {
+                e.local_part().to_string()
+            } else {
+                name.clone()
+            };
+            Ok(NewSubscriber {
+                name: SubscriberName::parse(name)?,
+                email: e,
+            })
+        });
+
+        CsvRow {
+            line: record.position().map(|p| p.line()).unwrap_or_default(),
+            email,
+            name,
+            subscriber,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use claims::{assert_err, assert_ok};
+
+    use super::CsvLayout;
+
+    fn read(csv: &str) -> (CsvLayout, Vec<csv::StringRecord>) {
+        let mut reader = csv::ReaderBuilder::new()
+            .flexible(true)
+            .from_reader(csv.as_bytes());
+        let layout = CsvLayout::detect(reader.headers().unwrap()).unwrap();
+        let records = reader.records().map(Result::unwrap).collect();
+        (layout, records)
+    }
+
+    #[test]
+    fn a_mailchimp_export_is_understood() {
+        let (layout, records) = read(
+            "Email Address,First Name,Last Name,OPTIN_TIME\n\
+            ursula@example.com,Ursula,Le Guin,2023-01-01 00:00:00\n",
+        );
+        let row = layout.row(&records[0]);
+        assert_eq!(row.line, 2);
+        assert_eq!(row.email, "ursula@example.com");
+        assert_eq!(row.name, "Ursula Le Guin");
+        assert_ok!(row.subscriber);
+    }
+
+    #[test]
+    fn a_convertkit_export_is_understood() {
+        let (layout, records) = read(
+            "first_name,email_address,state\n\
+            Ursula,ursula@example.com,active\n",
+        );
+        let row = layout.row(&records[0]);
+        assert_eq!(row.email, "ursula@example.com");
+        assert_eq!(row.name, "Ursula");
+    }
+
+    #[test]
+    fn a_file_without_names_falls_back_to_the_email_local_part() {
+        let (layout, records) = read(
+            "email,active_subscription,created_at\n\
+            ursula@example.com,false,2023-01-01\n",
+        );
+        let subscriber = layout.row(&records[0]).subscriber.unwrap();
+        assert_eq!(subscriber.name.as_ref(), "ursula");
+    }
+
+    #[test]
+    fn a_file_without_an_email_column_is_rejected() {
+        let mut reader = csv::Reader::from_reader("name,phone\n".as_bytes());
+        assert_err!(CsvLayout::detect(reader.headers().unwrap()));
+    }
+
+    #[test]
+    fn invalid_rows_are_reported_with_the_validation_error() {
+        let (layout, records) = read(
+            "email,name\n\
+            not-an-email,Ursula\n\
+            ursula@example.com,Ursula (Le Guin)\n\
+            short-row@example.com\n",
+        );
+        assert_err!(layout.row(&records[0]).subscriber);
+        assert_err!(layout.row(&records[1]).subscriber);
+        assert_ok!(layout.row(&records[2]).subscriber);
+    }
+}
@@ -1,6 +1,9 @@
 use validator::validate_email;
 
-#[derive(Debug)]
+/// Providers that ignore dots in the local part of their addresses.
+const DOT_INSENSITIVE_DOMAINS: [&str; 2] = ["gmail.com", "googlemail.com"];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct SubscriberEmail(String);
 
 impl std::fmt::Display for SubscriberEmail {
@@ -10,11 +13,51 @@ impl std::fmt::Display for SubscriberEmail {
 }
 
 impl SubscriberEmail {
+    /// Returns an instance of `SubscriberEmail` if the input is a valid email
+    /// address, normalised by trimming surrounding whitespace and
+    /// lowercasing its domain.
+    ///
+    /// The local part is left untouched: it is case-sensitive as far as
+    /// RFC 5321 is concerned, see `dedup_key` for duplicate detection.
     pub fn parse(s: String) -> Result<SubscriberEmail, String> {
-        if validate_email(&s) {
-            Ok(Self(s))
+        let trimmed = s.trim();
+        if !validate_email(trimmed) {
+            return Err(format!("{} is not a valid subscriber email.", s));
+        }
+        // `validate_email` guarantees there is an `@`.
+        let (local_part, domain) = trimmed.rsplit_once('@').unwrap();
+        Ok(Self(format!("{}@{}", local_part, domain.to_lowercase())))
+    }
+
+    pub fn local_part(&self) -> &str {
+        self.0.rsplit_once('@').unwrap().0
+    }
+
+    pub fn domain(&self) -> &str {
+        self.0.rsplit_once('@').unwrap().1
+    }
+
+    /// The form of the address used to detect duplicate subscriptions.
+    ///
+    /// It is always lowercased. When `fold_aliases` is set, `+tags` are
+    /// stripped from the local part and, for Gmail, dots are ignored and
+    /// `googlemail.com` is treated as `gmail.com`, as those all reach the
+    /// same mailbox.
+    pub fn dedup_key(&self, fold_aliases: bool) -> String {
+        let local_part = self.local_part().to_lowercase();
+        let domain = self.domain();
+        if !fold_aliases {
+            return format!("{}@{}", local_part, domain);
+        }
+
+        let local_part = match local_part.split_once('+') {
+            Some((untagged, _)) if !untagged.is_empty() => untagged,
+            _ => &local_part,
+        };
+        if DOT_INSENSITIVE_DOMAINS.contains(&domain) {
+            format!("{}@gmail.com", local_part.replace('.', ""))
         } else {
-            Err(format!("{} is not a valid subscriber email.", s))
+            format!("{}@{}", local_part, domain)
         }
     }
 }
@@ -27,7 +70,7 @@ impl AsRef<str> for SubscriberEmail {
 
 #[cfg(test)]
 mod tests {
-    use claims::assert_err;
+    use claims::{assert_err, assert_ok_eq};
     use fake::faker::internet::en::SafeEmail;
     use fake::Fake;
 
@@ -51,6 +94,39 @@ mod tests {
         assert_err!(SubscriberEmail::parse(email));
     }
 
+    #[test]
+    fn surrounding_whitespace_is_trimmed_and_domain_lowercased() {
+        let email = "  Ursula.Le.Guin@Example.COM \n".to_string();
+        assert_ok_eq!(
+            SubscriberEmail::parse(email).map(|e| e.to_string()),
+            "Ursula.Le.Guin@example.com".to_string()
+        );
+    }
+
+    #[test]
+    fn dedup_key_is_lowercase() {
+        let email =
+            SubscriberEmail::parse("Ursula@Example.com".into()).unwrap();
+        assert_eq!(email.dedup_key(false), "ursula@example.com");
+        assert_eq!(email.dedup_key(true), "ursula@example.com");
+    }
+
+    #[test]
+    fn dedup_key_folds_gmail_dots_and_tags_only_when_asked() {
+        let email =
+            SubscriberEmail::parse("Le.Guin+news@googlemail.com".into())
+                .unwrap();
+        assert_eq!(email.dedup_key(false), "le.guin+news@googlemail.com");
+        assert_eq!(email.dedup_key(true), "leguin@gmail.com");
+    }
+
+    #[test]
+    fn dedup_key_keeps_dots_for_other_providers() {
+        let email =
+            SubscriberEmail::parse("le.guin+news@example.com".into()).unwrap();
+        assert_eq!(email.dedup_key(true), "le.guin@example.com");
+    }
+
     #[derive(Debug, Clone)]
     struct ValidEmailFixture(pub String);
 
@@ -67,4 +143,37 @@ mod tests {
     ) -> bool {
         SubscriberEmail::parse(valid_email.0).is_ok()
     }
+
+    #[quickcheck_macros::quickcheck]
+    fn parsing_is_idempotent(valid_email: ValidEmailFixture) -> bool {
+        let once = SubscriberEmail::parse(valid_email.0).unwrap();
+        let twice = SubscriberEmail::parse(once.to_string()).unwrap();
+        once == twice
+    }
+
+    #[quickcheck_macros::quickcheck]
+    fn whitespace_and_domain_case_do_not_change_the_parsed_email(
+        valid_email: ValidEmailFixture,
+    ) -> bool {
+        let (local_part, domain) = valid_email.0.rsplit_once('@').unwrap();
+        let noisy = format!(" {}@{}\t", local_part, domain.to_uppercase());
+        SubscriberEmail::parse(noisy) == SubscriberEmail::parse(valid_email.0)
+    }
+
+    #[quickcheck_macros::quickcheck]
+    fn tagged_gmail_aliases_share_the_dedup_key(
+        valid_email: ValidEmailFixture,
+        tag: u32,
+    ) -> bool {
+        let local_part = valid_email.0.rsplit_once('@').unwrap().0;
+        let gmail = SubscriberEmail::parse(format!("{}@gmail.com", local_part))
+            .unwrap();
+        let alias = SubscriberEmail::parse(format!(
+            "{}+{}@GoogleMail.com",
+            local_part.to_uppercase(),
+            tag
+        ))
+        .unwrap();
+        gmail.dedup_key(true) == alias.dedup_key(true)
+    }
 }
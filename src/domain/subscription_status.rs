@@ -0,0 +1,144 @@
+/// Where a subscriber stands, stored as the `subscription_status` Postgres
+/// enum.
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    serde::Serialize,
+    serde::Deserialize,
+    sqlx::Type,
+)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "subscription_status", rename_all = "snake_case")]
+pub enum SubscriptionStatus {
+    /// Signed up, waiting for the confirmation link to be clicked.
+    PendingConfirmation,
+    /// Receives newsletters.
+    Confirmed,
+    Unsubscribed,
+    /// Emails to the address cannot be delivered.
+    Bounced,
+    /// Marked one of our emails as spam.
+    Complained,
+    /// Personal data removed on request.
+    Erased,
+}
+
+impl SubscriptionStatus {
+    pub const ALL: [SubscriptionStatus; 6] = [
+        Self::PendingConfirmation,
+        Self::Confirmed,
+        Self::Unsubscribed,
+        Self::Bounced,
+        Self::Complained,
+        Self::Erased,
+    ];
+
+    pub fn parse(s: &str) -> Result<Self, String> {
+        Self::ALL
+            .into_iter()
+            .find(|status| status.as_str() == s)
+            .ok_or_else(|| format!("{} is not a valid status.", s))
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::PendingConfirmation => "pending_confirmation",
+            Self::Confirmed => "confirmed",
+            Self::Unsubscribed => "unsubscribed",
+            Self::Bounced => "bounced",
+            Self::Complained => "complained",
+            Self::Erased => "erased",
+        }
+    }
+
+    /// Whether a subscriber in this status may be moved to `next`.
+    ///
+    /// Only a pending subscriber can be confirmed: people who left, or whose
+    /// address stopped working, must opt in again. Erasure is always
+    /// possible and final.
+    pub fn can_transition_to(&self, next: SubscriptionStatus) -> bool {
+        use SubscriptionStatus::*;
+
+        match (self, next) {
+            (Erased, _) => false,
+            (_, Erased) => true,
+            (PendingConfirmation, Confirmed) => true,
+            (PendingConfirmation | Confirmed, Unsubscribed) => true,
+            (PendingConfirmation | Confirmed, Bounced | Complained) => true,
+            (Bounced, Unsubscribed) => true,
+            (Bounced | Unsubscribed, Complained) => true,
+            _ => false,
+        }
+    }
+
+    /// Returns `next` if the transition is legal.
+    pub fn transition_to(
+        &self,
+        next: SubscriptionStatus,
+    ) -> Result<SubscriptionStatus, String> {
+        if self.can_transition_to(next) {
+            Ok(next)
+        } else {
+            Err(format!("A subscriber cannot go from {} to {}.", self, next))
+        }
+    }
+}
+
+impl std::fmt::Display for SubscriptionStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.as_str().fmt(f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use claims::{assert_err, assert_ok_eq};
+
+    use super::SubscriptionStatus;
+    use super::SubscriptionStatus::*;
+
+    #[test]
+    fn statuses_round_trip_through_their_string_form() {
+        for status in SubscriptionStatus::ALL {
+            assert_ok_eq!(SubscriptionStatus::parse(status.as_str()), status);
+        }
+        assert_err!(SubscriptionStatus::parse("Confirmed"));
+    }
+
+    #[test]
+    fn only_pending_subscribers_can_be_confirmed() {
+        assert_ok_eq!(PendingConfirmation.transition_to(Confirmed), Confirmed);
+        for status in [Confirmed, Unsubscribed, Bounced, Complained, Erased] {
+            assert_err!(status.transition_to(Confirmed));
+        }
+    }
+
+    #[test]
+    fn erasure_is_always_possible_and_final() {
+        for status in SubscriptionStatus::ALL {
+            assert_eq!(
+                status.can_transition_to(Erased),
+                status != Erased,
+                "{} -> erased",
+                status
+            );
+            assert!(!Erased.can_transition_to(status));
+        }
+    }
+
+    #[test]
+    fn a_status_does_not_transition_to_itself() {
+        for status in SubscriptionStatus::ALL {
+            assert!(!status.can_transition_to(status));
+        }
+    }
+
+    #[test]
+    fn complaints_are_not_overridden_by_an_unsubscription() {
+        assert!(!Complained.can_transition_to(Unsubscribed));
+        assert!(Bounced.can_transition_to(Unsubscribed));
+    }
+}
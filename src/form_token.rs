@@ -0,0 +1,126 @@
+use hmac::{Hmac, Mac};
+use secrecy::{ExposeSecret, Secret};
+use sha2::Sha256;
+
+/// A timestamp recording when a form was rendered, signed with the
+/// application's HMAC secret so that clients cannot forge it.
+///
+/// Bots tend to post to our endpoints directly or fill forms in instantly:
+/// requiring a signed timestamp that is old enough (but not too old) lets
+/// us reject both.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FormToken {
+    pub issued_at: i64,
+    pub signature: String,
+}
+
+#[derive(thiserror::Error, Debug, PartialEq, Eq)]
+pub enum FormTokenError {
+    #[error("the form token signature is invalid")]
+    InvalidSignature,
+    #[error("the form was submitted {0} seconds after being rendered")]
+    SubmittedTooFast(i64),
+    #[error("the form was rendered {0} seconds ago")]
+    Expired(i64),
+}
+
+impl FormToken {
+    pub fn issue(secret: &Secret<String>, issued_at: i64) -> Self {
+        Self {
+            issued_at,
+            signature: sign(secret, issued_at),
+        }
+    }
+
+    /// Check the token was issued by us between `min_age_seconds` and
+    /// `max_age_seconds` before `now`.
+    pub fn verify(
+        &self,
+        secret: &Secret<String>,
+        now: i64,
+        min_age_seconds: i64,
+        max_age_seconds: i64,
+    ) -> Result<(), FormTokenError> {
+        let signature = hex::decode(&self.signature)
+            .map_err(|_| FormTokenError::InvalidSignature)?;
+        mac(secret, self.issued_at)
+            .verify_slice(&signature)
+            .map_err(|_| FormTokenError::InvalidSignature)?;
+
+        let age = now - self.issued_at;
+        if age < min_age_seconds {
+            return Err(FormTokenError::SubmittedTooFast(age));
+        }
+        if age > max_age_seconds {
+            return Err(FormTokenError::Expired(age));
+        }
+        Ok(())
+    }
+}
+
+fn mac(secret: &Secret<String>, issued_at: i64) -> Hmac<Sha256> {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.expose_secret().as_bytes())
+            .expect("HMAC can take a key of any size");
+    mac.update(format!("form_issued_at={}", issued_at).as_bytes());
+    mac
+}
+
+fn sign(secret: &Secret<String>, issued_at: i64) -> String {
+    hex::encode(mac(secret, issued_at).finalize().into_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use claims::{assert_err_eq, assert_ok};
+    use secrecy::Secret;
+
+    use super::{FormToken, FormTokenError};
+
+    fn secret() -> Secret<String> {
+        Secret::new("a-very-secret-key".into())
+    }
+
+    #[test]
+    fn a_token_submitted_within_the_allowed_window_is_accepted() {
+        let token = FormToken::issue(&secret(), 1_000);
+        assert_ok!(token.verify(&secret(), 1_010, 3, 60));
+    }
+
+    #[test]
+    fn a_token_submitted_too_fast_is_rejected() {
+        let token = FormToken::issue(&secret(), 1_000);
+        assert_err_eq!(
+            token.verify(&secret(), 1_001, 3, 60),
+            FormTokenError::SubmittedTooFast(1)
+        );
+    }
+
+    #[test]
+    fn an_expired_token_is_rejected() {
+        let token = FormToken::issue(&secret(), 1_000);
+        assert_err_eq!(
+            token.verify(&secret(), 1_061, 3, 60),
+            FormTokenError::Expired(61)
+        );
+    }
+
+    #[test]
+    fn a_token_with_a_tampered_timestamp_is_rejected() {
+        let mut token = FormToken::issue(&secret(), 1_000);
+        token.issued_at = 900;
+        assert_err_eq!(
+            token.verify(&secret(), 1_010, 3, 60),
+            FormTokenError::InvalidSignature
+        );
+    }
+
+    #[test]
+    fn a_token_signed_with_another_key_is_rejected() {
+        let token = FormToken::issue(&Secret::new("another-key".into()), 1_000);
+        assert_err_eq!(
+            token.verify(&secret(), 1_010, 3, 60),
+            FormTokenError::InvalidSignature
+        );
+    }
+}
@@ -1,8 +1,17 @@
+pub mod audit;
 pub mod authentication;
+pub mod client_info;
 pub mod configuration;
+pub mod confirmation_email_worker;
 pub mod domain;
 pub mod email_client;
+pub mod form_token;
+pub mod login_guard;
+pub mod pending_cleanup;
+pub mod rate_limit;
 pub mod routes;
 pub mod session;
+pub mod session_index;
 pub mod startup;
+pub mod subscription_guard;
 pub mod telemetry;
@@ -0,0 +1,321 @@
+use std::time::Duration;
+
+use anyhow::Context;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::client_info::ClientInfo;
+use crate::configuration::LoginThrottlingSettings;
+use crate::domain::SubscriberEmail;
+use crate::email_client::EmailClient;
+use crate::rate_limit::RateLimiter;
+
+/// Slows down and then stops password guessing, on the login form as well as
+/// on the Basic auth of the API.
+///
+/// Failed attempts are counted per username, to protect accounts against
+/// targeted guessing, and per IP address, against credential stuffing
+/// spread over many usernames. The address is the one `ClientInfo` resolves,
+/// so a forged `X-Forwarded-For` does not start a fresh count.
+///
+/// Attempts are counted before the password is checked, and taken back once
+/// it turns out right: parallel requests cannot all slip in while the
+/// previous ones are still being checked.
+pub struct LoginGuard {
+    rate_limiter: RateLimiter,
+    settings: LoginThrottlingSettings,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum LoginRejection {
+    #[error("the account is locked after too many failed attempts")]
+    AccountLocked,
+    #[error("too many failed attempts from ip address {0}")]
+    TooManyFailuresFromIp(String),
+}
+
+impl LoginGuard {
+    pub fn new(
+        rate_limiter: RateLimiter,
+        settings: LoginThrottlingSettings,
+    ) -> Self {
+        Self {
+            rate_limiter,
+            settings,
+        }
+    }
+
+    /// Count the attempt, and return the reason why it should be rejected
+    /// without checking the password, if any. Otherwise waits longer the more
+    /// attempts failed for `username` recently.
+    #[tracing::instrument(
+        name = "Screen login attempt",
+        skip(self, client_info)
+    )]
+    pub async fn screen(
+        &self,
+        username: &str,
+        client_info: &ClientInfo,
+    ) -> Result<Option<LoginRejection>, anyhow::Error> {
+        if self.rate_limiter.is_blocked(&user_key(username)).await? {
+            return Ok(Some(LoginRejection::AccountLocked));
+        }
+        let window = self.settings.window_seconds;
+        if let Some(ip_address) = &client_info.ip_address {
+            let attempts =
+                self.rate_limiter.hit(&ip_key(ip_address), window).await?;
+            if attempts > self.settings.max_failures_per_ip {
+                return Ok(Some(LoginRejection::TooManyFailuresFromIp(
+                    ip_address.clone(),
+                )));
+            }
+        }
+
+        let attempts =
+            self.rate_limiter.hit(&user_key(username), window).await?;
+        // Attempts still being checked when the lockout threshold is reached
+        // may not lock the account yet: those past it are refused anyway.
+        if attempts > self.settings.lockout_after_failures {
+            return Ok(Some(LoginRejection::AccountLocked));
+        }
+        let failures = attempts - 1;
+        let delay = self.settings.delay(failures);
+        if !delay.is_zero() {
+            tracing::info!(?delay, failures, "Delaying login attempt.");
+            tokio::time::sleep(delay).await;
+        }
+        Ok(None)
+    }
+
+    /// The password was wrong: lock the account if enough attempts failed,
+    /// and email its owner. Returns whether it is locked.
+    #[tracing::instrument(
+        name = "Record failed login attempt",
+        skip(self, pool, email_client)
+    )]
+    pub async fn record_failure(
+        &self,
+        username: &str,
+        pool: &PgPool,
+        email_client: &EmailClient,
+    ) -> Result<bool, anyhow::Error> {
+        // Counted by `screen` already.
+        let failures = self.rate_limiter.attempts(&user_key(username)).await?;
+        if failures < self.settings.lockout_after_failures {
+            return Ok(false);
+        }
+        self.lock_account(username, pool, email_client).await?;
+        Ok(true)
+    }
+
+    /// Whether `username` is locked, e.g. since they entered their password.
+    pub async fn is_locked(
+        &self,
+        username: &str,
+    ) -> Result<bool, anyhow::Error> {
+        self.rate_limiter.is_blocked(&user_key(username)).await
+    }
+
+    /// Count a wrong code at the second login step. Codes are counted per
+    /// user, across sessions, and lock the account past
+    /// `lockout_after_failures` just like wrong passwords. Returns whether it
+    /// did.
+    #[tracing::instrument(
+        name = "Record failed second factor",
+        skip(self, pool, email_client)
+    )]
+    pub async fn record_second_factor_failure(
+        &self,
+        user_id: Uuid,
+        username: &str,
+        pool: &PgPool,
+        email_client: &EmailClient,
+    ) -> Result<bool, anyhow::Error> {
+        let failures = self
+            .rate_limiter
+            .hit(&second_factor_key(user_id), self.settings.window_seconds)
+            .await?;
+        if failures < self.settings.lockout_after_failures {
+            return Ok(false);
+        }
+        self.rate_limiter.reset(&second_factor_key(user_id)).await?;
+        self.lock_account(username, pool, email_client).await?;
+        Ok(true)
+    }
+
+    #[tracing::instrument(name = "Record successful second factor", skip(self))]
+    pub async fn record_second_factor_success(
+        &self,
+        user_id: Uuid,
+    ) -> Result<(), anyhow::Error> {
+        self.rate_limiter.reset(&second_factor_key(user_id)).await
+    }
+
+    async fn lock_account(
+        &self,
+        username: &str,
+        pool: &PgPool,
+        email_client: &EmailClient,
+    ) -> Result<(), anyhow::Error> {
+        let newly_locked = self
+            .rate_limiter
+            .block(&user_key(username), self.settings.lockout_seconds)
+            .await?;
+        // The account starts afresh once the lockout expires.
+        self.rate_limiter.reset(&user_key(username)).await?;
+        // Parallel failures may all get here: only notify once.
+        if !newly_locked {
+            return Ok(());
+        }
+        tracing::warn!("Account locked after too many failed login attempts.");
+        // The account is locked whether or not the email goes out.
+        if let Err(e) = notify_account_locked(
+            pool,
+            email_client,
+            username,
+            self.settings.lockout_seconds.div_ceil(60),
+        )
+        .await
+        {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "Failed to notify the user of the lockout."
+            );
+        }
+        Ok(())
+    }
+
+    /// The password was right: earlier failures for `username` no longer
+    /// count, nor does this attempt for the IP address. Earlier failures of
+    /// the address still do, as an attacker could own one of the accounts
+    /// they are stuffing credentials into.
+    #[tracing::instrument(
+        name = "Record successful login",
+        skip(self, client_info)
+    )]
+    pub async fn record_success(
+        &self,
+        username: &str,
+        client_info: &ClientInfo,
+    ) -> Result<(), anyhow::Error> {
+        if let Some(ip_address) = &client_info.ip_address {
+            self.rate_limiter
+                .forgive(&ip_key(ip_address), self.settings.window_seconds)
+                .await?;
+        }
+        self.rate_limiter.reset(&user_key(username)).await
+    }
+}
+
+impl LoginThrottlingSettings {
+    /// The wait before checking a password after `failures` failed attempts,
+    /// doubling with each failure past `delay_after_failures`.
+    pub fn delay(&self, failures: u64) -> Duration {
+        let Some(excess) = failures.checked_sub(self.delay_after_failures)
+        else {
+            return Duration::ZERO;
+        };
+        let delay = self.base_delay_milliseconds.saturating_mul(
+            1u64.checked_shl(excess as u32).unwrap_or(u64::MAX),
+        );
+        Duration::from_millis(delay.min(self.max_delay_milliseconds))
+    }
+}
+
+fn user_key(username: &str) -> String {
+    format!("login:user:{}", username)
+}
+
+fn second_factor_key(user_id: Uuid) -> String {
+    format!("login:second_factor:{}", user_id)
+}
+
+fn ip_key(ip_address: &str) -> String {
+    format!("login:ip:{}", ip_address)
+}
+
+/// Let the owner of the account know that it got locked, in case they were
+/// not the one guessing. Users without an email address are skipped.
+#[tracing::instrument(
+    name = "Notify account lockout",
+    skip(pool, email_client)
+)]
+async fn notify_account_locked(
+    pool: &PgPool,
+    email_client: &EmailClient,
+    username: &str,
+    lockout_minutes: u64,
+) -> Result<(), anyhow::Error> {
+    let user = sqlx::query!(
+        "SELECT email FROM users WHERE username = $1 AND is_active",
+        username
+    )
+    .fetch_optional(pool)
+    .await
+    .context("failed to look up the email of the user")?;
+    let Some(email) = user.and_then(|u| u.email) else {
+        return Ok(());
+    };
+    let email = SubscriberEmail::parse(email).map_err(anyhow::Error::msg)?;
+
+    let plain_body = format!(
+        "There were too many failed attempts to log in as {username}, so the \
+        account is locked for {lockout_minutes} minutes.\n\
+        If this was not you, consider resetting your password once the lock \
+        expires."
+    );
+    let html_body = format!(
+        "There were too many failed attempts to log in as {}, so the \
+        account is locked for {} minutes.<br />\
+        If this was not you, consider resetting your password once the lock \
+        expires.",
+        htmlescape::encode_minimal(username),
+        lockout_minutes
+    );
+    email_client
+        .send_email(
+            &email,
+            "Your account has been locked",
+            &html_body,
+            &plain_body,
+        )
+        .await
+        .context("failed to send account lockout email")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::configuration::LoginThrottlingSettings;
+
+    fn settings() -> LoginThrottlingSettings {
+        LoginThrottlingSettings {
+            window_seconds: 900,
+            delay_after_failures: 3,
+            base_delay_milliseconds: 500,
+            max_delay_milliseconds: 4000,
+            lockout_after_failures: 10,
+            lockout_seconds: 900,
+            max_failures_per_ip: 100,
+        }
+    }
+
+    #[test]
+    fn the_first_failures_are_not_delayed() {
+        for failures in 0..3 {
+            assert_eq!(settings().delay(failures), Duration::ZERO);
+        }
+    }
+
+    #[test]
+    fn delays_double_up_to_the_maximum() {
+        let delays: Vec<_> = (3..8)
+            .map(|failures| settings().delay(failures).as_millis())
+            .collect();
+        assert_eq!(delays, [500, 1000, 2000, 4000, 4000]);
+        assert_eq!(settings().delay(u64::MAX), Duration::from_millis(4000));
+    }
+}
@@ -1,6 +1,10 @@
+use std::fmt::{Debug, Display};
+
+use tokio::task::JoinError;
 use zero2prod::configuration::get_configuration;
 use zero2prod::startup::Application;
 use zero2prod::telemetry::{get_subscriber, init_subscriber};
+use zero2prod::{confirmation_email_worker, pending_cleanup};
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
@@ -10,7 +14,46 @@ async fn main() -> anyhow::Result<()> {
 
     let configuration =
         get_configuration().expect("Failed to read configuration.");
-    let application = Application::build(configuration).await?;
-    application.run_until_stopped().await?;
+    let application = Application::build(configuration.clone()).await?;
+    let application_task = tokio::spawn(application.run_until_stopped());
+    let cleanup_task = tokio::spawn(pending_cleanup::run_worker_until_stopped(
+        configuration.clone(),
+    ));
+    let confirmation_task = tokio::spawn(
+        confirmation_email_worker::run_worker_until_stopped(configuration),
+    );
+
+    tokio::select! {
+        o = application_task => report_exit("API", o),
+        o = cleanup_task => report_exit("Pending subscribers cleanup", o),
+        o = confirmation_task => report_exit("Confirmation email worker", o),
+    };
     Ok(())
 }
+
+fn report_exit(
+    task_name: &str,
+    outcome: Result<Result<(), impl Debug + Display>, JoinError>,
+) {
+    match outcome {
+        Ok(Ok(())) => {
+            tracing::info!("{} has exited", task_name)
+        }
+        Ok(Err(e)) => {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "{} failed",
+                task_name
+            )
+        }
+        Err(e) => {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "{} task failed to complete",
+                task_name
+            )
+        }
+    }
+}
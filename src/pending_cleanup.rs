@@ -0,0 +1,160 @@
+use std::time::Duration;
+
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::configuration::{PendingCleanupSettings, Settings};
+use crate::domain::SubscriptionStatus;
+use crate::startup::get_connection_pool;
+
+/// Periodically delete subscribers who never clicked their confirmation
+/// link, so that their address can be used to sign up again.
+pub async fn run_worker_until_stopped(
+    configuration: Settings,
+) -> Result<(), anyhow::Error> {
+    let pool = get_connection_pool(&configuration.database);
+    let settings = configuration.subscriptions.pending_cleanup;
+    loop {
+        // A failed run is retried at the next interval.
+        if let Err(e) = delete_stale_pending_subscribers(&pool, &settings).await
+        {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "Failed to delete stale pending subscribers."
+            );
+        }
+        tokio::time::sleep(Duration::from_secs(settings.interval_seconds))
+            .await;
+    }
+}
+
+/// Delete, in batches, the subscribers still pending confirmation after
+/// `max_age_hours`, along with their tokens and consent records. Returns
+/// how many were deleted or, in dry-run mode, would have been.
+#[tracing::instrument(
+    name = "Delete stale pending subscribers",
+    skip_all,
+    fields(
+        dry_run = settings.dry_run,
+        max_age_hours = settings.max_age_hours,
+    )
+)]
+pub async fn delete_stale_pending_subscribers(
+    pool: &PgPool,
+    settings: &PendingCleanupSettings,
+) -> Result<u64, anyhow::Error> {
+    let cutoff = Utc::now() - chrono::Duration::hours(settings.max_age_hours);
+
+    if settings.dry_run {
+        let stale = count_stale_pending_subscribers(pool, cutoff).await?;
+        tracing::info!(
+            stale_subscribers = stale,
+            "Dry run: stale pending subscribers were left in place."
+        );
+        return Ok(stale);
+    }
+
+    let mut deleted = 0;
+    loop {
+        let batch = delete_batch(pool, cutoff, settings.batch_size).await?;
+        deleted += batch;
+        if batch > 0 {
+            tracing::info!(
+                batch_size = batch,
+                "Deleted a batch of stale pending subscribers."
+            );
+        }
+        if batch < settings.batch_size as u64 {
+            break;
+        }
+    }
+    tracing::info!(
+        deleted_subscribers = deleted,
+        "Finished deleting stale pending subscribers."
+    );
+    Ok(deleted)
+}
+
+async fn count_stale_pending_subscribers(
+    pool: &PgPool,
+    cutoff: DateTime<Utc>,
+) -> Result<u64, anyhow::Error> {
+    let count = sqlx::query!(
+        r#"
+        SELECT COUNT(*) AS "count!"
+        FROM subscriptions
+        WHERE status = $1 AND subscribed_at < $2
+        "#,
+        SubscriptionStatus::PendingConfirmation as SubscriptionStatus,
+        cutoff
+    )
+    .fetch_one(pool)
+    .await
+    .context("failed to count stale pending subscribers")?
+    .count;
+    Ok(count as u64)
+}
+
+async fn delete_batch(
+    pool: &PgPool,
+    cutoff: DateTime<Utc>,
+    batch_size: i64,
+) -> Result<u64, anyhow::Error> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("failed to acquire postgres connection from pool")?;
+    // Skip rows locked by a concurrent confirmation: they are no longer
+    // stale, or will be picked up by the next run.
+    let subscriber_ids: Vec<Uuid> = sqlx::query!(
+        r#"
+        SELECT id
+        FROM subscriptions
+        WHERE status = $1 AND subscribed_at < $2
+        ORDER BY subscribed_at
+        LIMIT $3
+        FOR UPDATE SKIP LOCKED
+        "#,
+        SubscriptionStatus::PendingConfirmation as SubscriptionStatus,
+        cutoff,
+        batch_size
+    )
+    .fetch_all(&mut transaction)
+    .await
+    .context("failed to retrieve stale pending subscribers")?
+    .into_iter()
+    .map(|r| r.id)
+    .collect();
+
+    sqlx::query!(
+        "DELETE FROM subscription_tokens WHERE subscriber_id = ANY($1)",
+        &subscriber_ids[..]
+    )
+    .execute(&mut transaction)
+    .await
+    .context("failed to delete subscription tokens")?;
+    sqlx::query!(
+        "DELETE FROM subscription_consents WHERE subscriber_id = ANY($1)",
+        &subscriber_ids[..]
+    )
+    .execute(&mut transaction)
+    .await
+    .context("failed to delete consent records")?;
+    // Events and status changes go with the subscriber, `ON DELETE CASCADE`.
+    sqlx::query!(
+        "DELETE FROM subscriptions WHERE id = ANY($1)",
+        &subscriber_ids[..]
+    )
+    .execute(&mut transaction)
+    .await
+    .context("failed to delete subscribers")?;
+    transaction
+        .commit()
+        .await
+        .context("failed to commit sql transaction to delete subscribers")?;
+
+    Ok(subscriber_ids.len() as u64)
+}
@@ -0,0 +1,152 @@
+use anyhow::Context;
+use redis::aio::ConnectionManager;
+use secrecy::{ExposeSecret, Secret};
+
+/// Fixed-window counters stored in Redis.
+///
+/// Every key is namespaced with `key_prefix` so that several deployments (or
+/// test runs) can share a single Redis instance.
+#[derive(Clone)]
+pub struct RateLimiter {
+    connection: ConnectionManager,
+    key_prefix: String,
+}
+
+impl RateLimiter {
+    pub async fn new(
+        redis_uri: &Secret<String>,
+        key_prefix: String,
+    ) -> Result<Self, anyhow::Error> {
+        let client = redis::Client::open(redis_uri.expose_secret().as_str())
+            .context("failed to parse redis uri")?;
+        let connection = ConnectionManager::new(client)
+            .await
+            .context("failed to connect to redis")?;
+        Ok(Self {
+            connection,
+            key_prefix,
+        })
+    }
+
+    /// Record an attempt against `key` and return the number of attempts
+    /// made in the current window, including this one.
+    ///
+    /// The window starts with the first attempt and lasts `window_seconds`.
+    #[tracing::instrument(name = "record rate limited attempt", skip(self))]
+    pub async fn hit(
+        &self,
+        key: &str,
+        window_seconds: u64,
+    ) -> Result<u64, anyhow::Error> {
+        let key = self.key(key);
+        let mut connection = self.connection.clone();
+        let (attempts,): (u64,) = redis::pipe()
+            .atomic()
+            .cmd("SET")
+            .arg(&key)
+            .arg(0)
+            .arg("NX")
+            .arg("EX")
+            .arg(window_seconds)
+            .ignore()
+            .incr(&key, 1)
+            .query_async(&mut connection)
+            .await
+            .context("failed to record attempt in redis")?;
+        Ok(attempts)
+    }
+
+    /// Take back an attempt recorded against `key`, e.g. once it turned out
+    /// to be legitimate. The window is left as it is.
+    #[tracing::instrument(name = "forgive rate limited attempt", skip(self))]
+    pub async fn forgive(
+        &self,
+        key: &str,
+        window_seconds: u64,
+    ) -> Result<(), anyhow::Error> {
+        let key = self.key(key);
+        let mut connection = self.connection.clone();
+        // Should the window have just expired, the next one starts one
+        // attempt short rather than never expiring.
+        redis::pipe()
+            .atomic()
+            .cmd("SET")
+            .arg(&key)
+            .arg(0)
+            .arg("NX")
+            .arg("EX")
+            .arg(window_seconds)
+            .ignore()
+            .decr(&key, 1)
+            .ignore()
+            .query_async::<_, ()>(&mut connection)
+            .await
+            .context("failed to forgive attempt in redis")?;
+        Ok(())
+    }
+
+    /// The number of attempts recorded against `key` in the current window.
+    #[tracing::instrument(name = "count rate limited attempts", skip(self))]
+    pub async fn attempts(&self, key: &str) -> Result<u64, anyhow::Error> {
+        let mut connection = self.connection.clone();
+        let attempts: Option<u64> = redis::cmd("GET")
+            .arg(self.key(key))
+            .query_async(&mut connection)
+            .await
+            .context("failed to read attempts from redis")?;
+        Ok(attempts.unwrap_or(0))
+    }
+
+    /// Forget the attempts recorded against `key`.
+    #[tracing::instrument(name = "reset rate limited attempts", skip(self))]
+    pub async fn reset(&self, key: &str) -> Result<(), anyhow::Error> {
+        let mut connection = self.connection.clone();
+        redis::cmd("DEL")
+            .arg(self.key(key))
+            .query_async::<_, ()>(&mut connection)
+            .await
+            .context("failed to reset attempts in redis")?;
+        Ok(())
+    }
+
+    /// Block `key` for `seconds`, whatever its attempts. Returns whether it
+    /// was not blocked already, in which case an existing block is left to
+    /// expire as it was.
+    #[tracing::instrument(name = "block rate limited key", skip(self))]
+    pub async fn block(
+        &self,
+        key: &str,
+        seconds: u64,
+    ) -> Result<bool, anyhow::Error> {
+        let mut connection = self.connection.clone();
+        let newly_blocked: Option<String> = redis::cmd("SET")
+            .arg(self.block_key(key))
+            .arg(1)
+            .arg("NX")
+            .arg("EX")
+            .arg(seconds)
+            .query_async(&mut connection)
+            .await
+            .context("failed to block key in redis")?;
+        Ok(newly_blocked.is_some())
+    }
+
+    #[tracing::instrument(name = "check blocked key", skip(self))]
+    pub async fn is_blocked(&self, key: &str) -> Result<bool, anyhow::Error> {
+        let mut connection = self.connection.clone();
+        let blocked: bool = redis::cmd("EXISTS")
+            .arg(self.block_key(key))
+            .query_async(&mut connection)
+            .await
+            .context("failed to check blocked key in redis")?;
+        Ok(blocked)
+    }
+
+    fn block_key(&self, key: &str) -> String {
+        format!("{}:blocked:{}", self.key_prefix, key)
+    }
+
+    fn key(&self, key: &str) -> String {
+        format!("{}:rate_limit:{}", self.key_prefix, key)
+    }
+}
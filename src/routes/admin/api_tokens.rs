@@ -0,0 +1,362 @@
+use std::fmt::Write;
+
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
+use anyhow::Context;
+use chrono::{DateTime, Duration, Utc};
+use secrecy::{ExposeSecret, Secret};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::audit::{record_audit_event, AuditAction};
+use crate::authentication::{
+    generate_api_token, hash_token, ApiScope, CsrfToken, Role, UserId,
+};
+use crate::client_info::ClientInfo;
+use crate::routes::{e500, seeother};
+
+const MAX_NAME_LENGTH: usize = 64;
+const MAX_VALIDITY_DAYS: i64 = 365;
+
+/// A new token as submitted by the form, with one `scope` field per ticked
+/// box.
+struct NewApiToken {
+    name: String,
+    scopes: Vec<ApiScope>,
+    expires_at: Option<DateTime<Utc>>,
+}
+
+impl NewApiToken {
+    fn parse(fields: &[(String, String)], role: Role) -> Result<Self, String> {
+        let mut name = "";
+        let mut expires_in_days = "";
+        let mut scopes = Vec::new();
+        for (key, value) in fields {
+            match key.as_str() {
+                "name" => name = value.trim(),
+                "expires_in_days" => expires_in_days = value.trim(),
+                "scope" => {
+                    let scope = ApiScope::parse(value)?;
+                    if !role.can(scope.permission()) {
+                        return Err(format!(
+                            "You cannot grant the {} scope.",
+                            scope
+                        ));
+                    }
+                    if !scopes.contains(&scope) {
+                        scopes.push(scope);
+                    }
+                }
+                _ => {}
+            }
+        }
+        if name.is_empty() {
+            return Err("The token needs a name.".into());
+        }
+        if name.chars().count() > MAX_NAME_LENGTH {
+            return Err(format!(
+                "The name must be at most {} characters long.",
+                MAX_NAME_LENGTH
+            ));
+        }
+        if scopes.is_empty() {
+            return Err("The token needs at least one scope.".into());
+        }
+        let expires_at = if expires_in_days.is_empty() {
+            None
+        } else {
+            match expires_in_days.parse::<i64>() {
+                Ok(days) if (1..=MAX_VALIDITY_DAYS).contains(&days) => {
+                    Some(Utc::now() + Duration::days(days))
+                }
+                _ => {
+                    return Err(format!(
+                        "The token must expire in 1 to {} days, or never.",
+                        MAX_VALIDITY_DAYS
+                    ))
+                }
+            }
+        };
+        Ok(Self {
+            name: name.to_string(),
+            scopes,
+            expires_at,
+        })
+    }
+}
+
+struct ApiTokenRow {
+    id: Uuid,
+    name: String,
+    scopes: Vec<String>,
+    created_at: DateTime<Utc>,
+    expires_at: Option<DateTime<Utc>>,
+    last_used_at: Option<DateTime<Utc>>,
+    revoked_at: Option<DateTime<Utc>>,
+}
+
+impl ApiTokenRow {
+    fn status(&self) -> &'static str {
+        if self.revoked_at.is_some() {
+            "revoked"
+        } else if self.expires_at.is_some_and(|e| e <= Utc::now()) {
+            "expired"
+        } else {
+            "active"
+        }
+    }
+}
+
+/// The API tokens of the logged-in user.
+pub async fn list_api_tokens(
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+    role: web::ReqData<Role>,
+    flash_messages: IncomingFlashMessages,
+    csrf_token: CsrfToken,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(
+            msg_html,
+            "<p><i>{}</i></p>",
+            htmlescape::encode_minimal(m.content())
+        )
+        .unwrap();
+    }
+    let csrf_field = csrf_token.form_field();
+
+    let format_time = |t: Option<DateTime<Utc>>, none: &str| {
+        t.map(|t| t.format("%Y-%m-%d %H:%M UTC").to_string())
+            .unwrap_or_else(|| none.to_string())
+    };
+    let mut rows_html = String::new();
+    for token in get_api_tokens(&pool, **user_id).await.map_err(e500)? {
+        let revoke_html = if token.status() == "active" {
+            format!(
+                r#"<form action="/admin/api_tokens/{}/revoke" method="post">{}<button type="submit">Revoke</button></form>"#,
+                token.id, csrf_field
+            )
+        } else {
+            String::new()
+        };
+        writeln!(
+            rows_html,
+            "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+            htmlescape::encode_minimal(&token.name),
+            token.scopes.join(", "),
+            format_time(Some(token.created_at), ""),
+            format_time(token.expires_at, "never"),
+            format_time(token.last_used_at, "never"),
+            token.status(),
+            revoke_html,
+        )
+        .unwrap();
+    }
+
+    // Only offer the scopes the user could use themselves.
+    let mut scopes_html = String::new();
+    for scope in ApiScope::ALL {
+        if role.can(scope.permission()) {
+            writeln!(
+                scopes_html,
+                r#"<label><input type="checkbox" name="scope" value="{scope}"> {scope}</label>"#
+            )
+            .unwrap();
+        }
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>API tokens</title>
+</head>
+<body>
+    {msg_html}
+    <table>
+        <tr><th>Name</th><th>Scopes</th><th>Created</th><th>Expires</th><th>Last used</th><th>Status</th><th></th></tr>
+        {rows_html}
+    </table>
+    <h2>New token</h2>
+    <form name="createApiToken" action="/admin/api_tokens" method="post">
+        {csrf_field}
+        <label>Name <input type="text" name="name" maxlength="{MAX_NAME_LENGTH}"></label>
+        {scopes_html}
+        <label>Expires in <input type="number" name="expires_in_days" min="1" max="{MAX_VALIDITY_DAYS}"> days (leave blank for never)</label>
+        <button type="submit">Create token</button>
+    </form>
+    <p><a href="/admin/dashboard">&lt;- Back</a></p>
+</body>
+</html>
+"#,
+        )))
+}
+
+/// Create a token and show it, for the only time.
+#[tracing::instrument(
+    name = "Create an API token",
+    skip(form, pool, role),
+    fields(user_id = %*user_id)
+)]
+pub async fn create_api_token(
+    form: web::Form<Vec<(String, String)>>,
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+    role: web::ReqData<Role>,
+    client_info: ClientInfo,
+) -> Result<HttpResponse, actix_web::Error> {
+    let new_token = match NewApiToken::parse(&form, *role) {
+        Ok(new_token) => new_token,
+        Err(e) => {
+            FlashMessage::error(e).send();
+            return Ok(seeother("/admin/api_tokens"));
+        }
+    };
+    let token = generate_api_token();
+    let token_id = store_api_token(&pool, **user_id, &new_token, &token)
+        .await
+        .map_err(e500)?;
+    record_audit_event(
+        pool.get_ref(),
+        **user_id,
+        &client_info,
+        AuditAction::ApiTokenCreated,
+        serde_json::json!({
+            "api_token_id": token_id,
+            "name": new_token.name,
+            "scopes": new_token
+                .scopes
+                .iter()
+                .map(ApiScope::as_str)
+                .collect::<Vec<_>>(),
+        }),
+    )
+    .await
+    .map_err(e500)?;
+
+    Ok(new_token_page(&new_token.name, &token))
+}
+
+/// Revoke one of the logged-in user's tokens. It cannot be used again.
+#[tracing::instrument(name = "Revoke an API token", skip(pool), fields(user_id = %*user_id))]
+pub async fn revoke_api_token(
+    token_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+    client_info: ClientInfo,
+) -> Result<HttpResponse, actix_web::Error> {
+    let revoked = sqlx::query!(
+        r#"
+        UPDATE api_tokens
+        SET revoked_at = now()
+        WHERE id = $1 AND user_id = $2 AND revoked_at IS NULL
+        RETURNING name
+        "#,
+        *token_id,
+        **user_id,
+    )
+    .fetch_optional(pool.get_ref())
+    .await
+    .context("failed to revoke API token")
+    .map_err(e500)?;
+    let Some(revoked) = revoked else {
+        return Err(actix_web::error::ErrorNotFound("No such API token."));
+    };
+    record_audit_event(
+        pool.get_ref(),
+        **user_id,
+        &client_info,
+        AuditAction::ApiTokenRevoked,
+        serde_json::json!({ "api_token_id": *token_id, "name": revoked.name }),
+    )
+    .await
+    .map_err(e500)?;
+
+    FlashMessage::info(format!(
+        "The {} token has been revoked.",
+        htmlescape::encode_minimal(&revoked.name)
+    ))
+    .send();
+    Ok(seeother("/admin/api_tokens"))
+}
+
+async fn get_api_tokens(
+    pool: &PgPool,
+    user_id: Uuid,
+) -> Result<Vec<ApiTokenRow>, anyhow::Error> {
+    let tokens = sqlx::query_as!(
+        ApiTokenRow,
+        r#"
+        SELECT id, name, scopes, created_at, expires_at, last_used_at, revoked_at
+        FROM api_tokens
+        WHERE user_id = $1
+        ORDER BY created_at DESC
+        "#,
+        user_id
+    )
+    .fetch_all(pool)
+    .await
+    .context("failed to retrieve API tokens")?;
+    Ok(tokens)
+}
+
+#[tracing::instrument(name = "Store API token", skip(pool, new_token, token))]
+async fn store_api_token(
+    pool: &PgPool,
+    user_id: Uuid,
+    new_token: &NewApiToken,
+    token: &Secret<String>,
+) -> Result<Uuid, anyhow::Error> {
+    let token_id = Uuid::new_v4();
+    let scopes: Vec<String> = new_token
+        .scopes
+        .iter()
+        .map(|s| s.as_str().to_string())
+        .collect();
+    sqlx::query!(
+        r#"
+        INSERT INTO api_tokens (
+            id, user_id, name, token_hash, scopes, created_at, expires_at
+        )
+        VALUES ($1, $2, $3, $4, $5, now(), $6)
+        "#,
+        token_id,
+        user_id,
+        new_token.name,
+        hash_token(token.expose_secret()),
+        &scopes,
+        new_token.expires_at,
+    )
+    .execute(pool)
+    .await
+    .context("failed to store API token")?;
+    Ok(token_id)
+}
+
+fn new_token_page(name: &str, token: &Secret<String>) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>New API token</title>
+</head>
+<body>
+    <p>The {name} token has been created.</p>
+    <p>Copy it now, it will not be shown again. Send it as <code>Authorization: Bearer &lt;token&gt;</code>.</p>
+    <p><code>{token}</code></p>
+    <p><a href="/admin/api_tokens">Continue</a></p>
+</body>
+</html>
+"#,
+            name = htmlescape::encode_minimal(name),
+            token = token.expose_secret(),
+        ))
+}
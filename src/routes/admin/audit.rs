@@ -0,0 +1,387 @@
+use std::fmt::Write;
+
+use actix_web::http::header::{ContentDisposition, ContentType};
+use actix_web::web::Bytes;
+use actix_web::{web, HttpResponse};
+use anyhow::Context;
+use chrono::{DateTime, NaiveDate, Utc};
+use futures_util::{Stream, TryStreamExt};
+use sqlx::PgPool;
+use tokio::sync::mpsc;
+use tracing::Instrument;
+use uuid::Uuid;
+
+use crate::audit::AuditAction;
+use crate::routes::{e500, neutralize_formula};
+
+pub const PAGE_SIZE: i64 = 50;
+/// Beyond this, the offset of the page would overflow.
+const MAX_PAGE: i64 = i64::MAX / PAGE_SIZE;
+/// Exported events are sent to the client in chunks of this many rows.
+const ROWS_PER_CHUNK: usize = 500;
+/// Chunks waiting to be written to the client, beyond which we stop reading
+/// from Postgres.
+const BUFFERED_CHUNKS: usize = 4;
+
+/// Query string of the audit log and its export. Blank values are ignored.
+#[derive(serde::Deserialize)]
+pub struct QueryParameters {
+    /// Username of the user who performed the action.
+    #[serde(default)]
+    user: String,
+    #[serde(default)]
+    action: String,
+    #[serde(default)]
+    from: String,
+    #[serde(default)]
+    to: String,
+    page: Option<i64>,
+}
+
+struct Filters {
+    username: Option<String>,
+    action: Option<AuditAction>,
+    from: Option<NaiveDate>,
+    to: Option<NaiveDate>,
+    page: i64,
+}
+
+impl TryFrom<QueryParameters> for Filters {
+    type Error = String;
+
+    fn try_from(value: QueryParameters) -> Result<Self, Self::Error> {
+        let non_empty = |s: String| {
+            let s = s.trim().to_string();
+            (!s.is_empty()).then_some(s)
+        };
+        let parse_date = |s: String| {
+            non_empty(s)
+                .map(|s| {
+                    NaiveDate::parse_from_str(&s, "%Y-%m-%d").map_err(|_| {
+                        format!("{} is not a valid date (YYYY-MM-DD).", s)
+                    })
+                })
+                .transpose()
+        };
+        Ok(Self {
+            username: non_empty(value.user),
+            action: non_empty(value.action)
+                .map(|s| AuditAction::parse(&s))
+                .transpose()?,
+            from: parse_date(value.from)?,
+            to: parse_date(value.to)?,
+            page: value.page.unwrap_or(1).clamp(1, MAX_PAGE),
+        })
+    }
+}
+
+impl Filters {
+    /// Query string with the same filters, without the page.
+    fn query(&self) -> String {
+        format!(
+            "user={}&action={}&from={}&to={}",
+            urlencoding::encode(self.username.as_deref().unwrap_or_default()),
+            self.action.map(|a| a.as_str()).unwrap_or_default(),
+            date(self.from),
+            date(self.to),
+        )
+    }
+}
+
+fn date(d: Option<NaiveDate>) -> String {
+    d.map(|d| d.format("%Y-%m-%d").to_string())
+        .unwrap_or_default()
+}
+
+struct AuditEvent {
+    occurred_at: DateTime<Utc>,
+    user_id: Uuid,
+    username: String,
+    ip_address: Option<String>,
+    action: String,
+    payload: serde_json::Value,
+}
+
+/// Who did what in the admin area, most recent first.
+pub async fn audit_log(
+    query: web::Query<QueryParameters>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let filters = Filters::try_from(query.0)
+        .map_err(actix_web::error::ErrorBadRequest)?;
+    let events = search_audit_events(
+        &pool,
+        &filters,
+        Some(PAGE_SIZE),
+        (filters.page - 1) * PAGE_SIZE,
+    )
+    .await
+    .map_err(e500)?;
+    let total = count_audit_events(&pool, &filters).await.map_err(e500)?;
+    let page_count = ((total + PAGE_SIZE - 1) / PAGE_SIZE).max(1);
+
+    let mut rows_html = String::new();
+    for e in &events {
+        writeln!(
+            rows_html,
+            "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td><code>{}</code></td></tr>",
+            e.occurred_at.to_rfc3339(),
+            htmlescape::encode_minimal(&e.username),
+            htmlescape::encode_minimal(e.ip_address.as_deref().unwrap_or_default()),
+            htmlescape::encode_minimal(&e.action),
+            htmlescape::encode_minimal(&e.payload.to_string()),
+        )
+        .unwrap();
+    }
+
+    let mut action_options = String::from(r#"<option value="">any</option>"#);
+    for action in AuditAction::ALL {
+        let selected = if filters.action == Some(action) {
+            " selected"
+        } else {
+            ""
+        };
+        write!(
+            action_options,
+            r#"<option value="{action}"{selected}>{action}</option>"#
+        )
+        .unwrap();
+    }
+
+    let mut pagination_html = String::new();
+    if filters.page > 1 {
+        write!(
+            pagination_html,
+            r#"<a href="/admin/audit?{}&page={}">&lt; Previous</a> "#,
+            filters.query(),
+            filters.page - 1
+        )
+        .unwrap();
+    }
+    write!(pagination_html, "Page {} of {}", filters.page, page_count).unwrap();
+    if filters.page < page_count {
+        write!(
+            pagination_html,
+            r#" <a href="/admin/audit?{}&page={}">Next &gt;</a>"#,
+            filters.query(),
+            filters.page + 1
+        )
+        .unwrap();
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Audit log</title>
+</head>
+<body>
+    <form name="searchAuditLog" action="/admin/audit" method="get">
+        <label>Username
+            <input type="text" name="user" value="{username}">
+        </label>
+        <label>Action
+            <select name="action">{action_options}</select>
+        </label>
+        <label>From
+            <input type="date" name="from" value="{from}">
+        </label>
+        <label>to
+            <input type="date" name="to" value="{to}">
+        </label>
+        <button type="submit">Search</button>
+    </form>
+    <p>{total} events</p>
+    <table>
+        <tr><th>Time</th><th>User</th><th>IP address</th><th>Action</th><th>Details</th></tr>
+        {rows_html}
+    </table>
+    <p>{pagination_html}</p>
+    <p><a href="/admin/audit/export.csv?{query}">Export as CSV</a></p>
+    <p><a href="/admin/dashboard">&lt;- Back</a></p>
+</body>
+</html>
+"#,
+            username = htmlescape::encode_attribute(
+                filters.username.as_deref().unwrap_or_default()
+            ),
+            from = date(filters.from),
+            to = date(filters.to),
+            query = filters.query(),
+        )))
+}
+
+/// Every event matching the filters of the audit log, as CSV.
+///
+/// Events are streamed from Postgres to the client through a bounded
+/// channel, so that memory usage does not depend on the size of the log.
+#[tracing::instrument(name = "Export audit log", skip(query, pool))]
+pub async fn export_audit_log(
+    query: web::Query<QueryParameters>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let filters = Filters::try_from(query.0)
+        .map_err(actix_web::error::ErrorBadRequest)?;
+
+    let (sender, receiver) = mpsc::channel(BUFFERED_CHUNKS);
+    let pool = pool.into_inner();
+    tokio::spawn(
+        async move {
+            if let Err(e) =
+                stream_audit_events(&pool, &filters, sender.clone()).await
+            {
+                tracing::error!(
+                    error.cause_chain = ?e,
+                    "Failed to export the audit log."
+                );
+                // Abort the response rather than leave a truncated file
+                // looking complete.
+                let _ = sender.send(Err(e)).await;
+            }
+        }
+        .instrument(tracing::Span::current()),
+    );
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/csv; charset=utf-8")
+        .insert_header(ContentDisposition::attachment("audit_log.csv"))
+        .streaming(futures_util::stream::unfold(
+            receiver,
+            |mut receiver| async move {
+                receiver.recv().await.map(|chunk| (chunk, receiver))
+            },
+        )))
+}
+
+/// Serialize matching events into CSV chunks and hand them to `sender`.
+/// Stops early, without error, if the client went away.
+async fn stream_audit_events(
+    pool: &PgPool,
+    filters: &Filters,
+    sender: mpsc::Sender<Result<Bytes, anyhow::Error>>,
+) -> Result<(), anyhow::Error> {
+    let mut events = fetch_audit_events(pool, filters, None, 0);
+
+    let mut chunk =
+        b"occurred_at,user_id,username,ip_address,action,payload\n".to_vec();
+    let mut rows_in_chunk = 0;
+    while let Some(e) = events
+        .try_next()
+        .await
+        .context("failed to fetch audit events")?
+    {
+        {
+            let mut writer = csv::WriterBuilder::new()
+                .has_headers(false)
+                .from_writer(&mut chunk);
+            writer.write_record([
+                e.occurred_at.to_rfc3339().as_str(),
+                &e.user_id.to_string(),
+                &neutralize_formula(&e.username),
+                e.ip_address.as_deref().unwrap_or_default(),
+                &e.action,
+                &neutralize_formula(&e.payload.to_string()),
+            ])?;
+            writer.flush()?;
+        }
+        rows_in_chunk += 1;
+        if rows_in_chunk == ROWS_PER_CHUNK {
+            if sender
+                .send(Ok(std::mem::take(&mut chunk).into()))
+                .await
+                .is_err()
+            {
+                return Ok(());
+            }
+            rows_in_chunk = 0;
+        }
+    }
+    if !chunk.is_empty() {
+        let _ = sender.send(Ok(chunk.into())).await;
+    }
+    Ok(())
+}
+
+/// Events matching `filters`, most recent first. `limit` is `None` for all
+/// of them.
+#[tracing::instrument(name = "Search audit events", skip(pool, filters))]
+async fn search_audit_events(
+    pool: &PgPool,
+    filters: &Filters,
+    limit: Option<i64>,
+    offset: i64,
+) -> Result<Vec<AuditEvent>, anyhow::Error> {
+    fetch_audit_events(pool, filters, limit, offset)
+        .try_collect()
+        .await
+        .context("failed to search audit events")
+}
+
+fn fetch_audit_events<'a>(
+    pool: &'a PgPool,
+    filters: &'a Filters,
+    limit: Option<i64>,
+    offset: i64,
+) -> impl Stream<Item = Result<AuditEvent, sqlx::Error>> + 'a {
+    sqlx::query_as!(
+        AuditEvent,
+        r#"
+        SELECT
+            a.occurred_at,
+            a.user_id,
+            u.username,
+            a.ip_address,
+            a.action,
+            a.payload
+        FROM audit_events a
+        JOIN users u ON u.user_id = a.user_id
+        WHERE ($1::text IS NULL OR u.username = $1)
+            AND ($2::text IS NULL OR a.action = $2)
+            AND ($3::date IS NULL
+                OR a.occurred_at >= $3::date::timestamp AT TIME ZONE 'UTC')
+            AND ($4::date IS NULL
+                OR a.occurred_at < ($4::date + 1)::timestamp AT TIME ZONE 'UTC')
+        ORDER BY a.occurred_at DESC, a.id DESC
+        LIMIT $5 OFFSET $6
+        "#,
+        filters.username,
+        filters.action.map(|a| a.as_str()),
+        filters.from,
+        filters.to,
+        limit,
+        offset,
+    )
+    .fetch(pool)
+}
+
+#[tracing::instrument(name = "Count audit events", skip(pool, filters))]
+async fn count_audit_events(
+    pool: &PgPool,
+    filters: &Filters,
+) -> Result<i64, anyhow::Error> {
+    let total = sqlx::query!(
+        r#"
+        SELECT COUNT(*) AS "count!"
+        FROM audit_events a
+        JOIN users u ON u.user_id = a.user_id
+        WHERE ($1::text IS NULL OR u.username = $1)
+            AND ($2::text IS NULL OR a.action = $2)
+            AND ($3::date IS NULL
+                OR a.occurred_at >= $3::date::timestamp AT TIME ZONE 'UTC')
+            AND ($4::date IS NULL
+                OR a.occurred_at < ($4::date + 1)::timestamp AT TIME ZONE 'UTC')
+        "#,
+        filters.username,
+        filters.action.map(|a| a.as_str()),
+        filters.from,
+        filters.to,
+    )
+    .fetch_one(pool)
+    .await
+    .context("failed to count audit events")?
+    .count;
+    Ok(total)
+}
@@ -1,16 +1,69 @@
+use std::fmt::Write;
+
 use actix_web::http::header::ContentType;
 use actix_web::{web, HttpResponse};
 use anyhow::Context;
+use chrono::{Duration, NaiveDate, Utc};
 use reqwest::header::LOCATION;
 use sqlx::PgPool;
 use uuid::Uuid;
 
+use crate::authentication::{CsrfToken, Permission, Role};
 use crate::routes::http_utils;
 use crate::session::TypedSession;
 
+/// Days covered by the sign-up breakdown when no range is selected.
+const DEFAULT_RANGE_DAYS: i64 = 30;
+
+/// Time range of the sign-up breakdown, both ends included. Blank values
+/// fall back to the last 30 days.
+#[derive(serde::Deserialize)]
+pub struct QueryParameters {
+    #[serde(default)]
+    from: String,
+    #[serde(default)]
+    to: String,
+}
+
+impl QueryParameters {
+    fn range(&self) -> Result<(NaiveDate, NaiveDate), String> {
+        let parse_date = |s: &str| {
+            let s = s.trim();
+            (!s.is_empty())
+                .then(|| {
+                    NaiveDate::parse_from_str(s, "%Y-%m-%d").map_err(|_| {
+                        format!("{} is not a valid date (YYYY-MM-DD).", s)
+                    })
+                })
+                .transpose()
+        };
+        let to =
+            parse_date(&self.to)?.unwrap_or_else(|| Utc::now().date_naive());
+        let from = parse_date(&self.from)?
+            .unwrap_or(to - Duration::days(DEFAULT_RANGE_DAYS - 1));
+        if from > to {
+            return Err(
+                "The start of the range must not be after its end.".into()
+            );
+        }
+        Ok((from, to))
+    }
+}
+
+struct SourceCount {
+    source: String,
+    utm_source: String,
+    utm_medium: String,
+    utm_campaign: String,
+    count: i64,
+}
+
 pub async fn admin_dashboard(
     session: TypedSession,
     pool: web::Data<PgPool>,
+    query: web::Query<QueryParameters>,
+    role: web::ReqData<Role>,
+    csrf_token: CsrfToken,
 ) -> Result<HttpResponse, actix_web::Error> {
     let username = if let Some(user_id) =
         session.get_user_id().map_err(http_utils::e500)?
@@ -23,6 +76,65 @@ pub async fn admin_dashboard(
             .insert_header((LOCATION, "/login"))
             .finish());
     };
+    let (from, to) =
+        query.range().map_err(actix_web::error::ErrorBadRequest)?;
+    let counts = confirmed_sign_ups_by_source(&pool, from, to)
+        .await
+        .map_err(http_utils::e500)?;
+
+    let mut rows_html = String::new();
+    for c in &counts {
+        writeln!(
+            rows_html,
+            "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+            htmlescape::encode_minimal(&c.source),
+            htmlescape::encode_minimal(&c.utm_source),
+            htmlescape::encode_minimal(&c.utm_medium),
+            htmlescape::encode_minimal(&c.utm_campaign),
+            c.count,
+        )
+        .unwrap();
+    }
+    let total: i64 = counts.iter().map(|c| c.count).sum();
+
+    // Only list what the user is allowed to do.
+    let mut actions_html = String::new();
+    for (permission, path, label) in [
+        (
+            Permission::PublishNewsletters,
+            "/admin/newsletter",
+            "Submit a newsletter",
+        ),
+        (
+            Permission::PublishNewsletters,
+            "/admin/api_tokens",
+            "API tokens",
+        ),
+        (
+            Permission::ViewSubscribers,
+            "/admin/subscribers",
+            "Manage subscribers",
+        ),
+        (
+            Permission::ManageSubscribers,
+            "/admin/subscribers/import",
+            "Import subscribers",
+        ),
+        (
+            Permission::ViewSubscribers,
+            "/admin/referrals",
+            "Referral leaderboard",
+        ),
+        (Permission::ManageUsers, "/admin/users", "Manage users"),
+        (Permission::ViewAuditLog, "/admin/audit", "Audit log"),
+    ] {
+        if role.can(permission) {
+            writeln!(actions_html, r#"<li><a href="{path}">{label}</a></li>"#)
+                .unwrap();
+        }
+    }
+
+    let csrf_field = csrf_token.form_field();
 
     Ok(HttpResponse::Ok()
         .content_type(ContentType::html())
@@ -37,17 +149,33 @@ pub async fn admin_dashboard(
     <p>Welcome {username}!</p>
     <p>Available actions:</p>
     <ol>
-        <li><a href="/admin/newsletter">Submit a newsletter</a></li>
+        {actions_html}
         <li><a href="/admin/password">Change password</a></li>
+        <li><a href="/admin/two_factor">Two-factor authentication</a></li>
+        <li><a href="/admin/sessions">Active sessions</a></li>
         <li>
             <form name="logoutForm" action="/admin/logout" method="post">
+                {csrf_field}
                 <input type="submit" value="logout">
             </form>
         </li>
     </ol>
+    <h2>Confirmed sign-ups by source</h2>
+    <form name="signUpSources" action="/admin/dashboard" method="get">
+        <label>From <input type="date" name="from" value="{from}"></label>
+        <label>to <input type="date" name="to" value="{to}"></label>
+        <button type="submit">Show</button>
+    </form>
+    <p>{total} confirmed sign-ups from {from} to {to}</p>
+    <table>
+        <tr><th>Source</th><th>UTM source</th><th>UTM medium</th><th>UTM campaign</th><th>Confirmed</th></tr>
+        {rows_html}
+    </table>
 </body>
 </html>
-        "#
+        "#,
+            from = from.format("%Y-%m-%d"),
+            to = to.format("%Y-%m-%d"),
         )))
 }
 
@@ -69,3 +197,40 @@ pub async fn get_username(
 
     Ok(row.username)
 }
+
+/// Subscribers who confirmed their subscription between `from` and `to`,
+/// grouped by where they signed up. Imported subscribers are left out, as
+/// they did not sign up through a form.
+#[tracing::instrument(name = "Count confirmed sign-ups by source", skip(pool))]
+async fn confirmed_sign_ups_by_source(
+    pool: &PgPool,
+    from: NaiveDate,
+    to: NaiveDate,
+) -> Result<Vec<SourceCount>, anyhow::Error> {
+    let counts = sqlx::query_as!(
+        SourceCount,
+        r#"
+        SELECT
+            COALESCE(s.source, '(none)') AS "source!",
+            COALESCE(s.utm_source, '') AS "utm_source!",
+            COALESCE(s.utm_medium, '') AS "utm_medium!",
+            COALESCE(s.utm_campaign, '') AS "utm_campaign!",
+            COUNT(DISTINCT s.id) AS "count!"
+        FROM subscriptions s
+        JOIN subscription_status_changes c ON c.subscriber_id = s.id
+        WHERE c.to_status = 'confirmed'
+            AND c.cause <> 'import'
+            AND c.occurred_at >= $1::date::timestamp AT TIME ZONE 'UTC'
+            AND c.occurred_at < ($2::date + 1)::timestamp AT TIME ZONE 'UTC'
+        GROUP BY 1, 2, 3, 4
+        ORDER BY 5 DESC, 1, 2, 3, 4
+        "#,
+        from,
+        to,
+    )
+    .fetch_all(pool)
+    .await
+    .context("failed to count confirmed sign-ups by source")?;
+
+    Ok(counts)
+}
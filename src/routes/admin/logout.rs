@@ -1,13 +1,39 @@
-use actix_web::HttpResponse;
+use actix_web::{web, HttpResponse};
 use actix_web_flash_messages::FlashMessage;
+use sqlx::PgPool;
 
+use crate::audit::{record_audit_event, AuditAction};
+use crate::authentication::UserId;
+use crate::client_info::ClientInfo;
 use crate::routes::http_utils;
 use crate::session::TypedSession;
+use crate::session_index::SessionIndex;
 
 pub async fn logout(
     session: TypedSession,
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+    session_index: web::Data<SessionIndex>,
+    client_info: ClientInfo,
 ) -> Result<HttpResponse, actix_web::Error> {
+    if let Some(session_id) =
+        session.get_session_id().map_err(http_utils::e500)?
+    {
+        session_index
+            .revoke(**user_id, session_id)
+            .await
+            .map_err(http_utils::e500)?;
+    }
     session.logout();
+    record_audit_event(
+        pool.get_ref(),
+        **user_id,
+        &client_info,
+        AuditAction::Logout,
+        serde_json::json!({}),
+    )
+    .await
+    .map_err(http_utils::e500)?;
     FlashMessage::info("You have successfully logged out.").send();
     Ok(http_utils::seeother("/login"))
 }
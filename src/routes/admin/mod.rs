@@ -1,9 +1,26 @@
-mod dashboard;
+mod api_tokens;
+pub(crate) mod audit;
+pub(crate) mod dashboard;
 mod logout;
 mod newsletter;
 mod password;
+mod referrals;
+mod sessions;
+mod subscribers;
+pub(crate) mod two_factor;
+mod users;
 
+pub use api_tokens::{create_api_token, list_api_tokens, revoke_api_token};
+pub use audit::{audit_log, export_audit_log};
 pub use dashboard::admin_dashboard;
 pub use logout::logout;
 pub use newsletter::publish_newsletter_form;
 pub use password::*;
+pub use referrals::referral_leaderboard;
+pub use sessions::{list_sessions, revoke_other_sessions, revoke_session};
+pub use subscribers::*;
+pub use two_factor::{
+    disable_two_factor_authentication, enable_two_factor_authentication,
+    two_factor_settings,
+};
+pub use users::*;
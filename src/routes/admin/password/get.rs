@@ -4,13 +4,17 @@ use actix_web::http::header::ContentType;
 use actix_web::HttpResponse;
 use actix_web_flash_messages::IncomingFlashMessages;
 
+use crate::authentication::CsrfToken;
+
 pub async fn change_password_form(
     flash_messages: IncomingFlashMessages,
+    csrf_token: CsrfToken,
 ) -> Result<HttpResponse, actix_web::Error> {
     let mut msg_html = String::new();
     for m in flash_messages.iter() {
         writeln!(msg_html, "<p><i>{}</i></p>", m.content()).unwrap();
     }
+    let csrf_field = csrf_token.form_field();
 
     Ok(HttpResponse::Ok()
         .content_type(ContentType::html())
@@ -24,6 +28,7 @@ pub async fn change_password_form(
 <body>
     {msg_html}
     <form action="/admin/password" method="post">
+        {csrf_field}
         <label>Current password
             <input
                 type="password"
@@ -1,13 +1,21 @@
 use actix_web::{web, HttpResponse};
 use actix_web_flash_messages::FlashMessage;
-use secrecy::{ExposeSecret, Secret};
+use secrecy::Secret;
 use sqlx::PgPool;
 
+use crate::audit::{record_audit_event, AuditAction};
 use crate::authentication::{
-    self, validate_credentials, AuthError, Credentials, UserId,
+    self, validate_credentials, AuthError, Credentials, PasswordHashing,
+    PasswordPolicy, PasswordPolicyError, UserId,
 };
+use crate::client_info::ClientInfo;
+use crate::email_client::EmailClient;
+use crate::login_guard::{LoginGuard, LoginRejection};
 use crate::routes::admin::dashboard::get_username;
+use crate::routes::admin::two_factor::end_locked_session;
 use crate::routes::{e500, http_utils, seeother};
+use crate::session::TypedSession;
+use crate::session_index::SessionIndex;
 
 #[derive(serde::Deserialize)]
 pub struct FormData {
@@ -16,52 +24,112 @@ pub struct FormData {
     new_password_validate: Secret<String>,
 }
 
-pub const MIN_PASSWORD_LENGTH: usize = 12;
-pub const MAX_PASSWORD_LENGTH: usize = 128;
-
+/// The current password is guessed against the same throttling as at login,
+/// so that a hijacked session cannot be used to find it out.
+#[allow(clippy::too_many_arguments)]
 pub async fn change_password(
     form: web::Form<FormData>,
     pool: web::Data<PgPool>,
+    hashing: web::Data<PasswordHashing>,
+    policy: web::Data<PasswordPolicy>,
+    session: TypedSession,
+    session_index: web::Data<SessionIndex>,
+    login_guard: web::Data<LoginGuard>,
+    email_client: web::Data<EmailClient>,
     user_id: web::ReqData<UserId>,
+    client_info: ClientInfo,
 ) -> Result<HttpResponse, actix_web::Error> {
-    if form.new_password.expose_secret()
-        != form.new_password_validate.expose_secret()
+    let username = get_username(**user_id, &pool).await.map_err(e500)?;
+    match policy
+        .check(
+            &form.new_password,
+            &form.new_password_validate,
+            &[&username],
+        )
+        .await
     {
-        FlashMessage::error("Password fields must match.").send();
-        return Ok(seeother("/admin/password"));
+        Ok(()) => {}
+        Err(PasswordPolicyError::Rejected(e)) => {
+            FlashMessage::error(e).send();
+            return Ok(seeother("/admin/password"));
+        }
+        Err(PasswordPolicyError::UnexpectedError(e)) => return Err(e500(e)),
+    }
+    match login_guard
+        .screen(&username, &client_info)
+        .await
+        .map_err(e500)?
+    {
+        None => {}
+        Some(LoginRejection::AccountLocked) => {
+            return end_locked_session(&session, &session_index, **user_id)
+                .await;
+        }
+        Some(LoginRejection::TooManyFailuresFromIp(_)) => {
+            FlashMessage::error(
+                "Too many failed login attempts, please try again later.",
+            )
+            .send();
+            return Ok(seeother("/admin/password"));
+        }
     }
-    let password_len = form.new_password.expose_secret().len();
-    if !(MIN_PASSWORD_LENGTH..=MAX_PASSWORD_LENGTH).contains(&password_len) {
-        FlashMessage::error(format!(
-            "New password must be between {} and {} characters.",
-            MIN_PASSWORD_LENGTH, MAX_PASSWORD_LENGTH,
-        ))
-        .send();
-        return Ok(seeother("/admin/password"));
-    };
-
-    let username = get_username(**user_id, &pool).await.map_err(e500)?;
     if let Err(e) = validate_credentials(
         Credentials {
-            username,
+            username: username.clone(),
             password: form.0.current_password,
         },
+        &hashing,
         &pool,
     )
     .await
     {
         return match e {
             AuthError::InvalidCredentials(_) => {
+                if login_guard
+                    .record_failure(&username, &pool, &email_client)
+                    .await
+                    .map_err(e500)?
+                {
+                    return end_locked_session(
+                        &session,
+                        &session_index,
+                        **user_id,
+                    )
+                    .await;
+                }
                 FlashMessage::error("Current password is incorrect.").send();
                 Ok(seeother("/admin/password"))
             }
             AuthError::UnexpectedError(_) => Err(e500(e)),
         };
     }
+    login_guard
+        .record_success(&username, &client_info)
+        .await
+        .map_err(e500)?;
 
-    authentication::change_password(**user_id, form.0.new_password, &pool)
+    authentication::change_password(
+        **user_id,
+        form.0.new_password,
+        &hashing,
+        &pool,
+    )
+    .await
+    .map_err(e500)?;
+    // Whoever knew the old password may still be logged in elsewhere.
+    let revoked_sessions = session_index
+        .revoke_all(**user_id, session.get_session_id().map_err(e500)?)
         .await
         .map_err(e500)?;
+    record_audit_event(
+        pool.get_ref(),
+        **user_id,
+        &client_info,
+        AuditAction::PasswordChanged,
+        serde_json::json!({ "revoked_session_ids": revoked_sessions }),
+    )
+    .await
+    .map_err(e500)?;
     FlashMessage::info("Your password has been changed.").send();
 
     Ok(http_utils::seeother("/admin/password"))
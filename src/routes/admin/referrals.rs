@@ -0,0 +1,97 @@
+use std::fmt::Write;
+
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use anyhow::Context;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::routes::e500;
+
+/// Number of referrers shown on the leaderboard.
+const LEADERBOARD_SIZE: i64 = 50;
+
+struct Referrer {
+    id: Uuid,
+    email: String,
+    name: String,
+    referral_count: i64,
+}
+
+/// Subscribers who brought in the most confirmed subscribers through their
+/// referral link.
+pub async fn referral_leaderboard(
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let referrers = get_top_referrers(&pool).await.map_err(e500)?;
+
+    let mut rows_html = String::new();
+    for (rank, r) in referrers.iter().enumerate() {
+        writeln!(
+            rows_html,
+            r#"<tr><td>{}</td><td><a href="/admin/subscribers/{}">{}</a></td><td>{}</td><td>{}</td></tr>"#,
+            rank + 1,
+            r.id,
+            htmlescape::encode_minimal(&r.email),
+            htmlescape::encode_minimal(&r.name),
+            r.referral_count,
+        )
+        .unwrap();
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Referrals</title>
+</head>
+<body>
+    <p>Confirmed subscribers who signed up through a referral link, by
+    referrer.</p>
+    <table>
+        <tr><th>Rank</th><th>Email</th><th>Name</th><th>Referrals</th></tr>
+        {rows_html}
+    </table>
+    <p><a href="/admin/dashboard">&lt;- Back</a></p>
+</body>
+</html>
+"#
+        )))
+}
+
+#[tracing::instrument(name = "Get top referrers", skip(pool))]
+async fn get_top_referrers(
+    pool: &PgPool,
+) -> Result<Vec<Referrer>, anyhow::Error> {
+    // A referral counts once the referred subscriber has confirmed, even if
+    // they have unsubscribed since.
+    let referrers = sqlx::query_as!(
+        Referrer,
+        r#"
+        SELECT
+            s.id,
+            s.email,
+            s.name,
+            COUNT(*) AS "referral_count!"
+        FROM subscriptions s
+        JOIN subscriptions r ON r.referred_by = s.id
+        WHERE EXISTS (
+            SELECT 1
+            FROM subscription_status_changes c
+            WHERE c.subscriber_id = r.id AND c.to_status = 'confirmed'
+        )
+        GROUP BY s.id
+        ORDER BY 4 DESC, s.email
+        LIMIT $1
+        "#,
+        LEADERBOARD_SIZE,
+    )
+    .fetch_all(pool)
+    .await
+    .context("failed to retrieve top referrers")?;
+
+    Ok(referrers)
+}
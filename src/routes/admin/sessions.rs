@@ -0,0 +1,145 @@
+use std::fmt::Write;
+
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::audit::{record_audit_event, AuditAction};
+use crate::authentication::{CsrfToken, UserId};
+use crate::client_info::ClientInfo;
+use crate::routes::{e500, seeother};
+use crate::session::TypedSession;
+use crate::session_index::SessionIndex;
+
+/// Where the logged-in user is logged in.
+pub async fn list_sessions(
+    session: TypedSession,
+    session_index: web::Data<SessionIndex>,
+    user_id: web::ReqData<UserId>,
+    flash_messages: IncomingFlashMessages,
+    csrf_token: CsrfToken,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(
+            msg_html,
+            "<p><i>{}</i></p>",
+            htmlescape::encode_minimal(m.content())
+        )
+        .unwrap();
+    }
+    let csrf_field = csrf_token.form_field();
+
+    let current_session_id = session.get_session_id().map_err(e500)?;
+    let sessions = session_index.list(**user_id).await.map_err(e500)?;
+    let mut rows_html = String::new();
+    for s in &sessions {
+        let action_html = if Some(s.id) == current_session_id {
+            "This session".to_string()
+        } else {
+            format!(
+                r#"<form action="/admin/sessions/{}/revoke" method="post">{}<button type="submit">Revoke</button></form>"#,
+                s.id, csrf_field
+            )
+        };
+        writeln!(
+            rows_html,
+            "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+            s.details.created_at.format("%Y-%m-%d %H:%M UTC"),
+            s.details.last_seen_at.format("%Y-%m-%d %H:%M UTC"),
+            htmlescape::encode_minimal(
+                s.details.ip_address.as_deref().unwrap_or("")
+            ),
+            htmlescape::encode_minimal(
+                s.details.user_agent.as_deref().unwrap_or("")
+            ),
+            action_html,
+        )
+        .unwrap();
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Active sessions</title>
+</head>
+<body>
+    {msg_html}
+    <p>You are logged in from these devices.</p>
+    <table>
+        <tr><th>Logged in</th><th>Last seen</th><th>IP address</th><th>Browser</th><th></th></tr>
+        {rows_html}
+    </table>
+    <form name="revokeOtherSessions" action="/admin/sessions/revoke_others" method="post">
+        {csrf_field}
+        <button type="submit">Log out all other sessions</button>
+    </form>
+    <p><a href="/admin/dashboard">&lt;- Back</a></p>
+</body>
+</html>
+"#,
+        )))
+}
+
+pub async fn revoke_session(
+    session_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    session_index: web::Data<SessionIndex>,
+    user_id: web::ReqData<UserId>,
+    client_info: ClientInfo,
+) -> Result<HttpResponse, actix_web::Error> {
+    if !session_index
+        .revoke(**user_id, *session_id)
+        .await
+        .map_err(e500)?
+    {
+        return Err(actix_web::error::ErrorNotFound("No such session."));
+    }
+    record_sessions_revoked(&pool, **user_id, &client_info, &[*session_id])
+        .await?;
+    FlashMessage::info("The session has been revoked.").send();
+    Ok(seeother("/admin/sessions"))
+}
+
+pub async fn revoke_other_sessions(
+    session: TypedSession,
+    pool: web::Data<PgPool>,
+    session_index: web::Data<SessionIndex>,
+    user_id: web::ReqData<UserId>,
+    client_info: ClientInfo,
+) -> Result<HttpResponse, actix_web::Error> {
+    let current_session_id = session.get_session_id().map_err(e500)?;
+    let revoked = session_index
+        .revoke_all(**user_id, current_session_id)
+        .await
+        .map_err(e500)?;
+    record_sessions_revoked(&pool, **user_id, &client_info, &revoked).await?;
+    FlashMessage::info("All other sessions have been revoked.").send();
+    Ok(seeother("/admin/sessions"))
+}
+
+async fn record_sessions_revoked(
+    pool: &PgPool,
+    user_id: Uuid,
+    client_info: &ClientInfo,
+    session_ids: &[Uuid],
+) -> Result<(), actix_web::Error> {
+    if session_ids.is_empty() {
+        return Ok(());
+    }
+    record_audit_event(
+        pool,
+        user_id,
+        client_info,
+        AuditAction::SessionsRevoked,
+        serde_json::json!({ "session_ids": session_ids }),
+    )
+    .await
+    .map_err(e500)
+}
@@ -0,0 +1,348 @@
+use std::collections::HashMap;
+
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use anyhow::Context;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::audit::{record_audit_event, AuditAction};
+use crate::authentication::UserId;
+use crate::client_info::ClientInfo;
+use crate::domain::{
+    AttributeSchema, NewSubscriber, SubscriberEmail, SubscriberName,
+    SubscriptionStatus,
+};
+use crate::email_client::EmailClient;
+use crate::routes::admin::subscribers::detail::get_subscriber;
+use crate::routes::{
+    change_subscription_status, e500, generate_subscription_token,
+    record_subscriber_event, seeother, send_confirmation_email, store_token,
+    StatusChangeError,
+};
+use crate::startup::ApplicationBaseUrl;
+
+fn subscriber_page(subscriber_id: Uuid) -> HttpResponse {
+    seeother(&format!("/admin/subscribers/{}", subscriber_id))
+}
+
+/// Apply a status change requested from the subscriber page, telling the
+/// admin when it is not allowed.
+async fn change_status(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+    to_status: SubscriptionStatus,
+    user_id: UserId,
+    client_info: &ClientInfo,
+    action: AuditAction,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("failed to acquire postgres connection from pool")
+        .map_err(e500)?;
+    match change_subscription_status(
+        &mut transaction,
+        subscriber_id,
+        to_status,
+        "admin",
+        Some(*user_id),
+    )
+    .await
+    {
+        Ok(()) => {}
+        Err(StatusChangeError::UnknownSubscriber(_)) => {
+            return Ok(HttpResponse::NotFound().finish())
+        }
+        Err(StatusChangeError::IllegalTransition(e)) => {
+            FlashMessage::error(e).send();
+            return Ok(subscriber_page(subscriber_id));
+        }
+        Err(e) => return Err(e500(e)),
+    }
+    record_audit_event(
+        &mut transaction,
+        *user_id,
+        client_info,
+        action,
+        serde_json::json!({ "subscriber_id": subscriber_id }),
+    )
+    .await
+    .map_err(e500)?;
+    transaction
+        .commit()
+        .await
+        .context("failed to commit sql transaction to change status")
+        .map_err(e500)?;
+
+    FlashMessage::info(format!("The subscriber is now {}.", to_status)).send();
+    Ok(subscriber_page(subscriber_id))
+}
+
+#[tracing::instrument(
+    name = "Confirm a subscriber manually",
+    skip(pool),
+    fields(user_id = %*user_id)
+)]
+pub async fn confirm_subscriber_manually(
+    subscriber_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+    client_info: ClientInfo,
+) -> Result<HttpResponse, actix_web::Error> {
+    change_status(
+        &pool,
+        *subscriber_id,
+        SubscriptionStatus::Confirmed,
+        *user_id,
+        &client_info,
+        AuditAction::SubscriberConfirmed,
+    )
+    .await
+}
+
+#[tracing::instrument(
+    name = "Resend a confirmation email",
+    skip(pool, email_client, base_url),
+    fields(user_id = %*user_id)
+)]
+pub async fn resend_confirmation(
+    subscriber_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    user_id: web::ReqData<UserId>,
+    client_info: ClientInfo,
+) -> Result<HttpResponse, actix_web::Error> {
+    let subscriber =
+        match get_subscriber(&pool, *subscriber_id).await.map_err(e500)? {
+            Some(subscriber) => subscriber,
+            None => return Ok(HttpResponse::NotFound().finish()),
+        };
+    if subscriber.status != SubscriptionStatus::PendingConfirmation {
+        FlashMessage::error(
+            "Confirmation emails can only be resent to pending subscribers.",
+        )
+        .send();
+        return Ok(subscriber_page(subscriber.id));
+    }
+    // Stored subscribers were valid when they signed up, but the rules may
+    // have been tightened since.
+    let new_subscriber = match SubscriberEmail::parse(subscriber.email)
+        .and_then(|email| {
+            Ok(NewSubscriber {
+                email,
+                name: SubscriberName::parse(subscriber.name)?,
+            })
+        }) {
+        Ok(new_subscriber) => new_subscriber,
+        Err(e) => {
+            FlashMessage::error(e).send();
+            return Ok(subscriber_page(subscriber.id));
+        }
+    };
+
+    let subscription_token = generate_subscription_token();
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("failed to acquire postgres connection from pool")
+        .map_err(e500)?;
+    store_token(&mut transaction, subscriber.id, &subscription_token)
+        .await
+        .map_err(e500)?;
+    record_subscriber_event(
+        &mut transaction,
+        subscriber.id,
+        "confirmation_resent",
+        Some(**user_id),
+    )
+    .await
+    .map_err(e500)?;
+    record_audit_event(
+        &mut transaction,
+        **user_id,
+        &client_info,
+        AuditAction::ConfirmationResent,
+        serde_json::json!({ "subscriber_id": subscriber.id }),
+    )
+    .await
+    .map_err(e500)?;
+    transaction
+        .commit()
+        .await
+        .context("failed to commit sql transaction to store new token")
+        .map_err(e500)?;
+
+    send_confirmation_email(
+        &email_client,
+        new_subscriber,
+        &base_url.0,
+        &subscription_token,
+    )
+    .await
+    .context("failed to send confirmation email")
+    .map_err(e500)?;
+
+    FlashMessage::info("The confirmation email has been sent again.").send();
+    Ok(subscriber_page(subscriber.id))
+}
+
+#[tracing::instrument(
+    name = "Unsubscribe a subscriber",
+    skip(pool),
+    fields(user_id = %*user_id)
+)]
+pub async fn unsubscribe_subscriber(
+    subscriber_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+    client_info: ClientInfo,
+) -> Result<HttpResponse, actix_web::Error> {
+    change_status(
+        &pool,
+        *subscriber_id,
+        SubscriptionStatus::Unsubscribed,
+        *user_id,
+        &client_info,
+        AuditAction::SubscriberUnsubscribed,
+    )
+    .await
+}
+
+/// Replace the custom attributes of a subscriber with the values submitted
+/// from the subscriber page.
+#[tracing::instrument(
+    name = "Update subscriber attributes",
+    skip(form, pool, attribute_schema),
+    fields(user_id = %*user_id)
+)]
+pub async fn update_subscriber_attributes(
+    subscriber_id: web::Path<Uuid>,
+    form: web::Form<HashMap<String, String>>,
+    pool: web::Data<PgPool>,
+    attribute_schema: web::Data<AttributeSchema>,
+    user_id: web::ReqData<UserId>,
+    client_info: ClientInfo,
+) -> Result<HttpResponse, actix_web::Error> {
+    let subscriber_id = subscriber_id.into_inner();
+    let attributes = match attribute_schema.parse_form(&form) {
+        Ok(attributes) => attributes,
+        Err(e) => {
+            FlashMessage::error(e.to_string()).send();
+            return Ok(subscriber_page(subscriber_id));
+        }
+    };
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("failed to acquire postgres connection from pool")
+        .map_err(e500)?;
+    let updated = sqlx::query!(
+        "UPDATE subscriptions SET attributes = $1 WHERE id = $2",
+        attributes.to_json(),
+        subscriber_id
+    )
+    .execute(&mut transaction)
+    .await
+    .context("failed to update subscriber attributes")
+    .map_err(e500)?
+    .rows_affected();
+    if updated == 0 {
+        return Ok(HttpResponse::NotFound().finish());
+    }
+    record_subscriber_event(
+        &mut transaction,
+        subscriber_id,
+        "attributes_updated",
+        Some(**user_id),
+    )
+    .await
+    .map_err(e500)?;
+    record_audit_event(
+        &mut transaction,
+        **user_id,
+        &client_info,
+        AuditAction::SubscriberAttributesUpdated,
+        serde_json::json!({
+            "subscriber_id": subscriber_id,
+            "attributes": attributes.to_json(),
+        }),
+    )
+    .await
+    .map_err(e500)?;
+    transaction
+        .commit()
+        .await
+        .context("failed to commit sql transaction to update attributes")
+        .map_err(e500)?;
+
+    FlashMessage::info("The attributes have been updated.").send();
+    Ok(subscriber_page(subscriber_id))
+}
+
+/// Remove the subscriber and everything we store about them.
+#[tracing::instrument(
+    name = "Delete a subscriber",
+    skip(pool),
+    fields(user_id = %*user_id)
+)]
+pub async fn delete_subscriber(
+    subscriber_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+    client_info: ClientInfo,
+) -> Result<HttpResponse, actix_web::Error> {
+    let subscriber_id = subscriber_id.into_inner();
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("failed to acquire postgres connection from pool")
+        .map_err(e500)?;
+    sqlx::query!(
+        "DELETE FROM subscription_tokens WHERE subscriber_id = $1",
+        subscriber_id
+    )
+    .execute(&mut transaction)
+    .await
+    .context("failed to delete subscription tokens")
+    .map_err(e500)?;
+    sqlx::query!(
+        "DELETE FROM subscription_consents WHERE subscriber_id = $1",
+        subscriber_id
+    )
+    .execute(&mut transaction)
+    .await
+    .context("failed to delete consent record")
+    .map_err(e500)?;
+    // Events and status changes go with the subscriber, `ON DELETE CASCADE`.
+    // The audit event only keeps their id, not their contact details.
+    let deleted =
+        sqlx::query!("DELETE FROM subscriptions WHERE id = $1", subscriber_id)
+            .execute(&mut transaction)
+            .await
+            .context("failed to delete subscriber")
+            .map_err(e500)?
+            .rows_affected();
+    if deleted == 0 {
+        return Ok(HttpResponse::NotFound().finish());
+    }
+    record_audit_event(
+        &mut transaction,
+        **user_id,
+        &client_info,
+        AuditAction::SubscriberDeleted,
+        serde_json::json!({ "subscriber_id": subscriber_id }),
+    )
+    .await
+    .map_err(e500)?;
+    transaction
+        .commit()
+        .await
+        .context("failed to commit sql transaction to delete subscriber")
+        .map_err(e500)?;
+
+    FlashMessage::info("The subscriber has been deleted.").send();
+    Ok(seeother("/admin/subscribers"))
+}
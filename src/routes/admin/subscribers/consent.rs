@@ -0,0 +1,159 @@
+use actix_web::http::header::{ContentDisposition, ContentType};
+use actix_web::{web, HttpResponse};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::domain::SubscriptionStatus;
+use crate::routes::e500;
+
+/// Evidence of a subscriber's opt-in, as captured by `subscribe` and
+/// `confirm`.
+#[derive(serde::Serialize)]
+pub struct ConsentRecord {
+    pub subscriber_id: Uuid,
+    pub email: String,
+    pub name: String,
+    pub status: SubscriptionStatus,
+    pub consented_at: DateTime<Utc>,
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+    pub form_id: String,
+    pub consent_text_version: String,
+    pub confirmed_at: Option<DateTime<Utc>>,
+    pub confirmation_ip_address: Option<String>,
+    pub confirmation_user_agent: Option<String>,
+    /// How subscribers imported from another platform opted in there.
+    pub import_consent_note: Option<String>,
+}
+
+pub async fn subscriber_consent(
+    subscriber_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let record = match get_consent_record(&pool, *subscriber_id)
+        .await
+        .map_err(e500)?
+    {
+        Some(record) => record,
+        None => return Ok(HttpResponse::NotFound().finish()),
+    };
+
+    let or_unknown = |v: &Option<String>| {
+        v.as_deref()
+            .map(htmlescape::encode_minimal)
+            .unwrap_or_else(|| "unknown".into())
+    };
+    let confirmation_html = match record.confirmed_at {
+        Some(confirmed_at) => format!(
+            r#"<tr><th>Confirmed at</th><td>{}</td></tr>
+        <tr><th>Confirmation IP address</th><td>{}</td></tr>
+        <tr><th>Confirmation User-Agent</th><td>{}</td></tr>"#,
+            confirmed_at.to_rfc3339(),
+            or_unknown(&record.confirmation_ip_address),
+            or_unknown(&record.confirmation_user_agent),
+        ),
+        None => "<tr><th>Confirmed at</th><td>not confirmed</td></tr>".into(),
+    };
+    let import_html = match &record.import_consent_note {
+        Some(note) => format!(
+            "<tr><th>Import consent note</th><td>{}</td></tr>",
+            htmlescape::encode_minimal(note)
+        ),
+        None => String::new(),
+    };
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Consent record</title>
+</head>
+<body>
+    <p>Consent record for {email}</p>
+    <table>
+        <tr><th>Name</th><td>{name}</td></tr>
+        <tr><th>Status</th><td>{status}</td></tr>
+        <tr><th>Signed up at</th><td>{consented_at}</td></tr>
+        <tr><th>IP address</th><td>{ip_address}</td></tr>
+        <tr><th>User-Agent</th><td>{user_agent}</td></tr>
+        <tr><th>Form</th><td>{form_id}</td></tr>
+        <tr><th>Consent text version</th><td>{consent_text_version}</td></tr>
+        {confirmation_html}
+        {import_html}
+    </table>
+    <p><a href="/admin/subscribers/{subscriber_id}/consent.json">Download as JSON</a></p>
+    <p><a href="/admin/dashboard">&lt;- Back</a></p>
+</body>
+</html>
+"#,
+            email = htmlescape::encode_minimal(&record.email),
+            name = htmlescape::encode_minimal(&record.name),
+            status = record.status,
+            consented_at = record.consented_at.to_rfc3339(),
+            ip_address = or_unknown(&record.ip_address),
+            user_agent = or_unknown(&record.user_agent),
+            form_id = record.form_id,
+            consent_text_version =
+                htmlescape::encode_minimal(&record.consent_text_version),
+            subscriber_id = record.subscriber_id,
+        )))
+}
+
+pub async fn subscriber_consent_export(
+    subscriber_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    match get_consent_record(&pool, *subscriber_id)
+        .await
+        .map_err(e500)?
+    {
+        Some(record) => Ok(HttpResponse::Ok()
+            .insert_header(ContentDisposition::attachment(format!(
+                "consent-{}.json",
+                record.subscriber_id
+            )))
+            .json(record)),
+        None => Ok(HttpResponse::NotFound().finish()),
+    }
+}
+
+#[tracing::instrument(name = "get consent record", skip(pool))]
+pub async fn get_consent_record(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+) -> Result<Option<ConsentRecord>, anyhow::Error> {
+    let record = sqlx::query_as!(
+        ConsentRecord,
+        r#"
+        SELECT
+            s.id AS subscriber_id,
+            s.email,
+            s.name,
+            s.status AS "status: SubscriptionStatus",
+            c.consented_at,
+            c.ip_address,
+            c.user_agent,
+            c.form_id,
+            c.consent_text_version,
+            c.confirmed_at,
+            c.confirmation_ip_address,
+            c.confirmation_user_agent,
+            i.consent_note AS "import_consent_note?"
+        FROM subscriptions s
+        JOIN subscription_consents c ON c.subscriber_id = s.id
+        LEFT JOIN subscriber_imports i ON i.id = c.import_id
+        WHERE s.id = $1
+        "#,
+        subscriber_id
+    )
+    .fetch_optional(pool)
+    .await
+    .context("failed to retrieve consent record")?;
+
+    Ok(record)
+}
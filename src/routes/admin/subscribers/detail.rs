@@ -0,0 +1,219 @@
+use std::fmt::Write;
+
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::IncomingFlashMessages;
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::authentication::CsrfToken;
+use crate::domain::{
+    AttributeSchema, SubscriberAttributes, SubscriptionStatus,
+};
+use crate::routes::{attribute_inputs, e500};
+
+pub struct Subscriber {
+    pub id: Uuid,
+    pub email: String,
+    pub name: String,
+    pub status: SubscriptionStatus,
+    pub subscribed_at: DateTime<Utc>,
+    pub attributes: serde_json::Value,
+}
+
+struct SubscriberEvent {
+    occurred_at: DateTime<Utc>,
+    event: String,
+    performed_by: Option<String>,
+}
+
+pub async fn subscriber_details(
+    subscriber_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    attribute_schema: web::Data<AttributeSchema>,
+    flash_messages: IncomingFlashMessages,
+    csrf_token: CsrfToken,
+) -> Result<HttpResponse, actix_web::Error> {
+    let subscriber =
+        match get_subscriber(&pool, *subscriber_id).await.map_err(e500)? {
+            Some(subscriber) => subscriber,
+            None => return Ok(HttpResponse::NotFound().finish()),
+        };
+    let events = get_subscriber_events(&pool, subscriber.id)
+        .await
+        .map_err(e500)?;
+
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(
+            msg_html,
+            "<p><i>{}</i></p>",
+            htmlescape::encode_minimal(m.content())
+        )
+        .unwrap();
+    }
+    let csrf_field = csrf_token.form_field();
+
+    let mut history_html = String::new();
+    for e in &events {
+        writeln!(
+            history_html,
+            "<tr><td>{}</td><td>{}</td><td>{}</td></tr>",
+            e.occurred_at.to_rfc3339(),
+            htmlescape::encode_minimal(&e.event),
+            e.performed_by
+                .as_deref()
+                .map(htmlescape::encode_minimal)
+                .unwrap_or_default(),
+        )
+        .unwrap();
+    }
+
+    let action = |path: &str, label: &str| {
+        format!(
+            r#"<form action="/admin/subscribers/{}/{}" method="post">{}<button type="submit">{}</button></form>"#,
+            subscriber.id, path, csrf_field, label
+        )
+    };
+    let mut actions_html = String::new();
+    if subscriber
+        .status
+        .can_transition_to(SubscriptionStatus::Confirmed)
+    {
+        actions_html.push_str(&action("confirm", "Confirm"));
+    }
+    if subscriber.status == SubscriptionStatus::PendingConfirmation {
+        actions_html.push_str(&action(
+            "resend_confirmation",
+            "Resend confirmation email",
+        ));
+    }
+    if subscriber
+        .status
+        .can_transition_to(SubscriptionStatus::Unsubscribed)
+    {
+        actions_html.push_str(&action("unsubscribe", "Unsubscribe"));
+    }
+    actions_html.push_str(&action("delete", "Delete"));
+
+    let attributes_html = if attribute_schema.definitions().is_empty() {
+        String::new()
+    } else {
+        format!(
+            r#"<p>Attributes</p>
+    <form action="/admin/subscribers/{}/attributes" method="post">
+        {}
+        {}
+        <button type="submit">Save attributes</button>
+    </form>"#,
+            subscriber.id,
+            csrf_field,
+            attribute_inputs(
+                &attribute_schema,
+                &SubscriberAttributes::from_json(subscriber.attributes.clone())
+            )
+        )
+    };
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Subscriber</title>
+</head>
+<body>
+    {msg_html}
+    <p>Subscriber {email}</p>
+    <table>
+        <tr><th>Name</th><td>{name}</td></tr>
+        <tr><th>Status</th><td>{status}</td></tr>
+        <tr><th>Signed up at</th><td>{subscribed_at}</td></tr>
+    </table>
+    {attributes_html}
+    <p><a href="/admin/subscribers/{subscriber_id}/consent">Consent record</a></p>
+    <p>History</p>
+    <table>
+        <tr><th>Date</th><th>Event</th><th>Performed by</th></tr>
+        {history_html}
+    </table>
+    {actions_html}
+    <p><a href="/admin/subscribers">&lt;- Back</a></p>
+</body>
+</html>
+"#,
+            email = htmlescape::encode_minimal(&subscriber.email),
+            name = htmlescape::encode_minimal(&subscriber.name),
+            status = subscriber.status,
+            subscribed_at = subscriber.subscribed_at.to_rfc3339(),
+            subscriber_id = subscriber.id,
+        )))
+}
+
+#[tracing::instrument(name = "Get subscriber", skip(pool))]
+pub async fn get_subscriber(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+) -> Result<Option<Subscriber>, anyhow::Error> {
+    sqlx::query_as!(
+        Subscriber,
+        r#"
+        SELECT
+            id,
+            email,
+            name,
+            status AS "status: SubscriptionStatus",
+            subscribed_at,
+            attributes
+        FROM subscriptions
+        WHERE id = $1
+        "#,
+        subscriber_id
+    )
+    .fetch_optional(pool)
+    .await
+    .context("failed to retrieve subscriber")
+}
+
+/// Status changes and other events, oldest first.
+#[tracing::instrument(name = "Get subscriber events", skip(pool))]
+async fn get_subscriber_events(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+) -> Result<Vec<SubscriberEvent>, anyhow::Error> {
+    sqlx::query_as!(
+        SubscriberEvent,
+        r#"
+        SELECT
+            e.occurred_at AS "occurred_at!",
+            e.event AS "event!",
+            u.username AS "performed_by?"
+        FROM (
+            SELECT occurred_at, event, performed_by
+            FROM subscriber_events
+            WHERE subscriber_id = $1
+            UNION ALL
+            SELECT
+                occurred_at,
+                CASE
+                    WHEN from_status IS NULL
+                        THEN format('%s (%s)', to_status, cause)
+                    ELSE format('%s -> %s (%s)', from_status, to_status, cause)
+                END,
+                performed_by
+            FROM subscription_status_changes
+            WHERE subscriber_id = $1
+        ) e
+        LEFT JOIN users u ON u.user_id = e.performed_by
+        ORDER BY e.occurred_at
+        "#,
+        subscriber_id
+    )
+    .fetch_all(pool)
+    .await
+    .context("failed to retrieve subscriber events")
+}
@@ -0,0 +1,280 @@
+use std::borrow::Cow;
+
+use actix_web::http::header::ContentDisposition;
+use actix_web::web::Bytes;
+use actix_web::{web, HttpResponse};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use futures_util::TryStreamExt;
+use sqlx::PgPool;
+use tokio::sync::mpsc;
+use tracing::Instrument;
+use uuid::Uuid;
+
+use crate::domain::{
+    AttributeSchema, SubscriberAttributes, SubscriptionStatus,
+};
+use crate::routes::neutralize_formula;
+
+/// Rows are sent to the client in chunks of this many subscribers.
+const ROWS_PER_CHUNK: usize = 500;
+/// Chunks waiting to be written to the client, beyond which we stop reading
+/// from Postgres.
+const BUFFERED_CHUNKS: usize = 4;
+
+#[derive(serde::Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    #[default]
+    Csv,
+    Ndjson,
+}
+
+#[derive(serde::Deserialize)]
+pub struct ExportParameters {
+    #[serde(default)]
+    format: ExportFormat,
+    #[serde(default)]
+    status: String,
+    /// Only export people who signed up through this form, or `csv_import`
+    /// for those imported from another platform. There are no lists: this is
+    /// how an audience gets split.
+    #[serde(default)]
+    signup_form: String,
+    /// Name of a custom attribute that must equal `attribute_value`.
+    #[serde(default)]
+    attribute: String,
+    #[serde(default)]
+    attribute_value: String,
+}
+
+#[derive(serde::Serialize)]
+struct ExportedSubscriber {
+    id: Uuid,
+    email: String,
+    name: String,
+    status: SubscriptionStatus,
+    subscribed_at: DateTime<Utc>,
+    /// The `form_id` of the consent record.
+    signup_form: Option<String>,
+    consent_text_version: Option<String>,
+    ip_address: Option<String>,
+    user_agent: Option<String>,
+    confirmed_at: Option<DateTime<Utc>>,
+    confirmation_ip_address: Option<String>,
+    confirmation_user_agent: Option<String>,
+    /// How people imported from another platform had opted in there.
+    import_consent_note: Option<String>,
+    attributes: serde_json::Value,
+}
+
+/// CSV cannot nest values: custom attributes go in a single column, as a
+/// JSON object. Text that came from subscribers or their browsers cannot
+/// start a formula.
+#[derive(serde::Serialize)]
+struct CsvExportedSubscriber<'a> {
+    id: Uuid,
+    email: Cow<'a, str>,
+    name: Cow<'a, str>,
+    status: SubscriptionStatus,
+    subscribed_at: DateTime<Utc>,
+    signup_form: Option<Cow<'a, str>>,
+    consent_text_version: Option<&'a str>,
+    ip_address: Option<&'a str>,
+    user_agent: Option<Cow<'a, str>>,
+    confirmed_at: Option<DateTime<Utc>>,
+    confirmation_ip_address: Option<&'a str>,
+    confirmation_user_agent: Option<Cow<'a, str>>,
+    import_consent_note: Option<Cow<'a, str>>,
+    attributes: String,
+}
+
+impl<'a> From<&'a ExportedSubscriber> for CsvExportedSubscriber<'a> {
+    fn from(value: &'a ExportedSubscriber) -> Self {
+        let text =
+            |cell: &'a Option<String>| cell.as_deref().map(neutralize_formula);
+        Self {
+            id: value.id,
+            email: neutralize_formula(&value.email),
+            name: neutralize_formula(&value.name),
+            status: value.status,
+            subscribed_at: value.subscribed_at,
+            signup_form: text(&value.signup_form),
+            consent_text_version: value.consent_text_version.as_deref(),
+            ip_address: value.ip_address.as_deref(),
+            user_agent: text(&value.user_agent),
+            confirmed_at: value.confirmed_at,
+            confirmation_ip_address: value.confirmation_ip_address.as_deref(),
+            confirmation_user_agent: text(&value.confirmation_user_agent),
+            import_consent_note: text(&value.import_consent_note),
+            attributes: value.attributes.to_string(),
+        }
+    }
+}
+
+/// Export subscribers as CSV or newline-delimited JSON, along with the
+/// evidence of their consent.
+///
+/// Rows are streamed from Postgres to the client through a bounded channel,
+/// so that memory usage does not depend on the size of the audience.
+#[tracing::instrument(
+    name = "Export subscribers",
+    skip(parameters, pool, attribute_schema)
+)]
+pub async fn export_subscribers(
+    parameters: web::Query<ExportParameters>,
+    pool: web::Data<PgPool>,
+    attribute_schema: web::Data<AttributeSchema>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let ExportParameters {
+        format,
+        status,
+        signup_form,
+        attribute,
+        attribute_value,
+    } = parameters.0;
+    let status = Some(status)
+        .filter(|s| !s.is_empty())
+        .map(|s| SubscriptionStatus::parse(&s))
+        .transpose()
+        .map_err(actix_web::error::ErrorBadRequest)?;
+    let signup_form = Some(signup_form).filter(|s| !s.is_empty());
+    let attribute_filter = attribute_schema
+        .parse_filter(
+            Some((attribute.as_str(), attribute_value.as_str()))
+                .filter(|(name, _)| !name.is_empty()),
+        )
+        .map_err(actix_web::error::ErrorBadRequest)?;
+
+    let (sender, receiver) = mpsc::channel(BUFFERED_CHUNKS);
+    let pool = pool.into_inner();
+    tokio::spawn(
+        async move {
+            let result = stream_subscribers(
+                &pool,
+                status,
+                signup_form,
+                attribute_filter,
+                format,
+                sender.clone(),
+            )
+            .await;
+            if let Err(e) = result {
+                tracing::error!(
+                    error.cause_chain = ?e,
+                    "Failed to export subscribers."
+                );
+                // Abort the response rather than leave a truncated file
+                // looking complete.
+                let _ = sender.send(Err(e)).await;
+            }
+        }
+        .instrument(tracing::Span::current()),
+    );
+
+    let (content_type, extension) = match format {
+        ExportFormat::Csv => ("text/csv; charset=utf-8", "csv"),
+        ExportFormat::Ndjson => ("application/x-ndjson", "ndjson"),
+    };
+    Ok(HttpResponse::Ok()
+        .content_type(content_type)
+        .insert_header(ContentDisposition::attachment(format!(
+            "subscribers-{}.{}",
+            Utc::now().format("%Y-%m-%d"),
+            extension
+        )))
+        .streaming(futures_util::stream::unfold(
+            receiver,
+            |mut receiver| async move {
+                receiver.recv().await.map(|chunk| (chunk, receiver))
+            },
+        )))
+}
+
+/// Serialize matching subscribers into chunks and hand them to `sender`.
+/// Stops early, without error, if the client went away.
+async fn stream_subscribers(
+    pool: &PgPool,
+    status: Option<SubscriptionStatus>,
+    signup_form: Option<String>,
+    attribute_filter: SubscriberAttributes,
+    format: ExportFormat,
+    sender: mpsc::Sender<Result<Bytes, anyhow::Error>>,
+) -> Result<(), anyhow::Error> {
+    let mut rows = sqlx::query_as!(
+        ExportedSubscriber,
+        r#"
+        SELECT
+            s.id,
+            s.email,
+            s.name,
+            s.status AS "status: SubscriptionStatus",
+            s.subscribed_at,
+            c.form_id AS "signup_form?",
+            c.consent_text_version AS "consent_text_version?",
+            c.ip_address,
+            c.user_agent,
+            c.confirmed_at,
+            c.confirmation_ip_address,
+            c.confirmation_user_agent,
+            i.consent_note AS "import_consent_note?",
+            s.attributes
+        FROM subscriptions s
+        LEFT JOIN subscription_consents c ON c.subscriber_id = s.id
+        LEFT JOIN subscriber_imports i ON i.id = c.import_id
+        WHERE ($1::subscription_status IS NULL OR s.status = $1)
+            AND ($2::text IS NULL OR c.form_id = $2)
+            AND s.attributes @> $3
+        ORDER BY s.subscribed_at, s.id
+        "#,
+        status as Option<SubscriptionStatus>,
+        signup_form,
+        attribute_filter.to_json(),
+    )
+    .fetch(pool);
+
+    let mut chunk = Vec::new();
+    if let ExportFormat::Csv = format {
+        chunk.extend_from_slice(
+            b"id,email,name,status,subscribed_at,signup_form,\
+            consent_text_version,ip_address,user_agent,confirmed_at,\
+            confirmation_ip_address,confirmation_user_agent,\
+            import_consent_note,attributes\n",
+        );
+    }
+    let mut rows_in_chunk = 0;
+    while let Some(row) = rows
+        .try_next()
+        .await
+        .context("failed to fetch subscribers")?
+    {
+        match format {
+            ExportFormat::Csv => {
+                let mut writer = csv::WriterBuilder::new()
+                    .has_headers(false)
+                    .from_writer(&mut chunk);
+                writer.serialize(CsvExportedSubscriber::from(&row))?;
+                writer.flush()?;
+            }
+            ExportFormat::Ndjson => {
+                serde_json::to_writer(&mut chunk, &row)?;
+                chunk.push(b'\n');
+            }
+        }
+        rows_in_chunk += 1;
+        if rows_in_chunk == ROWS_PER_CHUNK {
+            if sender
+                .send(Ok(std::mem::take(&mut chunk).into()))
+                .await
+                .is_err()
+            {
+                return Ok(());
+            }
+            rows_in_chunk = 0;
+        }
+    }
+    if !chunk.is_empty() {
+        let _ = sender.send(Ok(chunk.into())).await;
+    }
+    Ok(())
+}
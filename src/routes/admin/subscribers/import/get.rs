@@ -0,0 +1,79 @@
+use std::fmt::Write;
+
+use actix_web::http::header::ContentType;
+use actix_web::HttpResponse;
+use actix_web_flash_messages::IncomingFlashMessages;
+
+use crate::authentication::CsrfToken;
+
+pub async fn import_subscribers_form(
+    flash_messages: IncomingFlashMessages,
+    csrf_token: CsrfToken,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        // Messages quote the mode and lines of the uploaded file.
+        writeln!(
+            msg_html,
+            "<p><i>{}</i></p>",
+            htmlescape::encode_minimal(m.content())
+        )
+        .unwrap();
+    }
+    let csrf_field = csrf_token.form_field();
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Import subscribers</title>
+</head>
+<body>
+    {msg_html}
+    <p>Import subscribers from a CSV file</p>
+    <p>
+        The file needs a header row with an email column. Exports from
+        Mailchimp, ConvertKit, Substack, Buttondown and MailerLite are
+        understood as they are.
+    </p>
+    <form
+        name="importSubscribers"
+        action="/admin/subscribers/import"
+        method="post"
+        enctype="multipart/form-data"
+    >
+        {csrf_field}
+        <label>CSV file<br>
+            <input type="file" name="file" accept=".csv,text/csv">
+        </label>
+        <br>
+        <label>
+            <input type="radio" name="mode" value="send_confirmation" checked>
+            Send a confirmation email to every imported subscriber
+        </label>
+        <br>
+        <label>
+            <input type="radio" name="mode" value="confirmed">
+            Import as confirmed: they already opted in elsewhere
+        </label>
+        <br>
+        <label>Consent note (required when importing as confirmed)<br>
+            <textarea
+                placeholder="How and when did these subscribers opt in?"
+                name="consent_note"
+                rows="5"
+                cols="50"
+            ></textarea>
+        </label>
+        <br>
+        <button type="submit">Import</button>
+    </form>
+    <p><a href="/admin/dashboard">&lt;- Back</a><p>
+</body>
+</html>
+"#,
+        )))
+}
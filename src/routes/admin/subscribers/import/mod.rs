@@ -0,0 +1,7 @@
+mod get;
+mod post;
+mod report;
+
+pub use get::import_subscribers_form;
+pub use post::import_subscribers;
+pub use report::{import_error_report, import_report};
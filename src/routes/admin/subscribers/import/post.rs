@@ -0,0 +1,498 @@
+use std::collections::{HashMap, HashSet};
+
+use actix_multipart::Multipart;
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use anyhow::Context;
+use chrono::Utc;
+use futures_util::TryStreamExt;
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::audit::{record_audit_event, AuditAction};
+use crate::authentication::UserId;
+use crate::client_info::ClientInfo;
+use crate::configuration::SubscriptionSettings;
+use crate::confirmation_email_worker::enqueue_confirmation_emails;
+use crate::domain::{
+    CsvLayout, CsvRow, EmailDomainBlocklist, NewSubscriber, SubscriptionStatus,
+};
+use crate::routes::{
+    e500, generate_subscription_token, neutralize_formula, seeother,
+};
+
+/// Form identifier recorded in the consent records of imported subscribers.
+pub const CSV_IMPORT_FORM_ID: &str = "csv_import";
+const MAX_UPLOAD_SIZE: usize = 20 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ImportMode {
+    /// Subscribers already opted in on the platform we migrate from.
+    Confirmed,
+    /// Subscribers go through our own double opt-in.
+    SendConfirmation,
+}
+
+impl ImportMode {
+    fn parse(s: &str) -> Result<Self, String> {
+        match s {
+            "confirmed" => Ok(Self::Confirmed),
+            "send_confirmation" => Ok(Self::SendConfirmation),
+            other => Err(format!("{} is not a valid import mode.", other)),
+        }
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Confirmed => "confirmed",
+            Self::SendConfirmation => "send_confirmation",
+        }
+    }
+
+    fn subscription_status(&self) -> SubscriptionStatus {
+        match self {
+            Self::Confirmed => SubscriptionStatus::Confirmed,
+            Self::SendConfirmation => SubscriptionStatus::PendingConfirmation,
+        }
+    }
+}
+
+#[derive(Default)]
+struct UploadForm {
+    file: Vec<u8>,
+    mode: String,
+    consent_note: String,
+}
+
+struct RejectedRow {
+    line: u64,
+    email: String,
+    name: String,
+    reason: String,
+}
+
+struct ImportedSubscriber {
+    line: u64,
+    id: Uuid,
+    email_canonical: String,
+    subscriber: NewSubscriber,
+    subscription_token: Option<String>,
+}
+
+#[tracing::instrument(
+    name = "Importing subscribers from CSV",
+    skip(
+        payload,
+        pool,
+        settings,
+        email_domain_blocklist,
+        client_info
+    ),
+    fields(user_id = %*user_id)
+)]
+pub async fn import_subscribers(
+    payload: Multipart,
+    pool: web::Data<PgPool>,
+    settings: web::Data<SubscriptionSettings>,
+    email_domain_blocklist: web::Data<EmailDomainBlocklist>,
+    user_id: web::ReqData<UserId>,
+    client_info: ClientInfo,
+) -> Result<HttpResponse, actix_web::Error> {
+    let form = match read_upload_form(payload).await? {
+        Ok(form) => form,
+        Err(e) => return Ok(import_failed(e)),
+    };
+    let mode = match ImportMode::parse(&form.mode) {
+        Ok(mode) => mode,
+        Err(e) => return Ok(import_failed(e)),
+    };
+    let consent_note = form.consent_note.trim();
+    if mode == ImportMode::Confirmed && consent_note.is_empty() {
+        return Ok(import_failed(
+            "A consent note is required to import subscribers as confirmed."
+                .into(),
+        ));
+    }
+
+    let mut reader = csv::ReaderBuilder::new()
+        .flexible(true)
+        .from_reader(form.file.as_slice());
+    let layout = match reader
+        .headers()
+        .map_err(|e| format!("The CSV file could not be read: {}.", e))
+        .and_then(CsvLayout::detect)
+    {
+        Ok(layout) => layout,
+        Err(e) => return Ok(import_failed(e)),
+    };
+
+    let mut rows = Vec::new();
+    for record in reader.records() {
+        match record {
+            Ok(record) => rows.push(layout.row(&record)),
+            Err(e) => {
+                return Ok(import_failed(format!(
+                    "The CSV file could not be read: {}.",
+                    e
+                )))
+            }
+        }
+    }
+
+    let (mut accepted, mut rejected) = validate_rows(
+        rows,
+        &email_domain_blocklist,
+        settings.fold_email_aliases,
+    );
+    if mode == ImportMode::SendConfirmation {
+        for s in accepted.iter_mut() {
+            s.subscription_token = Some(generate_subscription_token());
+        }
+    }
+
+    let import_id = Uuid::new_v4();
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("failed to acquire postgres connection from pool")
+        .map_err(e500)?;
+    // Existing subscribers are skipped by the insert itself, so that people
+    // signing up while we import do not fail the whole import.
+    let inserted = insert_subscriptions(&mut transaction, mode, &accepted)
+        .await
+        .context("failed to store imported subscribers")
+        .map_err(e500)?;
+    accepted.retain(|s| {
+        if inserted.contains(&s.id) {
+            return true;
+        }
+        rejected.push(RejectedRow {
+            line: s.line,
+            email: s.subscriber.email.to_string(),
+            name: s.subscriber.name.as_ref().to_string(),
+            reason: "Already subscribed.".into(),
+        });
+        false
+    });
+    rejected.sort_by_key(|r| r.line);
+    insert_import(
+        &mut transaction,
+        import_id,
+        **user_id,
+        mode,
+        (!consent_note.is_empty()).then_some(consent_note),
+        accepted.len(),
+        &rejected,
+    )
+    .await
+    .context("failed to store subscriber import")
+    .map_err(e500)?;
+    insert_consents_and_tokens(
+        &mut transaction,
+        import_id,
+        **user_id,
+        mode,
+        &settings.consent_text_version,
+        &accepted,
+    )
+    .await
+    .context("failed to store imported subscribers")
+    .map_err(e500)?;
+    // Sent by the worker: large imports would not fit in a request.
+    let subscription_tokens: Vec<String> = accepted
+        .iter()
+        .filter_map(|s| s.subscription_token.clone())
+        .collect();
+    enqueue_confirmation_emails(&mut transaction, &subscription_tokens)
+        .await
+        .context("failed to queue confirmation emails")
+        .map_err(e500)?;
+    record_audit_event(
+        &mut transaction,
+        **user_id,
+        &client_info,
+        AuditAction::SubscribersImported,
+        serde_json::json!({
+            "import_id": import_id,
+            "mode": mode.as_str(),
+            "imported": accepted.len(),
+            "rejected": rejected.len(),
+        }),
+    )
+    .await
+    .map_err(e500)?;
+    transaction
+        .commit()
+        .await
+        .context("failed to commit sql transaction to import subscribers")
+        .map_err(e500)?;
+
+    Ok(seeother(&format!(
+        "/admin/subscribers/imports/{}",
+        import_id
+    )))
+}
+
+fn import_failed(message: String) -> HttpResponse {
+    FlashMessage::error(message).send();
+    seeother("/admin/subscribers/import")
+}
+
+/// Buffer the fields of the upload form. Unknown fields are ignored, the
+/// outer `Result` is for transport errors and the inner one for invalid
+/// submissions.
+async fn read_upload_form(
+    mut payload: Multipart,
+) -> Result<Result<UploadForm, String>, actix_web::Error> {
+    let mut form = UploadForm::default();
+    let mut size = 0;
+    while let Some(mut field) = payload.try_next().await? {
+        let mut value = Vec::new();
+        while let Some(chunk) = field.try_next().await? {
+            size += chunk.len();
+            if size > MAX_UPLOAD_SIZE {
+                return Ok(Err(format!(
+                    "The CSV file must be smaller than {} MiB.",
+                    MAX_UPLOAD_SIZE / 1024 / 1024
+                )));
+            }
+            value.extend_from_slice(&chunk);
+        }
+        match field.name() {
+            Some("file") => form.file = value,
+            Some("mode") => {
+                form.mode = String::from_utf8_lossy(&value).into_owned()
+            }
+            Some("consent_note") => {
+                form.consent_note = String::from_utf8_lossy(&value).into_owned()
+            }
+            _ => {}
+        }
+    }
+    if form.file.is_empty() {
+        return Ok(Err("Please select a CSV file to import.".into()));
+    }
+    Ok(Ok(form))
+}
+
+/// Split rows between the subscribers to import and the rejected rows,
+/// including those that duplicate an earlier row of the same file.
+fn validate_rows(
+    rows: Vec<CsvRow>,
+    email_domain_blocklist: &EmailDomainBlocklist,
+    fold_email_aliases: bool,
+) -> (Vec<ImportedSubscriber>, Vec<RejectedRow>) {
+    let mut accepted = Vec::new();
+    let mut rejected = Vec::new();
+    let mut seen: HashMap<String, u64> = HashMap::new();
+    for row in rows {
+        let subscriber = row.subscriber.and_then(|s| {
+            email_domain_blocklist.check(&s.email)?;
+            Ok(s)
+        });
+        let reason = match subscriber {
+            Ok(subscriber) => {
+                let email_canonical =
+                    subscriber.email.dedup_key(fold_email_aliases);
+                match seen.get(&email_canonical) {
+                    Some(line) => format!("Duplicate of line {}.", line),
+                    None => {
+                        seen.insert(email_canonical.clone(), row.line);
+                        accepted.push(ImportedSubscriber {
+                            line: row.line,
+                            id: Uuid::new_v4(),
+                            email_canonical,
+                            subscriber,
+                            subscription_token: None,
+                        });
+                        continue;
+                    }
+                }
+            }
+            Err(e) => e,
+        };
+        rejected.push(RejectedRow {
+            line: row.line,
+            email: row.email,
+            name: row.name,
+            reason,
+        });
+    }
+    (accepted, rejected)
+}
+
+fn error_report(rejected: &[RejectedRow]) -> Result<String, anyhow::Error> {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    writer.write_record(["line", "email", "name", "reason"])?;
+    for row in rejected {
+        writer.write_record([
+            &row.line.to_string(),
+            &*neutralize_formula(&row.email),
+            &*neutralize_formula(&row.name),
+            &*neutralize_formula(&row.reason),
+        ])?;
+    }
+    Ok(String::from_utf8(writer.into_inner()?)?)
+}
+
+#[tracing::instrument(
+    name = "Store subscriber import",
+    skip(transaction, consent_note, rejected)
+)]
+async fn insert_import(
+    transaction: &mut Transaction<'_, Postgres>,
+    import_id: Uuid,
+    imported_by: Uuid,
+    mode: ImportMode,
+    consent_note: Option<&str>,
+    imported_count: usize,
+    rejected: &[RejectedRow],
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+    INSERT INTO subscriber_imports (
+        id,
+        imported_by,
+        imported_at,
+        mode,
+        consent_note,
+        imported_count,
+        rejected_count,
+        error_report
+    )
+    VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+        "#,
+        import_id,
+        imported_by,
+        Utc::now(),
+        mode.as_str(),
+        consent_note,
+        imported_count as i32,
+        rejected.len() as i32,
+        error_report(rejected)?,
+    )
+    .execute(transaction)
+    .await?;
+    Ok(())
+}
+
+/// Store subscribers in bulk, skipping those whose email address is taken.
+/// Returns the ids of the subscribers stored.
+#[tracing::instrument(
+    name = "Store imported subscribers",
+    skip(transaction, subscribers),
+    fields(subscribers = subscribers.len())
+)]
+async fn insert_subscriptions(
+    transaction: &mut Transaction<'_, Postgres>,
+    mode: ImportMode,
+    subscribers: &[ImportedSubscriber],
+) -> Result<HashSet<Uuid>, sqlx::Error> {
+    let ids: Vec<Uuid> = subscribers.iter().map(|s| s.id).collect();
+    let emails: Vec<String> = subscribers
+        .iter()
+        .map(|s| s.subscriber.email.to_string())
+        .collect();
+    let emails_canonical: Vec<String> = subscribers
+        .iter()
+        .map(|s| s.email_canonical.clone())
+        .collect();
+    let names: Vec<String> = subscribers
+        .iter()
+        .map(|s| s.subscriber.name.as_ref().to_string())
+        .collect();
+
+    let inserted = sqlx::query!(
+        r#"
+    INSERT INTO subscriptions (
+        id, email, email_canonical, name, subscribed_at, status
+    )
+    SELECT id, email, email_canonical, name, $5, $6
+    FROM UNNEST($1::uuid[], $2::text[], $3::text[], $4::text[])
+        AS t(id, email, email_canonical, name)
+    ON CONFLICT DO NOTHING
+    RETURNING id
+        "#,
+        &ids[..],
+        &emails[..],
+        &emails_canonical[..],
+        &names[..],
+        Utc::now(),
+        mode.subscription_status() as SubscriptionStatus,
+    )
+    .fetch_all(&mut *transaction)
+    .await?;
+    Ok(inserted.into_iter().map(|r| r.id).collect())
+}
+
+/// Store the consent records of imported subscribers and, when we are to
+/// send confirmation emails, their subscription tokens in bulk.
+#[tracing::instrument(
+    name = "Store consents of imported subscribers",
+    skip(transaction, subscribers),
+    fields(subscribers = subscribers.len())
+)]
+async fn insert_consents_and_tokens(
+    transaction: &mut Transaction<'_, Postgres>,
+    import_id: Uuid,
+    imported_by: Uuid,
+    mode: ImportMode,
+    consent_text_version: &str,
+    subscribers: &[ImportedSubscriber],
+) -> Result<(), sqlx::Error> {
+    let now = Utc::now();
+    let ids: Vec<Uuid> = subscribers.iter().map(|s| s.id).collect();
+
+    sqlx::query!(
+        r#"
+    INSERT INTO subscription_consents (
+        subscriber_id,
+        consented_at,
+        form_id,
+        consent_text_version,
+        import_id
+    )
+    SELECT subscriber_id, $2, $3, $4, $5
+    FROM UNNEST($1::uuid[]) AS t(subscriber_id)
+        "#,
+        &ids[..],
+        now,
+        CSV_IMPORT_FORM_ID,
+        consent_text_version,
+        import_id,
+    )
+    .execute(&mut *transaction)
+    .await?;
+
+    sqlx::query!(
+        r#"
+    INSERT INTO subscription_status_changes (
+        subscriber_id, occurred_at, to_status, cause, performed_by
+    )
+    SELECT subscriber_id, $2, $3, 'import', $4
+    FROM UNNEST($1::uuid[]) AS t(subscriber_id)
+        "#,
+        &ids[..],
+        now,
+        mode.subscription_status() as SubscriptionStatus,
+        imported_by,
+    )
+    .execute(&mut *transaction)
+    .await?;
+
+    let (token_ids, tokens): (Vec<Uuid>, Vec<String>) = subscribers
+        .iter()
+        .filter_map(|s| Some((s.id, s.subscription_token.clone()?)))
+        .unzip();
+    sqlx::query!(
+        r#"
+    INSERT INTO subscription_tokens (subscription_token, subscriber_id)
+    SELECT * FROM UNNEST($1::text[], $2::uuid[])
+        "#,
+        &tokens[..],
+        &token_ids[..],
+    )
+    .execute(&mut *transaction)
+    .await?;
+    Ok(())
+}
@@ -0,0 +1,95 @@
+use actix_web::http::header::{ContentDisposition, ContentType};
+use actix_web::{web, HttpResponse};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::routes::e500;
+
+struct SubscriberImport {
+    imported_at: DateTime<Utc>,
+    mode: String,
+    consent_note: Option<String>,
+    imported_count: i32,
+    rejected_count: i32,
+    error_report: String,
+}
+
+pub async fn import_report(
+    import_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let import = match get_import(&pool, *import_id).await.map_err(e500)? {
+        Some(import) => import,
+        None => return Ok(HttpResponse::NotFound().finish()),
+    };
+
+    let consent_note = import
+        .consent_note
+        .as_deref()
+        .map(htmlescape::encode_minimal)
+        .unwrap_or_default();
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Subscriber import</title>
+</head>
+<body>
+    <p>Import of {imported_at} ({mode})</p>
+    <p>Imported subscribers: {imported_count}</p>
+    <p>Rejected rows: {rejected_count}</p>
+    <p>Consent note: {consent_note}</p>
+    <p><a href="/admin/subscribers/imports/{import_id}/errors.csv">Download the error report</a></p>
+    <p><a href="/admin/dashboard">&lt;- Back</a></p>
+</body>
+</html>
+"#,
+            imported_at = import.imported_at.to_rfc3339(),
+            mode = import.mode,
+            imported_count = import.imported_count,
+            rejected_count = import.rejected_count,
+            import_id = import_id,
+        )))
+}
+
+pub async fn import_error_report(
+    import_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    match get_import(&pool, *import_id).await.map_err(e500)? {
+        Some(import) => Ok(HttpResponse::Ok()
+            .content_type("text/csv; charset=utf-8")
+            .insert_header(ContentDisposition::attachment(format!(
+                "import-{}-errors.csv",
+                import_id
+            )))
+            .body(import.error_report)),
+        None => Ok(HttpResponse::NotFound().finish()),
+    }
+}
+
+#[tracing::instrument(name = "get subscriber import", skip(pool))]
+async fn get_import(
+    pool: &PgPool,
+    import_id: Uuid,
+) -> Result<Option<SubscriberImport>, anyhow::Error> {
+    sqlx::query_as!(
+        SubscriberImport,
+        r#"
+        SELECT imported_at, mode, consent_note, imported_count,
+            rejected_count, error_report
+        FROM subscriber_imports
+        WHERE id = $1
+        "#,
+        import_id
+    )
+    .fetch_optional(pool)
+    .await
+    .context("failed to retrieve subscriber import")
+}
@@ -0,0 +1,380 @@
+use std::fmt::Write;
+
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::IncomingFlashMessages;
+use anyhow::Context;
+use chrono::{DateTime, NaiveDate, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::domain::{
+    AttributeError, AttributeSchema, SubscriberAttributes, SubscriptionStatus,
+};
+use crate::routes::e500;
+
+pub const PAGE_SIZE: i64 = 50;
+/// Beyond this, the offset of the page would overflow.
+const MAX_PAGE: i64 = i64::MAX / PAGE_SIZE;
+
+/// Query string of the subscriber list. Everything is optional and, as
+/// HTML forms submit empty inputs as empty strings, blank values are
+/// ignored.
+#[derive(serde::Deserialize)]
+pub struct QueryParameters {
+    #[serde(default)]
+    q: String,
+    #[serde(default)]
+    status: String,
+    #[serde(default)]
+    signed_up_from: String,
+    #[serde(default)]
+    signed_up_to: String,
+    /// Name of a custom attribute that must equal `attribute_value`.
+    #[serde(default)]
+    attribute: String,
+    #[serde(default)]
+    attribute_value: String,
+    page: Option<i64>,
+}
+
+struct Filters {
+    search: Option<String>,
+    status: Option<SubscriptionStatus>,
+    signed_up_from: Option<NaiveDate>,
+    signed_up_to: Option<NaiveDate>,
+    attribute: Option<(String, String)>,
+    page: i64,
+}
+
+impl TryFrom<QueryParameters> for Filters {
+    type Error = String;
+
+    fn try_from(value: QueryParameters) -> Result<Self, Self::Error> {
+        let non_empty = |s: String| {
+            let s = s.trim().to_string();
+            (!s.is_empty()).then_some(s)
+        };
+        let parse_date = |s: String| {
+            non_empty(s)
+                .map(|s| {
+                    NaiveDate::parse_from_str(&s, "%Y-%m-%d").map_err(|_| {
+                        format!("{} is not a valid date (YYYY-MM-DD).", s)
+                    })
+                })
+                .transpose()
+        };
+        Ok(Self {
+            search: non_empty(value.q),
+            status: non_empty(value.status)
+                .map(|s| SubscriptionStatus::parse(&s))
+                .transpose()?,
+            signed_up_from: parse_date(value.signed_up_from)?,
+            signed_up_to: parse_date(value.signed_up_to)?,
+            attribute: non_empty(value.attribute)
+                .map(|name| (name, value.attribute_value)),
+            page: value.page.unwrap_or(1).clamp(1, MAX_PAGE),
+        })
+    }
+}
+
+impl Filters {
+    /// Query string selecting `page` with the same filters.
+    fn page_query(&self, page: i64) -> String {
+        let date = |d: Option<NaiveDate>| {
+            d.map(|d| d.format("%Y-%m-%d").to_string())
+                .unwrap_or_default()
+        };
+        format!(
+            "q={}&status={}&signed_up_from={}&signed_up_to={}{}&page={}",
+            urlencoding::encode(self.search.as_deref().unwrap_or_default()),
+            self.status.map(|s| s.as_str()).unwrap_or_default(),
+            date(self.signed_up_from),
+            date(self.signed_up_to),
+            self.attribute_query(),
+            page
+        )
+    }
+
+    /// Query string parameters of the attribute filter, if any, with a
+    /// leading `&`.
+    fn attribute_query(&self) -> String {
+        self.attribute
+            .as_ref()
+            .map(|(name, value)| {
+                format!(
+                    "&attribute={}&attribute_value={}",
+                    urlencoding::encode(name),
+                    urlencoding::encode(value)
+                )
+            })
+            .unwrap_or_default()
+    }
+
+    /// Attributes matching subscribers must have, checked against the
+    /// schema.
+    fn attribute_filter(
+        &self,
+        schema: &AttributeSchema,
+    ) -> Result<SubscriberAttributes, AttributeError> {
+        schema.parse_filter(
+            self.attribute
+                .iter()
+                .map(|(name, value)| (name.as_str(), value.as_str())),
+        )
+    }
+
+    /// `ILIKE` pattern matching the search term anywhere, with the
+    /// pattern's own wildcards escaped.
+    fn search_pattern(&self) -> Option<String> {
+        self.search.as_ref().map(|s| {
+            let escaped = s
+                .replace('\\', "\\\\")
+                .replace('%', "\\%")
+                .replace('_', "\\_");
+            format!("%{}%", escaped)
+        })
+    }
+}
+
+struct SubscriberSummary {
+    id: Uuid,
+    email: String,
+    name: String,
+    status: SubscriptionStatus,
+    subscribed_at: DateTime<Utc>,
+}
+
+pub async fn list_subscribers(
+    query: web::Query<QueryParameters>,
+    pool: web::Data<PgPool>,
+    attribute_schema: web::Data<AttributeSchema>,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let filters = Filters::try_from(query.0)
+        .map_err(actix_web::error::ErrorBadRequest)?;
+    let attribute_filter = filters
+        .attribute_filter(&attribute_schema)
+        .map_err(actix_web::error::ErrorBadRequest)?;
+    let (subscribers, total) =
+        search_subscribers(&pool, &filters, &attribute_filter)
+            .await
+            .map_err(e500)?;
+    let page_count = ((total + PAGE_SIZE - 1) / PAGE_SIZE).max(1);
+
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(
+            msg_html,
+            "<p><i>{}</i></p>",
+            htmlescape::encode_minimal(m.content())
+        )
+        .unwrap();
+    }
+
+    let mut rows_html = String::new();
+    for s in &subscribers {
+        writeln!(
+            rows_html,
+            r#"<tr><td><a href="/admin/subscribers/{}">{}</a></td><td>{}</td><td>{}</td><td>{}</td></tr>"#,
+            s.id,
+            htmlescape::encode_minimal(&s.email),
+            htmlescape::encode_minimal(&s.name),
+            s.status,
+            s.subscribed_at.to_rfc3339(),
+        )
+        .unwrap();
+    }
+
+    let mut status_options = String::from(r#"<option value="">any</option>"#);
+    for status in SubscriptionStatus::ALL {
+        let selected = if filters.status == Some(status) {
+            " selected"
+        } else {
+            ""
+        };
+        write!(
+            status_options,
+            r#"<option value="{status}"{selected}>{status}</option>"#
+        )
+        .unwrap();
+    }
+
+    let mut pagination_html = String::new();
+    if filters.page > 1 {
+        write!(
+            pagination_html,
+            r#"<a href="/admin/subscribers?{}">&lt; Previous</a> "#,
+            filters.page_query(filters.page - 1)
+        )
+        .unwrap();
+    }
+    write!(pagination_html, "Page {} of {}", filters.page, page_count).unwrap();
+    if filters.page < page_count {
+        write!(
+            pagination_html,
+            r#" <a href="/admin/subscribers?{}">Next &gt;</a>"#,
+            filters.page_query(filters.page + 1)
+        )
+        .unwrap();
+    }
+
+    let mut attribute_html = String::new();
+    if !attribute_schema.definitions().is_empty() {
+        let (selected_name, value) =
+            filters.attribute.clone().unwrap_or_default();
+        let mut options = String::from(r#"<option value="">any</option>"#);
+        for definition in attribute_schema.definitions() {
+            let selected = if definition.name == selected_name {
+                " selected"
+            } else {
+                ""
+            };
+            write!(
+                options,
+                r#"<option value="{name}"{selected}>{name}</option>"#,
+                name = definition.name
+            )
+            .unwrap();
+        }
+        write!(
+            attribute_html,
+            r#"<label>Attribute
+            <select name="attribute">{}</select>
+        </label>
+        <label>equal to
+            <input type="text" name="attribute_value" value="{}">
+        </label>"#,
+            options,
+            htmlescape::encode_attribute(&value)
+        )
+        .unwrap();
+    }
+
+    let date = |d: Option<NaiveDate>| {
+        d.map(|d| d.format("%Y-%m-%d").to_string())
+            .unwrap_or_default()
+    };
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Subscribers</title>
+</head>
+<body>
+    {msg_html}
+    <form name="searchSubscribers" action="/admin/subscribers" method="get">
+        <label>Email or name
+            <input type="search" name="q" value="{search}">
+        </label>
+        <label>Status
+            <select name="status">{status_options}</select>
+        </label>
+        <label>Signed up from
+            <input type="date" name="signed_up_from" value="{signed_up_from}">
+        </label>
+        <label>to
+            <input type="date" name="signed_up_to" value="{signed_up_to}">
+        </label>
+        {attribute_html}
+        <button type="submit">Search</button>
+    </form>
+    <p>{total} subscribers</p>
+    <table>
+        <tr><th>Email</th><th>Name</th><th>Status</th><th>Signed up at</th></tr>
+        {rows_html}
+    </table>
+    <p>{pagination_html}</p>
+    <p>
+        Export all {status_label} subscribers as
+        <a href="/admin/subscribers/export?format=csv&status={status}{attribute_query}">CSV</a>
+        or <a href="/admin/subscribers/export?format=ndjson&status={status}{attribute_query}">NDJSON</a>
+    </p>
+    <p><a href="/admin/dashboard">&lt;- Back</a></p>
+</body>
+</html>
+"#,
+            search = htmlescape::encode_attribute(
+                filters.search.as_deref().unwrap_or_default()
+            ),
+            signed_up_from = date(filters.signed_up_from),
+            signed_up_to = date(filters.signed_up_to),
+            status = filters.status.map(|s| s.as_str()).unwrap_or_default(),
+            status_label = filters.status.map(|s| s.as_str()).unwrap_or("the"),
+            attribute_query = filters.attribute_query(),
+        )))
+}
+
+/// One page of subscribers matching `filters`, most recent first, and the
+/// total number of matches.
+#[tracing::instrument(
+    name = "Search subscribers",
+    skip(pool, filters, attribute_filter)
+)]
+async fn search_subscribers(
+    pool: &PgPool,
+    filters: &Filters,
+    attribute_filter: &SubscriberAttributes,
+) -> Result<(Vec<SubscriberSummary>, i64), anyhow::Error> {
+    let search = filters.search_pattern();
+    let subscribers = sqlx::query_as!(
+        SubscriberSummary,
+        r#"
+        SELECT
+            id,
+            email,
+            name,
+            status AS "status: SubscriptionStatus",
+            subscribed_at
+        FROM subscriptions
+        WHERE ($1::text IS NULL OR email ILIKE $1 OR name ILIKE $1)
+            AND ($2::subscription_status IS NULL OR status = $2)
+            AND ($3::date IS NULL
+                OR subscribed_at >= $3::date::timestamp AT TIME ZONE 'UTC')
+            AND ($4::date IS NULL
+                OR subscribed_at < ($4::date + 1)::timestamp AT TIME ZONE 'UTC')
+            AND attributes @> $7
+        ORDER BY subscribed_at DESC, id
+        LIMIT $5 OFFSET $6
+        "#,
+        search,
+        filters.status as Option<SubscriptionStatus>,
+        filters.signed_up_from,
+        filters.signed_up_to,
+        PAGE_SIZE,
+        (filters.page - 1) * PAGE_SIZE,
+        attribute_filter.to_json(),
+    )
+    .fetch_all(pool)
+    .await
+    .context("failed to search subscribers")?;
+
+    let total = sqlx::query!(
+        r#"
+        SELECT COUNT(*) AS "count!"
+        FROM subscriptions
+        WHERE ($1::text IS NULL OR email ILIKE $1 OR name ILIKE $1)
+            AND ($2::subscription_status IS NULL OR status = $2)
+            AND ($3::date IS NULL
+                OR subscribed_at >= $3::date::timestamp AT TIME ZONE 'UTC')
+            AND ($4::date IS NULL
+                OR subscribed_at < ($4::date + 1)::timestamp AT TIME ZONE 'UTC')
+            AND attributes @> $5
+        "#,
+        search,
+        filters.status as Option<SubscriptionStatus>,
+        filters.signed_up_from,
+        filters.signed_up_to,
+        attribute_filter.to_json(),
+    )
+    .fetch_one(pool)
+    .await
+    .context("failed to count subscribers")?
+    .count;
+
+    Ok((subscribers, total))
+}
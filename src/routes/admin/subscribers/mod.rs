@@ -0,0 +1,13 @@
+mod actions;
+mod consent;
+mod detail;
+mod export;
+mod import;
+mod list;
+
+pub use actions::*;
+pub use consent::{subscriber_consent, subscriber_consent_export};
+pub use detail::subscriber_details;
+pub use export::export_subscribers;
+pub use import::*;
+pub use list::list_subscribers;
@@ -0,0 +1,316 @@
+use std::fmt::Write;
+
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
+use chrono::Utc;
+use secrecy::{ExposeSecret, Secret};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::audit::{record_audit_event, AuditAction};
+use crate::authentication::{
+    disable_two_factor, enable_two_factor, generate_recovery_codes, get_totp,
+    is_two_factor_required, verify_second_factor, CsrfToken, Totp, UserId,
+    TOTP_ISSUER,
+};
+use crate::client_info::ClientInfo;
+use crate::email_client::EmailClient;
+use crate::login_guard::LoginGuard;
+use crate::routes::admin::dashboard::get_username;
+use crate::routes::{e500, seeother};
+use crate::session::TypedSession;
+use crate::session_index::SessionIndex;
+
+#[derive(serde::Deserialize)]
+pub struct CodeFormData {
+    pub(crate) code: Secret<String>,
+}
+
+pub async fn two_factor_settings(
+    pool: web::Data<PgPool>,
+    session: TypedSession,
+    user_id: web::ReqData<UserId>,
+    flash_messages: IncomingFlashMessages,
+    csrf_token: CsrfToken,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(
+            msg_html,
+            "<p><i>{}</i></p>",
+            htmlescape::encode_minimal(m.content())
+        )
+        .unwrap();
+    }
+    let csrf_field = csrf_token.form_field();
+
+    let body_html = if get_totp(&pool, **user_id).await.map_err(e500)?.is_some()
+    {
+        let disable_html = if is_two_factor_required(&pool)
+            .await
+            .map_err(e500)?
+        {
+            "<p>It is required for all users and cannot be disabled.</p>"
+                .to_string()
+        } else {
+            format!(
+                r#"<form name="disableTwoFactor" action="/admin/two_factor/disable" method="post">
+        {csrf_field}
+        {code_input}
+        <button type="submit">Disable two-factor authentication</button>
+    </form>"#,
+                code_input = code_input_html("Authentication or recovery code"),
+            )
+        };
+        format!(
+            "<p>Two-factor authentication is enabled.</p>\n    {disable_html}"
+        )
+    } else {
+        let totp = enrolment(&session).map_err(e500)?;
+        let username = get_username(**user_id, &pool).await.map_err(e500)?;
+        enrolment_html(&totp, &username, "/admin/two_factor", &csrf_field)
+    };
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Two-factor authentication</title>
+</head>
+<body>
+    {msg_html}
+    {body_html}
+    <p><a href="/admin/dashboard">&lt;- Back</a></p>
+</body>
+</html>
+"#,
+        )))
+}
+
+/// Confirm the secret shown by `two_factor_settings` with a first code.
+pub async fn enable_two_factor_authentication(
+    form: web::Form<CodeFormData>,
+    pool: web::Data<PgPool>,
+    session: TypedSession,
+    user_id: web::ReqData<UserId>,
+    client_info: ClientInfo,
+) -> Result<HttpResponse, actix_web::Error> {
+    match confirm_enrolment(
+        &pool,
+        &session,
+        **user_id,
+        &client_info,
+        &form.code,
+    )
+    .await?
+    {
+        Some(recovery_codes) => {
+            Ok(recovery_codes_page(&recovery_codes, "/admin/dashboard"))
+        }
+        None => {
+            FlashMessage::error("Invalid authentication code.").send();
+            Ok(seeother("/admin/two_factor"))
+        }
+    }
+}
+
+/// Wrong codes count towards the same lockout as at login, so that a
+/// hijacked session cannot guess its way out of two-factor authentication.
+#[allow(clippy::too_many_arguments)]
+pub async fn disable_two_factor_authentication(
+    form: web::Form<CodeFormData>,
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+    session: TypedSession,
+    session_index: web::Data<SessionIndex>,
+    login_guard: web::Data<LoginGuard>,
+    email_client: web::Data<EmailClient>,
+    client_info: ClientInfo,
+) -> Result<HttpResponse, actix_web::Error> {
+    if is_two_factor_required(&pool).await.map_err(e500)? {
+        FlashMessage::error(
+            "Two-factor authentication is required for all users.",
+        )
+        .send();
+        return Ok(seeother("/admin/two_factor"));
+    }
+    let Some(totp) = get_totp(&pool, **user_id).await.map_err(e500)? else {
+        return Ok(seeother("/admin/two_factor"));
+    };
+    let username = get_username(**user_id, &pool).await.map_err(e500)?;
+    if login_guard.is_locked(&username).await.map_err(e500)? {
+        return end_locked_session(&session, &session_index, **user_id).await;
+    }
+    if !verify_second_factor(&pool, **user_id, &totp, &form.code)
+        .await
+        .map_err(e500)?
+    {
+        if login_guard
+            .record_second_factor_failure(
+                **user_id,
+                &username,
+                &pool,
+                &email_client,
+            )
+            .await
+            .map_err(e500)?
+        {
+            return end_locked_session(&session, &session_index, **user_id)
+                .await;
+        }
+        FlashMessage::error("Invalid authentication code.").send();
+        return Ok(seeother("/admin/two_factor"));
+    }
+    login_guard
+        .record_second_factor_success(**user_id)
+        .await
+        .map_err(e500)?;
+    disable_two_factor(&pool, **user_id).await.map_err(e500)?;
+    record_audit_event(
+        pool.get_ref(),
+        **user_id,
+        &client_info,
+        AuditAction::TwoFactorDisabled,
+        serde_json::json!({}),
+    )
+    .await
+    .map_err(e500)?;
+    FlashMessage::info("Two-factor authentication has been disabled.").send();
+    Ok(seeother("/admin/two_factor"))
+}
+
+/// Log out a session whose account got locked: it starts over with the
+/// password once the lockout expires.
+pub(crate) async fn end_locked_session(
+    session: &TypedSession,
+    session_index: &SessionIndex,
+    user_id: Uuid,
+) -> Result<HttpResponse, actix_web::Error> {
+    if let Some(session_id) = session.get_session_id().map_err(e500)? {
+        session_index
+            .revoke(user_id, session_id)
+            .await
+            .map_err(e500)?;
+    }
+    session.logout();
+    FlashMessage::error(
+        "Too many failed login attempts, please try again later.",
+    )
+    .send();
+    Ok(seeother("/login"))
+}
+
+/// The secret being set up in this session, or a new one.
+pub(crate) fn enrolment(session: &TypedSession) -> Result<Totp, anyhow::Error> {
+    if let Some(secret) = session.get_totp_enrolment()? {
+        if let Ok(totp) = Totp::from_base32(secret.expose_secret()) {
+            return Ok(totp);
+        }
+    }
+    let totp = Totp::generate();
+    session.insert_totp_enrolment(&totp.to_base32())?;
+    Ok(totp)
+}
+
+/// Store the secret being set up if `code` matches it, along with a fresh set
+/// of recovery codes that are returned to be shown once.
+pub(crate) async fn confirm_enrolment(
+    pool: &PgPool,
+    session: &TypedSession,
+    user_id: uuid::Uuid,
+    client_info: &ClientInfo,
+    code: &Secret<String>,
+) -> Result<Option<Vec<String>>, actix_web::Error> {
+    let Some(secret) = session.get_totp_enrolment().map_err(e500)? else {
+        return Ok(None);
+    };
+    let Ok(totp) = Totp::from_base32(secret.expose_secret()) else {
+        return Ok(None);
+    };
+    let Some(step) = totp.verify(code.expose_secret(), Utc::now().timestamp())
+    else {
+        return Ok(None);
+    };
+    let recovery_codes = generate_recovery_codes();
+    enable_two_factor(pool, user_id, &totp, step, &recovery_codes)
+        .await
+        .map_err(e500)?;
+    session.remove_totp_enrolment();
+    record_audit_event(
+        pool,
+        user_id,
+        client_info,
+        AuditAction::TwoFactorEnabled,
+        serde_json::json!({}),
+    )
+    .await
+    .map_err(e500)?;
+    Ok(Some(recovery_codes))
+}
+
+/// The QR code and secret to add to an authenticator app, and a form to
+/// confirm it with a first code. `hidden_fields` are added to the form, e.g.
+/// the CSRF token.
+pub(crate) fn enrolment_html(
+    totp: &Totp,
+    username: &str,
+    action: &str,
+    hidden_fields: &str,
+) -> String {
+    format!(
+        r#"<p>Scan this QR code with your authenticator app, or enter the secret by hand.</p>
+    {qr_code}
+    <p>Secret: <code>{secret}</code></p>
+    <form name="enableTwoFactor" action="{action}" method="post">
+        {hidden_fields}
+        {code_input}
+        <button type="submit">Enable two-factor authentication</button>
+    </form>"#,
+        qr_code = totp.qr_code_svg(TOTP_ISSUER, username),
+        secret = totp.to_base32().expose_secret(),
+        code_input = code_input_html("Authentication code"),
+    )
+}
+
+pub(crate) fn code_input_html(label: &str) -> String {
+    format!(
+        r#"<label>{label}
+            <input type="text" name="code" autocomplete="one-time-code" autofocus>
+        </label>"#
+    )
+}
+
+/// Recovery codes are only ever shown here, right after they are generated.
+pub(crate) fn recovery_codes_page(
+    recovery_codes: &[String],
+    continue_to: &str,
+) -> HttpResponse {
+    let mut codes_html = String::new();
+    for code in recovery_codes {
+        writeln!(codes_html, "<li><code>{code}</code></li>").unwrap();
+    }
+    HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Recovery codes</title>
+</head>
+<body>
+    <p>Two-factor authentication is enabled.</p>
+    <p>Keep these recovery codes somewhere safe. Each of them lets you log in once without your authenticator app, and they will not be shown again.</p>
+    <ul>
+        {codes_html}
+    </ul>
+    <p><a href="{continue_to}">Continue</a></p>
+</body>
+</html>
+"#,
+        ))
+}
@@ -0,0 +1,366 @@
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use anyhow::Context;
+use chrono::{Duration, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::audit::{record_audit_event, AuditAction};
+use crate::authentication::{
+    hash_token, set_two_factor_required, Role, UserId,
+};
+use crate::client_info::ClientInfo;
+use crate::domain::SubscriberEmail;
+use crate::email_client::EmailClient;
+use crate::routes::admin::dashboard::get_username;
+use crate::routes::{e500, generate_subscription_token, seeother};
+use crate::session_index::SessionIndex;
+use crate::startup::ApplicationBaseUrl;
+
+/// How long an invitation link can be used for.
+pub const INVITATION_VALIDITY_HOURS: i64 = 72;
+
+#[derive(serde::Deserialize)]
+pub struct InvitationFormData {
+    email: String,
+    role: String,
+}
+
+#[derive(serde::Deserialize)]
+pub struct RoleFormData {
+    role: String,
+}
+
+#[derive(serde::Deserialize)]
+pub struct TwoFactorRequirementFormData {
+    required: bool,
+}
+
+/// Email an invitation link to a colleague. Inviting the same address again
+/// replaces the previous invitation.
+#[tracing::instrument(
+    name = "Invite a user",
+    skip(form, pool, email_client, base_url),
+    fields(user_id = %*user_id)
+)]
+pub async fn invite_user(
+    form: web::Form<InvitationFormData>,
+    pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    user_id: web::ReqData<UserId>,
+    client_info: ClientInfo,
+) -> Result<HttpResponse, actix_web::Error> {
+    let parsed = SubscriberEmail::parse(form.0.email.trim().to_string())
+        .and_then(|email| Ok((email, Role::parse(&form.0.role)?)));
+    let (email, role) = match parsed {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            FlashMessage::error(e).send();
+            return Ok(seeother("/admin/users"));
+        }
+    };
+    if email_is_taken(&pool, &email).await.map_err(e500)? {
+        FlashMessage::error(format!("{} already has an account.", email))
+            .send();
+        return Ok(seeother("/admin/users"));
+    }
+
+    let token = generate_subscription_token();
+    store_invitation(&pool, &email, role, &token, **user_id)
+        .await
+        .map_err(e500)?;
+    let inviter = get_username(**user_id, &pool).await.map_err(e500)?;
+    send_invitation_email(&email_client, &email, &inviter, &base_url.0, &token)
+        .await
+        .context("failed to send invitation email")
+        .map_err(e500)?;
+    record_audit_event(
+        pool.get_ref(),
+        **user_id,
+        &client_info,
+        AuditAction::UserInvited,
+        serde_json::json!({ "email": email.as_ref(), "role": role }),
+    )
+    .await
+    .map_err(e500)?;
+
+    FlashMessage::info(format!("An invitation has been sent to {}.", email))
+        .send();
+    Ok(seeother("/admin/users"))
+}
+
+async fn email_is_taken(
+    pool: &PgPool,
+    email: &SubscriberEmail,
+) -> Result<bool, anyhow::Error> {
+    let user = sqlx::query!(
+        "SELECT user_id FROM users WHERE lower(email) = lower($1)",
+        email.as_ref()
+    )
+    .fetch_optional(pool)
+    .await
+    .context("failed to look up users by email")?;
+    Ok(user.is_some())
+}
+
+#[tracing::instrument(name = "Store invitation", skip(pool, token))]
+async fn store_invitation(
+    pool: &PgPool,
+    email: &SubscriberEmail,
+    role: Role,
+    token: &str,
+    invited_by: Uuid,
+) -> Result<(), anyhow::Error> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("failed to acquire postgres connection from pool")?;
+    sqlx::query!(
+        r#"
+        DELETE FROM user_invitations
+        WHERE lower(email) = lower($1) AND accepted_at IS NULL
+        "#,
+        email.as_ref()
+    )
+    .execute(&mut transaction)
+    .await
+    .context("failed to delete previous invitations")?;
+    let now = Utc::now();
+    sqlx::query!(
+        r#"
+        INSERT INTO user_invitations (
+            id, email, role, token_hash, invited_by, created_at, expires_at
+        )
+        VALUES ($1, $2, $3, $4, $5, $6, $7)
+        "#,
+        Uuid::new_v4(),
+        email.as_ref(),
+        role as Role,
+        hash_token(token),
+        invited_by,
+        now,
+        now + Duration::hours(INVITATION_VALIDITY_HOURS),
+    )
+    .execute(&mut transaction)
+    .await
+    .context("failed to store invitation")?;
+    transaction
+        .commit()
+        .await
+        .context("failed to commit sql transaction to store invitation")?;
+    Ok(())
+}
+
+#[tracing::instrument(
+    name = "Send an invitation email",
+    skip(email_client, base_url, token)
+)]
+async fn send_invitation_email(
+    email_client: &EmailClient,
+    email: &SubscriberEmail,
+    inviter: &str,
+    base_url: &str,
+    token: &str,
+) -> Result<(), reqwest::Error> {
+    let invitation_link = format!("{}/invitations?token={}", base_url, token);
+    let plain_body = format!(
+        "{} invited you to help run the newsletter.\n\
+        Visit {} to choose your username and password. \
+        The link expires in {} hours.",
+        inviter, invitation_link, INVITATION_VALIDITY_HOURS
+    );
+    let html_body = format!(
+        "{} invited you to help run the newsletter.<br />\
+        Click <a href=\"{}\">here</a> to choose your username and password. \
+        The link expires in {} hours.",
+        htmlescape::encode_minimal(inviter),
+        invitation_link,
+        INVITATION_VALIDITY_HOURS
+    );
+    email_client
+        .send_email(
+            email,
+            "You have been invited to the newsletter admin",
+            &html_body,
+            &plain_body,
+        )
+        .await
+}
+
+/// Prevent a user from logging in, and log them out of their sessions. They
+/// stay in the database, so that what they did remains attributed to them.
+#[tracing::instrument(
+    name = "Deactivate a user",
+    skip(pool, session_index),
+    fields(user_id = %*user_id)
+)]
+pub async fn deactivate_user(
+    target_user_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    session_index: web::Data<SessionIndex>,
+    user_id: web::ReqData<UserId>,
+    client_info: ClientInfo,
+) -> Result<HttpResponse, actix_web::Error> {
+    if *target_user_id == **user_id {
+        FlashMessage::error("You cannot deactivate your own account.").send();
+        return Ok(seeother("/admin/users"));
+    }
+    let response =
+        set_user_active(&pool, *target_user_id, false, **user_id, &client_info)
+            .await?;
+    session_index
+        .revoke_all(*target_user_id, None)
+        .await
+        .map_err(e500)?;
+    Ok(response)
+}
+
+#[tracing::instrument(
+    name = "Reactivate a user",
+    skip(pool),
+    fields(user_id = %*user_id)
+)]
+pub async fn reactivate_user(
+    target_user_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+    client_info: ClientInfo,
+) -> Result<HttpResponse, actix_web::Error> {
+    set_user_active(&pool, *target_user_id, true, **user_id, &client_info).await
+}
+
+#[tracing::instrument(
+    name = "Change the role of a user",
+    skip(form, pool),
+    fields(user_id = %*user_id)
+)]
+pub async fn change_user_role(
+    target_user_id: web::Path<Uuid>,
+    form: web::Form<RoleFormData>,
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+    client_info: ClientInfo,
+) -> Result<HttpResponse, actix_web::Error> {
+    if *target_user_id == **user_id {
+        FlashMessage::error("You cannot change your own role.").send();
+        return Ok(seeother("/admin/users"));
+    }
+    let role = match Role::parse(&form.role) {
+        Ok(role) => role,
+        Err(e) => {
+            FlashMessage::error(e).send();
+            return Ok(seeother("/admin/users"));
+        }
+    };
+    let user = sqlx::query!(
+        "UPDATE users SET role = $1 WHERE user_id = $2 RETURNING username",
+        role as Role,
+        *target_user_id
+    )
+    .fetch_optional(pool.as_ref())
+    .await
+    .context("failed to update the role of the user")
+    .map_err(e500)?;
+    let Some(user) = user else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+    record_audit_event(
+        pool.get_ref(),
+        **user_id,
+        &client_info,
+        AuditAction::UserRoleChanged,
+        serde_json::json!({ "user_id": *target_user_id, "role": role }),
+    )
+    .await
+    .map_err(e500)?;
+
+    FlashMessage::info(format!("{} is now {}.", user.username, role)).send();
+    Ok(seeother("/admin/users"))
+}
+
+/// Make every user set up two-factor authentication the next time they log
+/// in, or stop requiring it.
+#[tracing::instrument(
+    name = "Change the two-factor requirement",
+    skip(form, pool),
+    fields(user_id = %*user_id, required = form.required)
+)]
+pub async fn require_two_factor(
+    form: web::Form<TwoFactorRequirementFormData>,
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+    client_info: ClientInfo,
+) -> Result<HttpResponse, actix_web::Error> {
+    set_two_factor_required(&pool, form.required)
+        .await
+        .map_err(e500)?;
+    record_audit_event(
+        pool.get_ref(),
+        **user_id,
+        &client_info,
+        AuditAction::TwoFactorRequirementChanged,
+        serde_json::json!({ "required": form.required }),
+    )
+    .await
+    .map_err(e500)?;
+    FlashMessage::info(if form.required {
+        "Two-factor authentication is now required for all users."
+    } else {
+        "Two-factor authentication is no longer required."
+    })
+    .send();
+    Ok(seeother("/admin/users"))
+}
+
+async fn set_user_active(
+    pool: &PgPool,
+    target_user_id: Uuid,
+    is_active: bool,
+    user_id: Uuid,
+    client_info: &ClientInfo,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user = sqlx::query!(
+        r#"
+        UPDATE users
+        SET is_active = $1
+        WHERE user_id = $2
+        RETURNING username
+        "#,
+        is_active,
+        target_user_id
+    )
+    .fetch_optional(pool)
+    .await
+    .context("failed to update user")
+    .map_err(e500)?;
+    let Some(user) = user else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+    let action = if is_active {
+        AuditAction::UserReactivated
+    } else {
+        AuditAction::UserDeactivated
+    };
+    record_audit_event(
+        pool,
+        user_id,
+        client_info,
+        action,
+        serde_json::json!({ "user_id": target_user_id }),
+    )
+    .await
+    .map_err(e500)?;
+
+    FlashMessage::info(format!(
+        "{} has been {}.",
+        user.username,
+        if is_active {
+            "reactivated"
+        } else {
+            "deactivated"
+        }
+    ))
+    .send();
+    Ok(seeother("/admin/users"))
+}
@@ -0,0 +1,212 @@
+use std::fmt::Write;
+
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::IncomingFlashMessages;
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::authentication::{is_two_factor_required, CsrfToken, Role, UserId};
+use crate::routes::e500;
+
+struct User {
+    user_id: Uuid,
+    username: String,
+    email: Option<String>,
+    role: Role,
+    is_active: bool,
+    two_factor: bool,
+    created_at: DateTime<Utc>,
+}
+
+struct PendingInvitation {
+    email: String,
+    role: Role,
+    invited_by: String,
+    expires_at: DateTime<Utc>,
+}
+
+pub async fn list_users(
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+    flash_messages: IncomingFlashMessages,
+    csrf_token: CsrfToken,
+) -> Result<HttpResponse, actix_web::Error> {
+    let users = get_users(&pool).await.map_err(e500)?;
+    let invitations = get_pending_invitations(&pool).await.map_err(e500)?;
+    let two_factor_required =
+        is_two_factor_required(&pool).await.map_err(e500)?;
+
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(
+            msg_html,
+            "<p><i>{}</i></p>",
+            htmlescape::encode_minimal(m.content())
+        )
+        .unwrap();
+    }
+    let csrf_field = csrf_token.form_field();
+
+    let mut users_html = String::new();
+    for u in &users {
+        let action = |path: &str, label: &str| {
+            format!(
+                r#"<form action="/admin/users/{}/{}" method="post">{}<button type="submit">{}</button></form>"#,
+                u.user_id, path, csrf_field, label
+            )
+        };
+        // Admins cannot lock themselves out.
+        let (role_html, action_html) = if u.user_id == **user_id {
+            (u.role.to_string(), String::new())
+        } else {
+            let role_html = format!(
+                r#"<form action="/admin/users/{}/role" method="post">{}<select name="role">{}</select><button type="submit">Change role</button></form>"#,
+                u.user_id,
+                csrf_field,
+                role_options(u.role)
+            );
+            if u.is_active {
+                (role_html, action("deactivate", "Deactivate"))
+            } else {
+                (role_html, action("reactivate", "Reactivate"))
+            }
+        };
+        writeln!(
+            users_html,
+            "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+            htmlescape::encode_minimal(&u.username),
+            htmlescape::encode_minimal(u.email.as_deref().unwrap_or_default()),
+            role_html,
+            if u.is_active { "active" } else { "deactivated" },
+            if u.two_factor { "enabled" } else { "disabled" },
+            u.created_at.to_rfc3339(),
+            action_html,
+        )
+        .unwrap();
+    }
+
+    let mut invitations_html = String::new();
+    for i in &invitations {
+        writeln!(
+            invitations_html,
+            "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+            htmlescape::encode_minimal(&i.email),
+            i.role,
+            htmlescape::encode_minimal(&i.invited_by),
+            i.expires_at.to_rfc3339(),
+        )
+        .unwrap();
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Users</title>
+</head>
+<body>
+    {msg_html}
+    <table>
+        <tr><th>Username</th><th>Email</th><th>Role</th><th>Status</th><th>Two-factor</th><th>Created at</th><th></th></tr>
+        {users_html}
+    </table>
+    <form name="twoFactorRequirement" action="/admin/users/two_factor_requirement" method="post">
+        {csrf_field}
+        <p>{two_factor_status}</p>
+        <input type="hidden" name="required" value="{require_value}">
+        <button type="submit">{require_label}</button>
+    </form>
+    <p>Pending invitations</p>
+    <table>
+        <tr><th>Email</th><th>Role</th><th>Invited by</th><th>Expires at</th></tr>
+        {invitations_html}
+    </table>
+    <form name="inviteUser" action="/admin/users/invitations" method="post">
+        {csrf_field}
+        <label>Email
+            <input type="email" placeholder="Enter their email" name="email">
+        </label>
+        <label>Role
+            <select name="role">{invitation_role_options}</select>
+        </label>
+        <button type="submit">Invite</button>
+    </form>
+    <p><a href="/admin/dashboard">&lt;- Back</a></p>
+</body>
+</html>
+"#,
+            invitation_role_options = role_options(Role::Viewer),
+            two_factor_status = if two_factor_required {
+                "Two-factor authentication is required for all users."
+            } else {
+                "Two-factor authentication is optional."
+            },
+            require_value = !two_factor_required,
+            require_label = if two_factor_required {
+                "Make two-factor authentication optional"
+            } else {
+                "Require two-factor authentication"
+            },
+        )))
+}
+
+fn role_options(selected: Role) -> String {
+    Role::ALL
+        .into_iter()
+        .map(|role| {
+            let selected = if role == selected { " selected" } else { "" };
+            format!(r#"<option value="{role}"{selected}>{role}</option>"#)
+        })
+        .collect()
+}
+
+#[tracing::instrument(name = "Get users", skip(pool))]
+async fn get_users(pool: &PgPool) -> Result<Vec<User>, anyhow::Error> {
+    sqlx::query_as!(
+        User,
+        r#"
+        SELECT
+            user_id,
+            username,
+            email,
+            role AS "role: Role",
+            is_active,
+            totp_secret IS NOT NULL AS "two_factor!",
+            created_at
+        FROM users
+        ORDER BY created_at, username
+        "#
+    )
+    .fetch_all(pool)
+    .await
+    .context("failed to retrieve users")
+}
+
+#[tracing::instrument(name = "Get pending invitations", skip(pool))]
+async fn get_pending_invitations(
+    pool: &PgPool,
+) -> Result<Vec<PendingInvitation>, anyhow::Error> {
+    sqlx::query_as!(
+        PendingInvitation,
+        r#"
+        SELECT
+            i.email,
+            i.role AS "role: Role",
+            u.username AS invited_by,
+            i.expires_at
+        FROM user_invitations i
+        JOIN users u ON u.user_id = i.invited_by
+        WHERE i.accepted_at IS NULL AND i.expires_at > now()
+        ORDER BY i.created_at
+        "#
+    )
+    .fetch_all(pool)
+    .await
+    .context("failed to retrieve pending invitations")
+}
@@ -0,0 +1,5 @@
+mod actions;
+mod list;
+
+pub use actions::*;
+pub use list::list_users;
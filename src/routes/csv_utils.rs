@@ -0,0 +1,12 @@
+use std::borrow::Cow;
+
+/// Spreadsheets evaluate cells starting with these as formulas: exports and
+/// reports echo text people typed in, so such cells are quoted with a
+/// leading `'` to be shown as text.
+pub fn neutralize_formula(cell: &str) -> Cow<'_, str> {
+    if cell.starts_with(['=', '+', '-', '@', '\t', '\r']) {
+        Cow::Owned(format!("'{}", cell))
+    } else {
+        Cow::Borrowed(cell)
+    }
+}
@@ -0,0 +1,101 @@
+use std::fmt::Write;
+
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::IncomingFlashMessages;
+use anyhow::Context;
+use sqlx::PgPool;
+
+use crate::authentication::hash_token;
+use crate::routes::e500;
+
+#[derive(serde::Deserialize)]
+pub struct Parameters {
+    token: String,
+}
+
+/// Where invited users choose their username and password.
+pub async fn accept_invitation_form(
+    parameters: web::Query<Parameters>,
+    pool: web::Data<PgPool>,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let Some(email) = get_pending_invitation_email(&pool, &parameters.token)
+        .await
+        .map_err(e500)?
+    else {
+        return Ok(HttpResponse::NotFound()
+            .content_type(ContentType::plaintext())
+            .body("This invitation link is invalid or has expired."));
+    };
+
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(
+            msg_html,
+            "<p><i>{}</i></p>",
+            htmlescape::encode_minimal(m.content())
+        )
+        .unwrap();
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Accept invitation</title>
+</head>
+<body>
+    {msg_html}
+    <p>Choose a username and a password for {email}.</p>
+    <form action="/invitations" method="post">
+        <input hidden type="text" name="token" value="{token}">
+        <label>Username
+            <input type="text" placeholder="Enter username" name="username">
+        </label>
+        <br>
+        <label>Password
+            <input type="password" placeholder="Enter password" name="password">
+        </label>
+        <br>
+        <label>Confirm password
+            <input
+                type="password"
+                placeholder="Enter password again"
+                name="password_validate"
+            >
+        </label>
+        <br>
+        <button type="submit">Create account</button>
+    </form>
+</body>
+</html>
+"#,
+            email = htmlescape::encode_minimal(&email),
+            token = htmlescape::encode_attribute(&parameters.token),
+        )))
+}
+
+/// The email address an invitation was sent to, if its token can still be
+/// used.
+#[tracing::instrument(name = "Get pending invitation", skip(pool, token))]
+async fn get_pending_invitation_email(
+    pool: &PgPool,
+    token: &str,
+) -> Result<Option<String>, anyhow::Error> {
+    let invitation = sqlx::query!(
+        r#"
+        SELECT email
+        FROM user_invitations
+        WHERE token_hash = $1 AND accepted_at IS NULL AND expires_at > now()
+        "#,
+        hash_token(token)
+    )
+    .fetch_optional(pool)
+    .await
+    .context("failed to retrieve invitation")?;
+    Ok(invitation.map(|i| i.email))
+}
@@ -0,0 +1,157 @@
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use anyhow::Context;
+use secrecy::{ExposeSecret, Secret};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::audit::{record_audit_event, AuditAction};
+use crate::authentication::{
+    hash_token, PasswordHashing, PasswordPolicy, PasswordPolicyError, Role,
+};
+use crate::client_info::ClientInfo;
+use crate::routes::{e500, seeother};
+use crate::telemetry::spawn_blocking_with_tracing;
+
+const MAX_USERNAME_LENGTH: usize = 64;
+
+#[derive(serde::Deserialize)]
+pub struct FormData {
+    token: String,
+    username: String,
+    password: Secret<String>,
+    password_validate: Secret<String>,
+}
+
+/// Create the invited user's account. The invitation can only be used once.
+#[tracing::instrument(
+    name = "Accept an invitation",
+    skip(form, pool, hashing, policy),
+    fields(username = %form.username)
+)]
+pub async fn accept_invitation(
+    form: web::Form<FormData>,
+    pool: web::Data<PgPool>,
+    hashing: web::Data<PasswordHashing>,
+    policy: web::Data<PasswordPolicy>,
+    client_info: ClientInfo,
+) -> Result<HttpResponse, actix_web::Error> {
+    let form = form.into_inner();
+    let invitation_page =
+        format!("/invitations?token={}", urlencoding::encode(&form.token));
+    let username = form.username.trim().to_string();
+    if username.is_empty() || username.chars().count() > MAX_USERNAME_LENGTH {
+        FlashMessage::error(format!(
+            "Username must be between 1 and {} characters.",
+            MAX_USERNAME_LENGTH
+        ))
+        .send();
+        return Ok(seeother(&invitation_page));
+    }
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("failed to acquire postgres connection from pool")
+        .map_err(e500)?;
+    // Lock the invitation, so that it cannot be accepted twice.
+    let invitation = sqlx::query!(
+        r#"
+        SELECT id, email, role AS "role: Role"
+        FROM user_invitations
+        WHERE token_hash = $1 AND accepted_at IS NULL AND expires_at > now()
+        FOR UPDATE
+        "#,
+        hash_token(&form.token)
+    )
+    .fetch_optional(&mut transaction)
+    .await
+    .context("failed to retrieve invitation")
+    .map_err(e500)?;
+    let Some(invitation) = invitation else {
+        return Ok(HttpResponse::NotFound()
+            .body("This invitation link is invalid or has expired."));
+    };
+
+    let username_is_taken =
+        sqlx::query!("SELECT user_id FROM users WHERE username = $1", username)
+            .fetch_optional(&mut transaction)
+            .await
+            .context("failed to look up username")
+            .map_err(e500)?
+            .is_some();
+    if username_is_taken {
+        FlashMessage::error("This username is taken.").send();
+        return Ok(seeother(&invitation_page));
+    }
+    match policy
+        .check(
+            &form.password,
+            &form.password_validate,
+            &[&username, &invitation.email],
+        )
+        .await
+    {
+        Ok(()) => {}
+        Err(PasswordPolicyError::Rejected(e)) => {
+            FlashMessage::error(e).send();
+            return Ok(seeother(&invitation_page));
+        }
+        Err(PasswordPolicyError::UnexpectedError(e)) => return Err(e500(e)),
+    }
+
+    let password = form.password;
+    let password_hash =
+        spawn_blocking_with_tracing(move || hashing.hash(password))
+            .await
+            .context("failed to spawn blocking task.")
+            .map_err(e500)?
+            .map_err(e500)?;
+
+    let user_id = Uuid::new_v4();
+    sqlx::query!(
+        r#"
+        INSERT INTO users (user_id, username, password_hash, email, role)
+        VALUES ($1, $2, $3, $4, $5)
+        "#,
+        user_id,
+        username,
+        password_hash.expose_secret(),
+        invitation.email,
+        invitation.role as Role,
+    )
+    .execute(&mut transaction)
+    .await
+    .context("failed to create user")
+    .map_err(e500)?;
+    sqlx::query!(
+        r#"
+        UPDATE user_invitations
+        SET accepted_at = now(), user_id = $1
+        WHERE id = $2
+        "#,
+        user_id,
+        invitation.id
+    )
+    .execute(&mut transaction)
+    .await
+    .context("failed to mark invitation as accepted")
+    .map_err(e500)?;
+    record_audit_event(
+        &mut transaction,
+        user_id,
+        &client_info,
+        AuditAction::InvitationAccepted,
+        serde_json::json!({ "username": username, "role": invitation.role }),
+    )
+    .await
+    .map_err(e500)?;
+    transaction
+        .commit()
+        .await
+        .context("failed to commit sql transaction to create user")
+        .map_err(e500)?;
+
+    FlashMessage::info("Your account has been created, you can now log in.")
+        .send();
+    Ok(seeother("/login"))
+}
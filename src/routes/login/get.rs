@@ -7,7 +7,12 @@ use actix_web_flash_messages::IncomingFlashMessages;
 pub async fn login_form(flash_messages: IncomingFlashMessages) -> HttpResponse {
     let mut error_html = String::new();
     for m in flash_messages.iter() {
-        writeln!(error_html, "<p><i>{}</i></p>", m.content()).unwrap();
+        writeln!(
+            error_html,
+            "<p><i>{}</i></p>",
+            htmlescape::encode_minimal(m.content())
+        )
+        .unwrap();
     }
 
     HttpResponse::Ok()
@@ -39,6 +44,7 @@ pub async fn login_form(flash_messages: IncomingFlashMessages) -> HttpResponse {
             </label>
             <button type="submit">Login</button>
         </form>
+        <p><a href="/password_reset">Forgot your password?</a></p>
     </body>
 </html>"#,
         ))
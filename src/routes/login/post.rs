@@ -6,9 +6,17 @@ use reqwest::StatusCode;
 use secrecy::Secret;
 use sqlx::PgPool;
 
-use crate::authentication::{validate_credentials, AuthError, Credentials};
+use crate::audit::{record_audit_event, AuditAction};
+use crate::authentication::{
+    needs_second_factor, validate_credentials, AuthError, Credentials,
+    PasswordHashing,
+};
+use crate::client_info::ClientInfo;
+use crate::email_client::EmailClient;
+use crate::login_guard::LoginGuard;
 use crate::routes::error_chain_fmt;
 use crate::session::TypedSession;
+use crate::session_index::SessionIndex;
 
 #[derive(serde::Deserialize)]
 pub struct FormData {
@@ -16,25 +24,74 @@ pub struct FormData {
     password: Secret<String>,
 }
 
+#[allow(clippy::too_many_arguments)]
 pub async fn login(
     form: web::Form<FormData>,
     pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+    login_guard: web::Data<LoginGuard>,
+    hashing: web::Data<PasswordHashing>,
+    session_index: web::Data<SessionIndex>,
+    client_info: ClientInfo,
     session: TypedSession,
 ) -> Result<HttpResponse, InternalError<LoginError>> {
+    let username = form.0.username;
     let creds = Credentials {
-        username: form.0.username,
+        username: username.clone(),
         password: form.0.password,
     };
 
     tracing::Span::current()
-        .record("username", &tracing::field::display(&creds.username));
+        .record("username", tracing::field::display(&creds.username));
 
-    match validate_credentials(creds, &pool).await {
+    if let Some(rejection) =
+        login_guard
+            .screen(&username, &client_info)
+            .await
+            .map_err(|e| login_redirect(LoginError::UnexpectedError(e)))?
+    {
+        return Err(login_redirect(LoginError::Throttled(rejection.into())));
+    }
+
+    match validate_credentials(creds, &hashing, &pool).await {
         Ok(user_id) => {
             tracing::Span::current()
-                .record("user_id", &tracing::field::display(&user_id));
+                .record("user_id", tracing::field::display(&user_id));
+            login_guard
+                .record_success(&username, &client_info)
+                .await
+                .map_err(|e| login_redirect(LoginError::UnexpectedError(e)))?;
+            let needs_second_factor = needs_second_factor(&pool, user_id)
+                .await
+                .map_err(|e| login_redirect(LoginError::UnexpectedError(e)))?;
             session.renew();
-            session.insert_user_id(user_id).map_err(|e| {
+            if needs_second_factor {
+                // The session is only half-authenticated until the second
+                // step succeeds, which `reject_anonymous_users` does not let
+                // through.
+                session.insert_pending_user_id(user_id).map_err(|e| {
+                    login_redirect(LoginError::UnexpectedError(e.into()))
+                })?;
+                return Ok(HttpResponse::SeeOther()
+                    .insert_header((LOCATION, "/login/two_factor"))
+                    .finish());
+            }
+            let session_id = session.insert_user_id(user_id).map_err(|e| {
+                login_redirect(LoginError::UnexpectedError(e.into()))
+            })?;
+            session_index
+                .add(user_id, session_id, &client_info)
+                .await
+                .map_err(|e| login_redirect(LoginError::UnexpectedError(e)))?;
+            record_audit_event(
+                pool.get_ref(),
+                user_id,
+                &client_info,
+                AuditAction::Login,
+                serde_json::json!({ "two_factor": false }),
+            )
+            .await
+            .map_err(|e| {
                 login_redirect(LoginError::UnexpectedError(e.into()))
             })?;
             Ok(HttpResponse::SeeOther()
@@ -44,7 +101,17 @@ pub async fn login(
         Err(e) => {
             let e = match e {
                 AuthError::InvalidCredentials(_) => {
-                    LoginError::AuthError(e.into())
+                    let locked = login_guard
+                        .record_failure(&username, &pool, &email_client)
+                        .await
+                        .map_err(|e| {
+                            login_redirect(LoginError::UnexpectedError(e))
+                        })?;
+                    if locked {
+                        LoginError::Throttled(e.into())
+                    } else {
+                        LoginError::AuthError(e.into())
+                    }
                 }
                 AuthError::UnexpectedError(_) => {
                     LoginError::UnexpectedError(e.into())
@@ -71,6 +138,9 @@ pub enum LoginError {
     #[error("Authentication failed")]
     AuthError(#[source] anyhow::Error),
 
+    #[error("Too many failed login attempts, please try again later.")]
+    Throttled(#[source] anyhow::Error),
+
     #[error("Something went wrong")]
     UnexpectedError(#[from] anyhow::Error),
 }
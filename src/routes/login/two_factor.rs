@@ -0,0 +1,211 @@
+use std::fmt::Write;
+
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::audit::{record_audit_event, AuditAction};
+use crate::authentication::{
+    get_totp, is_two_factor_required, verify_second_factor,
+};
+use crate::client_info::ClientInfo;
+use crate::email_client::EmailClient;
+use crate::login_guard::LoginGuard;
+use crate::routes::admin::dashboard::get_username;
+use crate::routes::admin::two_factor::{
+    code_input_html, confirm_enrolment, enrolment, enrolment_html,
+    recovery_codes_page, CodeFormData,
+};
+use crate::routes::{e500, seeother};
+use crate::session::TypedSession;
+use crate::session_index::SessionIndex;
+
+/// Second login step, for users who entered their password. Users who have
+/// to use two-factor authentication but have not set it up do so here.
+pub async fn two_factor_form(
+    pool: web::Data<PgPool>,
+    session: TypedSession,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let Some(user_id) = session.get_pending_user_id().map_err(e500)? else {
+        return Ok(seeother("/login"));
+    };
+
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(
+            msg_html,
+            "<p><i>{}</i></p>",
+            htmlescape::encode_minimal(m.content())
+        )
+        .unwrap();
+    }
+
+    let body_html = if get_totp(&pool, user_id).await.map_err(e500)?.is_some() {
+        format!(
+            r#"<form action="/login/two_factor" method="post">
+        {code_input}
+        <button type="submit">Verify</button>
+    </form>"#,
+            code_input = code_input_html(
+                "Enter the code from your authenticator app, or a recovery code"
+            ),
+        )
+    } else {
+        let totp = enrolment(&session).map_err(e500)?;
+        let username = get_username(user_id, &pool).await.map_err(e500)?;
+        format!(
+            "<p>Two-factor authentication is required, please set it up to continue.</p>\n    {}",
+            enrolment_html(&totp, &username, "/login/two_factor", "")
+        )
+    };
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Two-factor authentication</title>
+</head>
+<body>
+    {msg_html}
+    {body_html}
+</body>
+</html>
+"#,
+        )))
+}
+
+pub async fn verify_two_factor(
+    form: web::Form<CodeFormData>,
+    pool: web::Data<PgPool>,
+    session: TypedSession,
+    session_index: web::Data<SessionIndex>,
+    login_guard: web::Data<LoginGuard>,
+    email_client: web::Data<EmailClient>,
+    client_info: ClientInfo,
+) -> Result<HttpResponse, actix_web::Error> {
+    let Some(user_id) = session.get_pending_user_id().map_err(e500)? else {
+        return Ok(seeother("/login"));
+    };
+    let username = get_username(user_id, &pool).await.map_err(e500)?;
+    // Wrong codes from another session may have locked the account since the
+    // password was entered.
+    if login_guard.is_locked(&username).await.map_err(e500)? {
+        return Ok(abandon_login(&session));
+    }
+
+    match get_totp(&pool, user_id).await.map_err(e500)? {
+        Some(totp) => {
+            if verify_second_factor(&pool, user_id, &totp, &form.code)
+                .await
+                .map_err(e500)?
+            {
+                complete_login(
+                    &pool,
+                    &session,
+                    &session_index,
+                    &login_guard,
+                    user_id,
+                    &client_info,
+                    true,
+                )
+                .await?;
+                return Ok(seeother("/admin/dashboard"));
+            }
+        }
+        None if is_two_factor_required(&pool).await.map_err(e500)? => {
+            if let Some(recovery_codes) = confirm_enrolment(
+                &pool,
+                &session,
+                user_id,
+                &client_info,
+                &form.code,
+            )
+            .await?
+            {
+                complete_login(
+                    &pool,
+                    &session,
+                    &session_index,
+                    &login_guard,
+                    user_id,
+                    &client_info,
+                    true,
+                )
+                .await?;
+                return Ok(recovery_codes_page(
+                    &recovery_codes,
+                    "/admin/dashboard",
+                ));
+            }
+        }
+        // Two-factor authentication was disabled in the meantime.
+        None => {
+            complete_login(
+                &pool,
+                &session,
+                &session_index,
+                &login_guard,
+                user_id,
+                &client_info,
+                false,
+            )
+            .await?;
+            return Ok(seeother("/admin/dashboard"));
+        }
+    }
+    if login_guard
+        .record_second_factor_failure(user_id, &username, &pool, &email_client)
+        .await
+        .map_err(e500)?
+    {
+        return Ok(abandon_login(&session));
+    }
+    FlashMessage::error("Invalid authentication code.").send();
+    Ok(seeother("/login/two_factor"))
+}
+
+/// Drop the half-authenticated session of a locked account: the user starts
+/// over with their password once the lockout expires.
+fn abandon_login(session: &TypedSession) -> HttpResponse {
+    session.logout();
+    FlashMessage::error(
+        "Too many failed login attempts, please try again later.",
+    )
+    .send();
+    seeother("/login")
+}
+
+async fn complete_login(
+    pool: &PgPool,
+    session: &TypedSession,
+    session_index: &SessionIndex,
+    login_guard: &LoginGuard,
+    user_id: Uuid,
+    client_info: &ClientInfo,
+    two_factor: bool,
+) -> Result<(), actix_web::Error> {
+    login_guard
+        .record_second_factor_success(user_id)
+        .await
+        .map_err(e500)?;
+    let session_id = session.complete_login(user_id).map_err(e500)?;
+    session_index
+        .add(user_id, session_id, client_info)
+        .await
+        .map_err(e500)?;
+    record_audit_event(
+        pool,
+        user_id,
+        client_info,
+        AuditAction::Login,
+        serde_json::json!({ "two_factor": two_factor }),
+    )
+    .await
+    .map_err(e500)
+}
@@ -1,17 +1,29 @@
 mod admin;
+mod csv_utils;
 mod health_check;
 mod home;
 mod http_utils;
+mod invitations;
 mod login;
 mod newsletters;
+mod password_reset;
+mod subscription_status;
 mod subscriptions;
 mod subscriptions_confirm;
+mod subscriptions_form;
+mod subscriptions_widget;
 
 pub use admin::*;
+pub use csv_utils::*;
 pub use health_check::*;
 pub use home::*;
 pub use http_utils::*;
+pub use invitations::*;
 pub use login::*;
 pub use newsletters::*;
+pub use password_reset::*;
+pub use subscription_status::*;
 pub use subscriptions::*;
 pub use subscriptions_confirm::*;
+pub use subscriptions_form::*;
+pub use subscriptions_widget::*;
@@ -1,21 +1,38 @@
+use std::collections::HashMap;
+
 use actix_web::http::header;
 use actix_web::{web, HttpRequest, HttpResponse, ResponseError};
 use anyhow::Context;
 use reqwest::header::HeaderValue;
 use reqwest::StatusCode;
+use secrecy::Secret;
 use sqlx::PgPool;
+use uuid::Uuid;
 
+use crate::audit::{record_audit_event, AuditAction};
 use crate::authentication::{
-    basic_authentication, validate_credentials, AuthError,
+    basic_authentication, bearer_token, get_active_user_role,
+    needs_second_factor, validate_api_token, validate_credentials, ApiScope,
+    AuthError, PasswordHashing, Permission,
+};
+use crate::client_info::ClientInfo;
+use crate::domain::{
+    AttributeSchema, NewsletterTemplate, SubscriberAttributes, SubscriberEmail,
+    SubscriptionStatus,
 };
-use crate::domain::SubscriberEmail;
 use crate::email_client::EmailClient;
-use crate::routes::error_chain_fmt;
+use crate::login_guard::LoginGuard;
+use crate::routes::{error_chain_fmt, referral_link};
+use crate::startup::ApplicationBaseUrl;
 
 #[derive(serde::Deserialize)]
 pub struct BodyData {
     title: String,
     content: Content,
+    /// Only send the issue to subscribers whose custom attributes have these
+    /// values.
+    #[serde(default)]
+    audience: HashMap<String, serde_json::Value>,
 }
 
 #[derive(serde::Deserialize)]
@@ -26,13 +43,71 @@ pub struct Content {
 
 struct ConfirmedSubscriber {
     email: SubscriberEmail,
+    name: String,
+    attributes: SubscriberAttributes,
+    referral_code: String,
+    /// Confirmed subscribers who signed up through their referral link.
+    referral_count: i64,
+}
+
+/// Variables every subscriber has, on top of their custom attributes.
+const TEMPLATE_VARIABLES: [&str; 4] =
+    ["name", "email", "referral_link", "referral_count"];
+
+struct Issue {
+    title: NewsletterTemplate,
+    html: NewsletterTemplate,
+    text: NewsletterTemplate,
+}
+
+impl Issue {
+    fn parse(
+        body: &BodyData,
+        schema: &AttributeSchema,
+    ) -> Result<Self, String> {
+        let variables: Vec<&str> = TEMPLATE_VARIABLES
+            .into_iter()
+            .chain(schema.definitions().iter().map(|d| d.name.as_str()))
+            .collect();
+        Ok(Self {
+            title: NewsletterTemplate::parse(&body.title, &variables)?,
+            html: NewsletterTemplate::parse(&body.content.html, &variables)?,
+            text: NewsletterTemplate::parse(&body.content.text, &variables)?,
+        })
+    }
+}
+
+impl ConfirmedSubscriber {
+    fn variable(&self, name: &str, base_url: &str) -> Option<String> {
+        match name {
+            "name" => Some(self.name.clone()),
+            "email" => Some(self.email.as_ref().to_string()),
+            "referral_link" => {
+                Some(referral_link(base_url, &self.referral_code))
+            }
+            "referral_count" => Some(self.referral_count.to_string()),
+            _ => self.attributes.display(name),
+        }
+    }
 }
 
 #[derive(thiserror::Error)]
 pub enum PublishError {
+    #[error("{0}")]
+    ValidationError(String),
+
     #[error("authentication failed")]
     AuthError(#[source] anyhow::Error),
 
+    #[error("the user is not allowed to publish newsletters")]
+    Forbidden,
+
+    #[error("too many failed authentication attempts")]
+    Throttled(#[source] anyhow::Error),
+
+    #[error("the user has two-factor authentication: an API token is needed")]
+    TwoFactorRequired,
+
     #[error(transparent)]
     UnexpectedError(#[from] anyhow::Error),
 }
@@ -45,10 +120,26 @@ impl std::fmt::Debug for PublishError {
 impl ResponseError for PublishError {
     fn error_response(&self) -> HttpResponse {
         match self {
+            PublishError::ValidationError(_) => {
+                HttpResponse::new(StatusCode::BAD_REQUEST)
+            }
+            PublishError::Forbidden => HttpResponse::new(StatusCode::FORBIDDEN),
+            PublishError::Throttled(_) => {
+                HttpResponse::new(StatusCode::TOO_MANY_REQUESTS)
+            }
             PublishError::UnexpectedError(_) => {
                 HttpResponse::new(StatusCode::INTERNAL_SERVER_ERROR)
             }
 
+            PublishError::TwoFactorRequired => {
+                HttpResponse::build(StatusCode::UNAUTHORIZED)
+                    .insert_header((header::WWW_AUTHENTICATE, "Bearer"))
+                    .body(
+                        "Two-factor authentication is enabled for this \
+                        account: publish with an API token instead of a \
+                        password.",
+                    )
+            }
             PublishError::AuthError(_) => {
                 let mut resp = HttpResponse::new(StatusCode::UNAUTHORIZED);
 
@@ -64,56 +155,100 @@ impl ResponseError for PublishError {
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 #[tracing::instrument(
     name = "publish a newsletter issue",
-    skip(body, pool, email_client, request),
+    skip(
+        body,
+        pool,
+        email_client,
+        base_url,
+        attribute_schema,
+        login_guard,
+        hashing,
+        request
+    ),
     fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
 )]
 pub async fn publish_newsletter(
     body: web::Json<BodyData>,
     pool: web::Data<PgPool>,
     email_client: web::Data<EmailClient>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    attribute_schema: web::Data<AttributeSchema>,
+    login_guard: web::Data<LoginGuard>,
+    hashing: web::Data<PasswordHashing>,
     request: HttpRequest,
 ) -> Result<HttpResponse, PublishError> {
-    let creds = basic_authentication(request.headers())
-        .map_err(PublishError::AuthError)?;
-
+    let client_info = ClientInfo::from_http_request(&request);
+    let publisher = match bearer_token(request.headers()) {
+        Some(token) => authenticate_with_token(&token, &pool).await?,
+        None => {
+            authenticate_with_password(
+                &request,
+                &client_info,
+                &pool,
+                &login_guard,
+                &hashing,
+                &email_client,
+            )
+            .await?
+        }
+    };
+    let user_id = publisher.user_id;
     tracing::Span::current()
-        .record("username", &tracing::field::display(&creds.username));
-
-    let user_id =
-        validate_credentials(creds, &pool)
-            .await
-            .map_err(|e| match e {
-                AuthError::InvalidCredentials(_) => {
-                    PublishError::AuthError(e.into())
-                }
-                AuthError::UnexpectedError(_) => {
-                    PublishError::UnexpectedError(e.into())
-                }
-            })?;
+        .record("user_id", tracing::field::display(&user_id));
+    let role = get_active_user_role(&pool, user_id).await?;
+    if !role.is_some_and(|role| role.can(Permission::PublishNewsletters)) {
+        return Err(PublishError::Forbidden);
+    }
 
-    tracing::Span::current()
-        .record("user_id", &tracing::field::display(&user_id));
+    let issue = Issue::parse(&body, &attribute_schema)
+        .map_err(PublishError::ValidationError)?;
+    let audience: Vec<(&str, String)> = body
+        .audience
+        .iter()
+        .map(|(name, value)| {
+            let value = match value {
+                serde_json::Value::String(s) => s.clone(),
+                other => other.to_string(),
+            };
+            (name.as_str(), value)
+        })
+        .collect();
+    let audience = attribute_schema
+        .parse_filter(
+            audience.iter().map(|(name, value)| (*name, value.as_str())),
+        )
+        .map_err(|e| PublishError::ValidationError(e.to_string()))?;
 
-    let subscribers = get_confirmed_subscribers(&pool).await?;
+    let subscribers = get_confirmed_subscribers(&pool, &audience).await?;
 
+    let mut recipients = 0;
     for subscriber in subscribers {
         match subscriber {
-            Ok(subscriber) => email_client
-                .send_email(
-                    &subscriber.email,
-                    &body.title,
-                    &body.content.html,
-                    &body.content.text,
-                )
-                .await
-                .with_context(|| {
-                    format!(
-                        "failed to send newsltter issue to {}",
-                        subscriber.email
+            Ok(subscriber) => {
+                let variable =
+                    |name: &str| subscriber.variable(name, &base_url.0);
+                email_client
+                    .send_email(
+                        &subscriber.email,
+                        &issue.title.render(variable),
+                        &issue.html.render(|name| {
+                            variable(name)
+                                .map(|v| htmlescape::encode_minimal(&v))
+                        }),
+                        &issue.text.render(variable),
                     )
-                })?,
+                    .await
+                    .with_context(|| {
+                        format!(
+                            "failed to send newsltter issue to {}",
+                            subscriber.email
+                        )
+                    })?;
+                recipients += 1;
+            }
             Err(error) => {
                 tracing::warn!(
                     error.cauase_chain=?error,
@@ -123,19 +258,124 @@ pub async fn publish_newsletter(
         }
     }
 
+    record_audit_event(
+        pool.get_ref(),
+        user_id,
+        &client_info,
+        AuditAction::NewsletterPublished,
+        serde_json::json!({
+            "title": body.title,
+            "audience": body.audience,
+            "recipients": recipients,
+            "api_token_id": publisher.api_token_id,
+        }),
+    )
+    .await
+    .context("failed to record audit event")?;
+
     Ok(HttpResponse::Ok().finish())
 }
 
-#[tracing::instrument(name = "get confirmed subscribers", skip(pool))]
+/// Who is publishing, and through which API token if any.
+struct Publisher {
+    user_id: Uuid,
+    api_token_id: Option<Uuid>,
+}
+
+async fn authenticate_with_token(
+    token: &Secret<String>,
+    pool: &PgPool,
+) -> Result<Publisher, PublishError> {
+    let token = validate_api_token(token, pool).await.map_err(|e| match e {
+        AuthError::InvalidCredentials(_) => PublishError::AuthError(e.into()),
+        AuthError::UnexpectedError(_) => {
+            PublishError::UnexpectedError(e.into())
+        }
+    })?;
+    if !token.scopes.contains(&ApiScope::PublishNewsletters) {
+        return Err(PublishError::Forbidden);
+    }
+    Ok(Publisher {
+        user_id: token.user_id,
+        api_token_id: Some(token.id),
+    })
+}
+
+async fn authenticate_with_password(
+    request: &HttpRequest,
+    client_info: &ClientInfo,
+    pool: &PgPool,
+    login_guard: &LoginGuard,
+    hashing: &web::Data<PasswordHashing>,
+    email_client: &EmailClient,
+) -> Result<Publisher, PublishError> {
+    let creds = basic_authentication(request.headers())
+        .map_err(PublishError::AuthError)?;
+    let username = creds.username.clone();
+
+    tracing::Span::current()
+        .record("username", tracing::field::display(&creds.username));
+
+    // Basic auth is as open to password guessing as the login form.
+    if let Some(rejection) = login_guard.screen(&username, client_info).await? {
+        return Err(PublishError::Throttled(rejection.into()));
+    }
+    let user_id = match validate_credentials(creds, hashing, pool).await {
+        Ok(user_id) => user_id,
+        Err(e @ AuthError::InvalidCredentials(_)) => {
+            let locked = login_guard
+                .record_failure(&username, pool, email_client)
+                .await?;
+            return Err(if locked {
+                PublishError::Throttled(e.into())
+            } else {
+                PublishError::AuthError(e.into())
+            });
+        }
+        Err(e @ AuthError::UnexpectedError(_)) => {
+            return Err(PublishError::UnexpectedError(e.into()));
+        }
+    };
+    login_guard.record_success(&username, client_info).await?;
+    // Basic auth has no room for a second factor: scripts of users who need
+    // one have to use an API token.
+    if needs_second_factor(pool, user_id).await? {
+        return Err(PublishError::TwoFactorRequired);
+    }
+    Ok(Publisher {
+        user_id,
+        api_token_id: None,
+    })
+}
+
+#[tracing::instrument(name = "get confirmed subscribers", skip(pool, audience))]
 async fn get_confirmed_subscribers(
     pool: &PgPool,
+    audience: &SubscriberAttributes,
 ) -> Result<Vec<Result<ConfirmedSubscriber, anyhow::Error>>, anyhow::Error> {
     let rows = sqlx::query!(
         r#"
-        SELECT email 
-        FROM subscriptions 
-        WHERE status='confirmed';
+        SELECT
+            s.email,
+            s.name,
+            s.attributes,
+            s.referral_code,
+            (
+                SELECT COUNT(*)
+                FROM subscriptions r
+                WHERE r.referred_by = s.id
+                    AND EXISTS (
+                        SELECT 1
+                        FROM subscription_status_changes c
+                        WHERE c.subscriber_id = r.id
+                            AND c.to_status = 'confirmed'
+                    )
+            ) AS "referral_count!"
+        FROM subscriptions s
+        WHERE s.status = $1 AND s.attributes @> $2;
         "#,
+        SubscriptionStatus::Confirmed as SubscriptionStatus,
+        audience.to_json(),
     )
     .fetch_all(pool)
     .await?;
@@ -144,7 +384,13 @@ async fn get_confirmed_subscribers(
     let confirmed_subscribers = rows
         .into_iter()
         .map(|r| match SubscriberEmail::parse(r.email) {
-            Ok(email) => Ok(ConfirmedSubscriber { email }),
+            Ok(email) => Ok(ConfirmedSubscriber {
+                email,
+                name: r.name,
+                attributes: SubscriberAttributes::from_json(r.attributes),
+                referral_code: r.referral_code,
+                referral_count: r.referral_count,
+            }),
             Err(error) => Err(anyhow::anyhow!(error)),
         })
         .collect();
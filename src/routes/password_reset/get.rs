@@ -0,0 +1,140 @@
+use std::fmt::Write;
+
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::IncomingFlashMessages;
+use anyhow::Context;
+use sqlx::PgPool;
+
+use crate::authentication::hash_token;
+use crate::routes::e500;
+
+#[derive(serde::Deserialize)]
+pub struct Parameters {
+    token: String,
+}
+
+/// Where users who forgot their password ask for a reset link.
+pub async fn request_password_reset_form(
+    flash_messages: IncomingFlashMessages,
+) -> HttpResponse {
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(
+            msg_html,
+            "<p><i>{}</i></p>",
+            htmlescape::encode_minimal(m.content())
+        )
+        .unwrap();
+    }
+
+    HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Forgot password</title>
+</head>
+<body>
+    {msg_html}
+    <p>Enter the email address of your account and we will send you a link to reset your password.</p>
+    <form action="/password_reset" method="post">
+        <label>Email
+            <input type="email" placeholder="Enter your email" name="email">
+        </label>
+        <button type="submit">Send reset link</button>
+    </form>
+    <p><a href="/login">&lt;- Back to login</a></p>
+</body>
+</html>
+"#,
+        ))
+}
+
+/// Where users choose a new password, from the link they were emailed.
+pub async fn password_reset_form(
+    parameters: web::Query<Parameters>,
+    pool: web::Data<PgPool>,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    if !reset_token_is_valid(&pool, &parameters.token)
+        .await
+        .map_err(e500)?
+    {
+        return Ok(HttpResponse::NotFound()
+            .content_type(ContentType::plaintext())
+            .body("This password reset link is invalid or has expired."));
+    }
+
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(
+            msg_html,
+            "<p><i>{}</i></p>",
+            htmlescape::encode_minimal(m.content())
+        )
+        .unwrap();
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Reset password</title>
+</head>
+<body>
+    {msg_html}
+    <form action="/password_reset/confirm" method="post">
+        <input hidden type="text" name="token" value="{token}">
+        <label>New password
+            <input
+                type="password"
+                placeholder="Enter new password"
+                name="new_password"
+            >
+        </label>
+        <br>
+        <label>Confirm new password
+            <input
+                type="password"
+                placeholder="Enter new password again"
+                name="new_password_validate"
+            >
+        </label>
+        <br>
+        <button type="submit">Reset password</button>
+    </form>
+</body>
+</html>
+"#,
+            token = htmlescape::encode_attribute(&parameters.token),
+        )))
+}
+
+#[tracing::instrument(name = "Check password reset token", skip(pool, token))]
+async fn reset_token_is_valid(
+    pool: &PgPool,
+    token: &str,
+) -> Result<bool, anyhow::Error> {
+    let reset = sqlx::query!(
+        r#"
+        SELECT r.user_id
+        FROM password_reset_tokens r
+        JOIN users u ON u.user_id = r.user_id
+        WHERE r.token_hash = $1
+            AND r.used_at IS NULL
+            AND r.expires_at > now()
+            AND u.is_active
+        "#,
+        hash_token(token)
+    )
+    .fetch_optional(pool)
+    .await
+    .context("failed to retrieve password reset token")?;
+    Ok(reset.is_some())
+}
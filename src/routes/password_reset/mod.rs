@@ -0,0 +1,8 @@
+mod get;
+mod post;
+
+pub use get::{password_reset_form, request_password_reset_form};
+pub use post::{request_password_reset, reset_password};
+
+/// How long a password reset link can be used for.
+pub const PASSWORD_RESET_VALIDITY_MINUTES: i64 = 60;
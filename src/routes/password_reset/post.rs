@@ -0,0 +1,341 @@
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use anyhow::Context;
+use chrono::{Duration, Utc};
+use secrecy::{ExposeSecret, Secret};
+use sqlx::PgPool;
+use tracing::Instrument;
+use uuid::Uuid;
+
+use super::PASSWORD_RESET_VALIDITY_MINUTES;
+use crate::audit::{record_audit_event, AuditAction};
+use crate::authentication::{
+    hash_token, PasswordHashing, PasswordPolicy, PasswordPolicyError,
+};
+use crate::client_info::ClientInfo;
+use crate::configuration::PasswordResetSettings;
+use crate::domain::SubscriberEmail;
+use crate::email_client::EmailClient;
+use crate::rate_limit::RateLimiter;
+use crate::routes::{e500, generate_subscription_token, seeother};
+use crate::session_index::SessionIndex;
+use crate::startup::ApplicationBaseUrl;
+use crate::telemetry::spawn_blocking_with_tracing;
+
+#[derive(serde::Deserialize)]
+pub struct RequestFormData {
+    email: String,
+}
+
+#[derive(serde::Deserialize)]
+pub struct ResetFormData {
+    token: String,
+    new_password: Secret<String>,
+    new_password_validate: Secret<String>,
+}
+
+/// Email a password reset link to the user with this address, if any. The
+/// response is the same either way, so that it does not tell who has an
+/// account.
+#[tracing::instrument(
+    name = "Request a password reset",
+    skip(
+        form,
+        pool,
+        email_client,
+        base_url,
+        rate_limiter,
+        settings,
+        client_info
+    )
+)]
+pub async fn request_password_reset(
+    form: web::Form<RequestFormData>,
+    pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    rate_limiter: web::Data<RateLimiter>,
+    settings: web::Data<PasswordResetSettings>,
+    client_info: ClientInfo,
+) -> Result<HttpResponse, actix_web::Error> {
+    let email = match SubscriberEmail::parse(form.0.email.trim().to_string()) {
+        Ok(email) => email,
+        Err(_) => {
+            FlashMessage::error("Please enter a valid email address.").send();
+            return Ok(seeother("/password_reset"));
+        }
+    };
+
+    if allow_password_reset(&rate_limiter, &settings, &email, &client_info)
+        .await
+        .map_err(e500)?
+    {
+        // Off the request path: neither the time taken nor a failure may
+        // tell whether the address has an account.
+        let email = email.clone();
+        tokio::spawn(
+            async move {
+                if let Err(e) = send_password_reset(
+                    &pool,
+                    &email_client,
+                    &base_url.0,
+                    &email,
+                )
+                .await
+                {
+                    tracing::error!(
+                        error.cause_chain = ?e,
+                        error.message = %e,
+                        "Failed to send a password reset email."
+                    );
+                }
+            }
+            .instrument(tracing::Span::current()),
+        );
+    } else {
+        tracing::warn!("Throttled password reset requests.");
+    }
+
+    FlashMessage::info(format!(
+        "If {} belongs to an account, we have sent it a link to reset the \
+        password.",
+        email
+    ))
+    .send();
+    Ok(seeother("/login"))
+}
+
+/// Set the new password and log the user out of all their sessions. The
+/// link can only be used once.
+#[tracing::instrument(
+    name = "Reset a password",
+    skip(form, pool, hashing, policy, session_index)
+)]
+pub async fn reset_password(
+    form: web::Form<ResetFormData>,
+    pool: web::Data<PgPool>,
+    hashing: web::Data<PasswordHashing>,
+    policy: web::Data<PasswordPolicy>,
+    session_index: web::Data<SessionIndex>,
+    client_info: ClientInfo,
+) -> Result<HttpResponse, actix_web::Error> {
+    let form = form.into_inner();
+    let reset_page = format!(
+        "/password_reset/confirm?token={}",
+        urlencoding::encode(&form.token)
+    );
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("failed to acquire postgres connection from pool")
+        .map_err(e500)?;
+    // Lock the token, so that it cannot be used twice.
+    let reset = sqlx::query!(
+        r#"
+        SELECT r.user_id, u.username, u.email
+        FROM password_reset_tokens r
+        JOIN users u ON u.user_id = r.user_id
+        WHERE r.token_hash = $1
+            AND r.used_at IS NULL
+            AND r.expires_at > now()
+            AND u.is_active
+        FOR UPDATE OF r
+        "#,
+        hash_token(&form.token)
+    )
+    .fetch_optional(&mut transaction)
+    .await
+    .context("failed to retrieve password reset token")
+    .map_err(e500)?;
+    let Some(reset) = reset else {
+        return Ok(HttpResponse::NotFound()
+            .body("This password reset link is invalid or has expired."));
+    };
+    let mut user_inputs = vec![reset.username.as_str()];
+    user_inputs.extend(reset.email.as_deref());
+    match policy
+        .check(
+            &form.new_password,
+            &form.new_password_validate,
+            &user_inputs,
+        )
+        .await
+    {
+        Ok(()) => {}
+        Err(PasswordPolicyError::Rejected(e)) => {
+            FlashMessage::error(e).send();
+            return Ok(seeother(&reset_page));
+        }
+        Err(PasswordPolicyError::UnexpectedError(e)) => return Err(e500(e)),
+    }
+
+    let password = form.new_password;
+    let password_hash =
+        spawn_blocking_with_tracing(move || hashing.hash(password))
+            .await
+            .context("failed to spawn blocking task.")
+            .map_err(e500)?
+            .map_err(e500)?;
+
+    sqlx::query!(
+        r#"
+        UPDATE users
+        SET password_hash = $1, sessions_invalidated_at = now()
+        WHERE user_id = $2
+        "#,
+        password_hash.expose_secret(),
+        reset.user_id
+    )
+    .execute(&mut transaction)
+    .await
+    .context("failed to reset password")
+    .map_err(e500)?;
+    // Other links sent to the user stop working as well.
+    sqlx::query!(
+        r#"
+        UPDATE password_reset_tokens
+        SET used_at = now()
+        WHERE user_id = $1 AND used_at IS NULL
+        "#,
+        reset.user_id
+    )
+    .execute(&mut transaction)
+    .await
+    .context("failed to mark password reset token as used")
+    .map_err(e500)?;
+    record_audit_event(
+        &mut transaction,
+        reset.user_id,
+        &client_info,
+        AuditAction::PasswordReset,
+        serde_json::json!({}),
+    )
+    .await
+    .map_err(e500)?;
+    transaction
+        .commit()
+        .await
+        .context("failed to commit sql transaction to reset password")
+        .map_err(e500)?;
+    session_index
+        .revoke_all(reset.user_id, None)
+        .await
+        .map_err(e500)?;
+
+    FlashMessage::info("Your password has been reset, you can now log in.")
+        .send();
+    Ok(seeother("/login"))
+}
+
+/// Whether a password reset email may be sent to `email`. Requests count
+/// whether or not the address has an account, so that the limits do not
+/// tell either.
+async fn allow_password_reset(
+    rate_limiter: &RateLimiter,
+    settings: &PasswordResetSettings,
+    email: &SubscriberEmail,
+    client_info: &ClientInfo,
+) -> Result<bool, anyhow::Error> {
+    let window = settings.window_seconds;
+    if let Some(ip_address) = &client_info.ip_address {
+        let requests = rate_limiter
+            .hit(&format!("password_reset:ip:{}", ip_address), window)
+            .await?;
+        if requests > settings.max_requests_per_ip {
+            return Ok(false);
+        }
+    }
+    let requests = rate_limiter
+        .hit(
+            &format!("password_reset:email:{}", email.as_ref().to_lowercase()),
+            window,
+        )
+        .await?;
+    Ok(requests <= settings.max_emails_per_address)
+}
+
+/// Email a reset link to the active user with this address, if any.
+async fn send_password_reset(
+    pool: &PgPool,
+    email_client: &EmailClient,
+    base_url: &str,
+    email: &SubscriberEmail,
+) -> Result<(), anyhow::Error> {
+    let Some(user_id) = get_active_user_by_email(pool, email).await? else {
+        return Ok(());
+    };
+    let token = generate_subscription_token();
+    store_reset_token(pool, user_id, &token).await?;
+    send_password_reset_email(email_client, email, base_url, &token)
+        .await
+        .context("failed to send password reset email")
+}
+
+#[tracing::instrument(name = "Get active user by email", skip(pool))]
+async fn get_active_user_by_email(
+    pool: &PgPool,
+    email: &SubscriberEmail,
+) -> Result<Option<Uuid>, anyhow::Error> {
+    let user = sqlx::query!(
+        "SELECT user_id FROM users WHERE lower(email) = lower($1) AND is_active",
+        email.as_ref()
+    )
+    .fetch_optional(pool)
+    .await
+    .context("failed to look up users by email")?;
+    Ok(user.map(|u| u.user_id))
+}
+
+#[tracing::instrument(name = "Store password reset token", skip(pool, token))]
+async fn store_reset_token(
+    pool: &PgPool,
+    user_id: Uuid,
+    token: &str,
+) -> Result<(), anyhow::Error> {
+    let now = Utc::now();
+    sqlx::query!(
+        r#"
+        INSERT INTO password_reset_tokens (
+            token_hash, user_id, created_at, expires_at
+        )
+        VALUES ($1, $2, $3, $4)
+        "#,
+        hash_token(token),
+        user_id,
+        now,
+        now + Duration::minutes(PASSWORD_RESET_VALIDITY_MINUTES),
+    )
+    .execute(pool)
+    .await
+    .context("failed to store password reset token")?;
+    Ok(())
+}
+
+#[tracing::instrument(
+    name = "Send a password reset email",
+    skip(email_client, base_url, token)
+)]
+async fn send_password_reset_email(
+    email_client: &EmailClient,
+    email: &SubscriberEmail,
+    base_url: &str,
+    token: &str,
+) -> Result<(), reqwest::Error> {
+    let reset_link =
+        format!("{}/password_reset/confirm?token={}", base_url, token);
+    let plain_body = format!(
+        "Visit {} to choose a new password. \
+        The link expires in {} minutes.\n\
+        If you did not ask to reset your password, you can ignore this email.",
+        reset_link, PASSWORD_RESET_VALIDITY_MINUTES
+    );
+    let html_body = format!(
+        "Click <a href=\"{}\">here</a> to choose a new password. \
+        The link expires in {} minutes.<br />\
+        If you did not ask to reset your password, you can ignore this email.",
+        reset_link, PASSWORD_RESET_VALIDITY_MINUTES
+    );
+    email_client
+        .send_email(email, "Reset your password", &html_body, &plain_body)
+        .await
+}
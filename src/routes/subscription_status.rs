@@ -0,0 +1,115 @@
+use anyhow::Context;
+use chrono::Utc;
+use sqlx::{PgExecutor, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::domain::SubscriptionStatus;
+use crate::routes::error_chain_fmt;
+
+#[derive(thiserror::Error)]
+pub enum StatusChangeError {
+    #[error("There is no subscriber with id {0}.")]
+    UnknownSubscriber(Uuid),
+
+    #[error("{0}")]
+    IllegalTransition(String),
+
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for StatusChangeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+/// Move a subscriber to `to_status`, if `SubscriptionStatus` allows it, and
+/// record the change with its `cause` and the admin who `performed_by` it,
+/// if any.
+#[tracing::instrument(name = "Change subscription status", skip(transaction))]
+pub async fn change_subscription_status(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    to_status: SubscriptionStatus,
+    cause: &str,
+    performed_by: Option<Uuid>,
+) -> Result<(), StatusChangeError> {
+    // Lock the row so that concurrent changes are applied one at a time.
+    let from_status = sqlx::query!(
+        r#"
+        SELECT status AS "status: SubscriptionStatus"
+        FROM subscriptions
+        WHERE id = $1
+        FOR UPDATE
+        "#,
+        subscriber_id
+    )
+    .fetch_optional(&mut *transaction)
+    .await
+    .context("failed to retrieve subscription status")?
+    .ok_or(StatusChangeError::UnknownSubscriber(subscriber_id))?
+    .status;
+    from_status
+        .transition_to(to_status)
+        .map_err(StatusChangeError::IllegalTransition)?;
+
+    sqlx::query!(
+        "UPDATE subscriptions SET status = $1 WHERE id = $2",
+        to_status as SubscriptionStatus,
+        subscriber_id
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("failed to update subscription status")?;
+    record_status_change(
+        &mut *transaction,
+        subscriber_id,
+        Some(from_status),
+        to_status,
+        cause,
+        performed_by,
+    )
+    .await
+    .context("failed to record subscription status change")?;
+    Ok(())
+}
+
+/// Append to the status history. Use `change_subscription_status` to
+/// change the status of an existing subscriber, this is for recording the
+/// status a subscriber is created with.
+#[tracing::instrument(
+    name = "Record subscription status change",
+    skip(executor)
+)]
+pub async fn record_status_change(
+    executor: impl PgExecutor<'_>,
+    subscriber_id: Uuid,
+    from_status: Option<SubscriptionStatus>,
+    to_status: SubscriptionStatus,
+    cause: &str,
+    performed_by: Option<Uuid>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+    INSERT INTO subscription_status_changes (
+        subscriber_id,
+        occurred_at,
+        from_status,
+        to_status,
+        cause,
+        performed_by
+    )
+    VALUES ($1, $2, $3, $4, $5, $6)
+        "#,
+        subscriber_id,
+        Utc::now(),
+        from_status as Option<SubscriptionStatus>,
+        to_status as SubscriptionStatus,
+        cause,
+        performed_by,
+    )
+    .execute(executor)
+    .await?;
+    Ok(())
+}
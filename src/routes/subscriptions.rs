@@ -1,4 +1,5 @@
-use std::convert::{TryFrom, TryInto};
+use std::collections::HashMap;
+use std::convert::TryFrom;
 
 use actix_web::{web, HttpResponse, ResponseError};
 use anyhow::Context;
@@ -6,26 +7,184 @@ use chrono::Utc;
 use rand::distributions::Alphanumeric;
 use rand::{thread_rng, Rng};
 use reqwest::StatusCode;
-use sqlx::{PgPool, Postgres, Transaction};
+use serde_aux::field_attributes::deserialize_bool_from_anything;
+use sqlx::{PgExecutor, PgPool, Postgres, Transaction};
 use uuid::Uuid;
 
-use crate::domain::{NewSubscriber, SubscriberEmail, SubscriberName};
+use crate::client_info::ClientInfo;
+use crate::configuration::SubscriptionSettings;
+use crate::domain::{
+    suggest_email_correction, AttributeSchema, EmailDomainBlocklist,
+    NewSubscriber, SignUpSource, SubscriberAttributes, SubscriberEmail,
+    SubscriberName, SubscriptionStatus,
+};
 use crate::email_client::EmailClient;
+use crate::form_token::FormToken;
+use crate::routes::record_status_change;
 use crate::startup::ApplicationBaseUrl;
+use crate::subscription_guard::{SubscriptionAttempt, SubscriptionGuard};
 
 #[derive(serde::Deserialize)]
 pub struct FormData {
     email: String,
     name: String,
+    /// Identifies the form (or page) the sign-up was submitted from.
+    form_id: Option<String>,
+    /// Honeypot field, hidden from humans: only bots fill it in.
+    #[serde(default)]
+    website: String,
+    // Kept as a string: fields next to a flattened map cannot be parsed
+    // into numbers by the form deserializer.
+    form_issued_at: Option<String>,
+    form_signature: Option<String>,
+    /// Set when the subscriber confirms their email address is spelled
+    /// correctly despite our suggestion.
+    #[serde(default, deserialize_with = "deserialize_bool_from_anything")]
+    confirm_email: bool,
+    /// `source` and `utm_*` parameters.
+    #[serde(flatten)]
+    source: SignUpSource,
+    /// Referral code of the subscriber whose link was followed.
+    #[serde(rename = "ref")]
+    referral_code: Option<String>,
+    /// Every other field, checked against the custom attribute schema.
+    #[serde(flatten)]
+    attributes: HashMap<String, String>,
 }
 
-impl TryFrom<FormData> for NewSubscriber {
-    type Error = String;
+/// Body of a sign-up submitted as JSON, e.g. by the embeddable widget.
+/// Custom attributes are nested under `attributes`.
+#[derive(serde::Deserialize)]
+pub struct JsonData {
+    #[serde(default)]
+    email: String,
+    #[serde(default)]
+    name: String,
+    form_id: Option<String>,
+    #[serde(default)]
+    website: String,
+    form_issued_at: Option<i64>,
+    form_signature: Option<String>,
+    #[serde(default)]
+    confirm_email: bool,
+    #[serde(flatten)]
+    source: SignUpSource,
+    #[serde(rename = "ref")]
+    referral_code: Option<String>,
+    #[serde(default)]
+    attributes: HashMap<String, serde_json::Value>,
+}
+
+/// A sign-up, whichever way it was submitted.
+struct SignUp {
+    email: String,
+    name: String,
+    form_id: Option<String>,
+    honeypot: String,
+    form_token: Option<FormToken>,
+    confirm_email: bool,
+    source: SignUpSource,
+    referral_code: Option<String>,
+    attributes: HashMap<String, String>,
+}
 
-    fn try_from(value: FormData) -> Result<Self, Self::Error> {
-        let name = SubscriberName::parse(value.name)?;
-        let email = SubscriberEmail::parse(value.email)?;
-        Ok(Self { email, name })
+impl From<FormData> for SignUp {
+    fn from(value: FormData) -> Self {
+        let form_token = value
+            .form_issued_at
+            .and_then(|issued_at| issued_at.parse().ok())
+            .zip(value.form_signature)
+            .map(|(issued_at, signature)| FormToken {
+                issued_at,
+                signature,
+            });
+        Self {
+            email: value.email,
+            name: value.name,
+            form_id: value.form_id,
+            honeypot: value.website,
+            form_token,
+            confirm_email: value.confirm_email,
+            source: value.source,
+            referral_code: value.referral_code,
+            attributes: value.attributes,
+        }
+    }
+}
+
+impl TryFrom<JsonData> for SignUp {
+    type Error = FieldError;
+
+    fn try_from(value: JsonData) -> Result<Self, Self::Error> {
+        let mut attributes = HashMap::new();
+        for (name, attribute) in value.attributes {
+            let attribute = match attribute {
+                serde_json::Value::Null => continue,
+                serde_json::Value::String(s) => s,
+                serde_json::Value::Bool(_) | serde_json::Value::Number(_) => {
+                    attribute.to_string()
+                }
+                _ => {
+                    let message = format!("{} must be a single value.", name);
+                    return Err(FieldError::new(&name, message));
+                }
+            };
+            attributes.insert(name, attribute);
+        }
+        let form_token = value.form_issued_at.zip(value.form_signature).map(
+            |(issued_at, signature)| FormToken {
+                issued_at,
+                signature,
+            },
+        );
+        Ok(Self {
+            email: value.email,
+            name: value.name,
+            form_id: value.form_id,
+            honeypot: value.website,
+            form_token,
+            confirm_email: value.confirm_email,
+            source: value.source,
+            referral_code: value.referral_code,
+            attributes,
+        })
+    }
+}
+
+/// A validation error along with the field it is about, so that forms can
+/// display it next to the right input.
+#[derive(Debug, serde::Serialize)]
+pub struct FieldError {
+    pub field: String,
+    pub message: String,
+}
+
+impl FieldError {
+    fn new(field: &str, message: impl Into<String>) -> Self {
+        Self {
+            field: field.to_string(),
+            message: message.into(),
+        }
+    }
+}
+
+/// Form identifier recorded for sign-ups that do not provide one.
+pub const DEFAULT_FORM_ID: &str = "default";
+const MAX_FORM_ID_LENGTH: usize = 64;
+
+fn parse_form_id(form_id: Option<String>) -> Result<String, String> {
+    let form_id = match form_id {
+        Some(form_id) if !form_id.trim().is_empty() => form_id,
+        _ => return Ok(DEFAULT_FORM_ID.to_string()),
+    };
+    let is_valid = form_id.len() <= MAX_FORM_ID_LENGTH
+        && form_id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if is_valid {
+        Ok(form_id)
+    } else {
+        Err(format!("{} is not a valid form identifier.", form_id))
     }
 }
 
@@ -55,8 +214,8 @@ impl std::error::Error for StoreTokenError {
 
 #[derive(thiserror::Error)]
 pub enum SubscribeError {
-    #[error("{0}")]
-    ValidationError(String),
+    #[error("{}", display_field_errors(.0))]
+    ValidationError(Vec<FieldError>),
 
     #[error(transparent)]
     UnexpectedError(#[from] anyhow::Error),
@@ -68,12 +227,32 @@ impl std::fmt::Debug for SubscribeError {
     }
 }
 
-impl From<String> for SubscribeError {
-    fn from(value: String) -> Self {
-        Self::ValidationError(value)
+impl From<FieldError> for SubscribeError {
+    fn from(value: FieldError) -> Self {
+        Self::ValidationError(vec![value])
     }
 }
 
+/// The value of `result`, or `None` after recording the error against
+/// `field`.
+fn check<T>(
+    errors: &mut Vec<FieldError>,
+    field: &str,
+    result: Result<T, String>,
+) -> Option<T> {
+    result
+        .map_err(|e| errors.push(FieldError::new(field, e)))
+        .ok()
+}
+
+fn display_field_errors(errors: &[FieldError]) -> String {
+    errors
+        .iter()
+        .map(|e| e.message.as_str())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
 impl ResponseError for SubscribeError {
     fn status_code(&self) -> reqwest::StatusCode {
         match self {
@@ -99,32 +278,198 @@ pub fn error_chain_fmt(
     Ok(())
 }
 
-#[allow(clippy::async_yields_async)]
-#[tracing::instrument(
-    name = "Adding a new subscriber",
-    skip(form, pool, email_client, base_url),
-    fields(
-        subscriber_email = %form.email,
-        subscriber_name = %form.name
-    )
-)]
+#[allow(clippy::too_many_arguments)]
 pub async fn subscribe(
     form: web::Form<FormData>,
     pool: web::Data<PgPool>,
     email_client: web::Data<EmailClient>,
     base_url: web::Data<ApplicationBaseUrl>,
+    settings: web::Data<SubscriptionSettings>,
+    guard: web::Data<SubscriptionGuard>,
+    email_domain_blocklist: web::Data<EmailDomainBlocklist>,
+    attribute_schema: web::Data<AttributeSchema>,
+    client_info: ClientInfo,
+) -> Result<HttpResponse, SubscribeError> {
+    register_subscriber(
+        form.0.into(),
+        &pool,
+        &email_client,
+        &base_url,
+        &settings,
+        &guard,
+        &email_domain_blocklist,
+        &attribute_schema,
+        &client_info,
+    )
+    .await?;
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Sign-up submitted as JSON, typically from another site through the
+/// embeddable widget. Validation errors are returned as a list of field
+/// errors, e.g. `{"errors": [{"field": "email", "message": "..."}]}`.
+#[allow(clippy::too_many_arguments)]
+pub async fn subscribe_json(
+    body: web::Json<JsonData>,
+    pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    settings: web::Data<SubscriptionSettings>,
+    guard: web::Data<SubscriptionGuard>,
+    email_domain_blocklist: web::Data<EmailDomainBlocklist>,
+    attribute_schema: web::Data<AttributeSchema>,
+    client_info: ClientInfo,
 ) -> Result<HttpResponse, SubscribeError> {
-    let new_subscriber =
-        form.0.try_into().map_err(SubscribeError::ValidationError)?;
+    let result = match SignUp::try_from(body.0) {
+        Ok(sign_up) => {
+            register_subscriber(
+                sign_up,
+                &pool,
+                &email_client,
+                &base_url,
+                &settings,
+                &guard,
+                &email_domain_blocklist,
+                &attribute_schema,
+                &client_info,
+            )
+            .await
+        }
+        Err(e) => Err(e.into()),
+    };
+    match result {
+        Ok(()) => Ok(HttpResponse::Ok().json(serde_json::json!({}))),
+        Err(SubscribeError::ValidationError(errors)) => {
+            Ok(HttpResponse::BadRequest()
+                .json(serde_json::json!({ "errors": errors })))
+        }
+        Err(e) => Err(e),
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+#[tracing::instrument(
+    name = "Adding a new subscriber",
+    skip_all,
+    fields(
+        subscriber_email = %sign_up.email,
+        subscriber_name = %sign_up.name
+    )
+)]
+async fn register_subscriber(
+    sign_up: SignUp,
+    pool: &PgPool,
+    email_client: &EmailClient,
+    base_url: &ApplicationBaseUrl,
+    settings: &SubscriptionSettings,
+    guard: &SubscriptionGuard,
+    email_domain_blocklist: &EmailDomainBlocklist,
+    attribute_schema: &AttributeSchema,
+    client_info: &ClientInfo,
+) -> Result<(), SubscribeError> {
+    // Report every invalid field at once, rather than one per attempt.
+    let mut errors = Vec::new();
+    let form_id = check(&mut errors, "form_id", parse_form_id(sign_up.form_id));
+    let name = check(&mut errors, "name", SubscriberName::parse(sign_up.name));
+    let email = check(
+        &mut errors,
+        "email",
+        SubscriberEmail::parse(sign_up.email).and_then(|email| {
+            email_domain_blocklist.check(&email)?;
+            Ok(email)
+        }),
+    );
+    let source = sign_up
+        .source
+        .parse()
+        .map_err(|e| {
+            errors.extend(e.into_iter().map(|(f, m)| FieldError::new(f, m)))
+        })
+        .ok();
+    let attributes = attribute_schema
+        .parse_form(&sign_up.attributes)
+        .map_err(|e| errors.push(FieldError::new(&e.attribute, e.message)))
+        .ok();
+    let (
+        Some(form_id),
+        Some(name),
+        Some(email),
+        Some(source),
+        Some(attributes),
+    ) = (form_id, name, email, source, attributes)
+    else {
+        return Err(SubscribeError::ValidationError(errors));
+    };
+    let new_subscriber = NewSubscriber { email, name };
+    if !sign_up.confirm_email {
+        if let Some(suggestion) =
+            suggest_email_correction(&new_subscriber.email)
+        {
+            return Err(FieldError::new(
+                "email",
+                format!("Did you mean {}?", suggestion),
+            )
+            .into());
+        }
+    }
+
+    let rejection = guard
+        .screen(SubscriptionAttempt {
+            honeypot: &sign_up.honeypot,
+            form_token: sign_up.form_token,
+            email: &new_subscriber.email,
+            client_info,
+        })
+        .await
+        .context("failed to screen subscription attempt")?;
+    if let Some(rejection) = rejection {
+        // Respond exactly as we would on success, so that bots cannot tell
+        // they have been caught.
+        tracing::warn!(
+            reason = %rejection,
+            ip_address = ?client_info.ip_address,
+            "Rejected a subscription attempt."
+        );
+        return Ok(());
+    }
 
     let mut transaction = pool
         .begin()
         .await
         .context("failed to acquire postgres connection from pool")?;
 
-    let subscriber_id = insert_subscriber(&mut transaction, &new_subscriber)
-        .await
-        .context("failed to insert new subscriber into database")?;
+    // Links may be mangled or outlive the referrer's subscription: sign-ups
+    // through them go ahead, unattributed.
+    let referred_by = match sign_up.referral_code {
+        Some(code) => find_referrer(&mut transaction, &code)
+            .await
+            .context("failed to look up referrer")?,
+        None => None,
+    };
+    let subscriber_id = match insert_subscriber(
+        &mut transaction,
+        &new_subscriber,
+        &source,
+        referred_by,
+        &attributes,
+        settings.fold_email_aliases,
+    )
+    .await
+    {
+        Ok(subscriber_id) => subscriber_id,
+        Err(e) if is_unique_violation(&e) => {
+            // The address, or a variant of it, is already on the list.
+            // Respond as on success, so that the form cannot be used to
+            // find out who is subscribed.
+            tracing::info!("Ignored a sign-up for an address already listed.");
+            return Ok(());
+        }
+        Err(e) => {
+            return Err(anyhow::Error::new(e)
+                .context("failed to insert new subscriber into database")
+                .into())
+        }
+    };
 
     let subscription_token = generate_subscription_token();
 
@@ -132,13 +477,34 @@ pub async fn subscribe(
         .await
         .context("failed to store confirmation token for new subscriber")?;
 
+    store_consent(
+        &mut transaction,
+        subscriber_id,
+        &form_id,
+        &settings.consent_text_version,
+        client_info,
+    )
+    .await
+    .context("failed to store consent record for new subscriber")?;
+
+    record_status_change(
+        &mut transaction,
+        subscriber_id,
+        None,
+        SubscriptionStatus::PendingConfirmation,
+        "sign_up",
+        None,
+    )
+    .await
+    .context("failed to record status of new subscriber")?;
+
     transaction
         .commit()
         .await
         .context("failed to commit sql transaction to store new subscriber")?;
 
     send_confirmation_email(
-        &email_client,
+        email_client,
         new_subscriber,
         &base_url.0,
         &subscription_token,
@@ -146,10 +512,10 @@ pub async fn subscribe(
     .await
     .context("failed to send confirmation email")?;
 
-    Ok(HttpResponse::Ok().finish())
+    Ok(())
 }
 
-fn generate_subscription_token() -> String {
+pub fn generate_subscription_token() -> String {
     let mut rng = thread_rng();
     std::iter::repeat_with(|| rng.sample(Alphanumeric))
         .map(char::from)
@@ -186,22 +552,40 @@ pub async fn send_confirmation_email(
 
 #[tracing::instrument(
     name = "Saving new subscriber details in the database",
-    skip(new_subscriber, transaction)
+    skip(new_subscriber, source, attributes, transaction)
 )]
 pub async fn insert_subscriber(
     transaction: &mut Transaction<'_, Postgres>,
     new_subscriber: &NewSubscriber,
+    source: &SignUpSource,
+    referred_by: Option<Uuid>,
+    attributes: &SubscriberAttributes,
+    fold_email_aliases: bool,
 ) -> Result<Uuid, sqlx::Error> {
     let subscriber_id = Uuid::new_v4();
     sqlx::query!(
         r#"
-    INSERT INTO subscriptions (id, email, name, subscribed_at, status)
-    VALUES ($1, $2, $3, $4, 'pending_confirmation')
+    INSERT INTO subscriptions (
+        id, email, email_canonical, name, subscribed_at, status, attributes,
+        source, utm_source, utm_medium, utm_campaign, utm_term, utm_content,
+        referred_by
+    )
+    VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14)
             "#,
         subscriber_id,
         new_subscriber.email.as_ref(),
+        new_subscriber.email.dedup_key(fold_email_aliases),
         new_subscriber.name.as_ref(),
-        Utc::now()
+        Utc::now(),
+        SubscriptionStatus::PendingConfirmation as SubscriptionStatus,
+        attributes.to_json(),
+        source.source,
+        source.utm_source,
+        source.utm_medium,
+        source.utm_campaign,
+        source.utm_term,
+        source.utm_content,
+        referred_by,
     )
     .execute(transaction)
     .await?;
@@ -209,6 +593,36 @@ pub async fn insert_subscriber(
     Ok(subscriber_id)
 }
 
+/// Whether `e` is a violation of a UNIQUE constraint (SQLSTATE 23505).
+fn is_unique_violation(e: &sqlx::Error) -> bool {
+    matches!(
+        e,
+        sqlx::Error::Database(e) if e.code().as_deref() == Some("23505")
+    )
+}
+
+/// The confirmed subscriber `referral_code` belongs to, if any.
+#[tracing::instrument(name = "Find referrer", skip(transaction))]
+pub async fn find_referrer(
+    transaction: &mut Transaction<'_, Postgres>,
+    referral_code: &str,
+) -> Result<Option<Uuid>, sqlx::Error> {
+    let referrer = sqlx::query!(
+        "SELECT id FROM subscriptions WHERE referral_code = $1 AND status = $2",
+        referral_code.trim(),
+        SubscriptionStatus::Confirmed as SubscriptionStatus,
+    )
+    .fetch_optional(transaction)
+    .await?;
+    Ok(referrer.map(|r| r.id))
+}
+
+/// The link a subscriber shares to refer others, leading to the sign-up
+/// form.
+pub fn referral_link(base_url: &str, referral_code: &str) -> String {
+    format!("{}/subscriptions?ref={}", base_url, referral_code)
+}
+
 #[tracing::instrument(
     name = "Store subscription token in the database",
     skip(subscription_token, transaction)
@@ -231,3 +645,64 @@ pub async fn store_token(
     .map_err(StoreTokenError)?;
     Ok(())
 }
+
+#[tracing::instrument(
+    name = "Store consent record in the database",
+    skip(transaction, client_info)
+)]
+pub async fn store_consent(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    form_id: &str,
+    consent_text_version: &str,
+    client_info: &ClientInfo,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+    INSERT INTO subscription_consents (
+        subscriber_id,
+        consented_at,
+        ip_address,
+        user_agent,
+        form_id,
+        consent_text_version
+    )
+    VALUES ($1, $2, $3, $4, $5, $6)
+        "#,
+        subscriber_id,
+        Utc::now(),
+        client_info.ip_address,
+        client_info.user_agent,
+        form_id,
+        consent_text_version,
+    )
+    .execute(transaction)
+    .await?;
+    Ok(())
+}
+
+/// Append an entry to the history shown on the subscriber's admin page.
+/// `performed_by` is the admin who triggered the event, if any.
+#[tracing::instrument(name = "Record subscriber event", skip(executor))]
+pub async fn record_subscriber_event(
+    executor: impl PgExecutor<'_>,
+    subscriber_id: Uuid,
+    event: &str,
+    performed_by: Option<Uuid>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+    INSERT INTO subscriber_events (
+        subscriber_id, occurred_at, event, performed_by
+    )
+    VALUES ($1, $2, $3, $4)
+        "#,
+        subscriber_id,
+        Utc::now(),
+        event,
+        performed_by,
+    )
+    .execute(executor)
+    .await?;
+    Ok(())
+}
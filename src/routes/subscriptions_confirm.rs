@@ -1,7 +1,12 @@
 use actix_web::{web, HttpResponse};
-use sqlx::PgPool;
+use chrono::Utc;
+use sqlx::{PgExecutor, PgPool, Postgres, Transaction};
 use uuid::Uuid;
 
+use crate::client_info::ClientInfo;
+use crate::domain::SubscriptionStatus;
+use crate::routes::{change_subscription_status, StatusChangeError};
+
 #[derive(serde::Deserialize)]
 pub struct Parameters {
     subscription_token: String,
@@ -10,11 +15,12 @@ pub struct Parameters {
 #[allow(clippy::async_yields_async)]
 #[tracing::instrument(
     name = "Confirm a pending subscriber",
-    skip(parameters, pool)
+    skip(parameters, pool, client_info)
 )]
 pub async fn confirm(
     parameters: web::Query<Parameters>,
     pool: web::Data<PgPool>,
+    client_info: ClientInfo,
 ) -> HttpResponse {
     let id = match get_subscriber_id_from_token(
         &pool,
@@ -29,7 +35,30 @@ pub async fn confirm(
         // Non-existing token!
         None => HttpResponse::Unauthorized().finish(),
         Some(subscriber_id) => {
-            if confirm_subscriber(&pool, subscriber_id).await.is_err() {
+            let mut transaction = match pool.begin().await {
+                Ok(transaction) => transaction,
+                Err(_) => return HttpResponse::InternalServerError().finish(),
+            };
+            match confirm_subscriber(&mut transaction, subscriber_id).await {
+                Ok(()) => {
+                    if record_confirmation_consent(
+                        &mut transaction,
+                        subscriber_id,
+                        &client_info,
+                    )
+                    .await
+                    .is_err()
+                    {
+                        return HttpResponse::InternalServerError().finish();
+                    }
+                }
+                // Later clicks on the link, or on an old link after
+                // unsubscribing, leave the subscriber and their consent
+                // record alone.
+                Err(StatusChangeError::IllegalTransition(_)) => {}
+                Err(_) => return HttpResponse::InternalServerError().finish(),
+            }
+            if transaction.commit().await.is_err() {
                 return HttpResponse::InternalServerError().finish();
             }
             HttpResponse::Ok().finish()
@@ -37,20 +66,49 @@ pub async fn confirm(
     }
 }
 
-#[allow(clippy::async_yields_async)]
 #[tracing::instrument(
     name = "Mark subscriber as confirmed",
-    skip(subscriber_id, pool)
+    skip(subscriber_id, transaction)
 )]
 pub async fn confirm_subscriber(
-    pool: &PgPool,
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+) -> Result<(), StatusChangeError> {
+    change_subscription_status(
+        transaction,
+        subscriber_id,
+        SubscriptionStatus::Confirmed,
+        "confirmation_link",
+        None,
+    )
+    .await
+}
+
+#[tracing::instrument(
+    name = "Record confirmation click on consent record",
+    skip(subscriber_id, executor, client_info)
+)]
+pub async fn record_confirmation_consent(
+    executor: impl PgExecutor<'_>,
     subscriber_id: Uuid,
+    client_info: &ClientInfo,
 ) -> Result<(), sqlx::Error> {
+    // Only the first click is evidence of the opt-in, later visits to the
+    // confirmation link must not overwrite it.
     sqlx::query!(
-        r#"UPDATE subscriptions SET status = 'confirmed' WHERE id = $1"#,
+        r#"
+        UPDATE subscription_consents
+        SET confirmed_at = $1,
+            confirmation_ip_address = $2,
+            confirmation_user_agent = $3
+        WHERE subscriber_id = $4 AND confirmed_at IS NULL
+        "#,
+        Utc::now(),
+        client_info.ip_address,
+        client_info.user_agent,
         subscriber_id,
     )
-    .execute(pool)
+    .execute(executor)
     .await
     .map_err(|e| {
         tracing::error!("Failed to execute query: {:?}", e);
@@ -0,0 +1,136 @@
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+
+use crate::domain::{
+    AttributeSchema, AttributeType, SignUpSource, SubscriberAttributes,
+};
+use crate::subscription_guard::SubscriptionGuard;
+
+#[derive(serde::Deserialize)]
+pub struct FormParameters {
+    #[serde(flatten)]
+    source: SignUpSource,
+    #[serde(rename = "ref")]
+    referral_code: Option<String>,
+}
+
+/// The sign-up form. `source`, `utm_*` and `ref` query parameters of the
+/// page are submitted along with the form, so that campaign and referral
+/// links can point here. Invalid sources are dropped.
+pub async fn subscribe_form(
+    guard: web::Data<SubscriptionGuard>,
+    attribute_schema: web::Data<AttributeSchema>,
+    parameters: web::Query<FormParameters>,
+) -> HttpResponse {
+    let form_token = guard.issue_form_token();
+    let attribute_inputs =
+        attribute_inputs(&attribute_schema, &SubscriberAttributes::default());
+    let FormParameters {
+        source,
+        referral_code,
+    } = parameters.into_inner();
+    // A mangled campaign link should not keep visitors from signing up.
+    let source = source.parse().unwrap_or_default();
+    let source_inputs: String = source
+        .fields()
+        .chain(referral_code.as_deref().map(|code| ("ref", code)))
+        .map(|(name, value)| {
+            format!(
+                r#"<input hidden type="text" name="{}" value="{}">"#,
+                name,
+                htmlescape::encode_attribute(value)
+            )
+        })
+        .collect();
+
+    HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+    <head>
+        <meta http-equiv="content-type" content="text/html; charset=utf-8">
+        <title>Subscribe</title>
+    </head>
+    <body>
+        <form action="/subscriptions" method="post">
+            <label>Name
+                <input type="text" placeholder="Enter your name" name="name">
+            </label>
+            <label>Email
+                <input type="email" placeholder="Enter your email" name="email">
+            </label>
+            {attribute_inputs}
+            <label>
+                <input type="checkbox" name="confirm_email" value="true">
+                My email address is spelled correctly
+            </label>
+            <div style="position: absolute; left: -10000px;" aria-hidden="true">
+                <label>Leave this field empty
+                    <input type="text" name="website" tabindex="-1" autocomplete="off">
+                </label>
+            </div>
+            <input hidden type="text" name="form_issued_at" value="{issued_at}">
+            <input hidden type="text" name="form_signature" value="{signature}">
+            {source_inputs}
+            <button type="submit">Subscribe</button>
+        </form>
+    </body>
+</html>"#,
+            issued_at = form_token.issued_at,
+            signature = form_token.signature,
+        ))
+}
+
+/// One input per custom attribute, pre-filled with `values`. Booleans are
+/// picked from a list, so that "not answered" stays distinct from "no".
+pub fn attribute_inputs(
+    schema: &AttributeSchema,
+    values: &SubscriberAttributes,
+) -> String {
+    schema
+        .definitions()
+        .iter()
+        .map(|definition| {
+            // Names are validated identifiers, safe to embed as is.
+            let name = &definition.name;
+            let value = values.display(&definition.name).unwrap_or_default();
+            let required = if definition.required { " required" } else { "" };
+            let input = match definition.kind {
+                AttributeType::Text | AttributeType::Number => {
+                    let (input_type, extra_attributes) = match definition.kind {
+                        AttributeType::Number => ("number", r#" step="any""#),
+                        _ => ("text", ""),
+                    };
+                    format!(
+                        r#"<input type="{}"{} name="{}" value="{}"{}>"#,
+                        input_type,
+                        extra_attributes,
+                        name,
+                        htmlescape::encode_attribute(&value),
+                        required
+                    )
+                }
+                AttributeType::Boolean => {
+                    let options = [("", ""), ("true", "Yes"), ("false", "No")]
+                        .iter()
+                        .map(|(option, label)| {
+                            let selected =
+                                if *option == value { " selected" } else { "" };
+                            format!(
+                                r#"<option value="{}"{}>{}</option>"#,
+                                option, selected, label
+                            )
+                        })
+                        .collect::<String>();
+                    format!(
+                        r#"<select name="{}"{}>{}</select>"#,
+                        name, required, options
+                    )
+                }
+            };
+            format!("<label>{}\n    {}\n</label>", name, input)
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
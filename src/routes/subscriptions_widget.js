@@ -0,0 +1,99 @@
+// Sign-up widget, embedded with a script tag and served by
+// `GET /subscriptions/widget.js`. `__CONFIG__` is replaced when serving.
+(function () {
+  "use strict";
+
+  var config = __CONFIG__;
+  var script = document.currentScript;
+  var endpoint = new URL("/subscriptions", script.src).href;
+  var formId = script.getAttribute("data-form-id") || "widget";
+  var source = script.getAttribute("data-source");
+  var utmFields = [
+    "utm_source",
+    "utm_medium",
+    "utm_campaign",
+    "utm_term",
+    "utm_content",
+  ];
+
+  var container = document.createElement("div");
+  container.className = "zero2prod-signup";
+  container.innerHTML = config.formHtml;
+  script.parentNode.insertBefore(container, script.nextSibling);
+
+  var form = container.querySelector("form");
+  var status = container.querySelector(".zero2prod-status");
+  var fields = ["email", "name", "website", "form_signature"];
+
+  function clearErrors() {
+    container.querySelectorAll(".zero2prod-error").forEach(function (error) {
+      error.remove();
+    });
+    status.textContent = "";
+  }
+
+  function showError(field, message) {
+    var error = document.createElement("p");
+    error.className = "zero2prod-error";
+    error.textContent = message;
+    var input = form.querySelector('[name="' + field + '"]');
+    var anchor = input && (input.closest("label") || input);
+    if (anchor) {
+      anchor.insertAdjacentElement("afterend", error);
+    } else {
+      status.appendChild(error);
+    }
+  }
+
+  form.addEventListener("submit", function (event) {
+    event.preventDefault();
+    clearErrors();
+
+    var body = { form_id: formId, confirm_email: false, attributes: {} };
+    if (source) {
+      body.source = source;
+    }
+    // Credit the campaign or the referrer that brought the visitor to the
+    // host page.
+    var params = new URLSearchParams(window.location.search);
+    utmFields.concat(["ref"]).forEach(function (field) {
+      if (params.get(field)) {
+        body[field] = params.get(field);
+      }
+    });
+    new FormData(form).forEach(function (value, name) {
+      if (fields.indexOf(name) >= 0) {
+        body[name] = value;
+      } else if (name === "form_issued_at") {
+        body[name] = Number(value);
+      } else if (name === "confirm_email") {
+        body.confirm_email = true;
+      } else if (value !== "") {
+        body.attributes[name] = value;
+      }
+    });
+
+    fetch(endpoint, {
+      method: "POST",
+      headers: { "Content-Type": "application/json" },
+      body: JSON.stringify(body),
+    })
+      .then(function (response) {
+        if (response.ok) {
+          form.hidden = true;
+          status.textContent = config.successMessage;
+        } else if (response.status === 400) {
+          return response.json().then(function (result) {
+            result.errors.forEach(function (error) {
+              showError(error.field, error.message);
+            });
+          });
+        } else {
+          status.textContent = config.failureMessage;
+        }
+      })
+      .catch(function () {
+        status.textContent = config.failureMessage;
+      });
+  });
+})();
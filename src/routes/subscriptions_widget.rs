@@ -0,0 +1,91 @@
+use actix_web::http::header::{CacheControl, CacheDirective, ContentType};
+use actix_web::{web, HttpResponse};
+
+use crate::domain::{AttributeSchema, SubscriberAttributes};
+use crate::routes::attribute_inputs;
+use crate::startup::ApplicationBaseUrl;
+use crate::subscription_guard::SubscriptionGuard;
+
+const WIDGET_SCRIPT: &str = include_str!("subscriptions_widget.js");
+
+/// Script rendering the sign-up form on another site, right where it is
+/// included. The form is submitted as JSON to `POST /subscriptions`, so the
+/// site's origin must be listed in `subscriptions.cors_allowed_origins`.
+pub async fn subscribe_widget_script(
+    guard: web::Data<SubscriptionGuard>,
+    attribute_schema: web::Data<AttributeSchema>,
+) -> HttpResponse {
+    let form_token = guard.issue_form_token();
+    let form_html = format!(
+        r#"<form>
+    <label>Name <input type="text" name="name" required></label>
+    <label>Email <input type="email" name="email" required></label>
+    {attribute_inputs}
+    <label>
+        <input type="checkbox" name="confirm_email" value="true">
+        My email address is spelled correctly
+    </label>
+    <div style="position: absolute; left: -10000px;" aria-hidden="true">
+        <label>Leave this field empty
+            <input type="text" name="website" tabindex="-1" autocomplete="off">
+        </label>
+    </div>
+    <input hidden type="text" name="form_issued_at" value="{issued_at}">
+    <input hidden type="text" name="form_signature" value="{signature}">
+    <button type="submit">Subscribe</button>
+</form>
+<p class="zero2prod-status" role="status"></p>"#,
+        attribute_inputs = attribute_inputs(
+            &attribute_schema,
+            &SubscriberAttributes::default()
+        ),
+        issued_at = form_token.issued_at,
+        signature = form_token.signature,
+    );
+    let config = serde_json::json!({
+        "formHtml": form_html,
+        "successMessage": "Thanks! Check your inbox to confirm your subscription.",
+        "failureMessage": "Something went wrong, please try again later.",
+    });
+
+    HttpResponse::Ok()
+        .content_type("application/javascript; charset=utf-8")
+        // Every copy embeds its own form token, which expires.
+        .insert_header(CacheControl(vec![CacheDirective::NoStore]))
+        .body(WIDGET_SCRIPT.replace("__CONFIG__", &config.to_string()))
+}
+
+/// The snippet to paste into another site to embed the sign-up widget,
+/// along with a preview.
+pub async fn subscribe_widget(
+    base_url: web::Data<ApplicationBaseUrl>,
+) -> HttpResponse {
+    let snippet = format!(
+        r#"<script src="{}/subscriptions/widget.js" data-form-id="widget" async></script>"#,
+        base_url.0
+    );
+
+    HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+    <head>
+        <meta http-equiv="content-type" content="text/html; charset=utf-8">
+        <title>Sign-up widget</title>
+    </head>
+    <body>
+        <p>Paste this snippet where the sign-up form should appear, and add
+        the site's origin to <code>subscriptions.cors_allowed_origins</code>.
+        Set <code>data-form-id</code> to tell sign-ups from each site apart,
+        and <code>data-source</code> to tell placements apart on the
+        dashboard. <code>utm_*</code> and <code>ref</code> parameters of the page are sent
+        along.</p>
+        <pre><code>{snippet}</code></pre>
+        <p>Preview</p>
+        <script src="/subscriptions/widget.js" data-form-id="widget"></script>
+    </body>
+</html>"#,
+            snippet = htmlescape::encode_minimal(&snippet),
+        ))
+}
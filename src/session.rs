@@ -2,29 +2,193 @@ use std::future::{ready, Ready};
 
 use actix_session::{Session, SessionExt, SessionGetError, SessionInsertError};
 use actix_web::FromRequest;
+use chrono::{DateTime, Duration, Utc};
+use secrecy::{ExposeSecret, Secret};
 use uuid::Uuid;
 
 #[derive(Clone)]
 pub struct TypedSession(Session);
 
+/// How long admin sessions last.
+#[derive(Clone, Copy, Debug)]
+pub struct SessionTimeouts {
+    /// Without any request.
+    pub idle: Duration,
+    /// Since logging in, however active the session is.
+    pub absolute: Duration,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SessionExpiry {
+    Idle,
+    Absolute,
+}
+
+impl SessionExpiry {
+    /// Why the user has to log in again.
+    pub fn message(&self) -> &'static str {
+        match self {
+            Self::Idle => {
+                "You have been logged out after a period of inactivity."
+            }
+            Self::Absolute => "Your session has expired, please log in again.",
+        }
+    }
+}
+
+impl SessionTimeouts {
+    /// Whether a session logged in at `authenticated_at` and last used at
+    /// `last_seen_at` has expired by `now`. Sessions that do not know when
+    /// they were logged in are treated as too old.
+    pub fn expiry(
+        &self,
+        authenticated_at: Option<DateTime<Utc>>,
+        last_seen_at: Option<DateTime<Utc>>,
+        now: DateTime<Utc>,
+    ) -> Option<SessionExpiry> {
+        let Some(authenticated_at) = authenticated_at else {
+            return Some(SessionExpiry::Absolute);
+        };
+        if now - authenticated_at >= self.absolute {
+            return Some(SessionExpiry::Absolute);
+        }
+        let last_seen_at = last_seen_at.unwrap_or(authenticated_at);
+        if now - last_seen_at >= self.idle {
+            return Some(SessionExpiry::Idle);
+        }
+        None
+    }
+}
+
 impl TypedSession {
     const USER_ID_KEY: &'static str = "user_id";
+    const SESSION_ID_KEY: &'static str = "session_id";
+    const AUTHENTICATED_AT_KEY: &'static str = "authenticated_at";
+    const LAST_SEEN_AT_KEY: &'static str = "last_seen_at";
+    const PENDING_USER_ID_KEY: &'static str = "pending_user_id";
+    const TOTP_ENROLMENT_KEY: &'static str = "totp_enrolment";
+    const CSRF_TOKEN_KEY: &'static str = "csrf_token";
 
     pub fn renew(&self) {
         self.0.renew();
     }
 
+    /// Log `user_id` in. Returns the id of the new session, under which it
+    /// is to be listed in the `SessionIndex`.
     pub fn insert_user_id(
         &self,
         user_id: Uuid,
-    ) -> Result<(), SessionInsertError> {
-        self.0.insert(Self::USER_ID_KEY, user_id)
+    ) -> Result<Uuid, SessionInsertError> {
+        let session_id = Uuid::new_v4();
+        let now = Utc::now();
+        self.0.insert(Self::AUTHENTICATED_AT_KEY, now)?;
+        self.0.insert(Self::LAST_SEEN_AT_KEY, now)?;
+        self.0.insert(Self::SESSION_ID_KEY, session_id)?;
+        // Forms rendered before logging in are not trusted afterwards.
+        self.0.remove(Self::CSRF_TOKEN_KEY);
+        self.0.insert(Self::USER_ID_KEY, user_id)?;
+        Ok(session_id)
     }
 
     pub fn get_user_id(&self) -> Result<Option<Uuid>, SessionGetError> {
         self.0.get(Self::USER_ID_KEY)
     }
 
+    pub fn get_session_id(&self) -> Result<Option<Uuid>, SessionGetError> {
+        self.0.get(Self::SESSION_ID_KEY)
+    }
+
+    /// Give a session logged in before sessions had ids one.
+    pub fn insert_session_id(&self) -> Result<Uuid, SessionInsertError> {
+        let session_id = Uuid::new_v4();
+        self.0.insert(Self::SESSION_ID_KEY, session_id)?;
+        Ok(session_id)
+    }
+
+    /// When the user logged in, to tell whether the session predates a
+    /// password reset.
+    pub fn get_authenticated_at(
+        &self,
+    ) -> Result<Option<DateTime<Utc>>, SessionGetError> {
+        self.0.get(Self::AUTHENTICATED_AT_KEY)
+    }
+
+    /// Whether the session has been idle or open for too long. If not, the
+    /// current request is recorded as its latest activity.
+    pub fn check_timeouts(
+        &self,
+        timeouts: &SessionTimeouts,
+    ) -> Result<Option<SessionExpiry>, anyhow::Error> {
+        let now = Utc::now();
+        let expiry = timeouts.expiry(
+            self.get_authenticated_at()?,
+            self.0.get(Self::LAST_SEEN_AT_KEY)?,
+            now,
+        );
+        if expiry.is_none() {
+            self.0.insert(Self::LAST_SEEN_AT_KEY, now)?;
+        }
+        Ok(expiry)
+    }
+
+    /// The user who entered their password but still has to provide their
+    /// second factor. They are not logged in until `complete_login`.
+    pub fn insert_pending_user_id(
+        &self,
+        user_id: Uuid,
+    ) -> Result<(), SessionInsertError> {
+        self.0.insert(Self::PENDING_USER_ID_KEY, user_id)
+    }
+
+    pub fn get_pending_user_id(&self) -> Result<Option<Uuid>, SessionGetError> {
+        self.0.get(Self::PENDING_USER_ID_KEY)
+    }
+
+    pub fn complete_login(
+        &self,
+        user_id: Uuid,
+    ) -> Result<Uuid, SessionInsertError> {
+        self.0.renew();
+        self.0.remove(Self::PENDING_USER_ID_KEY);
+        self.0.remove(Self::TOTP_ENROLMENT_KEY);
+        self.insert_user_id(user_id)
+    }
+
+    /// The base32 TOTP secret being set up, kept until the user confirms it
+    /// with a first code.
+    pub fn insert_totp_enrolment(
+        &self,
+        secret: &Secret<String>,
+    ) -> Result<(), SessionInsertError> {
+        self.0
+            .insert(Self::TOTP_ENROLMENT_KEY, secret.expose_secret())
+    }
+
+    pub fn get_totp_enrolment(
+        &self,
+    ) -> Result<Option<Secret<String>>, SessionGetError> {
+        Ok(self
+            .0
+            .get::<String>(Self::TOTP_ENROLMENT_KEY)?
+            .map(Secret::new))
+    }
+
+    pub fn remove_totp_enrolment(&self) {
+        self.0.remove(Self::TOTP_ENROLMENT_KEY);
+    }
+
+    /// See `CsrfToken`.
+    pub fn get_csrf_token(&self) -> Result<Option<String>, SessionGetError> {
+        self.0.get(Self::CSRF_TOKEN_KEY)
+    }
+
+    pub fn insert_csrf_token(
+        &self,
+        token: &str,
+    ) -> Result<(), SessionInsertError> {
+        self.0.insert(Self::CSRF_TOKEN_KEY, token)
+    }
+
     pub fn logout(&self) {
         self.0.purge()
     }
@@ -42,3 +206,56 @@ impl FromRequest for TypedSession {
         ready(Ok(TypedSession(req.get_session())))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, Utc};
+
+    use super::{SessionExpiry, SessionTimeouts};
+
+    fn timeouts() -> SessionTimeouts {
+        SessionTimeouts {
+            idle: Duration::minutes(30),
+            absolute: Duration::hours(12),
+        }
+    }
+
+    #[test]
+    fn recently_used_sessions_are_valid() {
+        let now = Utc::now();
+        let expiry = timeouts().expiry(
+            Some(now - Duration::hours(11)),
+            Some(now - Duration::minutes(29)),
+            now,
+        );
+        assert_eq!(expiry, None);
+    }
+
+    #[test]
+    fn idle_sessions_expire() {
+        let now = Utc::now();
+        let expiry = timeouts().expiry(
+            Some(now - Duration::hours(1)),
+            Some(now - Duration::minutes(30)),
+            now,
+        );
+        assert_eq!(expiry, Some(SessionExpiry::Idle));
+        // Sessions that never recorded their activity were last seen when
+        // they logged in.
+        let expiry =
+            timeouts().expiry(Some(now - Duration::hours(1)), None, now);
+        assert_eq!(expiry, Some(SessionExpiry::Idle));
+    }
+
+    #[test]
+    fn sessions_expire_after_the_absolute_timeout_however_active() {
+        let now = Utc::now();
+        let expiry =
+            timeouts().expiry(Some(now - Duration::hours(12)), Some(now), now);
+        assert_eq!(expiry, Some(SessionExpiry::Absolute));
+        assert_eq!(
+            timeouts().expiry(None, Some(now), now),
+            Some(SessionExpiry::Absolute)
+        );
+    }
+}
@@ -0,0 +1,238 @@
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use redis::aio::ConnectionManager;
+use secrecy::{ExposeSecret, Secret};
+use uuid::Uuid;
+
+use crate::client_info::ClientInfo;
+use crate::session::SessionTimeouts;
+
+/// The sessions each user is logged in with, so that they can see where they
+/// are logged in and revoke sessions remotely.
+///
+/// Stored in the Redis instance backing the sessions: one set of session ids
+/// per user, and one key per session with its details. A session whose
+/// details are gone is revoked, and gets logged out on its next request.
+/// The set of a user expires with their last session, and drops the ids of
+/// sessions that expired on their own whenever it is listed.
+#[derive(Clone)]
+pub struct SessionIndex {
+    connection: ConnectionManager,
+    key_prefix: String,
+    /// How long an unused session is remembered, i.e. the idle timeout.
+    ttl_seconds: i64,
+    /// How long the sessions of a user are remembered after the last one
+    /// was created, i.e. the absolute timeout.
+    user_ttl_seconds: i64,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct SessionDetails {
+    pub created_at: DateTime<Utc>,
+    pub last_seen_at: DateTime<Utc>,
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+pub struct ActiveSession {
+    pub id: Uuid,
+    pub details: SessionDetails,
+}
+
+impl SessionIndex {
+    pub async fn new(
+        redis_uri: &Secret<String>,
+        key_prefix: String,
+        timeouts: SessionTimeouts,
+    ) -> Result<Self, anyhow::Error> {
+        let client = redis::Client::open(redis_uri.expose_secret().as_str())
+            .context("failed to parse redis uri")?;
+        let connection = ConnectionManager::new(client)
+            .await
+            .context("failed to connect to redis")?;
+        Ok(Self {
+            connection,
+            key_prefix,
+            ttl_seconds: timeouts.idle.num_seconds(),
+            user_ttl_seconds: timeouts.absolute.num_seconds(),
+        })
+    }
+
+    /// Record a new session of `user_id`.
+    #[tracing::instrument(name = "Index session", skip(self, client_info))]
+    pub async fn add(
+        &self,
+        user_id: Uuid,
+        session_id: Uuid,
+        client_info: &ClientInfo,
+    ) -> Result<(), anyhow::Error> {
+        let now = Utc::now();
+        let details = SessionDetails {
+            created_at: now,
+            last_seen_at: now,
+            ip_address: client_info.ip_address.clone(),
+            user_agent: client_info.user_agent.clone(),
+        };
+        let mut connection = self.connection.clone();
+        redis::pipe()
+            .atomic()
+            .cmd("SET")
+            .arg(self.session_key(session_id))
+            .arg(serde_json::to_string(&details)?)
+            .arg("EX")
+            .arg(self.ttl_seconds)
+            .ignore()
+            .cmd("SADD")
+            .arg(self.user_key(user_id))
+            .arg(session_id.to_string())
+            .ignore()
+            // No session outlives the absolute timeout, so neither does the
+            // set once the newest of them is gone.
+            .cmd("EXPIRE")
+            .arg(self.user_key(user_id))
+            .arg(self.user_ttl_seconds)
+            .ignore()
+            .query_async::<_, ()>(&mut connection)
+            .await
+            .context("failed to index session in redis")?;
+        Ok(())
+    }
+
+    /// Record a request made with the session. Returns `false` if the
+    /// session has been revoked.
+    #[tracing::instrument(name = "Touch session", skip(self, client_info))]
+    pub async fn touch(
+        &self,
+        session_id: Uuid,
+        client_info: &ClientInfo,
+    ) -> Result<bool, anyhow::Error> {
+        let mut connection = self.connection.clone();
+        let key = self.session_key(session_id);
+        let details: Option<String> = redis::cmd("GET")
+            .arg(&key)
+            .query_async(&mut connection)
+            .await
+            .context("failed to read session from redis")?;
+        let Some(details) = details else {
+            return Ok(false);
+        };
+        let mut details: SessionDetails = serde_json::from_str(&details)
+            .context("failed to parse session details")?;
+        details.last_seen_at = Utc::now();
+        details.ip_address = client_info.ip_address.clone();
+        details.user_agent = client_info.user_agent.clone();
+        // XX: a session revoked in the meantime stays revoked.
+        let updated: Option<String> = redis::cmd("SET")
+            .arg(&key)
+            .arg(serde_json::to_string(&details)?)
+            .arg("XX")
+            .arg("EX")
+            .arg(self.ttl_seconds)
+            .query_async(&mut connection)
+            .await
+            .context("failed to update session in redis")?;
+        Ok(updated.is_some())
+    }
+
+    /// The sessions of `user_id`, most recently used first.
+    #[tracing::instrument(name = "List sessions", skip(self))]
+    pub async fn list(
+        &self,
+        user_id: Uuid,
+    ) -> Result<Vec<ActiveSession>, anyhow::Error> {
+        let mut connection = self.connection.clone();
+        let ids: Vec<String> = redis::cmd("SMEMBERS")
+            .arg(self.user_key(user_id))
+            .query_async(&mut connection)
+            .await
+            .context("failed to list sessions in redis")?;
+        let ids: Vec<Uuid> =
+            ids.iter().filter_map(|id| id.parse().ok()).collect();
+        if ids.is_empty() {
+            return Ok(Vec::new());
+        }
+        let details: Vec<Option<String>> = redis::cmd("MGET")
+            .arg(
+                ids.iter()
+                    .map(|id| self.session_key(*id))
+                    .collect::<Vec<_>>(),
+            )
+            .query_async(&mut connection)
+            .await
+            .context("failed to read sessions from redis")?;
+
+        let mut sessions = Vec::new();
+        let mut expired = Vec::new();
+        for (id, details) in ids.into_iter().zip(details) {
+            match details.and_then(|d| serde_json::from_str(&d).ok()) {
+                Some(details) => sessions.push(ActiveSession { id, details }),
+                None => expired.push(id.to_string()),
+            }
+        }
+        if !expired.is_empty() {
+            redis::cmd("SREM")
+                .arg(self.user_key(user_id))
+                .arg(expired)
+                .query_async::<_, ()>(&mut connection)
+                .await
+                .context("failed to forget expired sessions in redis")?;
+        }
+        sessions.sort_by(|a, b| {
+            b.details.last_seen_at.cmp(&a.details.last_seen_at)
+        });
+        Ok(sessions)
+    }
+
+    /// Revoke one of the sessions of `user_id`. Returns `false` if it is not
+    /// one of theirs.
+    #[tracing::instrument(name = "Revoke session", skip(self))]
+    pub async fn revoke(
+        &self,
+        user_id: Uuid,
+        session_id: Uuid,
+    ) -> Result<bool, anyhow::Error> {
+        let mut connection = self.connection.clone();
+        let removed: u64 = redis::cmd("SREM")
+            .arg(self.user_key(user_id))
+            .arg(session_id.to_string())
+            .query_async(&mut connection)
+            .await
+            .context("failed to revoke session in redis")?;
+        if removed == 0 {
+            return Ok(false);
+        }
+        redis::cmd("DEL")
+            .arg(self.session_key(session_id))
+            .query_async::<_, ()>(&mut connection)
+            .await
+            .context("failed to revoke session in redis")?;
+        Ok(true)
+    }
+
+    /// Revoke the sessions of `user_id`, but `except` if given. Returns the
+    /// ids of the revoked sessions.
+    #[tracing::instrument(name = "Revoke all sessions", skip(self))]
+    pub async fn revoke_all(
+        &self,
+        user_id: Uuid,
+        except: Option<Uuid>,
+    ) -> Result<Vec<Uuid>, anyhow::Error> {
+        let mut revoked = Vec::new();
+        for session in self.list(user_id).await? {
+            if Some(session.id) != except
+                && self.revoke(user_id, session.id).await?
+            {
+                revoked.push(session.id);
+            }
+        }
+        Ok(revoked)
+    }
+
+    fn user_key(&self, user_id: Uuid) -> String {
+        format!("{}:user_sessions:{}", self.key_prefix, user_id)
+    }
+
+    fn session_key(&self, session_id: Uuid) -> String {
+        format!("{}:session:{}", self.key_prefix, session_id)
+    }
+}
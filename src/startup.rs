@@ -1,23 +1,34 @@
 use std::net::TcpListener;
 
+use actix_cors::Cors;
+use actix_session::config::BrowserSession;
 use actix_session::storage::RedisSessionStore;
 use actix_session::SessionMiddleware;
 use actix_web::cookie::Key;
-use actix_web::dev::Server;
+use actix_web::dev::{RequestHead, Server};
+use actix_web::http::header::{self, HeaderValue};
 use actix_web::web::Data;
-use actix_web::{web, App, HttpServer};
+use actix_web::{guard, web, App, HttpServer};
 use actix_web_flash_messages::storage::CookieMessageStore;
 use actix_web_flash_messages::FlashMessagesFramework;
 use actix_web_lab::middleware::from_fn;
+use anyhow::Context;
 use secrecy::{ExposeSecret, Secret};
 use sqlx::postgres::PgPoolOptions;
 use sqlx::PgPool;
 use tracing_actix_web::TracingLogger;
 
-use crate::authentication::reject_anonymous_users;
+use crate::authentication::{
+    reject_anonymous_users, reject_invalid_csrf_tokens, require,
+    PasswordHashing, PasswordPolicy, Permission,
+};
 use crate::configuration::{DatabaseSettings, Settings};
 use crate::email_client::EmailClient;
+use crate::login_guard::LoginGuard;
+use crate::rate_limit::RateLimiter;
 use crate::routes;
+use crate::session_index::SessionIndex;
+use crate::subscription_guard::SubscriptionGuard;
 
 pub struct Application {
     port: u16,
@@ -27,17 +38,7 @@ pub struct Application {
 impl Application {
     pub async fn build(configuration: Settings) -> Result<Self, anyhow::Error> {
         let connection_pool = get_connection_pool(&configuration.database);
-        let sender_email = configuration
-            .email_client
-            .sender()
-            .expect("Invalid sender email address.");
-        let timeout = configuration.email_client.timeout();
-        let email_client = EmailClient::new(
-            configuration.email_client.base_url,
-            sender_email,
-            configuration.email_client.authorization_token,
-            timeout,
-        );
+        let email_client = configuration.email_client.clone().client();
 
         let address = format!(
             "{}:{}",
@@ -45,15 +46,8 @@ impl Application {
         );
         let listener = TcpListener::bind(address)?;
         let port = listener.local_addr().unwrap().port();
-        let server = run(
-            listener,
-            connection_pool,
-            email_client,
-            configuration.application.base_url,
-            configuration.application.hmac_secret,
-            configuration.redis_uri,
-        )
-        .await?;
+        let server =
+            run(listener, connection_pool, email_client, configuration).await?;
 
         Ok(Self { port, server })
     }
@@ -79,13 +73,61 @@ async fn run(
     listener: TcpListener,
     db_pool: PgPool,
     email_client: EmailClient,
-    base_url: String,
-    hmac_secret: Secret<String>,
-    redis_uri: Secret<String>,
+    configuration: Settings,
 ) -> Result<Server, anyhow::Error> {
+    let session_timeouts = configuration.application.session_timeouts();
+    let trusted_proxies =
+        Data::new(configuration.application.trusted_proxies());
+    let hmac_secret = configuration.application.hmac_secret;
+    let redis_uri = configuration.redis_uri;
+
     let db_pool = Data::new(db_pool);
     let email_client = Data::new(email_client);
-    let base_url = Data::new(ApplicationBaseUrl(base_url));
+    let base_url =
+        Data::new(ApplicationBaseUrl(configuration.application.base_url));
+
+    let rate_limiter =
+        RateLimiter::new(&redis_uri, configuration.redis_key_prefix.clone())
+            .await?;
+    let subscription_guard = Data::new(SubscriptionGuard::new(
+        hmac_secret.clone(),
+        rate_limiter.clone(),
+        configuration.subscriptions.clone(),
+    ));
+    let session_index = Data::new(
+        SessionIndex::new(
+            &redis_uri,
+            configuration.redis_key_prefix,
+            session_timeouts,
+        )
+        .await?,
+    );
+    let login_guard = Data::new(LoginGuard::new(
+        rate_limiter.clone(),
+        configuration.login_throttling,
+    ));
+    let rate_limiter = Data::new(rate_limiter);
+    let password_hashing =
+        Data::new(PasswordHashing::new(&configuration.password_hashing)?);
+    let password_policy =
+        Data::new(PasswordPolicy::new(&configuration.password_policy)?);
+    let email_domain_blocklist = Data::new(
+        configuration
+            .subscriptions
+            .email_domain_blocklist()
+            .context("failed to read the email domain blocklist")?,
+    );
+    let attribute_schema = Data::new(
+        configuration
+            .subscriptions
+            .attribute_schema()
+            .map_err(anyhow::Error::msg)
+            .context("invalid custom subscriber attributes")?,
+    );
+    let cors_allowed_origins =
+        configuration.subscriptions.cors_allowed_origins.clone();
+    let subscription_settings = Data::new(configuration.subscriptions);
+    let password_reset_settings = Data::new(configuration.password_reset);
 
     let secret_key = Key::from(hmac_secret.expose_secret().as_bytes());
     let message_store = CookieMessageStore::builder(secret_key.clone()).build();
@@ -97,14 +139,27 @@ async fn run(
     let server = HttpServer::new(move || {
         App::new()
             .wrap(message_framework.clone())
-            .wrap(SessionMiddleware::new(
-                redis_store.clone(),
-                secret_key.clone(),
-            ))
+            .wrap(
+                SessionMiddleware::builder(
+                    redis_store.clone(),
+                    secret_key.clone(),
+                )
+                // Long enough for the session to tell why it expired, see
+                // `reject_anonymous_users`.
+                .session_lifecycle(BrowserSession::default().state_ttl(
+                    actix_web::cookie::time::Duration::seconds(
+                        session_timeouts.absolute.num_seconds(),
+                    ),
+                ))
+                .build(),
+            )
             .wrap(TracingLogger::default())
             .route("/", web::get().to(routes::home))
             .service(
                 web::scope("/admin")
+                    // Anonymous users are redirected to the login form
+                    // before their requests are checked for a CSRF token.
+                    .wrap(from_fn(reject_invalid_csrf_tokens))
                     .wrap(from_fn(reject_anonymous_users))
                     .route("/dashboard", web::get().to(routes::admin_dashboard))
                     .route("/logout", web::post().to(routes::logout))
@@ -113,9 +168,190 @@ async fn run(
                         web::get().to(routes::change_password_form),
                     )
                     .route("/password", web::post().to(routes::change_password))
+                    .route("/sessions", web::get().to(routes::list_sessions))
+                    .route(
+                        "/sessions/revoke_others",
+                        web::post().to(routes::revoke_other_sessions),
+                    )
+                    .route(
+                        "/sessions/{session_id}/revoke",
+                        web::post().to(routes::revoke_session),
+                    )
+                    .route(
+                        "/two_factor",
+                        web::get().to(routes::two_factor_settings),
+                    )
+                    .route(
+                        "/two_factor",
+                        web::post()
+                            .to(routes::enable_two_factor_authentication),
+                    )
+                    .route(
+                        "/two_factor/disable",
+                        web::post()
+                            .to(routes::disable_two_factor_authentication),
+                    )
                     .route(
                         "/newsletter",
-                        web::get().to(routes::publish_newsletter_form),
+                        web::get()
+                            .to(routes::publish_newsletter_form)
+                            .wrap(require(Permission::PublishNewsletters)),
+                    )
+                    .route(
+                        "/api_tokens",
+                        web::get()
+                            .to(routes::list_api_tokens)
+                            .wrap(require(Permission::PublishNewsletters)),
+                    )
+                    .route(
+                        "/api_tokens",
+                        web::post()
+                            .to(routes::create_api_token)
+                            .wrap(require(Permission::PublishNewsletters)),
+                    )
+                    .route(
+                        "/api_tokens/{token_id}/revoke",
+                        web::post()
+                            .to(routes::revoke_api_token)
+                            .wrap(require(Permission::PublishNewsletters)),
+                    )
+                    .route(
+                        "/referrals",
+                        web::get()
+                            .to(routes::referral_leaderboard)
+                            .wrap(require(Permission::ViewSubscribers)),
+                    )
+                    .route(
+                        "/audit",
+                        web::get()
+                            .to(routes::audit_log)
+                            .wrap(require(Permission::ViewAuditLog)),
+                    )
+                    .route(
+                        "/audit/export.csv",
+                        web::get()
+                            .to(routes::export_audit_log)
+                            .wrap(require(Permission::ViewAuditLog)),
+                    )
+                    .route(
+                        "/users",
+                        web::get()
+                            .to(routes::list_users)
+                            .wrap(require(Permission::ManageUsers)),
+                    )
+                    .route(
+                        "/users/invitations",
+                        web::post()
+                            .to(routes::invite_user)
+                            .wrap(require(Permission::ManageUsers)),
+                    )
+                    .route(
+                        "/users/two_factor_requirement",
+                        web::post()
+                            .to(routes::require_two_factor)
+                            .wrap(require(Permission::ManageUsers)),
+                    )
+                    .route(
+                        "/users/{user_id}/deactivate",
+                        web::post()
+                            .to(routes::deactivate_user)
+                            .wrap(require(Permission::ManageUsers)),
+                    )
+                    .route(
+                        "/users/{user_id}/role",
+                        web::post()
+                            .to(routes::change_user_role)
+                            .wrap(require(Permission::ManageUsers)),
+                    )
+                    .route(
+                        "/users/{user_id}/reactivate",
+                        web::post()
+                            .to(routes::reactivate_user)
+                            .wrap(require(Permission::ManageUsers)),
+                    )
+                    .route(
+                        "/subscribers",
+                        web::get()
+                            .to(routes::list_subscribers)
+                            .wrap(require(Permission::ViewSubscribers)),
+                    )
+                    .route(
+                        "/subscribers/export",
+                        web::get()
+                            .to(routes::export_subscribers)
+                            .wrap(require(Permission::ViewSubscribers)),
+                    )
+                    .route(
+                        "/subscribers/import",
+                        web::get()
+                            .to(routes::import_subscribers_form)
+                            .wrap(require(Permission::ManageSubscribers)),
+                    )
+                    .route(
+                        "/subscribers/import",
+                        web::post()
+                            .to(routes::import_subscribers)
+                            .wrap(require(Permission::ManageSubscribers)),
+                    )
+                    .route(
+                        "/subscribers/imports/{import_id}",
+                        web::get()
+                            .to(routes::import_report)
+                            .wrap(require(Permission::ManageSubscribers)),
+                    )
+                    .route(
+                        "/subscribers/imports/{import_id}/errors.csv",
+                        web::get()
+                            .to(routes::import_error_report)
+                            .wrap(require(Permission::ManageSubscribers)),
+                    )
+                    .route(
+                        "/subscribers/{subscriber_id}",
+                        web::get()
+                            .to(routes::subscriber_details)
+                            .wrap(require(Permission::ViewSubscribers)),
+                    )
+                    .route(
+                        "/subscribers/{subscriber_id}/confirm",
+                        web::post()
+                            .to(routes::confirm_subscriber_manually)
+                            .wrap(require(Permission::ManageSubscribers)),
+                    )
+                    .route(
+                        "/subscribers/{subscriber_id}/resend_confirmation",
+                        web::post()
+                            .to(routes::resend_confirmation)
+                            .wrap(require(Permission::ManageSubscribers)),
+                    )
+                    .route(
+                        "/subscribers/{subscriber_id}/unsubscribe",
+                        web::post()
+                            .to(routes::unsubscribe_subscriber)
+                            .wrap(require(Permission::ManageSubscribers)),
+                    )
+                    .route(
+                        "/subscribers/{subscriber_id}/attributes",
+                        web::post()
+                            .to(routes::update_subscriber_attributes)
+                            .wrap(require(Permission::ManageSubscribers)),
+                    )
+                    .route(
+                        "/subscribers/{subscriber_id}/delete",
+                        web::post()
+                            .to(routes::delete_subscriber)
+                            .wrap(require(Permission::ManageSubscribers)),
+                    )
+                    .route(
+                        "/subscribers/{subscriber_id}/consent",
+                        web::get()
+                            .to(routes::subscriber_consent)
+                            .wrap(require(Permission::ViewSubscribers)),
+                    )
+                    .route(
+                        "/subscribers/{subscriber_id}/consent.json",
+                        web::get()
+                            .to(routes::subscriber_consent_export)
+                            .wrap(require(Permission::ViewSubscribers)),
                     ),
                 //
                 // .route(
@@ -127,17 +363,68 @@ async fn run(
             .service(
                 web::scope("/login")
                     .route("", web::get().to(routes::login_form))
-                    .route("", web::post().to(routes::login)),
+                    .route("", web::post().to(routes::login))
+                    .route(
+                        "/two_factor",
+                        web::get().to(routes::two_factor_form),
+                    )
+                    .route(
+                        "/two_factor",
+                        web::post().to(routes::verify_two_factor),
+                    ),
+            )
+            .service(
+                web::scope("/password_reset")
+                    .route(
+                        "",
+                        web::get().to(routes::request_password_reset_form),
+                    )
+                    .route("", web::post().to(routes::request_password_reset))
+                    .route(
+                        "/confirm",
+                        web::get().to(routes::password_reset_form),
+                    )
+                    .route("/confirm", web::post().to(routes::reset_password)),
+            )
+            .service(
+                web::scope("/invitations")
+                    .route("", web::get().to(routes::accept_invitation_form))
+                    .route("", web::post().to(routes::accept_invitation)),
             )
             .route("/newsletter", web::post().to(routes::publish_newsletter))
             .service(
                 web::scope("/subscriptions")
+                    .wrap(subscriptions_cors(cors_allowed_origins.clone()))
+                    .route("", web::get().to(routes::subscribe_form))
+                    .route(
+                        "",
+                        web::post()
+                            .guard(guard::fn_guard(|ctx| is_json(ctx.head())))
+                            .to(routes::subscribe_json),
+                    )
                     .route("", web::post().to(routes::subscribe))
-                    .route("/confirm", web::get().to(routes::confirm)),
+                    .route("/confirm", web::get().to(routes::confirm))
+                    .route("/widget", web::get().to(routes::subscribe_widget))
+                    .route(
+                        "/widget.js",
+                        web::get().to(routes::subscribe_widget_script),
+                    ),
             )
             .app_data(db_pool.clone())
             .app_data(email_client.clone())
             .app_data(base_url.clone())
+            .app_data(subscription_settings.clone())
+            .app_data(subscription_guard.clone())
+            .app_data(password_reset_settings.clone())
+            .app_data(rate_limiter.clone())
+            .app_data(login_guard.clone())
+            .app_data(password_hashing.clone())
+            .app_data(password_policy.clone())
+            .app_data(session_index.clone())
+            .app_data(Data::new(session_timeouts))
+            .app_data(trusted_proxies.clone())
+            .app_data(email_domain_blocklist.clone())
+            .app_data(attribute_schema.clone())
             .app_data(Data::new(HmacSecret(hmac_secret.clone())))
     })
     .listen(listener)?
@@ -145,5 +432,46 @@ async fn run(
     Ok(server)
 }
 
+/// Let the sites embedding the sign-up widget call the subscription
+/// endpoints from a browser. Same-origin requests, e.g. from our own sign-up
+/// form, are always allowed.
+fn subscriptions_cors(allowed_origins: Vec<String>) -> Cors {
+    Cors::default()
+        .allowed_origin_fn(move |origin, request| {
+            allowed_origins
+                .iter()
+                .any(|o| o.as_bytes() == origin.as_bytes())
+                || is_same_origin(origin, request)
+        })
+        .allowed_methods(["GET", "POST"])
+        .allowed_header(header::CONTENT_TYPE)
+        .max_age(3600)
+}
+
+fn is_same_origin(origin: &HeaderValue, request: &RequestHead) -> bool {
+    let host = origin
+        .to_str()
+        .ok()
+        .and_then(|origin| origin.split_once("://"))
+        .map(|(_, host)| host);
+    host.is_some()
+        && host.map(str::as_bytes)
+            == request
+                .headers()
+                .get(header::HOST)
+                .map(HeaderValue::as_bytes)
+}
+
+fn is_json(request: &RequestHead) -> bool {
+    request
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.split(';').next())
+        .is_some_and(|mime| {
+            mime.trim().eq_ignore_ascii_case("application/json")
+        })
+}
+
 #[derive(Clone)]
 pub struct HmacSecret(pub Secret<String>);
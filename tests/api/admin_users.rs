@@ -0,0 +1,308 @@
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+use crate::helpers::{spawn_app, TestApp};
+use crate::login::assert_is_redirect_to;
+
+/// Invite `email` as a viewer and return the invitation link sent to them.
+async fn invite(app: &TestApp, email: &str) -> reqwest::Url {
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+
+    let response = app
+        .post_form(
+            "/admin/users/invitations",
+            &[("email", email), ("role", "viewer")],
+        )
+        .await;
+    assert_is_redirect_to(&response, "/admin/users");
+
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let links = app.get_confirmation_links(&email_request);
+    assert_eq!(links.html, links.plain_text);
+    links.html
+}
+
+/// A client with its own cookies, i.e. a different person's browser.
+fn other_browser() -> reqwest::Client {
+    reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .cookie_store(true)
+        .build()
+        .unwrap()
+}
+
+async fn accept(
+    app: &TestApp,
+    link: &reqwest::Url,
+    username: &str,
+    password: &str,
+) -> reqwest::Response {
+    let token = link
+        .query_pairs()
+        .find(|(name, _)| name == "token")
+        .unwrap()
+        .1
+        .into_owned();
+    other_browser()
+        .post(format!("{}/invitations", app.address))
+        .form(&[
+            ("token", token.as_str()),
+            ("username", username),
+            ("password", password),
+            ("password_validate", password),
+        ])
+        .send()
+        .await
+        .unwrap()
+}
+
+async fn login_as(
+    app: &TestApp,
+    client: &reqwest::Client,
+    username: &str,
+    password: &str,
+) -> reqwest::Response {
+    client
+        .post(format!("{}/login", app.address))
+        .form(&[("username", username), ("password", password)])
+        .send()
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_manage_users() {
+    let app = spawn_app().await;
+
+    let response = app.get("/admin/users").await;
+    assert_is_redirect_to(&response, "/login");
+
+    let response = app
+        .post_form(
+            "/admin/users/invitations",
+            &[("email", "ged@example.com"), ("role", "editor")],
+        )
+        .await;
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn invited_users_choose_their_password_and_can_log_in() {
+    let app = spawn_app().await;
+    app.login().await;
+
+    let link = invite(&app, "ged@example.com").await;
+    let html_page = app.get("/admin/users").await.text().await.unwrap();
+    assert!(html_page.contains(
+        "<p><i>An invitation has been sent to ged@example.com.</i></p>"
+    ));
+    assert!(html_page.contains(&format!(
+        "<tr><td>ged@example.com</td><td>viewer</td><td>{}</td>",
+        app.test_user.username
+    )));
+
+    let html_page = reqwest::get(link.clone())
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(html_page
+        .contains("Choose a username and a password for ged@example.com."));
+
+    let response = accept(&app, &link, "ged", "a-long-enough-password").await;
+    assert_is_redirect_to(&response, "/login");
+
+    let browser = other_browser();
+    let response =
+        login_as(&app, &browser, "ged", "a-long-enough-password").await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+    let user = sqlx::query!(
+        r#"SELECT email, role::text AS "role!" FROM users WHERE username = 'ged'"#
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(user.email.as_deref(), Some("ged@example.com"));
+    assert_eq!(user.role, "viewer");
+}
+
+#[tokio::test]
+async fn invitation_links_can_only_be_used_once() {
+    let app = spawn_app().await;
+    app.login().await;
+    let link = invite(&app, "ged@example.com").await;
+
+    accept(&app, &link, "ged", "a-long-enough-password").await;
+
+    let response =
+        accept(&app, &link, "sparrowhawk", "a-long-enough-password").await;
+    assert_eq!(response.status().as_u16(), 404);
+    let response = reqwest::get(link).await.unwrap();
+    assert_eq!(response.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn expired_invitation_links_are_rejected() {
+    let app = spawn_app().await;
+    app.login().await;
+    let link = invite(&app, "ged@example.com").await;
+    sqlx::query!(
+        "UPDATE user_invitations SET expires_at = now() - interval '1 minute'"
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    let response = accept(&app, &link, "ged", "a-long-enough-password").await;
+
+    assert_eq!(response.status().as_u16(), 404);
+    let html_page = app.get("/admin/users").await.text().await.unwrap();
+    assert!(!html_page.contains("<td>ged@example.com</td>"));
+}
+
+#[tokio::test]
+async fn invitation_tokens_are_stored_hashed() {
+    let app = spawn_app().await;
+    app.login().await;
+    let link = invite(&app, "ged@example.com").await;
+
+    let invitation = sqlx::query!("SELECT token_hash FROM user_invitations")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+
+    assert!(!link.as_str().contains(&invitation.token_hash));
+}
+
+#[tokio::test]
+async fn accepting_an_invitation_requires_a_valid_username_and_password() {
+    let app = spawn_app().await;
+    app.login().await;
+    let link = invite(&app, "ged@example.com").await;
+
+    let test_cases = [
+        (
+            app.test_user.username.as_str(),
+            "a-long-enough-password",
+            "This username is taken.",
+        ),
+        (
+            "  ",
+            "a-long-enough-password",
+            "Username must be between 1 and 64 characters.",
+        ),
+        (
+            "ged",
+            "short",
+            "New password must be between 12 and 128 characters.",
+        ),
+        (
+            "ged",
+            "ged@example.com1",
+            "This password is too easy to guess.",
+        ),
+        (
+            "ged",
+            "correct horse battery staple",
+            "This password has appeared in a data breach",
+        ),
+    ];
+    for (username, password, error_message) in test_cases {
+        let response = accept(&app, &link, username, password).await;
+        assert_eq!(response.status().as_u16(), 303, "{}", error_message);
+        assert!(response.headers()["Location"]
+            .to_str()
+            .unwrap()
+            .starts_with("/invitations?token="));
+    }
+    // The invitation is still usable.
+    let response = accept(&app, &link, "ged", "a-long-enough-password").await;
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn inviting_an_existing_user_is_rejected() {
+    let app = spawn_app().await;
+    app.login().await;
+    let link = invite(&app, "ged@example.com").await;
+    accept(&app, &link, "ged", "a-long-enough-password").await;
+
+    let response = app
+        .post_form(
+            "/admin/users/invitations",
+            &[("email", "GED@example.com"), ("role", "editor")],
+        )
+        .await;
+    assert_is_redirect_to(&response, "/admin/users");
+
+    let html_page = app.get("/admin/users").await.text().await.unwrap();
+    assert!(html_page.contains("already has an account."));
+}
+
+#[tokio::test]
+async fn deactivated_users_are_logged_out_and_cannot_log_in() {
+    let app = spawn_app().await;
+    app.login().await;
+    let link = invite(&app, "ged@example.com").await;
+    accept(&app, &link, "ged", "a-long-enough-password").await;
+    let browser = other_browser();
+    login_as(&app, &browser, "ged", "a-long-enough-password").await;
+    let ged = sqlx::query!("SELECT user_id FROM users WHERE username = 'ged'")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+
+    let response = app
+        .post(&format!("/admin/users/{}/deactivate", ged.user_id))
+        .await;
+    assert_is_redirect_to(&response, "/admin/users");
+    let html_page = app.get("/admin/users").await.text().await.unwrap();
+    assert!(html_page.contains("<p><i>ged has been deactivated.</i></p>"));
+
+    let response = browser
+        .get(format!("{}/admin/dashboard", app.address))
+        .send()
+        .await
+        .unwrap();
+    assert_is_redirect_to(&response, "/login");
+    let response =
+        login_as(&app, &browser, "ged", "a-long-enough-password").await;
+    assert_is_redirect_to(&response, "/login");
+
+    app.post(&format!("/admin/users/{}/reactivate", ged.user_id))
+        .await;
+    let response =
+        login_as(&app, &browser, "ged", "a-long-enough-password").await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
+
+#[tokio::test]
+async fn admins_cannot_deactivate_themselves() {
+    let app = spawn_app().await;
+    app.login().await;
+
+    let response = app
+        .post(&format!(
+            "/admin/users/{}/deactivate",
+            app.test_user.user_id
+        ))
+        .await;
+    assert_is_redirect_to(&response, "/admin/users");
+
+    let html_page = app.get("/admin/users").await.text().await.unwrap();
+    assert!(html_page
+        .contains("<p><i>You cannot deactivate your own account.</i></p>"));
+    assert_eq!(app.get_admin_dashboard().await.status().as_u16(), 200);
+}
@@ -0,0 +1,200 @@
+use chrono::{Duration, Utc};
+use uuid::Uuid;
+use zero2prod::authentication::{hash_token, Role};
+
+use crate::helpers::{spawn_app, TestApp, TestUser};
+use crate::login::assert_is_redirect_to;
+
+/// Create a token through the admin UI and return it, as shown once.
+async fn create_token(app: &TestApp, form: &[(&str, &str)]) -> String {
+    let response = app.post_form("/admin/api_tokens", &form).await;
+    assert_eq!(response.status().as_u16(), 200);
+    let html_page = response.text().await.unwrap();
+    let start = html_page.find("<code>z2p_").unwrap() + "<code>".len();
+    let end = start + html_page[start..].find("</code>").unwrap();
+    html_page[start..end].to_string()
+}
+
+async fn publish_with_token(app: &TestApp, token: &str) -> reqwest::Response {
+    reqwest::Client::new()
+        .post(format!("{}/newsletter", app.address))
+        .bearer_auth(token)
+        .json(&serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": "<p>Newsletter body as HTML</p>",
+            }
+        }))
+        .send()
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn tokens_are_shown_once_and_stored_hashed() {
+    let app = spawn_app().await;
+    app.login().await;
+
+    let token =
+        create_token(&app, &[("name", "CI"), ("scope", "publish_newsletters")])
+            .await;
+
+    let saved = sqlx::query!(
+        "SELECT user_id, name, token_hash, scopes, expires_at FROM api_tokens"
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(saved.user_id, app.test_user.user_id);
+    assert_eq!(saved.name, "CI");
+    assert_eq!(saved.token_hash, hash_token(&token));
+    assert_eq!(saved.scopes, ["publish_newsletters"]);
+    assert!(saved.expires_at.is_none());
+
+    let html_page = app.get("/admin/api_tokens").await.text().await.unwrap();
+    assert!(html_page.contains("<td>CI</td>"));
+    assert!(!html_page.contains(&token));
+}
+
+#[tokio::test]
+async fn tokens_can_publish_newsletters_and_record_their_last_use() {
+    let app = spawn_app().await;
+    app.login().await;
+    let token = create_token(
+        &app,
+        &[
+            ("name", "CI"),
+            ("scope", "publish_newsletters"),
+            ("expires_in_days", "30"),
+        ],
+    )
+    .await;
+
+    let response = publish_with_token(&app, &token).await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    let saved = sqlx::query!("SELECT id, last_used_at FROM api_tokens")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(saved.last_used_at.is_some());
+    let event = sqlx::query!(
+        "SELECT payload FROM audit_events WHERE action = 'newsletter_published'"
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(event.payload["api_token_id"], saved.id.to_string());
+}
+
+#[tokio::test]
+async fn revoked_tokens_are_rejected() {
+    let app = spawn_app().await;
+    app.login().await;
+    let token =
+        create_token(&app, &[("name", "CI"), ("scope", "publish_newsletters")])
+            .await;
+    let token_id = sqlx::query!("SELECT id FROM api_tokens")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .id;
+
+    let response = app
+        .post(&format!("/admin/api_tokens/{}/revoke", token_id))
+        .await;
+    assert_is_redirect_to(&response, "/admin/api_tokens");
+    let html_page = app.get("/admin/api_tokens").await.text().await.unwrap();
+    assert!(html_page.contains("The CI token has been revoked."));
+    assert!(html_page.contains("<td>revoked</td>"));
+
+    let response = publish_with_token(&app, &token).await;
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn expired_and_unknown_tokens_are_rejected() {
+    let app = spawn_app().await;
+    let token = "z2p_expired";
+    sqlx::query!(
+        r#"
+        INSERT INTO api_tokens (id, user_id, name, token_hash, scopes, created_at, expires_at)
+        VALUES ($1, $2, 'old', $3, ARRAY['publish_newsletters'], now(), $4)
+        "#,
+        Uuid::new_v4(),
+        app.test_user.user_id,
+        hash_token(token),
+        Utc::now() - Duration::minutes(1),
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    for token in [token, "z2p_unknown"] {
+        let response = publish_with_token(&app, token).await;
+        assert_eq!(response.status().as_u16(), 401, "{}", token);
+    }
+}
+
+#[tokio::test]
+async fn tokens_cannot_do_more_than_their_owner() {
+    let app = spawn_app().await;
+    let viewer = TestUser::with_role(Role::Viewer);
+    viewer.store(&app.db_pool).await;
+    let token = "z2p_viewer";
+    sqlx::query!(
+        r#"
+        INSERT INTO api_tokens (id, user_id, name, token_hash, scopes, created_at)
+        VALUES ($1, $2, 'viewer', $3, ARRAY['publish_newsletters'], now())
+        "#,
+        Uuid::new_v4(),
+        viewer.user_id,
+        hash_token(token),
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    let response = publish_with_token(&app, token).await;
+
+    assert_eq!(response.status().as_u16(), 403);
+}
+
+#[tokio::test]
+async fn invalid_tokens_are_not_created() {
+    let app = spawn_app().await;
+    app.login().await;
+
+    for (form, message) in [
+        (
+            vec![("name", ""), ("scope", "publish_newsletters")],
+            "The token needs a name.",
+        ),
+        (vec![("name", "CI")], "The token needs at least one scope."),
+        (
+            vec![("name", "CI"), ("scope", "everything")],
+            "everything is not a valid scope.",
+        ),
+        (
+            vec![
+                ("name", "CI"),
+                ("scope", "publish_newsletters"),
+                ("expires_in_days", "0"),
+            ],
+            "The token must expire in 1 to 365 days, or never.",
+        ),
+    ] {
+        let response = app.post_form("/admin/api_tokens", &form).await;
+        assert_is_redirect_to(&response, "/admin/api_tokens");
+        let html_page =
+            app.get("/admin/api_tokens").await.text().await.unwrap();
+        assert!(html_page.contains(message), "{}", message);
+    }
+    let count = sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM api_tokens"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(count, 0);
+}
@@ -0,0 +1,214 @@
+use uuid::Uuid;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+use crate::helpers::{spawn_app, TestApp, TestUser};
+
+struct RecordedEvent {
+    user_id: Uuid,
+    ip_address: Option<String>,
+    action: String,
+    payload: serde_json::Value,
+}
+
+async fn recorded_events(app: &TestApp) -> Vec<RecordedEvent> {
+    sqlx::query_as!(
+        RecordedEvent,
+        "SELECT user_id, ip_address, action, payload FROM audit_events ORDER BY id"
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap()
+}
+
+fn actions(events: &[RecordedEvent]) -> Vec<&str> {
+    events.iter().map(|e| e.action.as_str()).collect()
+}
+
+#[tokio::test]
+async fn logins_password_changes_and_logouts_are_recorded() {
+    let app = spawn_app().await;
+    app.login().await;
+    let new_password = Uuid::new_v4().to_string();
+    app.post_change_password(&serde_json::json!({
+        "current_password": &app.test_user.password,
+        "new_password": &new_password,
+        "new_password_validate": &new_password,
+    }))
+    .await;
+    app.post_logout().await;
+
+    let events = recorded_events(&app).await;
+
+    assert_eq!(actions(&events), ["login", "password_changed", "logout"]);
+    for event in &events {
+        assert_eq!(event.user_id, app.test_user.user_id);
+        assert_eq!(event.ip_address.as_deref(), Some("127.0.0.1"));
+    }
+    assert_eq!(events[0].payload["two_factor"], false);
+}
+
+#[tokio::test]
+async fn publishing_a_newsletter_is_recorded() {
+    let app = spawn_app().await;
+
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": "<p>Newsletter body as HTML</p>",
+            }
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let events = recorded_events(&app).await;
+    assert_eq!(actions(&events), ["newsletter_published"]);
+    assert_eq!(events[0].payload["title"], "Newsletter title");
+    assert_eq!(events[0].payload["recipients"], 0);
+}
+
+#[tokio::test]
+async fn deleting_a_subscriber_is_recorded_without_their_details() {
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    app.post_subscriptions(
+        "name=le%20guin&email=ursula_le_guin%40gmail.com".into(),
+    )
+    .await;
+    let subscriber_id = sqlx::query!("SELECT id FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .id;
+    app.login().await;
+
+    app.post(&format!("/admin/subscribers/{}/delete", subscriber_id))
+        .await;
+
+    let events = recorded_events(&app).await;
+    assert_eq!(actions(&events), ["login", "subscriber_deleted"]);
+    assert_eq!(
+        events[1].payload,
+        serde_json::json!({ "subscriber_id": subscriber_id })
+    );
+}
+
+#[tokio::test]
+async fn user_management_is_recorded() {
+    let app = spawn_app().await;
+    let user = TestUser::with_role(zero2prod::authentication::Role::Viewer);
+    user.store(&app.db_pool).await;
+    app.login().await;
+
+    app.post_form(
+        &format!("/admin/users/{}/role", user.user_id),
+        &[("role", "editor")],
+    )
+    .await;
+    app.post(&format!("/admin/users/{}/deactivate", user.user_id))
+        .await;
+
+    let events = recorded_events(&app).await;
+    assert_eq!(
+        actions(&events),
+        ["login", "user_role_changed", "user_deactivated"]
+    );
+    assert_eq!(
+        events[1].payload,
+        serde_json::json!({ "user_id": user.user_id, "role": "editor" })
+    );
+    assert_eq!(
+        events[2].payload,
+        serde_json::json!({ "user_id": user.user_id })
+    );
+}
+
+#[tokio::test]
+async fn the_audit_log_can_be_filtered_and_exported() {
+    let app = spawn_app().await;
+    app.login().await;
+    app.post_logout().await;
+    app.login().await;
+
+    let html_page = app.get("/admin/audit").await.text().await.unwrap();
+    assert!(html_page.contains("<p>3 events</p>"));
+    assert!(html_page.contains(&format!(
+        "<td>{}</td><td>127.0.0.1</td><td>logout</td>",
+        app.test_user.username
+    )));
+
+    let html_page = app
+        .get("/admin/audit?action=logout")
+        .await
+        .text()
+        .await
+        .unwrap();
+    assert!(html_page.contains("<p>1 events</p>"));
+    let html_page = app
+        .get("/admin/audit?user=someone")
+        .await
+        .text()
+        .await
+        .unwrap();
+    assert!(html_page.contains("<p>0 events</p>"));
+    let response = app.get(&format!("/admin/audit?page={}", i64::MAX)).await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let response = app.get("/admin/audit/export.csv?action=login").await;
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response.headers()["content-type"]
+        .to_str()
+        .unwrap()
+        .starts_with("text/csv"));
+    let csv = response.text().await.unwrap();
+    let lines: Vec<_> = csv.lines().collect();
+    assert_eq!(
+        lines[0],
+        "occurred_at,user_id,username,ip_address,action,payload"
+    );
+    assert_eq!(lines.len(), 3);
+    assert!(lines[1].contains(&format!(
+        "{},{},127.0.0.1,login,",
+        app.test_user.user_id, app.test_user.username
+    )));
+}
+
+#[tokio::test]
+async fn large_audit_logs_are_exported_in_full() {
+    let app = spawn_app().await;
+    app.login().await;
+    sqlx::query!(
+        r#"
+        INSERT INTO audit_events (user_id, occurred_at, action, payload)
+        SELECT $1, now() - i * interval '1 second', 'login', '{}'
+        FROM generate_series(1, 1234) AS i
+        "#,
+        app.test_user.user_id,
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    let response = app.get("/admin/audit/export.csv").await;
+    assert_eq!(response.status().as_u16(), 200);
+    let csv = response.text().await.unwrap();
+    // The header, the seeded events and the login above.
+    assert_eq!(csv.lines().count(), 1 + 1234 + 1);
+}
+
+#[tokio::test]
+async fn invalid_filters_are_rejected() {
+    let app = spawn_app().await;
+    app.login().await;
+
+    for query in ["action=drop_tables", "from=yesterday"] {
+        let response = app.get(&format!("/admin/audit?{}", query)).await;
+        assert_eq!(response.status().as_u16(), 400, "{}", query);
+    }
+}
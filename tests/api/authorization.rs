@@ -0,0 +1,249 @@
+use uuid::Uuid;
+use zero2prod::authentication::Role;
+
+use crate::helpers::{csrf_token, spawn_app, TestApp, TestUser};
+use crate::login::assert_is_redirect_to;
+
+/// A user with `role`, logged in with their own cookies.
+async fn logged_in_as(app: &TestApp, role: Role) -> reqwest::Client {
+    let user = TestUser::with_role(role);
+    user.store(&app.db_pool).await;
+    let client = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .cookie_store(true)
+        .build()
+        .unwrap();
+    let response = client
+        .post(format!("{}/login", app.address))
+        .form(&[("username", &user.username), ("password", &user.password)])
+        .send()
+        .await
+        .unwrap();
+    assert_is_redirect_to(&response, "/admin/dashboard");
+    client
+}
+
+#[tokio::test]
+async fn admin_routes_enforce_the_permission_matrix() {
+    let app = spawn_app().await;
+    let id = Uuid::new_v4();
+    // Which of admin, editor and viewer may use each route. Requests carry
+    // no body, unknown ids and so on: allowed ones fail in other ways, but
+    // not with a 403.
+    let matrix = [
+        ("GET", "/admin/dashboard".to_string(), [true, true, true]),
+        ("GET", "/admin/password".into(), [true, true, true]),
+        ("GET", "/admin/two_factor".into(), [true, true, true]),
+        ("GET", "/admin/sessions".into(), [true, true, true]),
+        (
+            "POST",
+            "/admin/sessions/revoke_others".into(),
+            [true, true, true],
+        ),
+        (
+            "POST",
+            format!("/admin/sessions/{id}/revoke"),
+            [true, true, true],
+        ),
+        ("GET", "/admin/newsletter".into(), [true, true, false]),
+        ("GET", "/admin/api_tokens".into(), [true, true, false]),
+        ("POST", "/admin/api_tokens".into(), [true, true, false]),
+        (
+            "POST",
+            format!("/admin/api_tokens/{id}/revoke"),
+            [true, true, false],
+        ),
+        ("GET", "/admin/subscribers".into(), [true, true, true]),
+        (
+            "GET",
+            "/admin/subscribers/export".into(),
+            [true, true, true],
+        ),
+        (
+            "GET",
+            format!("/admin/subscribers/{id}"),
+            [true, true, true],
+        ),
+        (
+            "GET",
+            format!("/admin/subscribers/{id}/consent"),
+            [true, true, true],
+        ),
+        ("GET", "/admin/referrals".into(), [true, true, true]),
+        (
+            "GET",
+            "/admin/subscribers/import".into(),
+            [true, false, false],
+        ),
+        (
+            "POST",
+            "/admin/subscribers/import".into(),
+            [true, false, false],
+        ),
+        (
+            "POST",
+            format!("/admin/subscribers/{id}/confirm"),
+            [true, false, false],
+        ),
+        (
+            "POST",
+            format!("/admin/subscribers/{id}/unsubscribe"),
+            [true, false, false],
+        ),
+        (
+            "POST",
+            format!("/admin/subscribers/{id}/delete"),
+            [true, false, false],
+        ),
+        ("GET", "/admin/users".into(), [true, false, false]),
+        ("GET", "/admin/audit".into(), [true, false, false]),
+        (
+            "GET",
+            "/admin/audit/export.csv".into(),
+            [true, false, false],
+        ),
+        (
+            "POST",
+            "/admin/users/invitations".into(),
+            [true, false, false],
+        ),
+        (
+            "POST",
+            format!("/admin/users/{id}/deactivate"),
+            [true, false, false],
+        ),
+        (
+            "POST",
+            format!("/admin/users/{id}/role"),
+            [true, false, false],
+        ),
+        (
+            "POST",
+            "/admin/users/two_factor_requirement".into(),
+            [true, false, false],
+        ),
+    ];
+
+    for (role_index, role) in Role::ALL.into_iter().enumerate() {
+        let client = logged_in_as(&app, role).await;
+        let csrf_token = csrf_token(&client, &app.address).await;
+        for (method, path, allowed) in &matrix {
+            let url = format!("{}{}", app.address, path);
+            let response = match *method {
+                "GET" => client.get(url),
+                _ => client.post(url).header("X-CSRF-Token", &csrf_token),
+            }
+            .send()
+            .await
+            .unwrap();
+
+            let is_forbidden = response.status().as_u16() == 403;
+            assert_eq!(
+                is_forbidden,
+                !allowed[role_index],
+                "{} {} as {} returned {}",
+                method,
+                path,
+                role,
+                response.status()
+            );
+        }
+    }
+}
+
+#[tokio::test]
+async fn forbidden_requests_get_a_403_page() {
+    let app = spawn_app().await;
+    let client = logged_in_as(&app, Role::Viewer).await;
+
+    let response = client
+        .get(format!("{}/admin/users", app.address))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status().as_u16(), 403);
+    let html_page = response.text().await.unwrap();
+    assert!(html_page.contains("You do not have permission to do this."));
+}
+
+#[tokio::test]
+async fn the_dashboard_only_links_to_what_the_user_may_do() {
+    let app = spawn_app().await;
+    let client = logged_in_as(&app, Role::Viewer).await;
+
+    let html_page = client
+        .get(format!("{}/admin/dashboard", app.address))
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+
+    assert!(html_page.contains(r#"<a href="/admin/subscribers">"#));
+    assert!(!html_page.contains(r#"<a href="/admin/newsletter">"#));
+    assert!(!html_page.contains(r#"<a href="/admin/users">"#));
+}
+
+#[tokio::test]
+async fn only_editors_and_admins_can_publish_through_the_api() {
+    let app = spawn_app().await;
+    let newsletter = serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    });
+
+    for (role, status) in [(Role::Editor, 200), (Role::Viewer, 403)] {
+        let user = TestUser::with_role(role);
+        user.store(&app.db_pool).await;
+
+        let response = reqwest::Client::new()
+            .post(format!("{}/newsletter", app.address))
+            .basic_auth(&user.username, Some(&user.password))
+            .json(&newsletter)
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(response.status().as_u16(), status, "{}", role);
+    }
+}
+
+#[tokio::test]
+async fn admins_can_change_the_role_of_other_users() {
+    let app = spawn_app().await;
+    app.post_login(&serde_json::json!({
+        "username": &app.test_user.username,
+        "password": &app.test_user.password,
+    }))
+    .await;
+    let editor = TestUser::with_role(Role::Editor);
+    editor.store(&app.db_pool).await;
+
+    let response = app
+        .post_form(
+            &format!("/admin/users/{}/role", editor.user_id),
+            &[("role", "viewer")],
+        )
+        .await;
+    assert_is_redirect_to(&response, "/admin/users");
+    let html_page = app.get("/admin/users").await.text().await.unwrap();
+    assert!(html_page.contains(&format!(
+        "<p><i>{} is now viewer.</i></p>",
+        editor.username
+    )));
+
+    let response = app
+        .post_form(
+            &format!("/admin/users/{}/role", app.test_user.user_id),
+            &[("role", "viewer")],
+        )
+        .await;
+    assert_is_redirect_to(&response, "/admin/users");
+    let html_page = app.get("/admin/users").await.text().await.unwrap();
+    assert!(html_page.contains("You cannot change your own role."));
+}
@@ -1,7 +1,7 @@
 use uuid::Uuid;
-use zero2prod::routes::{MAX_PASSWORD_LENGTH, MIN_PASSWORD_LENGTH};
+use zero2prod::authentication::{MAX_PASSWORD_LENGTH, MIN_PASSWORD_LENGTH};
 
-use crate::helpers::spawn_app;
+use crate::helpers::{spawn_app, spawn_app_with};
 use crate::login::assert_is_redirect_to;
 
 #[tokio::test]
@@ -90,6 +90,47 @@ async fn current_password_must_be_valid() {
     assert!(html_page.contains("<p><i>Current password is incorrect.</i></p>"));
 }
 
+#[tokio::test]
+async fn guessing_the_current_password_locks_the_account() {
+    let app = spawn_app_with(|c| {
+        c.login_throttling.delay_after_failures = 100;
+        c.login_throttling.lockout_after_failures = 3;
+    })
+    .await;
+    let new_password = Uuid::new_v4().to_string();
+    app.login().await;
+
+    for _ in 0..2 {
+        let resp = app
+            .post_change_password(&serde_json::json!({
+                "current_password": Uuid::new_v4().to_string(),
+                "new_password": &new_password,
+                "new_password_validate": &new_password,
+            }))
+            .await;
+        assert_is_redirect_to(&resp, "/admin/password");
+    }
+    let resp = app
+        .post_change_password(&serde_json::json!({
+            "current_password": Uuid::new_v4().to_string(),
+            "new_password": &new_password,
+            "new_password_validate": &new_password,
+        }))
+        .await;
+
+    // The session is logged out, and the password is refused at login too.
+    assert_is_redirect_to(&resp, "/login");
+    let resp = app.get_admin_dashboard().await;
+    assert_is_redirect_to(&resp, "/login");
+    let resp = app
+        .post_login(&serde_json::json!({
+            "username": &app.test_user.username,
+            "password": &app.test_user.password,
+        }))
+        .await;
+    assert_is_redirect_to(&resp, "/login");
+}
+
 #[tokio::test]
 async fn validate_new_password_is_in_correct_length_range() {
     let app = spawn_app().await;
@@ -123,6 +164,47 @@ async fn validate_new_password_is_in_correct_length_range() {
     )))
 }
 
+#[tokio::test]
+async fn weak_and_breached_passwords_are_rejected() {
+    let app = spawn_app().await;
+    let login_body = serde_json::json!({
+        "username": &app.test_user.username,
+        "password": &app.test_user.password,
+    });
+    app.post_login(&login_body).await;
+
+    for (new_password, error_message) in [
+        (
+            "Password1234",
+            "This password is too easy to guess. Avoid common words and \
+            passwords such as \"password\" or \"welcome\". Avoid sequences \
+            such as \"abc\" or \"123\". Add a few more uncommon words or \
+            characters.",
+        ),
+        (
+            "correct horse battery staple",
+            "This password has appeared in a data breach, please choose \
+            another one.",
+        ),
+    ] {
+        let resp = app
+            .post_change_password(&serde_json::json!({
+                "current_password": &app.test_user.password,
+                "new_password": new_password,
+                "new_password_validate": new_password,
+            }))
+            .await;
+        assert_is_redirect_to(&resp, "/admin/password");
+
+        let html_page = app.post_change_password_html().await;
+        assert!(
+            html_page.contains(&format!("<p><i>{}</i></p>", error_message)),
+            "{}",
+            new_password
+        );
+    }
+}
+
 #[tokio::test]
 async fn changing_password_works() {
     let app = spawn_app().await;
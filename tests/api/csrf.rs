@@ -0,0 +1,198 @@
+use crate::helpers::{extract_csrf_token, spawn_app, TestApp};
+use crate::login::assert_is_redirect_to;
+
+/// Post `form` to `path` as it is, i.e. without adding the CSRF token.
+async fn post_raw(
+    app: &TestApp,
+    path: &str,
+    form: &[(&str, &str)],
+) -> reqwest::Response {
+    app.api_client
+        .post(format!("{}{}", app.address, path))
+        .form(form)
+        .send()
+        .await
+        .unwrap()
+}
+
+fn change_password_form<'a>(
+    app: &'a TestApp,
+    new_password: &'a str,
+) -> Vec<(&'a str, &'a str)> {
+    vec![
+        ("current_password", &app.test_user.password),
+        ("new_password", new_password),
+        ("new_password_validate", new_password),
+    ]
+}
+
+async fn assert_is_csrf_rejection(response: reqwest::Response) {
+    assert_eq!(response.status().as_u16(), 403);
+    let html_page = response.text().await.unwrap();
+    assert!(html_page
+        .contains("This form has expired or was not sent from this site."));
+}
+
+#[tokio::test]
+async fn admin_forms_embed_the_csrf_token_of_the_session() {
+    let app = spawn_app().await;
+    app.login().await;
+    let csrf_token = app.csrf_token().await;
+    assert!(!csrf_token.is_empty());
+
+    for path in [
+        "/admin/dashboard",
+        "/admin/password",
+        "/admin/newsletter",
+        "/admin/api_tokens",
+        "/admin/sessions",
+        "/admin/two_factor",
+        "/admin/users",
+        "/admin/subscribers/import",
+    ] {
+        let html_page = app.get(path).await.text().await.unwrap();
+        assert_eq!(
+            extract_csrf_token(&html_page).as_deref(),
+            Some(csrf_token.as_str()),
+            "{}",
+            path
+        );
+    }
+}
+
+#[tokio::test]
+async fn admin_forms_without_a_csrf_token_are_rejected() {
+    let app = spawn_app().await;
+    app.login().await;
+    let new_password = uuid::Uuid::new_v4().to_string();
+
+    let response = post_raw(
+        &app,
+        "/admin/password",
+        &change_password_form(&app, &new_password),
+    )
+    .await;
+    assert_is_csrf_rejection(response).await;
+
+    let response = post_raw(
+        &app,
+        "/admin/newsletter",
+        &[
+            ("title", "Newsletter title"),
+            ("text_content", "Newsletter body as plain text"),
+            ("html_content", "<p>Newsletter body as HTML</p>"),
+        ],
+    )
+    .await;
+    assert_is_csrf_rejection(response).await;
+
+    let response = post_raw(&app, "/admin/logout", &[]).await;
+    assert_is_csrf_rejection(response).await;
+
+    // Nothing happened: the user is still logged in with their password.
+    assert_eq!(app.get("/admin/dashboard").await.status().as_u16(), 200);
+    let changed = sqlx::query!(
+        "SELECT action FROM audit_events WHERE action IN \
+        ('password_changed', 'newsletter_published', 'logout')"
+    )
+    .fetch_optional(&app.db_pool)
+    .await
+    .unwrap();
+    assert!(changed.is_none());
+}
+
+#[tokio::test]
+async fn invalid_csrf_tokens_are_rejected() {
+    let app = spawn_app().await;
+    app.login().await;
+    let csrf_token = app.csrf_token().await;
+    let forged = "x".repeat(csrf_token.len());
+
+    let mut form = change_password_form(&app, "a-long-and-unusual-passphrase");
+    form.push(("csrf_token", &forged));
+    let response = post_raw(&app, "/admin/password", &form).await;
+    assert_is_csrf_rejection(response).await;
+
+    let response = app
+        .api_client
+        .post(format!("{}/admin/logout", app.address))
+        .header("X-CSRF-Token", &forged)
+        .send()
+        .await
+        .unwrap();
+    assert_is_csrf_rejection(response).await;
+}
+
+#[tokio::test]
+async fn the_csrf_token_can_be_sent_in_the_form() {
+    let app = spawn_app().await;
+    app.login().await;
+    let csrf_token = app.csrf_token().await;
+    let new_password = uuid::Uuid::new_v4().to_string();
+
+    let mut form = change_password_form(&app, &new_password);
+    form.push(("csrf_token", &csrf_token));
+    let response = post_raw(&app, "/admin/password", &form).await;
+
+    assert_is_redirect_to(&response, "/admin/password");
+    let html_page = app.get("/admin/password").await.text().await.unwrap();
+    assert!(html_page.contains("Your password has been changed."));
+}
+
+#[tokio::test]
+async fn the_csrf_token_can_be_sent_in_a_multipart_form() {
+    let app = spawn_app().await;
+    app.login().await;
+    let csrf_token = app.csrf_token().await;
+    let upload = |csrf_token: Option<&str>| {
+        let file =
+            reqwest::multipart::Part::text("email\nursula@example.com\n")
+                .file_name("subscribers.csv")
+                .mime_str("text/csv")
+                .unwrap();
+        let mut form = reqwest::multipart::Form::new()
+            .text("mode", "confirmed")
+            .text("consent_note", "Opted in on Substack")
+            .part("file", file);
+        if let Some(csrf_token) = csrf_token {
+            form = form.text("csrf_token", csrf_token.to_string());
+        }
+        app.api_client
+            .post(format!("{}/admin/subscribers/import", app.address))
+            .multipart(form)
+            .send()
+    };
+
+    let response = upload(None).await.unwrap();
+    assert_is_csrf_rejection(response).await;
+
+    let response = upload(Some(&csrf_token)).await.unwrap();
+    assert_eq!(response.status().as_u16(), 303);
+    let imported = sqlx::query!(
+        "SELECT email FROM subscriptions WHERE email = 'ursula@example.com'"
+    )
+    .fetch_optional(&app.db_pool)
+    .await
+    .unwrap();
+    assert!(imported.is_some());
+}
+
+#[tokio::test]
+async fn csrf_tokens_do_not_outlive_the_session() {
+    let app = spawn_app().await;
+    app.login().await;
+    let old_csrf_token = app.csrf_token().await;
+    let response = app.post_logout().await;
+    assert_is_redirect_to(&response, "/login");
+    app.login().await;
+
+    assert_ne!(app.csrf_token().await, old_csrf_token);
+    let response = app
+        .api_client
+        .post(format!("{}/admin/logout", app.address))
+        .header("X-CSRF-Token", &old_csrf_token)
+        .send()
+        .await
+        .unwrap();
+    assert_is_csrf_rejection(response).await;
+}
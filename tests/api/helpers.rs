@@ -1,13 +1,24 @@
 use argon2::password_hash::SaltString;
 use argon2::{Algorithm, Argon2, Params, PasswordHasher, Version};
+use chrono::Utc;
 use once_cell::sync::Lazy;
+use secrecy::Secret;
 use sqlx::{Connection, Executor, PgConnection, PgPool};
 use uuid::Uuid;
 use wiremock::MockServer;
-use zero2prod::configuration::{get_configuration, DatabaseSettings};
+use zero2prod::authentication::Role;
+use zero2prod::configuration::{get_configuration, DatabaseSettings, Settings};
+use zero2prod::confirmation_email_worker::{
+    try_execute_task, ExecutionOutcome,
+};
+use zero2prod::domain::{AttributeDefinition, AttributeType};
+use zero2prod::email_client::EmailClient;
+use zero2prod::form_token::FormToken;
 use zero2prod::startup::{get_connection_pool, Application};
 use zero2prod::telemetry::{get_subscriber, init_subscriber};
 
+use crate::login::assert_is_redirect_to;
+
 pub struct TestApp {
     pub address: String,
     pub port: u16,
@@ -15,12 +26,19 @@ pub struct TestApp {
     pub email_server: MockServer,
     pub test_user: TestUser,
     pub api_client: reqwest::Client,
+    pub hmac_secret: Secret<String>,
+    pub email_client: EmailClient,
+    pub base_url: String,
+    /// The Redis instance and namespace the application uses.
+    pub redis_uri: Secret<String>,
+    pub redis_key_prefix: String,
 }
 
 pub struct TestUser {
     pub user_id: Uuid,
     pub username: String,
     pub password: String,
+    pub role: Role,
 }
 
 /// Confirmation links embedded in the request to the email API.
@@ -30,9 +48,51 @@ pub struct ConfirmationLinks {
 }
 
 impl TestApp {
+    /// Submit the sign-up form as a human would, i.e. with a valid form
+    /// token issued a while ago.
     pub async fn post_subscriptions(&self, body: String) -> reqwest::Response {
+        let issued_at = Utc::now().timestamp() - 60;
+        self.post_subscriptions_with_token(body, issued_at).await
+    }
+
+    pub async fn post_subscriptions_with_token(
+        &self,
+        body: String,
+        issued_at: i64,
+    ) -> reqwest::Response {
+        let token = FormToken::issue(&self.hmac_secret, issued_at);
+        let body = format!(
+            "{}&form_issued_at={}&form_signature={}",
+            body, token.issued_at, token.signature
+        );
+        self.post_subscriptions_raw(body).await
+    }
+
+    /// Submit a sign-up as JSON, as the widget does, with a valid form
+    /// token issued a while ago.
+    pub async fn post_subscriptions_json(
+        &self,
+        mut body: serde_json::Value,
+    ) -> reqwest::Response {
+        let token =
+            FormToken::issue(&self.hmac_secret, Utc::now().timestamp() - 60);
+        body["form_issued_at"] = token.issued_at.into();
+        body["form_signature"] = token.signature.into();
         self.api_client
-            .post(&format!("{}/subscriptions", &self.address))
+            .post(format!("{}/subscriptions", &self.address))
+            .json(&body)
+            .send()
+            .await
+            .expect(ERR_API_REQUEST_FAILED)
+    }
+
+    /// Submit a sign-up without adding a form token.
+    pub async fn post_subscriptions_raw(
+        &self,
+        body: String,
+    ) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/subscriptions", &self.address))
             .header("Content-Type", "application/x-www-form-urlencoded")
             .body(body)
             .send()
@@ -40,6 +100,17 @@ impl TestApp {
             .expect(ERR_API_REQUEST_FAILED)
     }
 
+    pub async fn get_subscriptions_form_html(&self) -> String {
+        self.api_client
+            .get(format!("{}/subscriptions", &self.address))
+            .send()
+            .await
+            .expect(ERR_API_REQUEST_FAILED)
+            .text()
+            .await
+            .unwrap()
+    }
+
     /// Extract the confirmation links embedded in the request to the email API.
     pub fn get_confirmation_links(
         &self,
@@ -73,7 +144,7 @@ impl TestApp {
         body: serde_json::Value,
     ) -> reqwest::Response {
         self.api_client
-            .post(&format!("{}/newsletter", &self.address))
+            .post(format!("{}/newsletter", &self.address))
             .basic_auth(
                 &self.test_user.username,
                 Some(&self.test_user.password),
@@ -89,18 +160,29 @@ impl TestApp {
         Body: serde::Serialize,
     {
         self.api_client
-            .post(&format!("{}/login", &self.address))
+            .post(format!("{}/login", &self.address))
             .form(body)
             .send()
             .await
             .expect(ERR_API_REQUEST_FAILED)
     }
 
+    /// Log in as the test user.
+    pub async fn login(&self) {
+        let response = self
+            .post_login(&serde_json::json!({
+                "username": &self.test_user.username,
+                "password": &self.test_user.password,
+            }))
+            .await;
+        assert_is_redirect_to(&response, "/admin/dashboard");
+    }
+
     // tests only look at html page currently, therefore do not need to expose
     // underlying reqwest::Response object.
     pub async fn get_login_html(&self) -> String {
         self.api_client
-            .get(&format!("{}/login", &self.address))
+            .get(format!("{}/login", &self.address))
             .send()
             .await
             .expect(ERR_API_REQUEST_FAILED)
@@ -111,7 +193,7 @@ impl TestApp {
 
     pub async fn get_admin_dashboard(&self) -> reqwest::Response {
         self.api_client
-            .get(&format!("{}/admin/dashboard", &self.address))
+            .get(format!("{}/admin/dashboard", &self.address))
             .send()
             .await
             .expect(ERR_API_REQUEST_FAILED)
@@ -123,7 +205,7 @@ impl TestApp {
 
     pub async fn get_change_password(&self) -> reqwest::Response {
         self.api_client
-            .get(&format!("{}/admin/password", &self.address))
+            .get(format!("{}/admin/password", &self.address))
             .send()
             .await
             .expect(ERR_API_REQUEST_FAILED)
@@ -137,7 +219,8 @@ impl TestApp {
         Body: serde::Serialize,
     {
         self.api_client
-            .post(&format!("{}/admin/password", &self.address))
+            .post(format!("{}/admin/password", &self.address))
+            .header("X-CSRF-Token", self.csrf_token().await)
             .form(body)
             .send()
             .await
@@ -148,27 +231,180 @@ impl TestApp {
         self.get_change_password().await.text().await.unwrap()
     }
 
+    pub async fn get_subscriber_consent(
+        &self,
+        subscriber_id: Uuid,
+    ) -> reqwest::Response {
+        self.api_client
+            .get(format!(
+                "{}/admin/subscribers/{}/consent",
+                &self.address, subscriber_id
+            ))
+            .send()
+            .await
+            .expect(ERR_API_REQUEST_FAILED)
+    }
+
+    pub async fn get_subscriber_consent_export(
+        &self,
+        subscriber_id: Uuid,
+    ) -> reqwest::Response {
+        self.api_client
+            .get(format!(
+                "{}/admin/subscribers/{}/consent.json",
+                &self.address, subscriber_id
+            ))
+            .send()
+            .await
+            .expect(ERR_API_REQUEST_FAILED)
+    }
+
+    pub async fn get_import_subscribers_html(&self) -> String {
+        self.api_client
+            .get(format!("{}/admin/subscribers/import", &self.address))
+            .send()
+            .await
+            .expect(ERR_API_REQUEST_FAILED)
+            .text()
+            .await
+            .unwrap()
+    }
+
+    /// Upload `csv` through the import form, `consent_note` is only sent
+    /// when set.
+    pub async fn post_import_subscribers(
+        &self,
+        csv: &str,
+        mode: &str,
+        consent_note: Option<&str>,
+    ) -> reqwest::Response {
+        let file = reqwest::multipart::Part::text(csv.to_string())
+            .file_name("subscribers.csv")
+            .mime_str("text/csv")
+            .unwrap();
+        let mut form = reqwest::multipart::Form::new()
+            .part("file", file)
+            .text("mode", mode.to_string());
+        if let Some(consent_note) = consent_note {
+            form = form.text("consent_note", consent_note.to_string());
+        }
+        self.api_client
+            .post(format!("{}/admin/subscribers/import", &self.address))
+            .header("X-CSRF-Token", self.csrf_token().await)
+            .multipart(form)
+            .send()
+            .await
+            .expect(ERR_API_REQUEST_FAILED)
+    }
+
+    /// Send the queued confirmation emails that are due, as the worker
+    /// would.
+    pub async fn dispatch_all_pending_emails(&self) {
+        loop {
+            if let ExecutionOutcome::EmptyQueue = try_execute_task(
+                &self.db_pool,
+                &self.email_client,
+                &self.base_url,
+            )
+            .await
+            .unwrap()
+            {
+                break;
+            }
+        }
+    }
+
+    /// Fetch `path`, typically the location of a redirect, on the app.
+    pub async fn get(&self, path: &str) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}{}", &self.address, path))
+            .send()
+            .await
+            .expect(ERR_API_REQUEST_FAILED)
+    }
+
+    /// Post an empty form to `path`, as the admin action buttons do.
+    pub async fn post(&self, path: &str) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}{}", &self.address, path))
+            .header("X-CSRF-Token", self.csrf_token().await)
+            .send()
+            .await
+            .expect(ERR_API_REQUEST_FAILED)
+    }
+
+    /// Post `form` to `path`, url-encoded.
+    pub async fn post_form<Body>(
+        &self,
+        path: &str,
+        form: &Body,
+    ) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(format!("{}{}", &self.address, path))
+            .header("X-CSRF-Token", self.csrf_token().await)
+            .form(form)
+            .send()
+            .await
+            .expect(ERR_API_REQUEST_FAILED)
+    }
+
     pub async fn post_logout(&self) -> reqwest::Response {
         self.api_client
-            .post(&format!("{}/admin/logout", &self.address))
+            .post(format!("{}/admin/logout", &self.address))
+            .header("X-CSRF-Token", self.csrf_token().await)
             .send()
             .await
             .expect(ERR_API_REQUEST_FAILED)
     }
+
+    /// The CSRF token of the session, empty if not logged in.
+    pub async fn csrf_token(&self) -> String {
+        csrf_token(&self.api_client, &self.address).await
+    }
 }
 
-const ERR_API_REQUEST_FAILED: &'static str = "Failed to execute request.";
+/// The CSRF token embedded in the forms shown to `client`, empty if it is
+/// not logged in.
+pub async fn csrf_token(client: &reqwest::Client, address: &str) -> String {
+    let html_page = client
+        .get(format!("{}/admin/password", address))
+        .send()
+        .await
+        .expect(ERR_API_REQUEST_FAILED)
+        .text()
+        .await
+        .unwrap();
+    extract_csrf_token(&html_page).unwrap_or_default()
+}
+
+/// The value of the first `csrf_token` field of `html_page`.
+pub fn extract_csrf_token(html_page: &str) -> Option<String> {
+    let start = html_page.find(r#"name="csrf_token" value=""#)?
+        + r#"name="csrf_token" value=""#.len();
+    let end = start + html_page[start..].find('"')?;
+    Some(html_page[start..end].to_string())
+}
+
+const ERR_API_REQUEST_FAILED: &str = "Failed to execute request.";
 
 impl TestUser {
     pub fn generate() -> Self {
+        Self::with_role(Role::Admin)
+    }
+
+    pub fn with_role(role: Role) -> Self {
         Self {
             user_id: Uuid::new_v4(),
             username: Uuid::new_v4().to_string(),
             password: Uuid::new_v4().to_string(),
+            role,
         }
     }
 
-    async fn store(&self, pool: &PgPool) {
+    pub async fn store(&self, pool: &PgPool) {
         let salt = SaltString::generate(&mut rand::thread_rng());
         let password_hash = Argon2::new(
             Algorithm::Argon2id,
@@ -180,11 +416,12 @@ impl TestUser {
         .to_string();
 
         sqlx::query!(
-            r#"INSERT INTO users (user_id, username, password_hash)
-            VALUES ($1, $2, $3)"#,
+            r#"INSERT INTO users (user_id, username, password_hash, role)
+            VALUES ($1, $2, $3, $4)"#,
             self.user_id,
             self.username,
-            password_hash
+            password_hash,
+            self.role as Role,
         )
         .execute(pool)
         .await
@@ -192,7 +429,35 @@ impl TestUser {
     }
 }
 
+/// Collect an optional `company` (text), `team_size` (number) and
+/// `developer` (boolean) at sign-up.
+pub fn with_custom_attributes(c: &mut Settings) {
+    c.subscriptions.custom_attributes = vec![
+        AttributeDefinition {
+            name: "company".into(),
+            kind: AttributeType::Text,
+            required: false,
+        },
+        AttributeDefinition {
+            name: "team_size".into(),
+            kind: AttributeType::Number,
+            required: false,
+        },
+        AttributeDefinition {
+            name: "developer".into(),
+            kind: AttributeType::Boolean,
+            required: false,
+        },
+    ];
+}
+
 pub async fn spawn_app() -> TestApp {
+    spawn_app_with(|_| {}).await
+}
+
+/// Spawn the application after applying test-specific changes to the
+/// configuration.
+pub async fn spawn_app_with(configure: impl FnOnce(&mut Settings)) -> TestApp {
     Lazy::force(&TRACING);
 
     // Launch a mock server to stand in for Postmark's API
@@ -207,6 +472,9 @@ pub async fn spawn_app() -> TestApp {
         c.application.port = 0;
         // Use the mock server as email API
         c.email_client.base_url = email_server.uri();
+        // Use a different namespace in Redis for each test case
+        c.redis_key_prefix = Uuid::new_v4().to_string();
+        configure(&mut c);
         c
     };
 
@@ -218,6 +486,7 @@ pub async fn spawn_app() -> TestApp {
         .await
         .expect("Failed to build application.");
     let application_port = application.port();
+    #[allow(clippy::let_underscore_future)]
     let _ = tokio::spawn(application.run_until_stopped());
 
     let api_client = reqwest::Client::builder()
@@ -233,6 +502,11 @@ pub async fn spawn_app() -> TestApp {
         email_server,
         test_user: TestUser::generate(),
         api_client,
+        hmac_secret: configuration.application.hmac_secret.clone(),
+        email_client: configuration.email_client.client(),
+        base_url: configuration.application.base_url,
+        redis_uri: configuration.redis_uri,
+        redis_key_prefix: configuration.redis_key_prefix,
     };
 
     test_app.test_user.store(&test_app.db_pool).await;
@@ -1,4 +1,4 @@
-use crate::helpers::spawn_app;
+use crate::helpers::{spawn_app, spawn_app_with, TestApp};
 
 pub fn assert_is_redirect_to(response: &reqwest::Response, location: &str) {
     assert_eq!(response.status().as_u16(), 303);
@@ -44,3 +44,67 @@ async fn redirect_to_admin_dashboard_after_login_success() {
     let html_page = app.get_admin_dashboard_html().await;
     assert!(html_page.contains(&format!("Welcome {}", app.test_user.username)));
 }
+
+async fn stored_password_hash(app: &TestApp) -> String {
+    sqlx::query!(
+        "SELECT password_hash FROM users WHERE user_id = $1",
+        app.test_user.user_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .password_hash
+}
+
+#[tokio::test]
+async fn weaker_password_hashes_are_upgraded_on_login() {
+    // The test user is stored with m=15000,t=2,p=1.
+    let app = spawn_app_with(|c| c.password_hashing.memory_kib = 19456).await;
+    let login_body = serde_json::json!({
+        "username": &app.test_user.username,
+        "password": &app.test_user.password,
+    });
+
+    let resp = app.post_login(&login_body).await;
+    assert_is_redirect_to(&resp, "/admin/dashboard");
+
+    let password_hash = stored_password_hash(&app).await;
+    assert!(password_hash.starts_with("$argon2id$v=19$m=19456,t=2,p=1$"));
+
+    // The upgraded hash still matches the password.
+    app.post_logout().await;
+    let resp = app.post_login(&login_body).await;
+    assert_is_redirect_to(&resp, "/admin/dashboard");
+}
+
+#[tokio::test]
+async fn password_hashes_are_left_alone_on_failed_logins() {
+    let app = spawn_app_with(|c| c.password_hashing.memory_kib = 19456).await;
+    let before = stored_password_hash(&app).await;
+
+    let resp = app
+        .post_login(&serde_json::json!({
+            "username": &app.test_user.username,
+            "password": "wrong-password",
+        }))
+        .await;
+    assert_is_redirect_to(&resp, "/login");
+
+    assert_eq!(stored_password_hash(&app).await, before);
+}
+
+#[tokio::test]
+async fn current_password_hashes_are_not_rewritten() {
+    let app = spawn_app().await;
+    let before = stored_password_hash(&app).await;
+
+    let resp = app
+        .post_login(&serde_json::json!({
+            "username": &app.test_user.username,
+            "password": &app.test_user.password,
+        }))
+        .await;
+    assert_is_redirect_to(&resp, "/admin/dashboard");
+
+    assert_eq!(stored_password_hash(&app).await, before);
+}
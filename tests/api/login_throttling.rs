@@ -0,0 +1,224 @@
+use std::time::{Duration, Instant};
+
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+use zero2prod::configuration::Settings;
+
+use crate::helpers::{spawn_app_with, TestApp};
+use crate::login::assert_is_redirect_to;
+
+/// Lock accounts after three failures, without slowing the tests down.
+fn strict_throttling(c: &mut Settings) {
+    c.login_throttling.delay_after_failures = 100;
+    c.login_throttling.lockout_after_failures = 3;
+}
+
+async fn login_with(app: &TestApp, username: &str, password: &str) -> String {
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": username,
+            "password": password,
+        }))
+        .await;
+    if response.headers().get("Location").unwrap() == "/admin/dashboard" {
+        return "logged in".into();
+    }
+    assert_is_redirect_to(&response, "/login");
+    app.get_login_html().await
+}
+
+async fn wrong_password(app: &TestApp) -> String {
+    login_with(app, &app.test_user.username, "wrong-password").await
+}
+
+async fn right_password(app: &TestApp) -> String {
+    login_with(app, &app.test_user.username, &app.test_user.password).await
+}
+
+const LOCKED: &str = "Too many failed login attempts, please try again later.";
+
+#[tokio::test]
+async fn accounts_are_locked_after_repeated_failures() {
+    let app = spawn_app_with(strict_throttling).await;
+
+    for _ in 0..2 {
+        assert!(wrong_password(&app).await.contains("Authentication failed"));
+    }
+    assert!(wrong_password(&app).await.contains(LOCKED));
+
+    // Even the right password is refused until the lockout expires.
+    assert!(right_password(&app).await.contains(LOCKED));
+}
+
+#[tokio::test]
+async fn parallel_failures_cannot_exceed_the_lockout_threshold() {
+    let app = spawn_app_with(strict_throttling).await;
+    sqlx::query!(
+        "UPDATE users SET email = 'ursula_le_guin@gmail.com' WHERE user_id = $1",
+        app.test_user.user_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Basic auth tells a wrong password (401) from a refused attempt (429).
+    let statuses = futures_util::future::join_all((0..12).map(|_| {
+        app.api_client
+            .post(format!("{}/newsletter", &app.address))
+            .basic_auth(&app.test_user.username, Some("wrong-password"))
+            .json(&serde_json::json!({
+                "title": "Newsletter title",
+                "content": { "text": "Body", "html": "<p>Body</p>" },
+            }))
+            .send()
+    }))
+    .await
+    .into_iter()
+    .map(|response| response.unwrap().status().as_u16())
+    .collect::<Vec<_>>();
+
+    // Only the first three attempts got their password checked, and the
+    // last of them locked the account.
+    let wrong = statuses.iter().filter(|&&s| s == 401).count();
+    let refused = statuses.iter().filter(|&&s| s == 429).count();
+    assert!(wrong < 3, "{:?}", statuses);
+    assert_eq!(wrong + refused, 12, "{:?}", statuses);
+    assert!(right_password(&app).await.contains(LOCKED));
+}
+
+#[tokio::test]
+async fn the_owner_of_a_locked_account_is_notified() {
+    let app = spawn_app_with(strict_throttling).await;
+    sqlx::query!(
+        "UPDATE users SET email = 'ursula_le_guin@gmail.com' WHERE user_id = $1",
+        app.test_user.user_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    for _ in 0..3 {
+        wrong_password(&app).await;
+    }
+
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let body: serde_json::Value =
+        serde_json::from_slice(&email_request.body).unwrap();
+    assert_eq!(body["To"], "ursula_le_guin@gmail.com");
+    assert_eq!(body["Subject"], "Your account has been locked");
+}
+
+#[tokio::test]
+async fn a_successful_login_resets_the_failure_count() {
+    let app = spawn_app_with(strict_throttling).await;
+
+    for _ in 0..2 {
+        wrong_password(&app).await;
+    }
+    assert_eq!(right_password(&app).await, "logged in");
+    for _ in 0..2 {
+        assert!(wrong_password(&app).await.contains("Authentication failed"));
+    }
+
+    assert_eq!(right_password(&app).await, "logged in");
+}
+
+#[tokio::test]
+async fn failures_across_usernames_block_the_ip_address() {
+    let app = spawn_app_with(|c| {
+        c.login_throttling.delay_after_failures = 100;
+        c.login_throttling.max_failures_per_ip = 3;
+    })
+    .await;
+
+    for _ in 0..3 {
+        let username = uuid::Uuid::new_v4().to_string();
+        login_with(&app, &username, "wrong-password").await;
+    }
+
+    assert!(right_password(&app).await.contains(LOCKED));
+}
+
+#[tokio::test]
+async fn forged_forwarded_headers_do_not_reset_the_ip_address_count() {
+    let app = spawn_app_with(|c| {
+        c.login_throttling.delay_after_failures = 100;
+        c.login_throttling.max_failures_per_ip = 3;
+    })
+    .await;
+
+    for i in 0..3 {
+        let response = app
+            .api_client
+            .post(format!("{}/login", &app.address))
+            .header("X-Forwarded-For", format!("198.51.100.{}", i))
+            .form(&serde_json::json!({
+                "username": uuid::Uuid::new_v4().to_string(),
+                "password": "wrong-password",
+            }))
+            .send()
+            .await
+            .unwrap();
+        assert_is_redirect_to(&response, "/login");
+    }
+
+    assert!(right_password(&app).await.contains(LOCKED));
+}
+
+#[tokio::test]
+async fn repeated_failures_are_delayed() {
+    let app = spawn_app_with(|c| {
+        c.login_throttling.delay_after_failures = 1;
+        c.login_throttling.base_delay_milliseconds = 300;
+    })
+    .await;
+    wrong_password(&app).await;
+
+    let start = Instant::now();
+    wrong_password(&app).await;
+
+    assert!(start.elapsed() >= Duration::from_millis(300));
+}
+
+#[tokio::test]
+async fn basic_auth_is_throttled_like_the_login_form() {
+    let app = spawn_app_with(strict_throttling).await;
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    });
+    let publish_with = |password: &str| {
+        app.api_client
+            .post(format!("{}/newsletter", &app.address))
+            .basic_auth(&app.test_user.username, Some(password))
+            .json(&newsletter_request_body)
+            .send()
+    };
+
+    for _ in 0..2 {
+        let response = publish_with("wrong-password").await.unwrap();
+        assert_eq!(response.status().as_u16(), 401);
+    }
+    let response = publish_with("wrong-password").await.unwrap();
+    assert_eq!(response.status().as_u16(), 429);
+
+    let response = publish_with(&app.test_user.password).await.unwrap();
+    assert_eq!(response.status().as_u16(), 429);
+    // The login form shares the lockout.
+    assert!(right_password(&app).await.contains(LOCKED));
+}
@@ -1,8 +1,21 @@
 mod admin_dashboard;
+mod admin_subscribers;
+mod admin_users;
+mod api_tokens;
+mod audit;
+mod authorization;
 mod change_password;
+mod csrf;
 mod health_check;
 mod helpers;
 mod login;
+mod login_throttling;
 mod newsletter;
+mod password_reset;
+mod pending_cleanup;
+mod referrals;
+mod sessions;
 mod subscriptions;
 mod subscriptions_confirm;
+mod subscriptions_widget;
+mod two_factor;
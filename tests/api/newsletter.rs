@@ -2,7 +2,10 @@ use uuid::Uuid;
 use wiremock::matchers::{any, method, path};
 use wiremock::{Mock, ResponseTemplate};
 
-use crate::helpers::{spawn_app, ConfirmationLinks, TestApp};
+use crate::helpers::{
+    spawn_app, spawn_app_with, with_custom_attributes, ConfirmationLinks,
+    TestApp,
+};
 
 #[tokio::test]
 async fn newsletters_are_not_delivered_to_unconfirmed_subscribers() {
@@ -72,6 +75,27 @@ async fn newsletters_returns_400_for_invalid_data() {
             }),
             "missing content",
         ),
+        (
+            serde_json::json!({
+                "title": "Hi {{nmae}}",
+                "content": {
+                    "text": "newsletters plain text",
+                    "html": "<p>this ones html</p>",
+                }
+            }),
+            "unknown template variable",
+        ),
+        (
+            serde_json::json!({
+                "title": "what a title",
+                "content": {
+                    "text": "newsletters plain text",
+                    "html": "<p>this ones html</p>",
+                },
+                "audience": {"country": "FR"}
+            }),
+            "unknown audience attribute",
+        ),
     ];
 
     for (invalid_body, error_message) in test_cases {
@@ -87,8 +111,13 @@ async fn newsletters_returns_400_for_invalid_data() {
 }
 
 async fn create_unconfirmed_subscriber(app: &TestApp) -> ConfirmationLinks {
-    let body = "name=j&email=j%40mail.com";
+    create_unconfirmed_subscriber_with(app, "name=j&email=j%40mail.com").await
+}
 
+async fn create_unconfirmed_subscriber_with(
+    app: &TestApp,
+    body: &str,
+) -> ConfirmationLinks {
     let _mock_guard = Mock::given(path("/email"))
         .and(method("POST"))
         .respond_with(ResponseTemplate::new(200))
@@ -111,10 +140,14 @@ async fn create_unconfirmed_subscriber(app: &TestApp) -> ConfirmationLinks {
         .pop()
         .unwrap();
 
-    app.get_confirmation_links(&email_request)
+    app.get_confirmation_links(email_request)
 }
 async fn create_confirmed_subscriber(app: &TestApp) {
-    let confirmation_link = create_unconfirmed_subscriber(app).await;
+    create_confirmed_subscriber_with(app, "name=j&email=j%40mail.com").await
+}
+
+async fn create_confirmed_subscriber_with(app: &TestApp, body: &str) {
+    let confirmation_link = create_unconfirmed_subscriber_with(app, body).await;
     reqwest::get(confirmation_link.html)
         .await
         .unwrap()
@@ -127,7 +160,7 @@ async fn requests_missing_authorization_are_rejected() {
     let app = spawn_app().await;
 
     let response = reqwest::Client::new()
-        .post(&format!("{}/newsletter", &app.address))
+        .post(format!("{}/newsletter", &app.address))
         .json(&serde_json::json!({
             "title": "Newsletter title",
             "content": {
@@ -154,7 +187,7 @@ async fn non_existing_user_is_rejected() {
     let pass = Uuid::new_v4().to_string();
 
     let resp = reqwest::Client::new()
-        .post(&format!("{}/newsletter", &app.address))
+        .post(format!("{}/newsletter", &app.address))
         .basic_auth(user, Some(pass))
         .json(&serde_json::json!({
             "title":"newsletter title",
@@ -182,7 +215,7 @@ async fn invalid_password_is_rejected() {
     let pass = Uuid::new_v4().to_string();
 
     let resp = reqwest::Client::new()
-        .post(&format!("{}/newsletter", &app.address))
+        .post(format!("{}/newsletter", &app.address))
         .basic_auth(user, Some(pass))
         .json(&serde_json::json!({
             "title":"newsletter title",
@@ -201,3 +234,89 @@ async fn invalid_password_is_rejected() {
         resp.headers()["WWW-Authenticate"]
     )
 }
+
+#[tokio::test]
+async fn newsletter_template_variables_are_filled_in_per_subscriber() {
+    let app = spawn_app_with(with_custom_attributes).await;
+    create_confirmed_subscriber_with(
+        &app,
+        "name=Ged&email=ged%40roke.org&company=%3CRoke%3E",
+    )
+    .await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": "News for {{ name }}",
+            "content": {
+                "text": "Hi {{name}} from {{company}}{{team_size}}",
+                "html": "<p>Hi {{name}} from {{company}}</p>",
+            }
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let body: serde_json::Value =
+        serde_json::from_slice(&email_request.body).unwrap();
+    assert_eq!(body["Subject"], "News for Ged");
+    assert_eq!(body["TextBody"], "Hi Ged from <Roke>");
+    assert_eq!(body["HtmlBody"], "<p>Hi Ged from &lt;Roke&gt;</p>");
+}
+
+#[tokio::test]
+async fn newsletters_can_target_an_audience_by_attribute() {
+    let app = spawn_app_with(with_custom_attributes).await;
+    create_confirmed_subscriber_with(
+        &app,
+        "name=Ged&email=ged%40roke.org&developer=true",
+    )
+    .await;
+    create_confirmed_subscriber_with(
+        &app,
+        "name=Tenar&email=tenar%40atuan.org&developer=false",
+    )
+    .await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": "For developers",
+            "content": {
+                "text": "newsletter body as plain text",
+                "html": "<p>as html</p>",
+            },
+            "audience": {"developer": true}
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let body: serde_json::Value =
+        serde_json::from_slice(&email_request.body).unwrap();
+    assert_eq!(body["To"], "ged@roke.org");
+}
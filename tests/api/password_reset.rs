@@ -0,0 +1,319 @@
+use std::time::Duration;
+
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, Request, ResponseTemplate};
+
+use crate::helpers::{spawn_app, spawn_app_with, TestApp};
+use crate::login::assert_is_redirect_to;
+
+const EMAIL: &str = "ursula_le_guin@gmail.com";
+
+async fn set_test_user_email(app: &TestApp) {
+    sqlx::query!(
+        "UPDATE users SET email = $1 WHERE user_id = $2",
+        EMAIL,
+        app.test_user.user_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+}
+
+/// The requests received by the email API, once there are `count` of them:
+/// reset emails are sent in the background.
+async fn wait_for_emails(app: &TestApp, count: usize) -> Vec<Request> {
+    for _ in 0..50 {
+        let received = app.email_server.received_requests().await.unwrap();
+        if received.len() >= count {
+            return received;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    panic!("{} emails were not sent in time", count);
+}
+
+/// Ask for a reset link for the test user and return its token.
+async fn request_reset(app: &TestApp) -> String {
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+
+    let sent = app.email_server.received_requests().await.unwrap().len();
+
+    let response = app.post_form("/password_reset", &[("email", EMAIL)]).await;
+    assert_is_redirect_to(&response, "/login");
+
+    let email_request = wait_for_emails(app, sent + 1).await.pop().unwrap();
+    let links = app.get_confirmation_links(&email_request);
+    assert_eq!(links.html, links.plain_text);
+    assert_eq!(links.html.path(), "/password_reset/confirm");
+    links
+        .html
+        .query_pairs()
+        .find(|(name, _)| name == "token")
+        .unwrap()
+        .1
+        .into_owned()
+}
+
+async fn reset(
+    app: &TestApp,
+    token: &str,
+    password: &str,
+) -> reqwest::Response {
+    app.post_form(
+        "/password_reset/confirm",
+        &[
+            ("token", token),
+            ("new_password", password),
+            ("new_password_validate", password),
+        ],
+    )
+    .await
+}
+
+#[tokio::test]
+async fn the_login_page_links_to_the_password_reset_form() {
+    let app = spawn_app().await;
+
+    let html_page = app.get_login_html().await;
+    assert!(html_page.contains(r#"<a href="/password_reset">"#));
+    let response = app.get("/password_reset").await;
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn unknown_emails_get_the_same_answer_and_no_email() {
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app.post_form("/password_reset", &[("email", EMAIL)]).await;
+
+    assert_is_redirect_to(&response, "/login");
+    let html_page = app.get_login_html().await;
+    assert!(html_page.contains("we have sent it a link to reset the password."));
+}
+
+#[tokio::test]
+async fn invalid_emails_are_not_echoed_back() {
+    let app = spawn_app().await;
+
+    let response = app
+        .post_form("/password_reset", &[("email", "<script>alert(1)</script>")])
+        .await;
+
+    assert_is_redirect_to(&response, "/password_reset");
+    let html_page = app.get("/password_reset").await.text().await.unwrap();
+    assert!(
+        html_page.contains("<p><i>Please enter a valid email address.</i></p>")
+    );
+    assert!(!html_page.contains("<script>"));
+}
+
+#[tokio::test]
+async fn failing_to_send_the_email_gets_the_same_answer() {
+    let app = spawn_app().await;
+    set_test_user_email(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .mount(&app.email_server)
+        .await;
+
+    let response = app.post_form("/password_reset", &[("email", EMAIL)]).await;
+
+    assert_is_redirect_to(&response, "/login");
+    let html_page = app.get_login_html().await;
+    assert!(html_page.contains("we have sent it a link to reset the password."));
+    // Let the attempt finish before the mock server goes back to the pool.
+    wait_for_emails(&app, 1).await;
+}
+
+#[tokio::test]
+async fn reset_emails_are_rate_limited_per_address() {
+    let app = spawn_app_with(|c| {
+        c.password_reset.max_emails_per_address = 2;
+    })
+    .await;
+    set_test_user_email(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    for email in [EMAIL, EMAIL, "URSULA_LE_GUIN@gmail.com"] {
+        let response =
+            app.post_form("/password_reset", &[("email", email)]).await;
+        assert_is_redirect_to(&response, "/login");
+    }
+
+    wait_for_emails(&app, 2).await;
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    assert_eq!(app.email_server.received_requests().await.unwrap().len(), 2);
+}
+
+#[tokio::test]
+async fn reset_requests_are_rate_limited_per_ip_address() {
+    let app = spawn_app_with(|c| {
+        c.password_reset.max_requests_per_ip = 1;
+    })
+    .await;
+    set_test_user_email(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    for email in ["somebody@example.com", EMAIL] {
+        let response =
+            app.post_form("/password_reset", &[("email", email)]).await;
+        assert_is_redirect_to(&response, "/login");
+    }
+
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    assert!(app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .is_empty());
+}
+
+#[tokio::test]
+async fn a_reset_link_changes_the_password_once() {
+    let app = spawn_app().await;
+    set_test_user_email(&app).await;
+    let token = request_reset(&app).await;
+    // Only a hash of the token is stored.
+    let stored = sqlx::query!("SELECT token_hash FROM password_reset_tokens")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_ne!(stored.token_hash, token);
+
+    let response = app
+        .get(&format!("/password_reset/confirm?token={}", token))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+    let new_password = uuid::Uuid::new_v4().to_string();
+    let response = reset(&app, &token, &new_password).await;
+    assert_is_redirect_to(&response, "/login");
+    let html_page = app.get_login_html().await;
+    assert!(html_page.contains(
+        "<p><i>Your password has been reset, you can now log in.</i></p>"
+    ));
+
+    // The old password no longer works, the new one does.
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": &app.test_user.username,
+            "password": &app.test_user.password,
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/login");
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": &app.test_user.username,
+            "password": &new_password,
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+
+    // The link cannot be used again.
+    let response = app
+        .get(&format!("/password_reset/confirm?token={}", token))
+        .await;
+    assert_eq!(response.status().as_u16(), 404);
+    let response = reset(&app, &token, &uuid::Uuid::new_v4().to_string()).await;
+    assert_eq!(response.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn the_new_password_must_follow_the_length_rules() {
+    let app = spawn_app().await;
+    set_test_user_email(&app).await;
+    let token = request_reset(&app).await;
+
+    let response = reset(&app, &token, "too-short").await;
+
+    assert_is_redirect_to(
+        &response,
+        &format!("/password_reset/confirm?token={}", token),
+    );
+    let html_page = app
+        .get(&format!("/password_reset/confirm?token={}", token))
+        .await
+        .text()
+        .await
+        .unwrap();
+    assert!(html_page
+        .contains("New password must be between 12 and 128 characters."));
+}
+
+#[tokio::test]
+async fn the_new_password_must_be_hard_to_guess() {
+    let app = spawn_app().await;
+    set_test_user_email(&app).await;
+    let token = request_reset(&app).await;
+
+    let response = reset(&app, &token, "ursula_le_guin2023").await;
+
+    assert_is_redirect_to(
+        &response,
+        &format!("/password_reset/confirm?token={}", token),
+    );
+    let html_page = app
+        .get(&format!("/password_reset/confirm?token={}", token))
+        .await
+        .text()
+        .await
+        .unwrap();
+    assert!(html_page.contains("This password is too easy to guess."));
+    assert!(
+        html_page.contains("Do not include your username or email address.")
+    );
+}
+
+#[tokio::test]
+async fn expired_links_are_rejected() {
+    let app = spawn_app().await;
+    set_test_user_email(&app).await;
+    let token = request_reset(&app).await;
+    sqlx::query!("UPDATE password_reset_tokens SET expires_at = now()")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    let response = reset(&app, &token, &uuid::Uuid::new_v4().to_string()).await;
+
+    assert_eq!(response.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn resetting_the_password_logs_out_existing_sessions() {
+    let app = spawn_app().await;
+    set_test_user_email(&app).await;
+    app.post_login(&serde_json::json!({
+        "username": &app.test_user.username,
+        "password": &app.test_user.password,
+    }))
+    .await;
+    let response = app.get_admin_dashboard().await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let token = request_reset(&app).await;
+    reset(&app, &token, &uuid::Uuid::new_v4().to_string()).await;
+
+    let response = app.get_admin_dashboard().await;
+    assert_is_redirect_to(&response, "/login");
+}
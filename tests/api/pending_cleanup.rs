@@ -0,0 +1,134 @@
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+use zero2prod::configuration::PendingCleanupSettings;
+use zero2prod::pending_cleanup::delete_stale_pending_subscribers;
+
+use crate::helpers::{spawn_app, TestApp};
+
+fn cleanup_settings(dry_run: bool) -> PendingCleanupSettings {
+    PendingCleanupSettings {
+        max_age_hours: 24,
+        interval_seconds: 3600,
+        batch_size: 2,
+        dry_run,
+    }
+}
+
+#[test]
+fn cleanup_settings_must_make_progress() {
+    assert!(cleanup_settings(false).validate().is_ok());
+    let mut settings = cleanup_settings(false);
+    settings.batch_size = 0;
+    assert!(settings.validate().is_err());
+    let mut settings = cleanup_settings(false);
+    settings.interval_seconds = 0;
+    assert!(settings.validate().is_err());
+}
+
+/// Sign up `count` subscribers, the first `stale` of which signed up two
+/// days ago.
+async fn create_pending_subscribers(app: &TestApp, count: usize, stale: usize) {
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    for i in 0..count {
+        let body = format!("name=reader&email=reader{}%40example.com", i);
+        app.post_subscriptions(body)
+            .await
+            .error_for_status()
+            .unwrap();
+        if i < stale {
+            sqlx::query!(
+                "UPDATE subscriptions \
+                SET subscribed_at = now() - interval '2 days' \
+                WHERE email = $1",
+                format!("reader{}@example.com", i)
+            )
+            .execute(&app.db_pool)
+            .await
+            .unwrap();
+        }
+    }
+}
+
+#[tokio::test]
+async fn stale_pending_subscribers_are_deleted_in_batches() {
+    let app = spawn_app().await;
+    create_pending_subscribers(&app, 6, 5).await;
+    // Confirmed subscribers are kept, however old.
+    sqlx::query!(
+        "UPDATE subscriptions SET status = 'confirmed' \
+        WHERE email = 'reader0@example.com'"
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    let deleted = delete_stale_pending_subscribers(
+        &app.db_pool,
+        &cleanup_settings(false),
+    )
+    .await
+    .unwrap();
+    assert_eq!(deleted, 4);
+
+    let remaining =
+        sqlx::query!("SELECT email FROM subscriptions ORDER BY email")
+            .fetch_all(&app.db_pool)
+            .await
+            .unwrap();
+    let remaining: Vec<_> = remaining.into_iter().map(|r| r.email).collect();
+    assert_eq!(
+        remaining,
+        vec!["reader0@example.com", "reader5@example.com"]
+    );
+    let tokens =
+        sqlx::query!("SELECT COUNT(*) AS count FROM subscription_tokens")
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap();
+    assert_eq!(tokens.count, Some(2));
+}
+
+#[tokio::test]
+async fn a_dry_run_counts_stale_pending_subscribers_without_deleting_them() {
+    let app = spawn_app().await;
+    create_pending_subscribers(&app, 3, 2).await;
+
+    let stale =
+        delete_stale_pending_subscribers(&app.db_pool, &cleanup_settings(true))
+            .await
+            .unwrap();
+    assert_eq!(stale, 2);
+
+    let saved = sqlx::query!("SELECT COUNT(*) AS count FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.count, Some(3));
+}
+
+#[tokio::test]
+async fn a_deleted_pending_subscriber_can_sign_up_again() {
+    let app = spawn_app().await;
+    create_pending_subscribers(&app, 1, 1).await;
+
+    delete_stale_pending_subscribers(&app.db_pool, &cleanup_settings(false))
+        .await
+        .unwrap();
+
+    let body = "name=reader&email=reader0%40example.com";
+    app.post_subscriptions(body.into())
+        .await
+        .error_for_status()
+        .unwrap();
+    let saved = sqlx::query!("SELECT subscribed_at FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(
+        saved.subscribed_at > chrono::Utc::now() - chrono::Duration::hours(1)
+    );
+}
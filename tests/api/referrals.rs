@@ -0,0 +1,214 @@
+use uuid::Uuid;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+use crate::helpers::{spawn_app, ConfirmationLinks, TestApp};
+
+async fn sign_up(app: &TestApp, body: &str) -> ConfirmationLinks {
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+
+    app.post_subscriptions(body.into())
+        .await
+        .error_for_status()
+        .unwrap();
+
+    let email_request = &app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    app.get_confirmation_links(email_request)
+}
+
+async fn confirm(links: ConfirmationLinks) {
+    reqwest::get(links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+}
+
+struct Subscriber {
+    id: Uuid,
+    referral_code: String,
+    referred_by: Option<Uuid>,
+}
+
+async fn get_subscriber(app: &TestApp, email: &str) -> Subscriber {
+    sqlx::query_as!(
+        Subscriber,
+        "SELECT id, referral_code, referred_by FROM subscriptions WHERE email = $1",
+        email
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .expect("Failed to fetch saved subscription.")
+}
+
+#[tokio::test]
+async fn sign_ups_through_a_referral_link_are_attributed_to_the_referrer() {
+    let app = spawn_app().await;
+    confirm(sign_up(&app, "name=ursula&email=ursula%40example.com").await)
+        .await;
+    let referrer = get_subscriber(&app, "ursula@example.com").await;
+
+    sign_up(
+        &app,
+        &format!(
+            "name=ged&email=ged%40example.com&ref={}",
+            referrer.referral_code
+        ),
+    )
+    .await;
+
+    let referred = get_subscriber(&app, "ged@example.com").await;
+    assert_eq!(referred.referred_by, Some(referrer.id));
+    assert_ne!(referred.referral_code, referrer.referral_code);
+}
+
+#[tokio::test]
+async fn unknown_or_unconfirmed_referral_codes_are_ignored() {
+    let app = spawn_app().await;
+    sign_up(&app, "name=ursula&email=ursula%40example.com").await;
+    let unconfirmed = get_subscriber(&app, "ursula@example.com").await;
+
+    for (email, code) in [
+        ("ged", unconfirmed.referral_code.as_str()),
+        ("tenar", "not-a-code"),
+    ] {
+        sign_up(
+            &app,
+            &format!("name={email}&email={email}%40example.com&ref={code}"),
+        )
+        .await;
+        let subscriber =
+            get_subscriber(&app, &format!("{}@example.com", email)).await;
+        assert_eq!(subscriber.referred_by, None);
+    }
+}
+
+#[tokio::test]
+async fn subscribe_form_carries_the_referral_code_along() {
+    let app = spawn_app().await;
+
+    let html_page = app
+        .get("/subscriptions?ref=abc123")
+        .await
+        .text()
+        .await
+        .unwrap();
+
+    assert!(html_page
+        .contains(r#"<input hidden type="text" name="ref" value="abc123">"#));
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_see_the_referral_leaderboard() {
+    let app = spawn_app().await;
+
+    let response = app.get("/admin/referrals").await;
+
+    crate::login::assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn leaderboard_counts_confirmed_referrals_only() {
+    let app = spawn_app().await;
+    for name in ["ursula", "ged"] {
+        confirm(
+            sign_up(&app, &format!("name={name}&email={name}%40example.com"))
+                .await,
+        )
+        .await;
+    }
+    let ursula = get_subscriber(&app, "ursula@example.com").await;
+    let ged = get_subscriber(&app, "ged@example.com").await;
+    let referrals = [
+        ("tenar", &ursula, true),
+        ("arren", &ursula, true),
+        ("tehanu", &ursula, false),
+        ("ogion", &ged, true),
+    ];
+    for (name, referrer, confirmed) in referrals {
+        let links = sign_up(
+            &app,
+            &format!(
+                "name={name}&email={name}%40example.com&ref={}",
+                referrer.referral_code
+            ),
+        )
+        .await;
+        if confirmed {
+            confirm(links).await;
+        }
+    }
+
+    app.login().await;
+    let html_page = app.get("/admin/referrals").await.text().await.unwrap();
+
+    assert!(html_page.contains(&format!(
+        r#"<tr><td>1</td><td><a href="/admin/subscribers/{}">ursula@example.com</a></td><td>ursula</td><td>2</td></tr>"#,
+        ursula.id
+    )));
+    assert!(html_page.contains(&format!(
+        r#"<tr><td>2</td><td><a href="/admin/subscribers/{}">ged@example.com</a></td><td>ged</td><td>1</td></tr>"#,
+        ged.id
+    )));
+    assert!(!html_page.contains("tenar@example.com"));
+}
+
+#[tokio::test]
+async fn newsletters_can_include_the_referral_link_and_count() {
+    let app = spawn_app().await;
+    confirm(sign_up(&app, "name=ursula&email=ursula%40example.com").await)
+        .await;
+    let ursula = get_subscriber(&app, "ursula@example.com").await;
+    confirm(
+        sign_up(
+            &app,
+            &format!(
+                "name=ged&email=ged%40example.com&ref={}",
+                ursula.referral_code
+            ),
+        )
+        .await,
+    )
+    .await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": "Thanks {{name}}",
+            "content": {
+                "text": "{{referral_count}} {{referral_link}}",
+                "html": "<p>{{referral_count}} {{referral_link}}</p>",
+            }
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let email_requests = app.email_server.received_requests().await.unwrap();
+    let body = email_requests
+        .iter()
+        .map(|r| serde_json::from_slice::<serde_json::Value>(&r.body).unwrap())
+        .find(|body| body["Subject"] == "Thanks ursula")
+        .unwrap();
+    let text = body["TextBody"].as_str().unwrap();
+    assert!(text.starts_with("1 http"));
+    assert!(
+        text.ends_with(&format!("/subscriptions?ref={}", ursula.referral_code))
+    );
+}
@@ -0,0 +1,246 @@
+use std::time::Duration;
+
+use secrecy::ExposeSecret;
+use uuid::Uuid;
+use zero2prod::configuration::{get_configuration, Environment};
+
+use crate::helpers::{csrf_token, spawn_app, spawn_app_with, TestApp};
+use crate::login::assert_is_redirect_to;
+
+/// Log the test user in from another browser.
+async fn login_elsewhere(app: &TestApp) -> reqwest::Client {
+    let client = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .cookie_store(true)
+        .user_agent("other-browser")
+        .build()
+        .unwrap();
+    let response = client
+        .post(format!("{}/login", app.address))
+        .form(&[
+            ("username", &app.test_user.username),
+            ("password", &app.test_user.password),
+        ])
+        .send()
+        .await
+        .unwrap();
+    assert_is_redirect_to(&response, "/admin/dashboard");
+    client
+}
+
+async fn get_dashboard(
+    app: &TestApp,
+    client: &reqwest::Client,
+) -> reqwest::Response {
+    client
+        .get(format!("{}/admin/dashboard", app.address))
+        .send()
+        .await
+        .unwrap()
+}
+
+/// The ids of the sessions that can be revoked from the sessions page.
+fn revocable_session_ids(html_page: &str) -> Vec<Uuid> {
+    html_page
+        .split("/admin/sessions/")
+        .skip(1)
+        .filter_map(|rest| rest.split('/').next()?.parse().ok())
+        .collect()
+}
+
+#[tokio::test]
+async fn sessions_are_listed_with_the_browser_they_were_used_from() {
+    let app = spawn_app().await;
+    app.login().await;
+    let _other = login_elsewhere(&app).await;
+
+    let html_page = app.get("/admin/sessions").await.text().await.unwrap();
+
+    assert!(html_page.contains("This session"));
+    assert!(html_page.contains("<td>other-browser</td>"));
+    assert_eq!(revocable_session_ids(&html_page).len(), 1);
+}
+
+#[tokio::test]
+async fn revoked_sessions_are_logged_out() {
+    let app = spawn_app().await;
+    app.login().await;
+    let other = login_elsewhere(&app).await;
+    let html_page = app.get("/admin/sessions").await.text().await.unwrap();
+    let session_id = revocable_session_ids(&html_page)[0];
+
+    let response = app
+        .post(&format!("/admin/sessions/{}/revoke", session_id))
+        .await;
+
+    assert_is_redirect_to(&response, "/admin/sessions");
+    let html_page = app.get("/admin/sessions").await.text().await.unwrap();
+    assert!(html_page.contains("The session has been revoked."));
+    assert!(revocable_session_ids(&html_page).is_empty());
+    assert_is_redirect_to(&get_dashboard(&app, &other).await, "/login");
+    assert_eq!(app.get("/admin/dashboard").await.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn sessions_of_other_users_cannot_be_revoked() {
+    let app = spawn_app().await;
+    app.login().await;
+
+    let response = app
+        .post(&format!("/admin/sessions/{}/revoke", Uuid::new_v4()))
+        .await;
+
+    assert_eq!(response.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn all_other_sessions_can_be_revoked_at_once() {
+    let app = spawn_app().await;
+    app.login().await;
+    let first = login_elsewhere(&app).await;
+    let second = login_elsewhere(&app).await;
+
+    let response = app.post("/admin/sessions/revoke_others").await;
+
+    assert_is_redirect_to(&response, "/admin/sessions");
+    let html_page = app.get("/admin/sessions").await.text().await.unwrap();
+    assert!(html_page.contains("All other sessions have been revoked."));
+    assert!(html_page.contains("This session"));
+    for client in [first, second] {
+        assert_is_redirect_to(&get_dashboard(&app, &client).await, "/login");
+    }
+    let event = sqlx::query!(
+        "SELECT payload FROM audit_events WHERE action = 'sessions_revoked'"
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(event.payload["session_ids"].as_array().unwrap().len(), 2);
+}
+
+#[tokio::test]
+async fn changing_the_password_revokes_other_sessions() {
+    let app = spawn_app().await;
+    app.login().await;
+    let other = login_elsewhere(&app).await;
+    let new_password = Uuid::new_v4().to_string();
+
+    let response = app
+        .post_change_password(&serde_json::json!({
+            "current_password": &app.test_user.password,
+            "new_password": &new_password,
+            "new_password_validate": &new_password,
+        }))
+        .await;
+
+    assert_is_redirect_to(&response, "/admin/password");
+    assert_is_redirect_to(&get_dashboard(&app, &other).await, "/login");
+    assert_eq!(app.get("/admin/dashboard").await.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn logging_out_removes_the_session_from_the_list() {
+    let app = spawn_app().await;
+    app.login().await;
+    let other = login_elsewhere(&app).await;
+
+    let response = other
+        .post(format!("{}/admin/logout", app.address))
+        .header("X-CSRF-Token", csrf_token(&other, &app.address).await)
+        .send()
+        .await
+        .unwrap();
+    assert_is_redirect_to(&response, "/login");
+
+    let html_page = app.get("/admin/sessions").await.text().await.unwrap();
+    assert!(revocable_session_ids(&html_page).is_empty());
+    assert!(!html_page.contains("other-browser"));
+}
+
+#[tokio::test]
+async fn the_session_list_of_a_user_expires_with_their_last_session() {
+    let app = spawn_app_with(|c| {
+        c.application.session_absolute_timeout_seconds = 60;
+    })
+    .await;
+    app.login().await;
+
+    let client =
+        redis::Client::open(app.redis_uri.expose_secret().as_str()).unwrap();
+    let mut connection = client.get_async_connection().await.unwrap();
+    let ttl: i64 = redis::cmd("TTL")
+        .arg(format!(
+            "{}:user_sessions:{}",
+            app.redis_key_prefix, app.test_user.user_id
+        ))
+        .query_async(&mut connection)
+        .await
+        .unwrap();
+    assert!(0 < ttl && ttl <= 60, "{}", ttl);
+}
+
+#[test]
+fn session_timeouts_must_be_positive_and_ordered() {
+    let settings = get_configuration().unwrap().application;
+    assert!(settings.validate(&Environment::Local).is_ok());
+    let mut invalid = settings.clone();
+    invalid.session_idle_timeout_seconds = 0;
+    assert!(invalid.validate(&Environment::Local).is_err());
+    let mut invalid = settings;
+    invalid.session_absolute_timeout_seconds =
+        invalid.session_idle_timeout_seconds - 1;
+    assert!(invalid.validate(&Environment::Local).is_err());
+}
+
+#[tokio::test]
+async fn idle_sessions_are_logged_out() {
+    let app = spawn_app_with(|c| {
+        c.application.session_idle_timeout_seconds = 2;
+    })
+    .await;
+    app.login().await;
+
+    tokio::time::sleep(Duration::from_secs(3)).await;
+
+    assert_is_redirect_to(&app.get("/admin/dashboard").await, "/login");
+    let html_page = app.get("/login").await.text().await.unwrap();
+    assert!(html_page
+        .contains("You have been logged out after a period of inactivity."));
+    // The session is gone for good.
+    assert_is_redirect_to(&app.get("/admin/dashboard").await, "/login");
+}
+
+#[tokio::test]
+async fn requests_keep_sessions_from_going_idle() {
+    let app = spawn_app_with(|c| {
+        c.application.session_idle_timeout_seconds = 3;
+    })
+    .await;
+    app.login().await;
+
+    for _ in 0..3 {
+        tokio::time::sleep(Duration::from_millis(1500)).await;
+        assert_eq!(app.get("/admin/dashboard").await.status().as_u16(), 200);
+    }
+}
+
+#[tokio::test]
+async fn sessions_expire_after_the_absolute_timeout_however_active() {
+    let app = spawn_app_with(|c| {
+        c.application.session_absolute_timeout_seconds = 3;
+    })
+    .await;
+    app.login().await;
+
+    for _ in 0..2 {
+        tokio::time::sleep(Duration::from_millis(1000)).await;
+        assert_eq!(app.get("/admin/dashboard").await.status().as_u16(), 200);
+    }
+    tokio::time::sleep(Duration::from_millis(1500)).await;
+
+    assert_is_redirect_to(&app.get("/admin/dashboard").await, "/login");
+    let html_page = app.get("/login").await.text().await.unwrap();
+    assert!(
+        html_page.contains("Your session has expired, please log in again.")
+    );
+}
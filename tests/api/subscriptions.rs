@@ -1,7 +1,10 @@
-use wiremock::matchers::{method, path};
+use chrono::Utc;
+use wiremock::matchers::{any, method, path};
 use wiremock::{Mock, ResponseTemplate};
+use zero2prod::domain::SubscriptionStatus;
+use zero2prod::form_token::FormToken;
 
-use crate::helpers::spawn_app;
+use crate::helpers::{spawn_app, spawn_app_with, with_custom_attributes};
 
 #[tokio::test]
 async fn subscribe_returns_a_200_for_valid_form_data() {
@@ -26,14 +29,95 @@ async fn subscribe_persists_the_new_subscriber() {
 
     app.post_subscriptions(body.into()).await;
 
-    let saved = sqlx::query!("SELECT email, name, status FROM subscriptions",)
-        .fetch_one(&app.db_pool)
-        .await
-        .expect("Failed to fetch saved subscription.");
+    let saved = sqlx::query!(
+        r#"SELECT email, name, status AS "status: SubscriptionStatus"
+        FROM subscriptions"#,
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .expect("Failed to fetch saved subscription.");
 
     assert_eq!(saved.email, "ursula_le_guin@gmail.com");
     assert_eq!(saved.name, "le guin");
-    assert_eq!(saved.status, "pending_confirmation");
+    assert_eq!(saved.status, SubscriptionStatus::PendingConfirmation);
+}
+
+#[tokio::test]
+async fn subscribe_normalises_the_email_and_rejects_case_variants() {
+    let app = spawn_app().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_subscriptions(
+            "name=le%20guin&email=%20Ursula%40GMAIL.com%20".into(),
+        )
+        .await;
+    assert_eq!(200, response.status().as_u16());
+
+    // Same mailbox, different casing: it must not be stored twice, nor sent
+    // another email. The response does not tell it was already listed.
+    let response = app
+        .post_subscriptions("name=le%20guin&email=ursula%40gmail.com".into())
+        .await;
+    assert_eq!(200, response.status().as_u16());
+
+    let saved =
+        sqlx::query!("SELECT email, email_canonical FROM subscriptions")
+            .fetch_all(&app.db_pool)
+            .await
+            .expect("Failed to fetch saved subscriptions.");
+    assert_eq!(saved.len(), 1);
+    assert_eq!(saved[0].email, "Ursula@gmail.com");
+    assert_eq!(saved[0].email_canonical, "ursula@gmail.com");
+}
+
+#[tokio::test]
+async fn subscribe_records_how_the_subscriber_consented() {
+    let app = spawn_app().await;
+    let token = FormToken::issue(&app.hmac_secret, Utc::now().timestamp() - 60);
+    let body = format!(
+        "name=le%20guin&email=ursula_le_guin%40gmail.com&form_id=footer\
+        &form_issued_at={}&form_signature={}",
+        token.issued_at, token.signature
+    );
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    app.api_client
+        .post(format!("{}/subscriptions", &app.address))
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .header("User-Agent", "consent-test-agent")
+        .body(body)
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    let saved = sqlx::query!(
+        r#"
+        SELECT ip_address, user_agent, form_id, consent_text_version,
+            confirmed_at
+        FROM subscription_consents
+        "#,
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .expect("Failed to fetch saved consent record.");
+
+    assert_eq!(saved.ip_address.as_deref(), Some("127.0.0.1"));
+    assert_eq!(saved.user_agent.as_deref(), Some("consent-test-agent"));
+    assert_eq!(saved.form_id, "footer");
+    assert!(!saved.consent_text_version.is_empty());
+    assert!(saved.confirmed_at.is_none());
 }
 
 #[tokio::test]
@@ -53,24 +137,24 @@ async fn subscribe_sends_a_confirmation_email_for_valid_data() {
     // Mock asserts on drop
 }
 
-// #[tokio::test]
-// async fn subscribe_sends_a_confirmation_email_with_a_link() {
-//     let app = spawn_app().await;
-//     let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+#[tokio::test]
+async fn subscribe_sends_a_confirmation_email_with_a_link() {
+    let app = spawn_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
 
-//     Mock::given(path("/email"))
-//         .and(method("POST"))
-//         .respond_with(ResponseTemplate::new(200))
-//         .mount(&app.email_server)
-//         .await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
 
-//     app.post_subscriptions(body.into()).await;
+    app.post_subscriptions(body.into()).await;
 
-//     let email_request = &app.email_server.received_requests().await.unwrap()[0];
-//     let confirmation_links = app.get_confirmation_links(email_request);
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_links = app.get_confirmation_links(email_request);
 
-//     assert_eq!(confirmation_links.html, confirmation_links.plain_text);
-// }
+    assert_eq!(confirmation_links.html, confirmation_links.plain_text);
+}
 
 #[tokio::test]
 async fn subscribe_returns_a_400_when_data_is_missing() {
@@ -101,6 +185,14 @@ async fn subscribe_returns_a_400_when_fields_are_present_but_invalid() {
         ("name=&email=ursula_le_guin%40gmail.com", "empty name"),
         ("name=Ursula&email=", "empty email"),
         ("name=Ursula&email=definitely-not-an-email", "invalid email"),
+        (
+            "name=Ursula&email=ursula_le_guin%40gmail.com&form_id=%3Cscript%3E",
+            "invalid form id",
+        ),
+        (
+            "name=Ursula&email=ursula%40mailinator.com",
+            "disposable email domain",
+        ),
     ];
 
     for (body, description) in test_cases {
@@ -122,7 +214,7 @@ async fn subscribe_fails_if_there_is_a_fatal_database_error() {
 
     // sabotage the database.
     sqlx::query!(
-        "ALTER TABLE subscription_tokens DROP COLUMN subscription_token;",
+        "ALTER TABLE subscription_tokens DROP COLUMN subscription_token CASCADE;",
     )
     .execute(&app.db_pool)
     .await
@@ -132,3 +224,344 @@ async fn subscribe_fails_if_there_is_a_fatal_database_error() {
 
     assert_eq!(resp.status().as_u16(), 500);
 }
+
+#[tokio::test]
+async fn subscribe_form_embeds_a_form_token_and_a_honeypot() {
+    let app = spawn_app().await;
+
+    let html_page = app.get_subscriptions_form_html().await;
+
+    assert!(html_page.contains(r#"name="form_issued_at""#));
+    assert!(html_page.contains(r#"name="form_signature""#));
+    assert!(html_page.contains(r#"name="website""#));
+}
+
+#[tokio::test]
+async fn suspicious_subscriptions_are_silently_dropped() {
+    let app = spawn_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+    let now = Utc::now().timestamp();
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let test_cases = vec![
+        (
+            app.post_subscriptions(format!("{}&website=spam", body))
+                .await,
+            "filled in honeypot",
+        ),
+        (
+            app.post_subscriptions_raw(body.into()).await,
+            "missing form token",
+        ),
+        (
+            app.post_subscriptions_raw(format!(
+                "{}&form_issued_at={}&form_signature=deadbeef",
+                body,
+                now - 60
+            ))
+            .await,
+            "forged form token",
+        ),
+        (
+            app.post_subscriptions_with_token(body.into(), now).await,
+            "submitted too fast",
+        ),
+        (
+            app.post_subscriptions_with_token(body.into(), now - 7 * 86400)
+                .await,
+            "expired form token",
+        ),
+    ];
+
+    for (response, description) in test_cases {
+        assert_eq!(
+            200,
+            response.status().as_u16(),
+            "The API did not respond neutrally when the attempt had a {}.",
+            description
+        );
+    }
+
+    let saved = sqlx::query!("SELECT email FROM subscriptions")
+        .fetch_all(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscriptions.");
+    assert!(saved.is_empty());
+}
+
+#[tokio::test]
+async fn subscriptions_are_rate_limited_per_ip_address() {
+    let app = spawn_app_with(|c| {
+        c.subscriptions.rate_limit.max_attempts_per_ip = 1;
+    })
+    .await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    for email in ["ursula%40gmail.com", "le_guin%40gmail.com"] {
+        let response = app
+            .post_subscriptions(format!("name=le%20guin&email={}", email))
+            .await;
+        assert_eq!(200, response.status().as_u16());
+    }
+
+    let saved = sqlx::query!("SELECT email FROM subscriptions")
+        .fetch_all(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscriptions.");
+    assert_eq!(saved.len(), 1);
+}
+
+#[tokio::test]
+async fn subscriptions_are_rate_limited_per_email_address() {
+    let app = spawn_app_with(|c| {
+        c.subscriptions.rate_limit.max_attempts_per_email = 1;
+    })
+    .await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app.post_subscriptions(body.into()).await;
+    assert_eq!(200, response.status().as_u16());
+
+    // Without the rate limit this would try to store the email a second
+    // time.
+    let response = app.post_subscriptions(body.into()).await;
+    assert_eq!(200, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn subscribe_suggests_a_correction_for_misspelled_email_domains() {
+    let app = spawn_app().await;
+
+    let response = app
+        .post_subscriptions("name=le%20guin&email=ursula%40gmial.com".into())
+        .await;
+
+    assert_eq!(400, response.status().as_u16());
+    assert_eq!(
+        response.text().await.unwrap(),
+        "Did you mean ursula@gmail.com?"
+    );
+}
+
+#[tokio::test]
+async fn subscribe_accepts_a_misspelled_looking_domain_once_confirmed() {
+    let app = spawn_app().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_subscriptions(
+            "name=le%20guin&email=ursula%40gmial.com&confirm_email=true".into(),
+        )
+        .await;
+
+    assert_eq!(200, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn subscribe_stores_custom_attributes_with_their_type() {
+    let app = spawn_app_with(with_custom_attributes).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_subscriptions(
+            "name=le%20guin&email=ursula_le_guin%40gmail.com\
+            &company=Earthsea&team_size=12&developer=false&unknown=ignored"
+                .into(),
+        )
+        .await;
+    assert_eq!(200, response.status().as_u16());
+
+    let saved = sqlx::query!("SELECT attributes FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscription.");
+    assert_eq!(
+        saved.attributes,
+        serde_json::json!({
+            "company": "Earthsea",
+            "team_size": 12,
+            "developer": false
+        })
+    );
+}
+
+#[tokio::test]
+async fn subscribe_returns_a_400_for_missing_or_invalid_attributes() {
+    let app = spawn_app_with(|c| {
+        with_custom_attributes(c);
+        c.subscriptions.custom_attributes[0].required = true;
+    })
+    .await;
+    let test_cases = vec![
+        ("", "company is required."),
+        ("&company=", "company is required."),
+        (
+            "&company=Earthsea&team_size=a%20dozen",
+            "a dozen is not a valid number for team_size.",
+        ),
+        (
+            "&company=Earthsea&developer=maybe",
+            "maybe is not a valid boolean for developer.",
+        ),
+    ];
+
+    for (attributes, error_message) in test_cases {
+        let response = app
+            .post_subscriptions(format!(
+                "name=le%20guin&email=ursula_le_guin%40gmail.com{}",
+                attributes
+            ))
+            .await;
+
+        assert_eq!(
+            400,
+            response.status().as_u16(),
+            "The API did not fail with 400 Bad Request when {}",
+            error_message
+        );
+        assert_eq!(response.text().await.unwrap(), error_message);
+    }
+}
+
+#[tokio::test]
+async fn subscribe_form_has_an_input_per_custom_attribute() {
+    let app = spawn_app_with(with_custom_attributes).await;
+
+    let html_page = app.get_subscriptions_form_html().await;
+
+    assert!(
+        html_page.contains(r#"<input type="text" name="company" value="">"#)
+    );
+    assert!(html_page.contains(
+        r#"<input type="number" step="any" name="team_size" value="">"#
+    ));
+    assert!(html_page.contains(r#"<select name="developer">"#));
+}
+
+#[tokio::test]
+async fn subscribe_stores_the_sign_up_source_and_utm_parameters() {
+    let app = spawn_app().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_subscriptions(
+            "name=le%20guin&email=ursula_le_guin%40gmail.com\
+            &source=pricing-page&utm_source=newsletter&utm_medium=email\
+            &utm_campaign=Spring%20sale&utm_term=&utm_content=%20banner%20"
+                .into(),
+        )
+        .await;
+    assert_eq!(200, response.status().as_u16());
+
+    let saved = sqlx::query!(
+        r#"
+        SELECT source, utm_source, utm_medium, utm_campaign, utm_term,
+            utm_content, attributes
+        FROM subscriptions
+        "#
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .expect("Failed to fetch saved subscription.");
+    assert_eq!(saved.source.as_deref(), Some("pricing-page"));
+    assert_eq!(saved.utm_source.as_deref(), Some("newsletter"));
+    assert_eq!(saved.utm_medium.as_deref(), Some("email"));
+    assert_eq!(saved.utm_campaign.as_deref(), Some("Spring sale"));
+    assert_eq!(saved.utm_term, None);
+    assert_eq!(saved.utm_content.as_deref(), Some("banner"));
+    assert_eq!(saved.attributes, serde_json::json!({}));
+}
+
+#[tokio::test]
+async fn subscribe_returns_a_400_for_an_invalid_source_or_utm_parameter() {
+    let app = spawn_app().await;
+    let long_campaign = format!("&utm_campaign={}", "a".repeat(129));
+    let test_cases = vec![
+        ("&source=%3Cscript%3E", "<script> is not a valid source."),
+        (
+            long_campaign.as_str(),
+            "utm_campaign must be at most 128 characters long.",
+        ),
+        (
+            "&utm_term=new%0Aline",
+            "utm_term contains invalid characters.",
+        ),
+    ];
+
+    for (source, error_message) in test_cases {
+        let response = app
+            .post_subscriptions(format!(
+                "name=le%20guin&email=ursula_le_guin%40gmail.com{}",
+                source
+            ))
+            .await;
+
+        assert_eq!(
+            400,
+            response.status().as_u16(),
+            "The API did not fail with 400 Bad Request when {}",
+            error_message
+        );
+        assert_eq!(response.text().await.unwrap(), error_message);
+    }
+}
+
+#[tokio::test]
+async fn subscribe_form_carries_the_source_of_the_page_along() {
+    let app = spawn_app().await;
+
+    let html_page = app
+        .get("/subscriptions?source=blog&utm_campaign=spring&utm_term=")
+        .await
+        .text()
+        .await
+        .unwrap();
+
+    assert!(html_page
+        .contains(r#"<input hidden type="text" name="source" value="blog">"#));
+    assert!(html_page.contains(r#"name="utm_campaign" value="spring">"#));
+    assert!(!html_page.contains(r#"name="utm_term""#));
+
+    // A mangled campaign link does not block sign-ups.
+    let html_page = app
+        .get("/subscriptions?source=%3Cscript%3E")
+        .await
+        .text()
+        .await
+        .unwrap();
+    assert!(!html_page.contains(r#"name="source""#));
+}
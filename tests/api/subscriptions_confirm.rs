@@ -1,3 +1,9 @@
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+use zero2prod::domain::SubscriptionStatus;
+
+use crate::helpers::spawn_app;
+
 // use crate::helpers::spawn_app;
 // use wiremock::matchers::{method, path};
 // use wiremock::{Mock, ResponseTemplate};
@@ -61,3 +67,134 @@
 //     assert_eq!(saved.name, "le guin");
 //     assert_eq!(saved.status, "confirmed");
 // }
+
+#[tokio::test]
+async fn confirming_a_subscription_records_the_click_on_the_consent_record() {
+    let app = spawn_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions(body.into()).await;
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_links = app.get_confirmation_links(email_request);
+
+    app.api_client
+        .get(confirmation_links.html)
+        .header("User-Agent", "confirmation-test-agent")
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    let saved = sqlx::query!(
+        r#"
+        SELECT confirmed_at, confirmation_ip_address, confirmation_user_agent
+        FROM subscription_consents
+        "#,
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .expect("Failed to fetch saved consent record.");
+
+    assert!(saved.confirmed_at.is_some());
+    assert_eq!(saved.confirmation_ip_address.as_deref(), Some("127.0.0.1"));
+    assert_eq!(
+        saved.confirmation_user_agent.as_deref(),
+        Some("confirmation-test-agent")
+    );
+}
+
+#[tokio::test]
+async fn confirmation_links_do_not_bring_back_unsubscribed_subscribers() {
+    let app = spawn_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions(body.into()).await;
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_links = app.get_confirmation_links(email_request);
+    sqlx::query!("UPDATE subscriptions SET status = 'unsubscribed'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    let response = reqwest::get(confirmation_links.html).await.unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+
+    let saved = sqlx::query!(
+        r#"SELECT status AS "status: SubscriptionStatus" FROM subscriptions"#
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(saved.status, SubscriptionStatus::Unsubscribed);
+    // Nor is the click taken as evidence of an opt-in.
+    let consent =
+        sqlx::query!("SELECT confirmed_at FROM subscription_consents")
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap();
+    assert!(consent.confirmed_at.is_none());
+}
+
+#[tokio::test]
+async fn status_changes_are_recorded_with_their_cause() {
+    let app = spawn_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions(body.into()).await;
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_links = app.get_confirmation_links(email_request);
+    // Clicking twice must not record a second confirmation.
+    for _ in 0..2 {
+        reqwest::get(confirmation_links.html.clone())
+            .await
+            .unwrap()
+            .error_for_status()
+            .unwrap();
+    }
+
+    let changes = sqlx::query!(
+        r#"
+        SELECT
+            from_status AS "from_status: SubscriptionStatus",
+            to_status AS "to_status: SubscriptionStatus",
+            cause
+        FROM subscription_status_changes
+        ORDER BY id
+        "#
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(changes.len(), 2);
+    assert_eq!(changes[0].from_status, None);
+    assert_eq!(
+        changes[0].to_status,
+        SubscriptionStatus::PendingConfirmation
+    );
+    assert_eq!(changes[0].cause, "sign_up");
+    assert_eq!(
+        changes[1].from_status,
+        Some(SubscriptionStatus::PendingConfirmation)
+    );
+    assert_eq!(changes[1].to_status, SubscriptionStatus::Confirmed);
+    assert_eq!(changes[1].cause, "confirmation_link");
+}
@@ -0,0 +1,203 @@
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+use crate::helpers::{spawn_app, spawn_app_with, with_custom_attributes};
+
+#[tokio::test]
+async fn subscribe_accepts_json_and_stores_the_subscriber() {
+    let app = spawn_app_with(with_custom_attributes).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_subscriptions_json(serde_json::json!({
+            "name": "le guin",
+            "email": "ursula_le_guin@gmail.com",
+            "form_id": "homepage",
+            "attributes": {"company": "Earthsea", "team_size": 12}
+        }))
+        .await;
+    assert_eq!(200, response.status().as_u16());
+
+    let saved = sqlx::query!(
+        r#"
+        SELECT s.email, s.attributes, c.form_id
+        FROM subscriptions s
+        JOIN subscription_consents c ON c.subscriber_id = s.id
+        "#
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .expect("Failed to fetch saved subscription.");
+    assert_eq!(saved.email, "ursula_le_guin@gmail.com");
+    assert_eq!(saved.form_id, "homepage");
+    assert_eq!(
+        saved.attributes,
+        serde_json::json!({"company": "Earthsea", "team_size": 12})
+    );
+}
+
+#[tokio::test]
+async fn json_subscriptions_report_every_invalid_field() {
+    let app = spawn_app_with(with_custom_attributes).await;
+
+    let response = app
+        .post_subscriptions_json(serde_json::json!({
+            "name": "",
+            "email": "definitely-not-an-email",
+            "attributes": {"team_size": "a dozen"}
+        }))
+        .await;
+
+    assert_eq!(400, response.status().as_u16());
+    let body: serde_json::Value = response.json().await.unwrap();
+    let fields: Vec<&str> = body["errors"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|error| error["field"].as_str().unwrap())
+        .collect();
+    assert_eq!(fields, vec!["name", "email", "team_size"]);
+    assert_eq!(
+        body["errors"][2]["message"],
+        "a dozen is not a valid number for team_size."
+    );
+}
+
+#[tokio::test]
+async fn json_subscriptions_return_the_email_suggestion_as_a_field_error() {
+    let app = spawn_app().await;
+
+    let response = app
+        .post_subscriptions_json(serde_json::json!({
+            "name": "le guin",
+            "email": "ursula@gmial.com",
+        }))
+        .await;
+
+    assert_eq!(400, response.status().as_u16());
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(
+        body,
+        serde_json::json!({"errors": [{
+            "field": "email",
+            "message": "Did you mean ursula@gmail.com?"
+        }]})
+    );
+}
+
+#[tokio::test]
+async fn cross_origin_requests_are_only_allowed_from_configured_origins() {
+    let app = spawn_app_with(|c| {
+        c.subscriptions.cors_allowed_origins =
+            vec!["https://www.example.com".into()];
+    })
+    .await;
+    let preflight = |origin: &'static str| {
+        app.api_client
+            .request(
+                reqwest::Method::OPTIONS,
+                format!("{}/subscriptions", &app.address),
+            )
+            .header("Origin", origin)
+            .header("Access-Control-Request-Method", "POST")
+            .header("Access-Control-Request-Headers", "content-type")
+            .send()
+    };
+
+    let response = preflight("https://www.example.com").await.unwrap();
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(
+        response.headers()["Access-Control-Allow-Origin"],
+        "https://www.example.com"
+    );
+
+    let response = preflight("https://evil.example.org").await.unwrap();
+    assert!(response
+        .headers()
+        .get("Access-Control-Allow-Origin")
+        .is_none());
+}
+
+#[tokio::test]
+async fn same_origin_requests_are_always_allowed() {
+    let app = spawn_app().await;
+
+    // Browsers send an `Origin` header with form submissions, even to the
+    // site the form is on.
+    let response = app
+        .api_client
+        .get(format!("{}/subscriptions", &app.address))
+        .header("Origin", &app.address)
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(200, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn the_widget_script_embeds_a_fresh_form_and_is_not_cached() {
+    let app = spawn_app_with(with_custom_attributes).await;
+
+    let response = app.get("/subscriptions/widget.js").await;
+
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(
+        response.headers()["Content-Type"],
+        "application/javascript; charset=utf-8"
+    );
+    assert_eq!(response.headers()["Cache-Control"], "no-store");
+    let script = response.text().await.unwrap();
+    assert!(!script.contains("__CONFIG__"));
+    assert!(script.contains(r#"name=\"form_signature\""#));
+    assert!(script.contains(r#"name=\"company\""#));
+
+    let html_page =
+        app.get("/subscriptions/widget").await.text().await.unwrap();
+    assert!(html_page.contains("/subscriptions/widget.js"));
+}
+
+#[tokio::test]
+async fn json_subscriptions_store_the_sign_up_source() {
+    let app = spawn_app().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_subscriptions_json(serde_json::json!({
+            "name": "le guin",
+            "email": "ursula_le_guin@gmail.com",
+            "source": "partner-blog",
+            "utm_medium": "referral",
+        }))
+        .await;
+    assert_eq!(200, response.status().as_u16());
+
+    let saved = sqlx::query!("SELECT source, utm_medium FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscription.");
+    assert_eq!(saved.source.as_deref(), Some("partner-blog"));
+    assert_eq!(saved.utm_medium.as_deref(), Some("referral"));
+
+    let response = app
+        .post_subscriptions_json(serde_json::json!({
+            "name": "le guin",
+            "email": "ursula@example.com",
+            "source": "partner blog",
+        }))
+        .await;
+    assert_eq!(400, response.status().as_u16());
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["errors"][0]["field"], "source");
+}
@@ -0,0 +1,369 @@
+use chrono::Utc;
+use secrecy::ExposeSecret;
+use zero2prod::authentication::{Totp, RECOVERY_CODE_COUNT};
+
+use crate::helpers::{spawn_app, spawn_app_with, TestApp, TestUser};
+use crate::login::assert_is_redirect_to;
+
+fn login_body(user: &TestUser) -> serde_json::Value {
+    serde_json::json!({
+        "username": &user.username,
+        "password": &user.password,
+    })
+}
+
+/// The text between the first `start` and the following `end`.
+fn between<'a>(html: &'a str, start: &str, end: &str) -> &'a str {
+    let from = html.find(start).expect("start marker not found") + start.len();
+    let to = from + html[from..].find(end).expect("end marker not found");
+    &html[from..to]
+}
+
+/// The secret shown on an enrolment page.
+fn enrolment_secret(html: &str) -> Totp {
+    Totp::from_base32(between(html, "Secret: <code>", "</code>")).unwrap()
+}
+
+fn recovery_codes(html: &str) -> Vec<String> {
+    html.split("<li><code>")
+        .skip(1)
+        .map(|s| s[..s.find("</code>").unwrap()].to_string())
+        .collect()
+}
+
+/// The code of the time step before the current one, which is still
+/// accepted, for tests that need two different valid codes.
+fn previous_code(totp: &Totp) -> String {
+    totp.code(Utc::now().timestamp() - 30)
+}
+
+fn current_code(totp: &Totp) -> String {
+    totp.code(Utc::now().timestamp())
+}
+
+/// Log `user` in and set up two-factor authentication, confirming it with
+/// the code of the previous time step. Returns the secret, the code used and
+/// the recovery codes.
+async fn enrol(app: &TestApp, user: &TestUser) -> (Totp, String, Vec<String>) {
+    let response = app.post_login(&login_body(user)).await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+
+    let html_page = app.get("/admin/two_factor").await.text().await.unwrap();
+    assert!(html_page.contains("<svg"));
+    let totp = enrolment_secret(&html_page);
+    let code = previous_code(&totp);
+
+    let response = app
+        .post_form("/admin/two_factor", &serde_json::json!({ "code": code }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+    let codes = recovery_codes(&response.text().await.unwrap());
+    assert_eq!(codes.len(), RECOVERY_CODE_COUNT);
+
+    app.post_logout().await;
+    (totp, code, codes)
+}
+
+#[tokio::test]
+async fn users_can_enable_two_factor_with_a_valid_code() {
+    let app = spawn_app().await;
+    let user = TestUser::generate();
+    user.store(&app.db_pool).await;
+    app.post_login(&login_body(&user)).await;
+
+    let html_page = app.get("/admin/two_factor").await.text().await.unwrap();
+    let totp = enrolment_secret(&html_page);
+    let response = app
+        .post_form(
+            "/admin/two_factor",
+            &serde_json::json!({ "code": "000000" }),
+        )
+        .await;
+    assert_is_redirect_to(&response, "/admin/two_factor");
+    let html_page = app.get("/admin/two_factor").await.text().await.unwrap();
+    assert!(html_page.contains("<p><i>Invalid authentication code.</i></p>"));
+    // The secret stays the same until it is confirmed.
+    assert_eq!(
+        enrolment_secret(&html_page).to_base32().expose_secret(),
+        totp.to_base32().expose_secret()
+    );
+
+    let response = app
+        .post_form(
+            "/admin/two_factor",
+            &serde_json::json!({ "code": current_code(&totp) }),
+        )
+        .await;
+    let html_page = response.text().await.unwrap();
+    assert_eq!(recovery_codes(&html_page).len(), RECOVERY_CODE_COUNT);
+
+    let html_page = app.get("/admin/two_factor").await.text().await.unwrap();
+    assert!(html_page.contains("Two-factor authentication is enabled."));
+}
+
+#[tokio::test]
+async fn login_with_two_factor_requires_a_code() {
+    let app = spawn_app().await;
+    let user = TestUser::generate();
+    user.store(&app.db_pool).await;
+    let (totp, _, _) = enrol(&app, &user).await;
+
+    let response = app.post_login(&login_body(&user)).await;
+    assert_is_redirect_to(&response, "/login/two_factor");
+
+    // Half-authenticated sessions do not get into the admin area.
+    let response = app.get_admin_dashboard().await;
+    assert_is_redirect_to(&response, "/login");
+
+    let response = app
+        .post_form(
+            "/login/two_factor",
+            &serde_json::json!({ "code": "000000" }),
+        )
+        .await;
+    assert_is_redirect_to(&response, "/login/two_factor");
+    let html_page = app.get("/login/two_factor").await.text().await.unwrap();
+    assert!(html_page.contains("<p><i>Invalid authentication code.</i></p>"));
+
+    let response = app
+        .post_form(
+            "/login/two_factor",
+            &serde_json::json!({ "code": current_code(&totp) }),
+        )
+        .await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+    let html_page = app.get_admin_dashboard_html().await;
+    assert!(html_page.contains(&format!("Welcome {}", user.username)));
+}
+
+#[tokio::test]
+async fn the_second_step_needs_a_correct_password_first() {
+    let app = spawn_app().await;
+
+    let response = app.get("/login/two_factor").await;
+    assert_is_redirect_to(&response, "/login");
+    let response = app
+        .post_form(
+            "/login/two_factor",
+            &serde_json::json!({ "code": "000000" }),
+        )
+        .await;
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn repeated_wrong_codes_lock_the_account() {
+    let app = spawn_app_with(|c| {
+        c.login_throttling.delay_after_failures = 100;
+        c.login_throttling.lockout_after_failures = 3;
+    })
+    .await;
+    let user = TestUser::generate();
+    user.store(&app.db_pool).await;
+    enrol(&app, &user).await;
+    let wrong_code = serde_json::json!({ "code": "000000" });
+    let wrong_code = || app.post_form("/login/two_factor", &wrong_code);
+
+    // Entering the password again does not give a fresh set of attempts.
+    for _ in 0..2 {
+        let response = app.post_login(&login_body(&user)).await;
+        assert_is_redirect_to(&response, "/login/two_factor");
+        assert_is_redirect_to(&wrong_code().await, "/login/two_factor");
+    }
+    app.post_login(&login_body(&user)).await;
+    assert_is_redirect_to(&wrong_code().await, "/login");
+    let locked = "Too many failed login attempts, please try again later.";
+    assert!(app.get_login_html().await.contains(locked));
+
+    // The half-authenticated session is gone, and so is the password step.
+    assert_is_redirect_to(&app.get("/login/two_factor").await, "/login");
+    let response = app.post_login(&login_body(&user)).await;
+    assert_is_redirect_to(&response, "/login");
+    assert!(app.get_login_html().await.contains(locked));
+}
+
+#[tokio::test]
+async fn wrong_codes_to_disable_two_factor_lock_the_account() {
+    let app = spawn_app_with(|c| {
+        c.login_throttling.delay_after_failures = 100;
+        c.login_throttling.lockout_after_failures = 3;
+    })
+    .await;
+    let user = TestUser::generate();
+    user.store(&app.db_pool).await;
+    let (_, _, codes) = enrol(&app, &user).await;
+    app.post_login(&login_body(&user)).await;
+    app.post_form(
+        "/login/two_factor",
+        &serde_json::json!({ "code": codes[0] }),
+    )
+    .await;
+    let wrong_code = serde_json::json!({ "code": "000000" });
+    let wrong_code = || app.post_form("/admin/two_factor/disable", &wrong_code);
+
+    for _ in 0..2 {
+        assert_is_redirect_to(&wrong_code().await, "/admin/two_factor");
+    }
+    assert_is_redirect_to(&wrong_code().await, "/login");
+    let locked = "Too many failed login attempts, please try again later.";
+    assert!(app.get_login_html().await.contains(locked));
+
+    // The session is gone, and two-factor authentication is still on.
+    assert_is_redirect_to(&app.get("/admin/two_factor").await, "/login");
+    let totp_secret = sqlx::query!(
+        "SELECT totp_secret FROM users WHERE user_id = $1",
+        user.user_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .totp_secret;
+    assert!(totp_secret.is_some());
+}
+
+#[tokio::test]
+async fn codes_cannot_be_used_twice() {
+    let app = spawn_app().await;
+    let user = TestUser::generate();
+    user.store(&app.db_pool).await;
+    let (_, used_code, _) = enrol(&app, &user).await;
+
+    app.post_login(&login_body(&user)).await;
+    let response = app
+        .post_form(
+            "/login/two_factor",
+            &serde_json::json!({ "code": used_code }),
+        )
+        .await;
+
+    assert_is_redirect_to(&response, "/login/two_factor");
+}
+
+#[tokio::test]
+async fn recovery_codes_can_be_used_once() {
+    let app = spawn_app().await;
+    let user = TestUser::generate();
+    user.store(&app.db_pool).await;
+    let (_, _, codes) = enrol(&app, &user).await;
+    // Recovery codes are accepted regardless of case and dashes.
+    let code = codes[0].replace('-', "").to_uppercase();
+
+    app.post_login(&login_body(&user)).await;
+    let response = app
+        .post_form("/login/two_factor", &serde_json::json!({ "code": code }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+    app.post_logout().await;
+
+    app.post_login(&login_body(&user)).await;
+    let response = app
+        .post_form("/login/two_factor", &serde_json::json!({ "code": code }))
+        .await;
+    assert_is_redirect_to(&response, "/login/two_factor");
+}
+
+#[tokio::test]
+async fn two_factor_can_be_disabled_with_a_code() {
+    let app = spawn_app().await;
+    let user = TestUser::generate();
+    user.store(&app.db_pool).await;
+    let (_, _, codes) = enrol(&app, &user).await;
+    app.post_login(&login_body(&user)).await;
+    app.post_form(
+        "/login/two_factor",
+        &serde_json::json!({ "code": codes[0] }),
+    )
+    .await;
+
+    let response = app
+        .post_form(
+            "/admin/two_factor/disable",
+            &serde_json::json!({ "code": codes[1] }),
+        )
+        .await;
+    assert_is_redirect_to(&response, "/admin/two_factor");
+    let html_page = app.get("/admin/two_factor").await.text().await.unwrap();
+    assert!(html_page.contains(
+        "<p><i>Two-factor authentication has been disabled.</i></p>"
+    ));
+    app.post_logout().await;
+
+    let response = app.post_login(&login_body(&user)).await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
+
+#[tokio::test]
+async fn requiring_two_factor_makes_users_set_it_up_when_logging_in() {
+    let app = spawn_app().await;
+    let admin = TestUser::generate();
+    admin.store(&app.db_pool).await;
+    app.post_login(&login_body(&admin)).await;
+    let response = app
+        .post_form(
+            "/admin/users/two_factor_requirement",
+            &serde_json::json!({ "required": true }),
+        )
+        .await;
+    assert_is_redirect_to(&response, "/admin/users");
+    let html_page = app.get("/admin/users").await.text().await.unwrap();
+    assert!(html_page.contains(
+        "<p><i>Two-factor authentication is now required for all users.</i></p>"
+    ));
+    app.post_logout().await;
+
+    let user = TestUser::generate();
+    user.store(&app.db_pool).await;
+    let response = app.post_login(&login_body(&user)).await;
+    assert_is_redirect_to(&response, "/login/two_factor");
+    let html_page = app.get("/login/two_factor").await.text().await.unwrap();
+    assert!(html_page.contains("Two-factor authentication is required"));
+    let totp = enrolment_secret(&html_page);
+
+    let response = app
+        .post_form(
+            "/login/two_factor",
+            &serde_json::json!({ "code": current_code(&totp) }),
+        )
+        .await;
+    let html_page = response.text().await.unwrap();
+    assert_eq!(recovery_codes(&html_page).len(), RECOVERY_CODE_COUNT);
+    let html_page = app.get_admin_dashboard_html().await;
+    assert!(html_page.contains(&format!("Welcome {}", user.username)));
+
+    // It cannot be turned off while it is required.
+    let html_page = app.get("/admin/two_factor").await.text().await.unwrap();
+    assert!(html_page.contains("cannot be disabled"));
+}
+
+#[tokio::test]
+async fn basic_auth_is_refused_to_users_with_two_factor() {
+    let app = spawn_app().await;
+    let user = TestUser::generate();
+    user.store(&app.db_pool).await;
+    enrol(&app, &user).await;
+    let publish_as = |user: &TestUser| {
+        app.api_client
+            .post(format!("{}/newsletter", &app.address))
+            .basic_auth(&user.username, Some(&user.password))
+            .json(&serde_json::json!({
+                "title": "Newsletter title",
+                "content": {
+                    "text": "Newsletter body as plain text",
+                    "html": "<p>Newsletter body as HTML</p>",
+                }
+            }))
+            .send()
+    };
+
+    let response = publish_as(&user).await.unwrap();
+    assert_eq!(response.status().as_u16(), 401);
+    assert!(response.text().await.unwrap().contains("API token"));
+
+    // Nor can anybody use it once two-factor is required.
+    sqlx::query!("UPDATE admin_settings SET require_two_factor = true")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    let response = publish_as(&app.test_user).await.unwrap();
+    assert_eq!(response.status().as_u16(), 401);
+}